@@ -13,15 +13,29 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use core::fmt;
+use alloc::sync::Arc;
+use core::{cmp, fmt};
+use parity_wasm::elements;
 use sha2::Digest as _;
 
+pub use self::cache::{ModuleCache, ModuleStore};
+
+pub(crate) mod breakpoints;
+
+mod cache;
+
 /// Represents a successfully-parsed binary.
 ///
 /// This is the equivalent of an [ELF](https://en.wikipedia.org/wiki/Executable_and_Linkable_Format)
 /// or a [PE](https://en.wikipedia.org/wiki/Portable_Executable).
+///
+/// Cloning a `Module` is cheap, as the parsed module is shared between all the clones.
+#[derive(Clone)]
 pub struct Module {
-    inner: wasmi::Module,
+    inner: Arc<wasmi::Module>,
+    /// Module that `inner` has been built from. Kept in order to be able to derive modified
+    /// versions of `inner`.
+    elements: Arc<elements::Module>,
     hash: ModuleHash,
 }
 
@@ -36,10 +50,37 @@ pub struct FromBytesError {}
 impl Module {
     /// Parses a module from WASM bytes.
     pub fn from_bytes(buffer: impl AsRef<[u8]>) -> Result<Self, FromBytesError> {
-        let inner = wasmi::Module::from_buffer(buffer.as_ref()).map_err(|_| FromBytesError {})?;
+        let elements: elements::Module =
+            elements::deserialize_buffer(buffer.as_ref()).map_err(|_| FromBytesError {})?;
+        let inner = wasmi::Module::from_parity_wasm_module(elements.clone())
+            .map_err(|_| FromBytesError {})?;
+        let hash = ModuleHash::from_bytes(buffer);
+
+        Ok(Module {
+            inner: Arc::new(inner),
+            elements: Arc::new(elements),
+            hash,
+        })
+    }
+
+    /// Parses a module from WASM bytes, and instruments it so that breakpoints can be set on its
+    /// functions.
+    ///
+    /// The module behaves the same as the one returned by [`Module::from_bytes`], but each
+    /// function call interrupts the execution in order to check for breakpoints, which makes it
+    /// considerably slower. The hash is the one of the original, non-instrumented module.
+    pub fn from_bytes_with_breakpoints(buffer: impl AsRef<[u8]>) -> Result<Self, FromBytesError> {
+        let instrumented =
+            breakpoints::instrument(buffer.as_ref()).map_err(|_| FromBytesError {})?;
+        let inner = wasmi::Module::from_parity_wasm_module(instrumented.clone())
+            .map_err(|_| FromBytesError {})?;
         let hash = ModuleHash::from_bytes(buffer);
 
-        Ok(Module { inner, hash })
+        Ok(Module {
+            inner: Arc::new(inner),
+            elements: Arc::new(instrumented),
+            hash,
+        })
     }
 
     /// Turns some WASM text source into a `Module`.
@@ -54,6 +95,32 @@ impl Module {
         &self.inner
     }
 
+    /// Returns a copy of this module whose memory can't grow beyond `max_pages` pages of 64kiB.
+    ///
+    /// The limit is enforced by the interpreter, in other words `memory.grow` fails if it would
+    /// make the memory go over the limit. Returns an error if the initial size of the memory is
+    /// already above the limit.
+    pub(crate) fn with_max_memory_pages(&self, max_pages: u32) -> Result<Module, ()> {
+        let mut elements = (*self.elements).clone();
+        if let Some(section) = elements.memory_section_mut() {
+            for entry in section.entries_mut() {
+                let limits = entry.limits();
+                let maximum = cmp::min(limits.maximum().unwrap_or(max_pages), max_pages);
+                if limits.initial() > maximum {
+                    return Err(());
+                }
+                *entry = elements::MemoryType::new(limits.initial(), Some(maximum));
+            }
+        }
+
+        let inner = wasmi::Module::from_parity_wasm_module(elements.clone()).map_err(|_| ())?;
+        Ok(Module {
+            inner: Arc::new(inner),
+            elements: Arc::new(elements),
+            hash: self.hash.clone(),
+        })
+    }
+
     /// Returns the hash of that module.
     ///
     /// This gives the same result as calling `ModuleHash::from_bytes` on the original input.
@@ -81,6 +148,18 @@ impl ModuleHash {
     }
 }
 
+impl From<ModuleHash> for [u8; 32] {
+    fn from(hash: ModuleHash) -> [u8; 32] {
+        hash.0
+    }
+}
+
+impl fmt::Display for ModuleHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", bs58::encode(&self.0).into_string())
+    }
+}
+
 impl fmt::Debug for ModuleHash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "ModuleHash({})", bs58::encode(&self.0).into_string())
@@ -95,7 +174,7 @@ impl fmt::Display for FromBytesError {
 
 #[cfg(test)]
 mod tests {
-    use super::Module;
+    use super::{Module, ModuleHash};
 
     #[test]
     fn empty_wat_works() {
@@ -116,4 +195,22 @@ mod tests {
         )
         .unwrap();
     }
+
+    #[test]
+    fn instrumented_module_keeps_hash() {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func $add (param i32 i32) (result i32)
+                    get_local 0
+                    get_local 1
+                    i32.add)
+                (export "add" (func $add)))
+            "#,
+        )
+        .unwrap();
+
+        let module = Module::from_bytes_with_breakpoints(&wasm).unwrap();
+        assert_eq!(*module.hash(), ModuleHash::from_bytes(&wasm));
+    }
 }
@@ -0,0 +1,219 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Instrumentation of modules for debugging purposes.
+//!
+//! The interpreter doesn't provide any way to pause the execution in the middle of a function.
+//! In order to support breakpoints, we instead modify the module so that each function starts by
+//! calling an imported function (see [`BREAKPOINT_MODULE`] and [`BREAKPOINT_FUNCTION`]) whose
+//! parameter is the index of the function being entered.
+//!
+//! The indices passed to the breakpoint function are the ones of the *original* module, before
+//! instrumentation. Adding an import shifts the indices of all the functions defined by the
+//! module by one, and we take care of updating all the places where function indices appear.
+
+use alloc::{string::ToString as _, vec, vec::Vec};
+use core::{convert::TryFrom as _, mem};
+use parity_wasm::elements::{
+    self, External, FunctionType, ImportCountType, ImportEntry, ImportSection, IndexMap,
+    Instruction, Internal, Section, Type, TypeSection, ValueType,
+};
+
+/// Name of the module of the import added by the instrumentation.
+pub const BREAKPOINT_MODULE: &str = "redshirt-debug";
+/// Name of the function of the import added by the instrumentation.
+pub const BREAKPOINT_FUNCTION: &str = "breakpoint";
+
+/// Error that can happen when instrumenting a module.
+#[derive(Debug)]
+pub struct InstrumentError {}
+
+/// Parses the given module and adds a call to the breakpoint function at the start of each
+/// function.
+pub fn instrument(buffer: &[u8]) -> Result<elements::Module, InstrumentError> {
+    let mut module: elements::Module =
+        elements::deserialize_buffer(buffer).map_err(|_| InstrumentError {})?;
+
+    let type_index = breakpoint_type_index(&mut module)?;
+
+    // The new import is appended at the end of the imports. Since function indices count
+    // imported functions first, its index is the number of functions imported so far.
+    let breakpoint_fn_index = u32::try_from(module.import_count(ImportCountType::Function))
+        .map_err(|_| InstrumentError {})?;
+    let entry = ImportEntry::new(
+        BREAKPOINT_MODULE.to_string(),
+        BREAKPOINT_FUNCTION.to_string(),
+        External::Function(type_index),
+    );
+    match module.import_section_mut() {
+        Some(section) => section.entries_mut().push(entry),
+        None => {
+            // The import section must be right after the type section, which we know exists.
+            let position = module
+                .sections()
+                .iter()
+                .position(|s| match s {
+                    Section::Type(_) => true,
+                    _ => false,
+                })
+                .ok_or(InstrumentError {})?;
+            module.sections_mut().insert(
+                position + 1,
+                Section::Import(ImportSection::with_entries(vec![entry])),
+            );
+        }
+    }
+
+    let shift = |index: &mut u32| {
+        if *index >= breakpoint_fn_index {
+            *index += 1;
+        }
+    };
+
+    // Adjust all the places where indices of functions defined by the module are referenced.
+    if let Some(section) = module.export_section_mut() {
+        for export in section.entries_mut() {
+            if let Internal::Function(index) = export.internal_mut() {
+                shift(index);
+            }
+        }
+    }
+    if let Some(section) = module.elements_section_mut() {
+        for segment in section.entries_mut() {
+            for member in segment.members_mut() {
+                shift(member);
+            }
+        }
+    }
+    for section in module.sections_mut() {
+        if let Section::Start(index) = section {
+            shift(index);
+        }
+    }
+
+    // The names section, used for example to print backtraces, is indexed by function as well.
+    // If it can't be parsed, we remove it rather than keeping names that point to the wrong
+    // functions.
+    let mut module = match module.parse_names() {
+        Ok(module) => module,
+        Err((_, mut module)) => {
+            module.sections_mut().retain(|section| match section {
+                Section::Custom(custom) => custom.name() != "name",
+                _ => true,
+            });
+            module
+        }
+    };
+    if let Some(names) = module.names_section_mut() {
+        if let Some(functions) = names.functions_mut() {
+            shift_keys(functions.names_mut(), shift);
+        }
+        if let Some(locals) = names.locals_mut() {
+            shift_keys(locals.local_names_mut(), shift);
+        }
+    }
+
+    if let Some(section) = module.code_section_mut() {
+        for (body_index, body) in section.bodies_mut().iter_mut().enumerate() {
+            let instructions = body.code_mut().elements_mut();
+            for instruction in instructions.iter_mut() {
+                if let Instruction::Call(index) = instruction {
+                    shift(index);
+                }
+            }
+
+            // Index of this function in the original module.
+            let original_index = u32::try_from(body_index)
+                .ok()
+                .and_then(|i| i.checked_add(breakpoint_fn_index))
+                .ok_or(InstrumentError {})?;
+            instructions.insert(0, Instruction::I32Const(original_index as i32));
+            instructions.insert(1, Instruction::Call(breakpoint_fn_index));
+        }
+    }
+
+    Ok(module)
+}
+
+/// Applies `shift` to all the function indices used as keys in `map`.
+fn shift_keys<T>(map: &mut IndexMap<T>, shift: impl Fn(&mut u32)) {
+    *map = mem::replace(map, IndexMap::with_capacity(0))
+        .into_iter()
+        .map(|(mut index, value)| {
+            shift(&mut index);
+            (index, value)
+        })
+        .collect();
+}
+
+/// Returns the index of the `(i32) -> ()` type in the module, adding it if necessary.
+fn breakpoint_type_index(module: &mut elements::Module) -> Result<u32, InstrumentError> {
+    let expected = FunctionType::new(vec![ValueType::I32], None);
+
+    if module.type_section().is_none() {
+        // The type section must be before any other non-custom section.
+        module
+            .sections_mut()
+            .insert(0, Section::Type(TypeSection::with_types(Vec::new())));
+    }
+
+    let types = match module.type_section_mut() {
+        Some(section) => section.types_mut(),
+        None => unreachable!(),
+    };
+
+    if let Some(index) = types.iter().position(|t| match t {
+        Type::Function(f) => *f == expected,
+    }) {
+        return u32::try_from(index).map_err(|_| InstrumentError {});
+    }
+
+    types.push(Type::Function(expected));
+    u32::try_from(types.len() - 1).map_err(|_| InstrumentError {})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::instrument;
+
+    #[test]
+    fn names_are_shifted() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "imported" (func $imported))
+            (func $first (param $value i32))
+            (func $second
+                i32.const 1
+                call $first))
+        "#,
+        )
+        .unwrap();
+
+        let module = instrument(&wasm).unwrap();
+        let names = module.names_section().unwrap();
+
+        // The breakpoint function is imported right after `imported`.
+        let functions = names.functions().unwrap().names();
+        assert_eq!(functions.get(0).map(|n| &n[..]), Some("imported"));
+        assert!(functions.get(1).is_none());
+        assert_eq!(functions.get(2).map(|n| &n[..]), Some("first"));
+        assert_eq!(functions.get(3).map(|n| &n[..]), Some("second"));
+
+        let locals = names.locals().unwrap().local_names();
+        assert!(locals.get(1).is_none());
+        let first_locals = locals.get(2).unwrap();
+        assert_eq!(first_locals.get(0).map(|n| &n[..]), Some("value"));
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::module::{FromBytesError, Module, ModuleHash};
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+use hashbrown::HashMap;
+
+/// Collection of parsed and validated modules, indexed by their hash.
+///
+/// Parsing and validating a module is expensive. Passing through a [`ModuleCache`] guarantees
+/// that each module is only parsed once, and that processes started from the same hash all
+/// share the same [`Module`].
+///
+/// A [`ModuleCache`] can optionally be backed by a [`ModuleStore`], for example a directory on
+/// the disk. Modules that are added to the cache are written to the store, and modules that
+/// aren't in memory are looked up in the store.
+///
+/// > **Note**: The interpreter we use doesn't compile modules. If we ever switch to a JIT
+/// >           backend, the compiled code should be stored here as well.
+pub struct ModuleCache {
+    /// List of modules in memory.
+    modules: HashMap<ModuleHash, Module>,
+    /// Optional persistent storage.
+    store: Option<Box<dyn ModuleStore>>,
+}
+
+/// Persistent storage of modules for a [`ModuleCache`].
+pub trait ModuleStore: Send {
+    /// Returns the bytes of the module with the given hash, if the store contains it.
+    ///
+    /// The [`ModuleCache`] verifies that the bytes match the hash. It is therefore not necessary
+    /// for the store to do so.
+    fn load(&mut self, hash: &ModuleHash) -> Option<Vec<u8>>;
+
+    /// Stores the bytes of a module. Errors are expected to be ignored.
+    fn store(&mut self, hash: &ModuleHash, bytes: &[u8]);
+}
+
+impl ModuleCache {
+    /// Builds a new empty cache.
+    pub fn new() -> Self {
+        ModuleCache {
+            modules: HashMap::new(),
+            store: None,
+        }
+    }
+
+    /// Builds a new empty cache backed by the given store.
+    pub fn with_store(store: impl ModuleStore + 'static) -> Self {
+        ModuleCache {
+            modules: HashMap::new(),
+            store: Some(Box::new(store)),
+        }
+    }
+
+    /// Returns the module with the given hash, if it is in the cache or in the store.
+    ///
+    /// Modules found in the store that fail to parse or don't match the hash are ignored.
+    pub fn get(&mut self, hash: &ModuleHash) -> Option<Module> {
+        if let Some(module) = self.modules.get(hash) {
+            return Some(module.clone());
+        }
+
+        let bytes = self.store.as_mut()?.load(hash)?;
+        if ModuleHash::from_bytes(&bytes) != *hash {
+            return None;
+        }
+        let module = Module::from_bytes(&bytes).ok()?;
+        self.modules.insert(hash.clone(), module.clone());
+        Some(module)
+    }
+
+    /// Returns the module corresponding to the given bytes, parsing it if it isn't in the cache
+    /// yet. Newly-parsed modules are written to the store, if any.
+    pub fn get_or_parse(&mut self, bytes: impl AsRef<[u8]>) -> Result<Module, FromBytesError> {
+        let bytes = bytes.as_ref();
+        let hash = ModuleHash::from_bytes(bytes);
+        if let Some(module) = self.modules.get(&hash) {
+            return Ok(module.clone());
+        }
+
+        let module = Module::from_bytes(bytes)?;
+        if let Some(store) = self.store.as_mut() {
+            store.store(&hash, bytes);
+        }
+        self.modules.insert(hash, module.clone());
+        Ok(module)
+    }
+
+    /// Inserts an already-parsed module in the cache. Doesn't write it to the store, as the
+    /// original bytes aren't known.
+    ///
+    /// Returns the module that was already in the cache with the same hash, if any.
+    pub fn insert(&mut self, module: Module) -> Option<Module> {
+        self.modules.insert(module.hash().clone(), module)
+    }
+
+    /// Removes a module from the memory of the cache. Doesn't remove it from the store.
+    ///
+    /// Processes that have been started from this module are unaffected.
+    pub fn remove(&mut self, hash: &ModuleHash) -> Option<Module> {
+        self.modules.remove(hash)
+    }
+
+    /// Returns the number of modules in memory.
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// Returns true if there isn't any module in memory.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        ModuleCache::new()
+    }
+}
+
+impl fmt::Debug for ModuleCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.modules.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModuleCache, ModuleStore};
+    use crate::module::ModuleHash;
+    use alloc::{sync::Arc, vec::Vec};
+    use spin::Mutex;
+
+    #[test]
+    fn parses_once() {
+        let wasm = wat::parse_str("(module)").unwrap();
+        let mut cache = ModuleCache::new();
+        let module1 = cache.get_or_parse(&wasm).unwrap();
+        let module2 = cache.get_or_parse(&wasm).unwrap();
+        assert_eq!(module1.hash(), module2.hash());
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&ModuleHash::from_bytes(&wasm)).is_some());
+    }
+
+    #[test]
+    fn uses_store() {
+        #[derive(Clone, Default)]
+        struct Store(Arc<Mutex<Vec<(ModuleHash, Vec<u8>)>>>);
+        impl ModuleStore for Store {
+            fn load(&mut self, hash: &ModuleHash) -> Option<Vec<u8>> {
+                let list = self.0.lock();
+                list.iter().find(|(h, _)| h == hash).map(|(_, b)| b.clone())
+            }
+            fn store(&mut self, hash: &ModuleHash, bytes: &[u8]) {
+                self.0.lock().push((hash.clone(), bytes.to_vec()));
+            }
+        }
+
+        let store = Store::default();
+        let wasm = wat::parse_str("(module)").unwrap();
+        let hash = ModuleHash::from_bytes(&wasm);
+
+        let mut cache = ModuleCache::with_store(store.clone());
+        assert!(cache.get(&hash).is_none());
+        cache.get_or_parse(&wasm).unwrap();
+        assert_eq!(store.0.lock().len(), 1);
+
+        // A fresh cache finds the module in the store.
+        let mut cache = ModuleCache::with_store(store);
+        assert!(cache.get(&hash).is_some());
+    }
+}
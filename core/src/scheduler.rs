@@ -20,4 +20,5 @@ mod tests;
 mod vm;
 
 // TODO: move definition?
-pub use self::ipc::{Core, CoreBuilder, CoreProcess, CoreRunOutcome, CoreThread};
+pub use self::ipc::{Core, CoreBuilder, CoreProcess, CoreRunOutcome, CoreThread, ThreadState};
+pub use self::vm::ProcessConfig;
@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::module::Module;
+use crate::module::{breakpoints, Module};
 use crate::scheduler::{processes, vm};
 use crate::sig;
 use crate::{InterfaceHash, MessageId};
@@ -111,8 +111,23 @@ enum Extrinsic {
     EmitMessageError,
     EmitAnswer,
     CancelMessage,
+    /// Added by [`Module::from_bytes_with_breakpoints`] at the start of each function.
+    Breakpoint,
 }
 
+/// Error reported as the outcome of a process that has called an extrinsic with invalid
+/// parameters.
+#[derive(Debug)]
+struct BadExtrinsicCall;
+
+impl fmt::Display for BadExtrinsicCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid parameters passed to an extrinsic")
+    }
+}
+
+impl wasmi::HostError for BadExtrinsicCall {}
+
 /// Structure passed to the underlying [`processes::ProcessesCollection`] that tracks the state
 /// of the thread.
 #[derive(Debug)]
@@ -237,6 +252,16 @@ pub enum RunOneOutcome<'a, TPud, TTud> {
         message_id: MessageId,
     },
 
+    /// A thread in a process has entered a function of a module that has been instrumented for
+    /// breakpoints. The thread is ready to continue running.
+    ThreadBreakpoint {
+        /// Thread that has entered the function.
+        thread: ProcessesCollectionExtrinsicsThreadRegular<'a, TPud, TTud>,
+
+        /// Index of the function within the original module.
+        function_index: u32,
+    },
+
     /// No thread is ready to run. Nothing was done.
     Idle,
 }
@@ -254,6 +279,7 @@ impl<TPud, TTud> ProcessesCollectionExtrinsics<TPud, TTud> {
     pub fn execute(
         &mut self,
         module: &Module,
+        config: &vm::ProcessConfig,
         proc_user_data: TPud,
         main_thread_user_data: TTud,
     ) -> Result<ProcessesCollectionExtrinsicsProc<TPud, TTud>, vm::NewErr> {
@@ -263,7 +289,7 @@ impl<TPud, TTud> ProcessesCollectionExtrinsics<TPud, TTud> {
         };
         let process = self
             .inner
-            .execute(module, proc_user_data, main_thread_user_data)?;
+            .execute(module, config, proc_user_data, main_thread_user_data)?;
         Ok(ProcessesCollectionExtrinsicsProc { inner: process })
     }
 
@@ -308,7 +334,7 @@ impl<TPud, TTud> ProcessesCollectionExtrinsics<TPud, TTud> {
                 debug_assert!(thread.user_data().state.is_ready_to_run());
                 let next_msg = match parse_extrinsic_next_message(&mut thread, params) {
                     Ok(m) => m,
-                    Err(_) => panic!(), // TODO:
+                    Err(_) => return abort_bad_extrinsic_call(thread),
                 };
                 thread.user_data().state = LocalThreadState::MessageWait(next_msg);
                 RunOneOutcome::ThreadWaitMessage(ProcessesCollectionExtrinsicsThreadWaitMessage {
@@ -324,7 +350,7 @@ impl<TPud, TTud> ProcessesCollectionExtrinsics<TPud, TTud> {
                 debug_assert!(thread.user_data().state.is_ready_to_run());
                 let emit_msg = match parse_extrinsic_emit_message(&mut thread, params) {
                     Ok(m) => m,
-                    Err(_) => panic!(), // TODO:
+                    Err(_) => return abort_bad_extrinsic_call(thread),
                 };
                 thread.user_data().state = LocalThreadState::EmitMessage(emit_msg);
                 RunOneOutcome::ThreadEmitMessage(ProcessesCollectionExtrinsicsThreadEmitMessage {
@@ -340,7 +366,7 @@ impl<TPud, TTud> ProcessesCollectionExtrinsics<TPud, TTud> {
                 debug_assert!(thread.user_data().state.is_ready_to_run());
                 let emit_resp = match parse_extrinsic_emit_answer(&mut thread, params) {
                     Ok(m) => m,
-                    Err(_) => panic!(), // TODO:
+                    Err(_) => return abort_bad_extrinsic_call(thread),
                 };
                 thread.resume(None);
                 RunOneOutcome::ThreadEmitAnswer {
@@ -358,7 +384,7 @@ impl<TPud, TTud> ProcessesCollectionExtrinsics<TPud, TTud> {
                 debug_assert!(thread.user_data().state.is_ready_to_run());
                 let emit_msg_error = match parse_extrinsic_emit_message_error(&mut thread, params) {
                     Ok(m) => m,
-                    Err(_) => panic!(), // TODO:
+                    Err(_) => return abort_bad_extrinsic_call(thread),
                 };
                 thread.resume(None);
                 RunOneOutcome::ThreadEmitMessageError {
@@ -372,6 +398,23 @@ impl<TPud, TTud> ProcessesCollectionExtrinsics<TPud, TTud> {
                 id: Extrinsic::CancelMessage,
                 params,
             } => unimplemented!(),
+
+            processes::RunOneOutcome::Interrupted {
+                mut thread,
+                id: Extrinsic::Breakpoint,
+                params,
+            } => {
+                debug_assert!(thread.user_data().state.is_ready_to_run());
+                let function_index = match params.get(0) {
+                    Some(wasmi::RuntimeValue::I32(v)) if params.len() == 1 => *v as u32,
+                    _ => return abort_bad_extrinsic_call(thread),
+                };
+                thread.resume(None);
+                RunOneOutcome::ThreadBreakpoint {
+                    thread: ProcessesCollectionExtrinsicsThreadRegular { inner: thread },
+                    function_index,
+                }
+            }
         }
     }
 
@@ -431,6 +474,12 @@ impl Default for ProcessesCollectionExtrinsicsBuilder {
                 "cancel_message",
                 sig!((I32)),
                 Extrinsic::CancelMessage,
+            )
+            .with_extrinsic(
+                breakpoints::BREAKPOINT_MODULE,
+                breakpoints::BREAKPOINT_FUNCTION,
+                sig!((I32)),
+                Extrinsic::Breakpoint,
             );
 
         ProcessesCollectionExtrinsicsBuilder { inner }
@@ -503,6 +552,31 @@ impl<'a, TPud, TTud> ProcessesCollectionExtrinsicsProc<'a, TPud, TTud> {
         ProcessesCollectionExtrinsicsThread::from_inner(self.inner.main_thread())
     }
 
+    /// Copies the given memory range of the process into a `Vec<u8>`.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn read_memory(&mut self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
+        self.inner.read_memory(offset, size)
+    }
+
+    /// Write the data at the given memory location of the process.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), ()> {
+        self.inner.write_memory(offset, value)
+    }
+
+    /// Returns true if the process is paused.
+    pub fn is_paused(&self) -> bool {
+        self.inner.is_paused()
+    }
+
+    /// Pauses or unpauses the process. While a process is paused, none of its threads are
+    /// executed.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.inner.set_paused(paused)
+    }
+
     /// Aborts the process and returns the associated user data.
     pub fn abort(self) -> (TPud, Vec<(ThreadId, TTud)>) {
         //self.inner.abort()
@@ -941,6 +1015,26 @@ impl LocalThreadState {
     }
 }
 
+/// Kills the process of the given thread, after it has called an extrinsic with invalid
+/// parameters.
+///
+/// A process passing invalid parameters is a bug in that process, and must not affect the rest
+/// of the system.
+fn abort_bad_extrinsic_call<'a, TPud, TTud>(
+    thread: processes::ProcessesCollectionThread<'a, TPud, LocalThreadUserData<TTud>>,
+) -> RunOneOutcome<'a, TPud, TTud> {
+    let (pid, user_data, dead_threads) = thread.abort_process();
+    RunOneOutcome::ProcessFinished {
+        pid,
+        user_data,
+        dead_threads: dead_threads
+            .into_iter()
+            .map(|(id, state)| (id, state.external_user_data))
+            .collect(),
+        outcome: Err(wasmi::Trap::from(BadExtrinsicCall)),
+    }
+}
+
 /// Analyzes a call to `next_message` made by the given thread.
 ///
 /// The `thread` parameter is only used in order to read memory from the process. This function
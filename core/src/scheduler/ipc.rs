@@ -127,10 +127,33 @@ pub enum CoreRunOutcome<'a> {
         response: Result<EncodedMessage, ()>,
     },
 
+    /// A thread has entered a function on which a breakpoint was set with
+    /// [`CoreProcess::add_breakpoint`], or the process was being stepped with
+    /// [`CoreProcess::step`]. The process is now paused.
+    Breakpoint {
+        /// Process that has been paused.
+        pid: Pid,
+        /// Thread that has entered the function.
+        thread_id: ThreadId,
+        /// Index of the function within the original module.
+        function_index: u32,
+    },
+
     /// Nothing to do. No thread is ready to run.
     Idle,
 }
 
+/// State of a thread, as reported by [`CoreProcess::threads`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Thread is ready to run, unless the process is paused.
+    Ready,
+    /// Thread is waiting for a message to arrive.
+    MessageWait,
+    /// Thread is trying to emit a message on an interface that has no handler yet.
+    InterfaceWait,
+}
+
 /// Because of lifetime issues, this is the same as `CoreRunOutcome` but that holds `Pid`s instead
 /// of `CoreProcess`es.
 // TODO: remove this enum and solve borrowing issues
@@ -157,6 +180,11 @@ enum CoreRunOutcomeInner {
         message_id: MessageId,
         response: Result<EncodedMessage, ()>,
     },
+    Breakpoint {
+        pid: Pid,
+        thread_id: ThreadId,
+        function_index: u32,
+    },
     LoopAgain,
     Idle,
 }
@@ -184,6 +212,13 @@ struct Process {
 
     /// List of messages that the process is expected to answer.
     messages_to_answer: SmallVec<[MessageId; 8]>,
+
+    /// Indices of the functions that pause the process when entered. Only relevant if the
+    /// module has been instrumented with breakpoints.
+    breakpoints: HashSet<u32>,
+
+    /// If true, the process must be paused the next time a function is entered.
+    stepping: bool,
 }
 
 /// Access to a process within the core.
@@ -256,6 +291,15 @@ impl Core {
                     message_id,
                     response,
                 },
+                CoreRunOutcomeInner::Breakpoint {
+                    pid,
+                    thread_id,
+                    function_index,
+                } => CoreRunOutcome::Breakpoint {
+                    pid,
+                    thread_id,
+                    function_index,
+                },
             };
         }
     }
@@ -440,10 +484,39 @@ impl Core {
                     .unwrap_or(CoreRunOutcomeInner::LoopAgain)
             }
 
+            extrinsics::RunOneOutcome::ThreadBreakpoint {
+                mut thread,
+                function_index,
+            } => {
+                let pid = thread.pid();
+                let thread_id = thread.tid();
+                let process = thread.process_user_data();
+                if !process.stepping && !process.breakpoints.contains(&function_index) {
+                    return CoreRunOutcomeInner::LoopAgain;
+                }
+                process.stepping = false;
+
+                match self.processes.process_by_id(pid) {
+                    Some(mut p) => p.set_paused(true),
+                    None => unreachable!(),
+                }
+
+                CoreRunOutcomeInner::Breakpoint {
+                    pid,
+                    thread_id,
+                    function_index,
+                }
+            }
+
             extrinsics::RunOneOutcome::Idle => CoreRunOutcomeInner::Idle,
         }
     }
 
+    /// Returns the list of processes that exist in the core.
+    pub fn pids<'a>(&'a self) -> impl ExactSizeIterator<Item = Pid> + 'a {
+        self.processes.pids()
+    }
+
     /// Returns an object granting access to a process, if it exists.
     pub fn process_by_id(&mut self, pid: Pid) -> Option<CoreProcess> {
         let p = self.processes.process_by_id(pid)?;
@@ -717,15 +790,26 @@ impl Core {
     ///
     /// Each import of the [`Module`](crate::module::Module) is resolved.
     pub fn execute(&mut self, module: &Module) -> Result<CoreProcess, vm::NewErr> {
+        self.execute_with_config(module, &Default::default())
+    }
+
+    /// Same as [`execute`](Core::execute), but passes arguments and limits to the process.
+    pub fn execute_with_config(
+        &mut self,
+        module: &Module,
+        config: &vm::ProcessConfig,
+    ) -> Result<CoreProcess, vm::NewErr> {
         let proc_metadata = Process {
             messages_queue: VecDeque::new(),
             registered_interfaces: SmallVec::new(),
             used_interfaces: HashSet::new(),
             emitted_messages: SmallVec::new(),
             messages_to_answer: SmallVec::new(),
+            breakpoints: HashSet::new(),
+            stepping: false,
         };
 
-        let process = self.processes.execute(module, proc_metadata, ())?;
+        let process = self.processes.execute(module, config, proc_metadata, ())?;
 
         Ok(CoreProcess { process })
     }
@@ -749,6 +833,84 @@ impl<'a> CoreProcess<'a> {
         Ok(CoreThread { thread })
     }
 
+    /// Returns the list of threads of the process and their state. The first element is the
+    /// main thread.
+    pub fn threads(self) -> Vec<(ThreadId, ThreadState)> {
+        let mut out = Vec::new();
+        let mut thread = self.process.main_thread();
+        loop {
+            let state = match thread {
+                extrinsics::ProcessesCollectionExtrinsicsThread::Regular(_) => ThreadState::Ready,
+                extrinsics::ProcessesCollectionExtrinsicsThread::WaitMessage(_) => {
+                    ThreadState::MessageWait
+                }
+                extrinsics::ProcessesCollectionExtrinsicsThread::EmitMessage(_) => {
+                    ThreadState::InterfaceWait
+                }
+            };
+            out.push((thread.tid(), state));
+
+            match thread.next_thread() {
+                Some(t) => thread = t,
+                None => break out,
+            }
+        }
+    }
+
+    /// Copies the given memory range of the process into a `Vec<u8>`.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn read_memory(&mut self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
+        self.process.read_memory(offset, size)
+    }
+
+    /// Write the data at the given memory location of the process.
+    ///
+    /// Returns an error if the range is invalid or out of range.
+    pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), ()> {
+        self.process.write_memory(offset, value)
+    }
+
+    /// Returns true if the process is paused.
+    pub fn is_paused(&self) -> bool {
+        self.process.is_paused()
+    }
+
+    /// Stops running the threads of this process until [`CoreProcess::resume`] or
+    /// [`CoreProcess::step`] is called.
+    pub fn pause(&mut self) {
+        self.process.set_paused(true);
+    }
+
+    /// Resumes a process that has been paused.
+    pub fn resume(&mut self) {
+        self.process.user_data().stepping = false;
+        self.process.set_paused(false);
+    }
+
+    /// Resumes a process that has been paused, and pauses it again the next time one of its
+    /// threads enters a function.
+    ///
+    /// Has no effect on processes whose module hasn't been instrumented with breakpoints, other
+    /// than resuming them.
+    pub fn step(&mut self) {
+        self.process.user_data().stepping = true;
+        self.process.set_paused(false);
+    }
+
+    /// Pauses the process when one of its threads enters the function with the given index.
+    ///
+    /// Has no effect on processes whose module hasn't been instrumented with breakpoints. See
+    /// [`Module::from_bytes_with_breakpoints`](crate::module::Module::from_bytes_with_breakpoints).
+    pub fn add_breakpoint(&mut self, function_index: u32) {
+        self.process.user_data().breakpoints.insert(function_index);
+    }
+
+    /// Removes a breakpoint previously added with [`CoreProcess::add_breakpoint`].
+    pub fn remove_breakpoint(&mut self, function_index: u32) {
+        self.process.user_data().breakpoints.remove(&function_index);
+    }
+
     /// Kills the process immediately.
     pub fn abort(self) {
         self.process.abort(); // TODO: clean up
@@ -72,6 +72,10 @@ struct Process<TPud, TTud> {
 
     /// User-chosen data (opaque to us) that describes the process.
     user_data: TPud,
+
+    /// If true, none of the threads of this process are ever picked by
+    /// [`run`](ProcessesCollection::run).
+    paused: bool,
 }
 
 /// Additional data associated to a thread.
@@ -179,6 +183,7 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
     pub fn execute(
         &mut self,
         module: &Module,
+        config: &vm::ProcessConfig,
         proc_user_data: TPud,
         main_thread_user_data: TTud,
     ) -> Result<ProcessesCollectionProc<TPud, TTud>, vm::NewErr> {
@@ -193,6 +198,7 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
             let extrinsics_id_assign = &mut self.extrinsics_id_assign;
             vm::ProcessStateMachine::new(
                 module,
+                config,
                 main_thread_data,
                 move |interface, function, obtained_signature| {
                     if let Some((index, expected_signature)) =
@@ -217,6 +223,7 @@ impl<TExtr, TPud, TTud> ProcessesCollection<TExtr, TPud, TTud> {
             Process {
                 state_machine,
                 user_data: proc_user_data,
+                paused: false,
             },
         );
 
@@ -470,6 +477,10 @@ impl<TExtr> ProcessesCollectionBuilder<TExtr> {
 impl<TPud, TTud> Process<TPud, TTud> {
     /// Finds a thread in this process that is ready to be executed.
     fn ready_to_run_thread_index(&mut self) -> Option<usize> {
+        if self.paused {
+            return None;
+        }
+
         for thread_n in 0..self.state_machine.num_threads() {
             let mut thread = match self.state_machine.thread(thread_n) {
                 Some(t) => t,
@@ -539,6 +550,9 @@ impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
         }
     }
 
+    /// Copies the given memory range into a `Vec<u8>`.
+    ///
+    /// Returns an error if the range is invalid or out of range.
     pub fn read_memory(&mut self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
         self.process
             .get_mut()
@@ -556,6 +570,21 @@ impl<'a, TPud, TTud> ProcessesCollectionProc<'a, TPud, TTud> {
             .write_memory(offset, value)
     }
 
+    /// Returns true if the process has been paused with
+    /// [`set_paused`](ProcessesCollectionProc::set_paused).
+    pub fn is_paused(&self) -> bool {
+        self.process.get().paused
+    }
+
+    /// Pauses or unpauses the process. While a process is paused, none of its threads are
+    /// executed.
+    ///
+    /// Pausing a process doesn't interrupt a thread in the middle of its execution. The effect
+    /// is only visible the next time [`run`](ProcessesCollection::run) is called.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.process.get_mut().paused = paused;
+    }
+
     /// Aborts the process and returns the associated user data.
     pub fn abort(self) -> (TPud, Vec<(ThreadId, TTud)>) {
         let (_, proc) = self.process.remove_entry();
@@ -644,6 +673,9 @@ impl<'a, TPud, TTud> ProcessesCollectionThread<'a, TPud, TTud> {
         user_data.value_back = Some(value);
     }
 
+    /// Copies the given memory range into a `Vec<u8>`.
+    ///
+    /// Returns an error if the range is invalid or out of range.
     pub fn read_memory(&mut self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
         self.process
             .get_mut()
@@ -660,6 +692,18 @@ impl<'a, TPud, TTud> ProcessesCollectionThread<'a, TPud, TTud> {
             .state_machine
             .write_memory(offset, value)
     }
+
+    /// Aborts the process this thread belongs to, and returns its [`Pid`] and the associated
+    /// user data. The first element of the list of threads is the main thread's.
+    pub fn abort_process(self) -> (Pid, TPud, Vec<(ThreadId, TTud)>) {
+        let (pid, proc) = self.process.remove_entry();
+        let dead_threads = proc
+            .state_machine
+            .into_user_datas()
+            .map(|t| (t.thread_id, t.user_data))
+            .collect::<Vec<_>>();
+        (pid, proc.user_data, dead_threads)
+    }
 }
 
 impl<'a, TPud, TTud> fmt::Debug for ProcessesCollectionThread<'a, TPud, TTud>
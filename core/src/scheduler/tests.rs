@@ -75,3 +75,82 @@ fn trapping_module() {
         _ => panic!(),
     }
 }
+
+#[test]
+fn bad_extrinsic_call_kills_process() {
+    // The module doesn't have any memory, and the message ID can't be read.
+    let module = Module::from_wat(
+        r#"(module
+        (import "redshirt" "emit_message_error" (func $emit_message_error (param i32)))
+        (func $_start (result i32)
+            i32.const 0
+            call $emit_message_error
+            i32.const 5)
+        (export "_start" (func $_start)))
+    "#,
+    )
+    .unwrap();
+
+    let mut core = Core::new().build();
+    let expected_pid = core.execute(&module).unwrap().pid();
+
+    match core.run() {
+        CoreRunOutcome::ProgramFinished {
+            pid,
+            outcome: Err(_),
+            ..
+        } => {
+            assert_eq!(pid, expected_pid);
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn breakpoint_pauses_process() {
+    let wasm = wat::parse_str(
+        r#"(module
+        (func $foo (result i32)
+            i32.const 5)
+        (func $_start (result i32)
+            call $foo)
+        (export "_start" (func $_start)))
+    "#,
+    )
+    .unwrap();
+    let module = Module::from_bytes_with_breakpoints(&wasm).unwrap();
+
+    let mut core = Core::new().build();
+    let expected_pid = core.execute(&module).unwrap().pid();
+    core.process_by_id(expected_pid).unwrap().add_breakpoint(0);
+
+    match core.run() {
+        CoreRunOutcome::Breakpoint {
+            pid,
+            function_index: 0,
+            ..
+        } => {
+            assert_eq!(pid, expected_pid);
+        }
+        _ => panic!(),
+    }
+
+    match core.run() {
+        CoreRunOutcome::Idle => {}
+        _ => panic!(),
+    }
+
+    core.process_by_id(expected_pid).unwrap().resume();
+
+    match core.run() {
+        CoreRunOutcome::ProgramFinished {
+            pid,
+            outcome: Ok(ret_val),
+            ..
+        } => {
+            assert_eq!(pid, expected_pid);
+            assert_eq!(ret_val, Some(wasmi::RuntimeValue::I32(5)));
+        }
+        _ => panic!(),
+    }
+}
@@ -18,9 +18,15 @@ use alloc::{
     borrow::{Cow, ToOwned as _},
     boxed::Box,
     format,
+    string::String,
+    vec,
     vec::Vec,
 };
-use core::{cell::RefCell, convert::TryInto, fmt};
+use core::{
+    cell::RefCell,
+    convert::{TryFrom as _, TryInto},
+    fmt,
+};
 use smallvec::SmallVec;
 
 /// WASMI state machine dedicated to a process.
@@ -94,6 +100,23 @@ pub struct ProcessStateMachine<T> {
     is_poisoned: bool,
 }
 
+/// Configuration of a process. Passed when creating a [`ProcessStateMachine`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessConfig {
+    /// Arguments passed to the `main` function of the module, through `argc` and `argv`.
+    ///
+    /// If this list isn't empty, the module must export a `main` function and a memory. The
+    /// arguments are written, NUL-terminated, in new memory pages at the end of the memory.
+    pub arguments: Vec<String>,
+
+    /// Maximum number of 64kiB pages of memory that the process can use, or `None` for no limit.
+    ///
+    /// The limit becomes the maximum size of the memory of the module, meaning that `memory.grow`
+    /// fails if it would go over it. Creating the process fails if the initial memory, plus the
+    /// pages needed for the arguments, is already above the limit.
+    pub max_memory_pages: Option<u32>,
+}
+
 /// State of a single thread within the VM.
 struct ThreadState<T> {
     /// Execution context of this thread. This notably holds the program counter, state of the
@@ -192,6 +215,10 @@ pub enum NewErr {
     MemoryIsntMemory,
     /// If a "__indirect_function_table" symbol is provided, it must be a table.
     IndirectTableIsntTable,
+    /// Arguments have been passed, but the module doesn't export a "main" function and a memory.
+    ArgumentsUnsupported,
+    /// The memory of the module is larger than the limit passed in the [`ProcessConfig`].
+    MemoryLimitExceeded,
 }
 
 /// Error that can happen when starting a new thread.
@@ -228,9 +255,11 @@ impl<T> ProcessStateMachine<T> {
     /// the call.
     ///
     /// A single main thread (whose user data is passed by parameter) is automatically created and
-    /// is paused at the start of the "_start" function of the module.
+    /// is paused at the start of the "_start" function of the module, or of the "main" function
+    /// if the module has no "_start" or if `config` contains arguments.
     pub fn new(
         module: &Module,
+        config: &ProcessConfig,
         main_thread_user_data: T,
         mut symbols: impl FnMut(&str, &str, &wasmi::Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
@@ -292,6 +321,17 @@ impl<T> ProcessStateMachine<T> {
             }
         }
 
+        let limited_module;
+        let module = match config.max_memory_pages {
+            Some(max) => {
+                limited_module = module
+                    .with_max_memory_pages(max)
+                    .map_err(|()| NewErr::MemoryLimitExceeded)?;
+                &limited_module
+            }
+            None => module,
+        };
+
         let not_started =
             wasmi::ModuleInstance::new(module.as_ref(), &ImportResolve(RefCell::new(&mut symbols)))
                 .map_err(NewErr::Interpreter)?;
@@ -332,6 +372,22 @@ impl<T> ProcessStateMachine<T> {
             threads: SmallVec::new(),
         };
 
+        if !config.arguments.is_empty() {
+            let argv = state_machine.write_arguments(&config.arguments)?;
+
+            let argc = i32::try_from(config.arguments.len()).unwrap();
+            let params = vec![
+                wasmi::RuntimeValue::I32(argc),
+                wasmi::RuntimeValue::I32(argv),
+            ];
+            return match state_machine.start_thread_by_name("main", params, main_thread_user_data) {
+                Ok(_) => Ok(state_machine),
+                Err((StartErr::FunctionNotFound, _)) => Err(NewErr::ArgumentsUnsupported),
+                Err((StartErr::Poisoned, _)) => unreachable!(),
+                Err((StartErr::NotAFunction, _)) => Err(NewErr::StartIsntAFunction),
+            };
+        }
+
         // Try to start executing `_start` or `main`.
         // TODO: executing `main` is a hack right now in order to support wasm32-unknown-unknown which doesn't have
         // a `_start` function
@@ -354,6 +410,46 @@ impl<T> ProcessStateMachine<T> {
         Ok(state_machine)
     }
 
+    /// Writes the given arguments in new pages at the end of the memory, and returns the value
+    /// of `argv` to pass to the "main" function.
+    ///
+    /// `argv` points to an array of `arguments.len() + 1` pointers, the last one being null, each
+    /// pointing to a NUL-terminated string.
+    fn write_arguments(&mut self, arguments: &[String]) -> Result<i32, NewErr> {
+        const PAGE_SIZE: usize = 0x10000;
+
+        let memory = match self.memory.as_ref() {
+            Some(m) => m,
+            None => return Err(NewErr::ArgumentsUnsupported),
+        };
+
+        let pointers_len = (arguments.len() + 1) * 4;
+        let total_len = pointers_len + arguments.iter().map(|a| a.len() + 1).sum::<usize>();
+        let num_pages = (total_len + PAGE_SIZE - 1) / PAGE_SIZE;
+
+        // Growing can only fail if the memory would go above its maximum.
+        let previous_size = memory
+            .grow(wasmi::memory_units::Pages(num_pages))
+            .map_err(|_| NewErr::MemoryLimitExceeded)?;
+        let argv = u32::try_from(wasmi::memory_units::Bytes::from(previous_size).0)
+            .map_err(|_| NewErr::ArgumentsUnsupported)?;
+
+        let mut pointers = Vec::with_capacity(pointers_len);
+        let mut strings = Vec::with_capacity(total_len - pointers_len);
+        for argument in arguments {
+            let pointer = argv + u32::try_from(pointers_len + strings.len()).unwrap();
+            pointers.extend_from_slice(&pointer.to_le_bytes());
+            strings.extend_from_slice(argument.as_bytes());
+            strings.push(0);
+        }
+        pointers.extend_from_slice(&[0; 4]);
+        pointers.extend_from_slice(&strings);
+        memory.set(argv, &pointers).map_err(NewErr::Interpreter)?;
+
+        // WASM pointers are unsigned, but are passed as `i32`s.
+        Ok(argv as i32)
+    }
+
     /// Returns true if the state machine is in a poisoned state and cannot run anymore.
     pub fn is_poisoned(&self) -> bool {
         self.is_poisoned
@@ -471,7 +567,7 @@ impl<T> ProcessStateMachine<T> {
     pub fn read_memory(&self, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
         let mem = match self.memory.as_ref() {
             Some(m) => m,
-            None => unreachable!(),
+            None => return Err(()),
         };
 
         mem.get(offset, size.try_into().map_err(|_| ())?)
@@ -484,7 +580,7 @@ impl<T> ProcessStateMachine<T> {
     pub fn write_memory(&mut self, offset: u32, value: &[u8]) -> Result<(), ()> {
         let mem = match self.memory.as_ref() {
             Some(m) => m,
-            None => unreachable!(),
+            None => return Err(()),
         };
 
         mem.set(offset, value).map_err(|_| ())
@@ -599,6 +695,7 @@ impl<'a, T> Thread<'a, T> {
                     _ => unreachable!(),
                 };
                 thread_state.execution = Some(execution);
+
                 Ok(ExecOutcome::Interrupted {
                     thread: self,
                     id: interrupt.index,
@@ -656,6 +753,11 @@ impl fmt::Display for NewErr {
                 f,
                 "If a \"__indirect_function_table\" symbol is provided, it must be a table"
             ),
+            NewErr::ArgumentsUnsupported => write!(
+                f,
+                "Passing arguments requires a \"main\" function and a \"memory\" symbol"
+            ),
+            NewErr::MemoryLimitExceeded => write!(f, "Memory of the module exceeds the limit"),
         }
     }
 }
@@ -685,8 +787,9 @@ impl fmt::Display for RunErr {
 
 #[cfg(test)]
 mod tests {
-    use super::{ExecOutcome, NewErr, ProcessStateMachine};
+    use super::{ExecOutcome, NewErr, ProcessConfig, ProcessStateMachine};
     use crate::module::Module;
+    use alloc::{borrow::ToOwned as _, vec, vec::Vec};
 
     #[test]
     fn starts_if_main() {
@@ -700,7 +803,8 @@ mod tests {
         .unwrap();
 
         let _state_machine =
-            ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()).unwrap();
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!())
+                .unwrap();
     }
 
     #[test]
@@ -714,7 +818,7 @@ mod tests {
         )
         .unwrap();
 
-        match ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()) {
+        match ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!()) {
             Err(NewErr::StartNotFound) => {}
             _ => panic!(),
         }
@@ -732,7 +836,8 @@ mod tests {
         .unwrap();
 
         let mut state_machine =
-            ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()).unwrap();
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!())
+                .unwrap();
         match state_machine.thread(0).unwrap().run(None) {
             Ok(ExecOutcome::ThreadFinished {
                 return_value: Some(wasmi::RuntimeValue::I32(5)),
@@ -755,7 +860,8 @@ mod tests {
         )
         .unwrap();
 
-        let mut state_machine = ProcessStateMachine::new(&module, (), |_, _, _| Ok(9876)).unwrap();
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| Ok(9876)).unwrap();
         match state_machine.thread(0).unwrap().run(None) {
             Ok(ExecOutcome::Interrupted {
                 id: 9876,
@@ -791,7 +897,8 @@ mod tests {
         .unwrap();
 
         let mut state_machine =
-            ProcessStateMachine::new(&module, (), |_, _, _| unreachable!()).unwrap();
+            ProcessStateMachine::new(&module, &Default::default(), (), |_, _, _| unreachable!())
+                .unwrap();
         match state_machine.thread(0).unwrap().run(None) {
             Ok(ExecOutcome::Errored { .. }) => {}
             _ => panic!(),
@@ -802,5 +909,150 @@ mod tests {
         // TODO: start running another function and check that `Poisoned` error is returned
     }
 
+    #[test]
+    fn arguments_passed_to_main() {
+        let module = Module::from_wat(
+            r#"(module
+            (memory (export "memory") 1)
+            (func $main (param $argc i32) (param $argv i32) (result i32)
+                local.get $argc
+                i32.const 256
+                i32.mul
+                local.get $argv
+                i32.load offset=4
+                i32.load8_u
+                i32.add)
+            (export "main" (func $main)))
+        "#,
+        )
+        .unwrap();
+
+        let config = ProcessConfig {
+            arguments: vec!["foo".to_owned(), "bar".to_owned()],
+            ..Default::default()
+        };
+        let mut state_machine =
+            ProcessStateMachine::new(&module, &config, (), |_, _, _| unreachable!()).unwrap();
+
+        // The arguments are written right after the initial memory page.
+        let mut expected = Vec::new();
+        for pointer in &[0x1000cu32, 0x10010, 0] {
+            expected.extend_from_slice(&pointer.to_le_bytes());
+        }
+        expected.extend_from_slice(b"foo\0bar\0");
+        assert_eq!(state_machine.read_memory(0x10000, 20).unwrap(), expected);
+
+        match state_machine.thread(0).unwrap().run(None) {
+            Ok(ExecOutcome::ThreadFinished {
+                return_value: Some(wasmi::RuntimeValue::I32(v)),
+                ..
+            }) => assert_eq!(v, 2 * 256 + i32::from(b'b')),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn arguments_require_main() {
+        let module = Module::from_wat(
+            r#"(module
+            (memory (export "memory") 1)
+            (func $_start (result i32)
+                i32.const 5)
+            (export "_start" (func $_start)))
+        "#,
+        )
+        .unwrap();
+
+        let config = ProcessConfig {
+            arguments: vec!["foo".to_owned()],
+            ..Default::default()
+        };
+        match ProcessStateMachine::new(&module, &config, (), |_, _, _| unreachable!()) {
+            Err(NewErr::ArgumentsUnsupported) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn initial_memory_above_limit() {
+        let module = Module::from_wat(
+            r#"(module
+            (memory (export "memory") 2)
+            (func $_start (result i32)
+                i32.const 5)
+            (export "_start" (func $_start)))
+        "#,
+        )
+        .unwrap();
+
+        let config = ProcessConfig {
+            max_memory_pages: Some(1),
+            ..Default::default()
+        };
+        match ProcessStateMachine::new(&module, &config, (), |_, _, _| unreachable!()) {
+            Err(NewErr::MemoryLimitExceeded) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn memory_growth_above_limit() {
+        let module = Module::from_wat(
+            r#"(module
+            (memory (export "memory") 1 3)
+            (func $_start (result i32)
+                i32.const 1
+                memory.grow)
+            (export "_start" (func $_start)))
+        "#,
+        )
+        .unwrap();
+
+        // `memory.grow` returns the previous size on success, and -1 on failure.
+        for (max_memory_pages, expected) in &[(None, 1), (Some(2), 1), (Some(1), -1)] {
+            let config = ProcessConfig {
+                max_memory_pages: *max_memory_pages,
+                ..Default::default()
+            };
+            let mut state_machine =
+                ProcessStateMachine::new(&module, &config, (), |_, _, _| unreachable!()).unwrap();
+            match state_machine.thread(0).unwrap().run(None) {
+                Ok(ExecOutcome::ThreadFinished {
+                    return_value: Some(wasmi::RuntimeValue::I32(val)),
+                    ..
+                }) => assert_eq!(val, *expected),
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn arguments_above_memory_limit() {
+        let module = Module::from_wat(
+            r#"(module
+            (memory (export "memory") 1)
+            (func $main (param $argc i32) (param $argv i32) (result i32)
+                local.get $argc)
+            (export "main" (func $main)))
+        "#,
+        )
+        .unwrap();
+
+        let config = ProcessConfig {
+            arguments: vec!["foo".to_owned()],
+            max_memory_pages: Some(1),
+        };
+        match ProcessStateMachine::new(&module, &config, (), |_, _, _| unreachable!()) {
+            Err(NewErr::MemoryLimitExceeded) => {}
+            _ => panic!(),
+        }
+
+        let config = ProcessConfig {
+            arguments: vec!["foo".to_owned()],
+            max_memory_pages: Some(2),
+        };
+        assert!(ProcessStateMachine::new(&module, &config, (), |_, _, _| unreachable!()).is_ok());
+    }
+
     // TODO: start mutiple threads
 }
@@ -13,14 +13,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use crate::module::Module;
+use crate::module::{Module, ModuleCache, ModuleHash};
 use crate::native::{self, NativeProgramMessageIdWrite as _};
-use crate::scheduler::{Core, CoreBuilder, CoreRunOutcome};
+use crate::scheduler::{Core, CoreBuilder, CoreRunOutcome, ProcessConfig, ThreadState};
 use alloc::{vec, vec::Vec};
 use core::task::Poll;
 use futures::prelude::*;
 use hashbrown::{hash_map::Entry, HashMap, HashSet};
-use redshirt_syscalls_interface::{Decode, Encode, EncodedMessage, MessageId, Pid};
+use redshirt_syscalls_interface::{Decode, Encode, EncodedMessage, MessageId, Pid, ThreadId};
 use smallvec::SmallVec;
 
 /// Main struct that handles a system, including the scheduler, program loader,
@@ -61,6 +61,9 @@ pub struct System {
     /// All these messages expect a `redshirt_loader_interface::ffi::LoadResponse` as answer.
     // TODO: call shink_to_fit from time to time
     loading_programs: HashSet<MessageId>,
+
+    /// Modules that have already been parsed. Looked up before asking the loader for a program.
+    module_cache: ModuleCache,
 }
 
 /// Prototype for a [`System`].
@@ -77,11 +80,15 @@ pub struct SystemBuilder {
     /// "Virtual" Pid for handling messages on the `threads` interface.
     threads_interface_pid: Pid,
 
-    /// List of programs to start executing immediately after construction.
-    startup_processes: Vec<Module>,
+    /// List of programs to start executing immediately after construction, with their
+    /// configuration.
+    startup_processes: Vec<(Module, ProcessConfig)>,
 
     /// Same field as [`System::main_programs`].
     main_programs: Vec<[u8; 32]>,
+
+    /// Same field as [`System::module_cache`].
+    module_cache: ModuleCache,
 }
 
 /// Outcome of running the [`System`] once.
@@ -96,6 +103,17 @@ pub enum SystemRunOutcome {
         // TODO: change error type
         outcome: Result<(), wasmi::Error>,
     },
+
+    /// A thread has entered a function on which a breakpoint was set, or the process was being
+    /// stepped. The process is now paused. See [`System::add_breakpoint`].
+    Breakpoint {
+        /// Identifier of the process that has been paused.
+        pid: Pid,
+        /// Thread that has entered the function.
+        thread_id: ThreadId,
+        /// Index of the function within the original module.
+        function_index: u32,
+    },
 }
 
 impl System {
@@ -107,6 +125,89 @@ impl System {
             .pid() // TODO: don't unwrap
     }
 
+    /// Returns the list of Wasm processes currently running.
+    pub fn pids<'a>(&'a self) -> impl ExactSizeIterator<Item = Pid> + 'a {
+        self.core.pids()
+    }
+
+    /// Returns the list of threads of the given process and their state, or `None` if the
+    /// process doesn't exist. The first element is the main thread.
+    pub fn process_threads(&mut self, pid: Pid) -> Option<Vec<(ThreadId, ThreadState)>> {
+        Some(self.core.process_by_id(pid)?.threads())
+    }
+
+    /// Copies the given memory range of a process into a `Vec<u8>`.
+    ///
+    /// Returns an error if the process doesn't exist or if the range is out of range.
+    pub fn read_memory(&mut self, pid: Pid, offset: u32, size: u32) -> Result<Vec<u8>, ()> {
+        self.core
+            .process_by_id(pid)
+            .ok_or(())?
+            .read_memory(offset, size)
+    }
+
+    /// Writes data at the given memory location of a process.
+    ///
+    /// Returns an error if the process doesn't exist or if the range is out of range.
+    pub fn write_memory(&mut self, pid: Pid, offset: u32, value: &[u8]) -> Result<(), ()> {
+        self.core
+            .process_by_id(pid)
+            .ok_or(())?
+            .write_memory(offset, value)
+    }
+
+    /// Stops running the threads of the given process until [`System::resume_process`] or
+    /// [`System::step_process`] is called.
+    ///
+    /// Returns an error if the process doesn't exist.
+    pub fn pause_process(&mut self, pid: Pid) -> Result<(), ()> {
+        self.core.process_by_id(pid).ok_or(())?.pause();
+        Ok(())
+    }
+
+    /// Resumes a process that has been paused.
+    ///
+    /// Returns an error if the process doesn't exist.
+    pub fn resume_process(&mut self, pid: Pid) -> Result<(), ()> {
+        self.core.process_by_id(pid).ok_or(())?.resume();
+        Ok(())
+    }
+
+    /// Resumes a process that has been paused, and pauses it again the next time one of its
+    /// threads enters a function, which generates a [`SystemRunOutcome::Breakpoint`].
+    ///
+    /// Returns an error if the process doesn't exist.
+    pub fn step_process(&mut self, pid: Pid) -> Result<(), ()> {
+        self.core.process_by_id(pid).ok_or(())?.step();
+        Ok(())
+    }
+
+    /// Pauses the given process when one of its threads enters the function with the given index,
+    /// which generates a [`SystemRunOutcome::Breakpoint`].
+    ///
+    /// Only works if the process has been started from a module created with
+    /// [`Module::from_bytes_with_breakpoints`].
+    ///
+    /// Returns an error if the process doesn't exist.
+    pub fn add_breakpoint(&mut self, pid: Pid, function_index: u32) -> Result<(), ()> {
+        self.core
+            .process_by_id(pid)
+            .ok_or(())?
+            .add_breakpoint(function_index);
+        Ok(())
+    }
+
+    /// Removes a breakpoint added with [`System::add_breakpoint`].
+    ///
+    /// Returns an error if the process doesn't exist.
+    pub fn remove_breakpoint(&mut self, pid: Pid, function_index: u32) -> Result<(), ()> {
+        self.core
+            .process_by_id(pid)
+            .ok_or(())?
+            .remove_breakpoint(function_index);
+        Ok(())
+    }
+
     /// Runs the [`System`] once and returns the outcome.
     ///
     /// > **Note**: For now, can block a long time because it's waiting for the native programs
@@ -167,6 +268,18 @@ impl System {
                 }
                 CoreRunOutcome::ThreadWaitUnavailableInterface { .. } => {} // TODO: lazy-loading
 
+                CoreRunOutcome::Breakpoint {
+                    pid,
+                    thread_id,
+                    function_index,
+                } => {
+                    return Some(SystemRunOutcome::Breakpoint {
+                        pid,
+                        thread_id,
+                        function_index,
+                    });
+                }
+
                 CoreRunOutcome::MessageResponse {
                     message_id,
                     response,
@@ -175,7 +288,7 @@ impl System {
                     if self.loading_programs.remove(&message_id) {
                         let redshirt_loader_interface::ffi::LoadResponse { result } =
                             Decode::decode(response.unwrap()).unwrap();
-                        let module = Module::from_bytes(&result.unwrap()).unwrap();
+                        let module = self.module_cache.get_or_parse(&result.unwrap()).unwrap();
                         match self.core.execute(&module) {
                             Ok(_) => {}
                             Err(_) => panic!(),
@@ -313,6 +426,7 @@ impl SystemBuilder {
             startup_processes: Vec::new(),
             main_programs: Vec::new(),
             native_programs: native::NativeProgramsCollection::new(),
+            module_cache: ModuleCache::new(),
         }
     }
 
@@ -333,9 +447,19 @@ impl SystemBuilder {
     ///
     /// By default, the list is empty. Should at least contain a process that handles the `loader`
     /// interface.
-    pub fn with_startup_process(mut self, process: impl Into<Module>) -> Self {
+    pub fn with_startup_process(self, process: impl Into<Module>) -> Self {
+        self.with_startup_process_config(process, Default::default())
+    }
+
+    /// Same as [`with_startup_process`](SystemBuilder::with_startup_process), but passes
+    /// arguments and limits to the process.
+    pub fn with_startup_process_config(
+        mut self,
+        process: impl Into<Module>,
+        config: ProcessConfig,
+    ) -> Self {
         let process = process.into();
-        self.startup_processes.push(process);
+        self.startup_processes.push((process, config));
         self
     }
 
@@ -350,6 +474,17 @@ impl SystemBuilder {
         self
     }
 
+    /// Sets the cache of modules used by the [`System`].
+    ///
+    /// Main programs found in the cache are started immediately, without going through the
+    /// `loader` interface. Programs obtained from the `loader` interface are added to the cache.
+    ///
+    /// By default, an empty cache with no persistent storage is used.
+    pub fn with_module_cache(mut self, cache: ModuleCache) -> Self {
+        self.module_cache = cache;
+        self
+    }
+
     /// Builds the [`System`].
     pub fn build(mut self) -> System {
         let mut core = self.core.build();
@@ -371,11 +506,23 @@ impl SystemBuilder {
             Err(_) => unreachable!(),
         };
 
-        for program in self.startup_processes {
-            core.execute(&program)
+        for (program, config) in self.startup_processes {
+            core.execute_with_config(&program, &config)
                 .expect("failed to start startup program"); // TODO:
         }
 
+        // Main programs that are already in the cache don't need to go through the loader.
+        let module_cache = &mut self.module_cache;
+        self.main_programs.retain(|hash| {
+            match module_cache.get(&ModuleHash::from(*hash)) {
+                Some(module) => {
+                    core.execute(&module).expect("failed to start main program"); // TODO:
+                    false
+                }
+                None => true,
+            }
+        });
+
         self.main_programs.shrink_to_fit();
 
         System {
@@ -384,6 +531,7 @@ impl SystemBuilder {
             futex_waits: Default::default(),
             loading_programs: Default::default(),
             main_programs: self.main_programs,
+            module_cache: self.module_cache,
         }
     }
 }
@@ -0,0 +1,98 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xd0, 0x70, 0x21, 0x38, 0xdc, 0x2c, 0xa6, 0xd6, 0xf3, 0x3a, 0x08, 0xa0, 0x01, 0x42, 0xb8, 0x79,
+    0x95, 0x92, 0x77, 0x2b, 0xb6, 0xbf, 0xf9, 0x35, 0xc0, 0x15, 0x48, 0xc3, 0xd1, 0x4e, 0xd1, 0x66,
+]);
+
+/// Message in destination to the handler of block devices.
+///
+/// Block devices are identified by the combination of the process that registered them and an
+/// identifier chosen by this process.
+#[derive(Debug, Encode, Decode)]
+pub enum BlockMessage {
+    /// Notify of the existence of a new block device. No response is expected.
+    RegisterDevice {
+        /// Identifier of the device, chosen by the sender. Must be unique among the devices
+        /// registered by the same process.
+        id: u64,
+        /// Size in bytes of a sector. All reads and writes are performed on whole sectors.
+        sector_size: u32,
+        /// Number of sectors of the device.
+        num_sectors: u64,
+        /// If true, the device can't be written to.
+        read_only: bool,
+    },
+
+    /// Removes a device previously registered with [`BlockMessage::RegisterDevice`]. No
+    /// response is expected.
+    ///
+    /// Devices are automatically unregistered when the process that registered them terminates.
+    UnregisterDevice(u64),
+
+    /// Ask the handler for the next command to execute on the device. Must answer with a
+    /// [`BlockCommand`].
+    ///
+    /// The answer is only sent once a command is ready. Drivers are expected to only emit this
+    /// message once they are ready to accept a command.
+    NextCommand(u64),
+
+    /// Notify that a command previously obtained through [`BlockMessage::NextCommand`] has
+    /// finished executing. No response is expected.
+    CommandFinished {
+        /// Identifier of the device.
+        id: u64,
+        /// Identifier of the command, as found in [`BlockCommand::command_id`].
+        command_id: u64,
+        /// Outcome of the command. Contains the data that has been read in the case of a
+        /// [`BlockCommandKind::Read`], and is empty in the case of a [`BlockCommandKind::Write`].
+        result: Result<Vec<u8>, ()>,
+    },
+}
+
+/// Command to execute on a block device.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct BlockCommand {
+    /// Identifier of the command, chosen by the handler. Must be passed back in
+    /// [`BlockMessage::CommandFinished`].
+    pub command_id: u64,
+    /// What to do.
+    pub kind: BlockCommandKind,
+}
+
+/// Operation to perform on a block device.
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum BlockCommandKind {
+    /// Read sectors from the device.
+    Read {
+        /// Index of the first sector to read.
+        sector: u64,
+        /// Number of sectors to read.
+        num_sectors: u32,
+    },
+    /// Write sectors to the device.
+    Write {
+        /// Index of the first sector to write.
+        sector: u64,
+        /// Data to write. Its length must be a multiple of the sector size.
+        data: Vec<u8>,
+    },
+}
@@ -0,0 +1,114 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Block devices.
+//!
+//! This interface allows drivers of storage devices, such as hard drives, to plug into the
+//! program that handles this interface, such as a file system implementation.
+//!
+//! Use this interface if you're writing a driver for a storage device. Call
+//! [`register_device`] for each device, then execute the commands returned by
+//! [`BlockDeviceRegistration::next_command`] and report their outcome with
+//! [`BlockDeviceRegistration::command_finished`].
+
+#![deny(intra_doc_link_resolution_failure)]
+#![no_std]
+
+extern crate alloc;
+
+pub use self::ffi::{BlockCommand, BlockCommandKind};
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use futures::prelude::*;
+
+pub mod ffi;
+
+/// Configuration of a block device to register.
+#[derive(Debug, Clone)]
+pub struct DeviceConfig {
+    /// Size in bytes of a sector.
+    pub sector_size: u32,
+    /// Number of sectors of the device.
+    pub num_sectors: u64,
+    /// If true, the device can't be written to.
+    pub read_only: bool,
+}
+
+/// Registers a new block device towards the handler.
+///
+/// The device is unregistered when the returned object is dropped.
+pub fn register_device(config: DeviceConfig) -> BlockDeviceRegistration {
+    // Identifiers only have to be unique within our process.
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    unsafe {
+        let msg = ffi::BlockMessage::RegisterDevice {
+            id,
+            sector_size: config.sector_size,
+            num_sectors: config.num_sectors,
+            read_only: config.read_only,
+        };
+        redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg).unwrap();
+    }
+
+    BlockDeviceRegistration { id }
+}
+
+/// Block device registered towards the handler.
+pub struct BlockDeviceRegistration {
+    /// Identifier of the device within our process.
+    id: u64,
+}
+
+impl BlockDeviceRegistration {
+    /// Returns the next command to execute on the device.
+    ///
+    /// Only call this method once ready to execute a command.
+    pub fn next_command(&self) -> impl Future<Output = BlockCommand> {
+        unsafe {
+            let msg = ffi::BlockMessage::NextCommand(self.id);
+            redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+        }
+    }
+
+    /// Reports that a command returned by [`BlockDeviceRegistration::next_command`] has
+    /// finished executing.
+    ///
+    /// `result` must contain the data that has been read in the case of a read, and be empty
+    /// in the case of a write.
+    pub fn command_finished(&self, command_id: u64, result: Result<Vec<u8>, ()>) {
+        unsafe {
+            let msg = ffi::BlockMessage::CommandFinished {
+                id: self.id,
+                command_id,
+                result,
+            };
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg)
+                .unwrap();
+        }
+    }
+}
+
+impl Drop for BlockDeviceRegistration {
+    fn drop(&mut self) {
+        unsafe {
+            let msg = ffi::BlockMessage::UnregisterDevice(self.id);
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg)
+                .unwrap();
+        }
+    }
+}
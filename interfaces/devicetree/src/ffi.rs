@@ -0,0 +1,38 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xc6, 0x52, 0xf2, 0x6c, 0xac, 0xf1, 0x77, 0x11, 0x2d, 0xfa, 0x17, 0xd2, 0x9b, 0xcb, 0xa8, 0xd2,
+    0x44, 0x5a, 0xc9, 0xfa, 0x26, 0x1f, 0x5c, 0x70, 0x37, 0x2b, 0x45, 0x2f, 0x00, 0x93, 0xff, 0x9b,
+]);
+
+/// Message in destination to the device tree interface handler.
+#[derive(Debug, Encode, Decode)]
+pub enum DeviceTreeMessage {
+    /// Request the device tree. Answer with a [`GetDeviceTreeResponse`].
+    GetDeviceTree,
+}
+
+/// Response to [`DeviceTreeMessage::GetDeviceTree`].
+#[derive(Debug, Encode, Decode)]
+pub struct GetDeviceTreeResponse {
+    /// Device tree in its binary format, or `None` if the bootloader didn't pass any.
+    pub device_tree: Option<Vec<u8>>,
+}
@@ -0,0 +1,48 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Access to the device tree.
+//!
+//! On platforms where the hardware isn't discoverable, such as most ARM boards, the bootloader
+//! passes to the kernel a *device tree* describing the hardware. This interface gives access to
+//! this device tree, so that drivers can find the devices they handle and where their registers
+//! are mapped, similar to how drivers use the `pci` interface on x86.
+//!
+//! The device tree is returned in its binary format (DTB), and can be parsed with the
+//! `redshirt-fdt` crate.
+
+#![deny(intra_doc_link_resolution_failure)]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use futures::prelude::*;
+
+pub mod ffi;
+
+/// Returns the device tree passed by the bootloader, in its binary format.
+///
+/// Returns `None` if the bootloader didn't pass any device tree, which is for example the case
+/// on x86.
+pub fn get_device_tree() -> impl Future<Output = Option<Vec<u8>>> {
+    unsafe {
+        let msg = ffi::DeviceTreeMessage::GetDeviceTree;
+        // TODO: don't unwrap?
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg)
+            .unwrap()
+            .map(|response: ffi::GetDeviceTreeResponse| response.device_tree)
+    }
+}
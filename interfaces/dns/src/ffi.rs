@@ -0,0 +1,40 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x45, 0x94, 0x8d, 0x3f, 0xae, 0x05, 0xa5, 0x7b, 0x38, 0x22, 0x38, 0x8f, 0x02, 0x8b, 0x3a, 0xf8,
+    0x99, 0xd0, 0x14, 0xb0, 0xe1, 0xf0, 0xdf, 0x6c, 0x03, 0x63, 0x61, 0xdc, 0x60, 0x76, 0x41, 0x36,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum DnsMessage {
+    /// Resolves a hostname into a list of IP addresses. Must be answered with a
+    /// [`ResolveResponse`].
+    Resolve(String),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct ResolveResponse {
+    /// On success, the addresses the hostname resolves to. Always contains at least one
+    /// address.
+    ///
+    /// IP addresses are always represented as IPv6 addresses. IPv4 addresses are represented as
+    /// IPv4-mapped IPv6 addresses.
+    pub result: Result<Vec<[u16; 8]>, ()>,
+}
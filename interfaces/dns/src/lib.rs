@@ -0,0 +1,51 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Domain name resolution.
+
+#![deny(intra_doc_link_resolution_failure)]
+
+use futures::prelude::*;
+use std::net::{IpAddr, Ipv6Addr};
+
+pub mod ffi;
+
+/// Resolves a hostname into a list of IP addresses.
+///
+/// On success, the returned list always contains at least one address.
+pub fn resolve(hostname: &str) -> impl Future<Output = Result<Vec<IpAddr>, ()>> {
+    let msg = ffi::DnsMessage::Resolve(hostname.to_owned());
+    let response = unsafe {
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    };
+
+    response.map(|response: ffi::ResolveResponse| {
+        let addresses = response.result?;
+        Ok(addresses.into_iter().map(ip_from_ffi).collect())
+    })
+}
+
+/// Turns an IP address from the FFI into an `IpAddr`. IPv4-mapped addresses are turned into
+/// IPv4 addresses.
+fn ip_from_ffi(ip: [u16; 8]) -> IpAddr {
+    let ip = Ipv6Addr::from(ip);
+    let segments = ip.segments();
+    if segments[..5].iter().all(|s| *s == 0) && segments[5] == 0xffff {
+        // `to_ipv4` also accepts IPv4-compatible addresses, which we filter out above.
+        IpAddr::V4(ip.to_ipv4().unwrap())
+    } else {
+        IpAddr::V6(ip)
+    }
+}
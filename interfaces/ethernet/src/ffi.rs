@@ -0,0 +1,64 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x9a, 0xaa, 0x8c, 0x58, 0x73, 0x00, 0x20, 0xd9, 0xcf, 0xbb, 0xc9, 0x34, 0x02, 0x22, 0xe7, 0xe4,
+    0xe7, 0x0e, 0xba, 0x40, 0x0b, 0x75, 0x71, 0xc0, 0xce, 0xf0, 0x6e, 0xf1, 0x4f, 0xa4, 0x63, 0xbf,
+]);
+
+/// Message in destination to the network manager.
+///
+/// Network interfaces are identified by the combination of the process that registered them and
+/// an identifier chosen by this process.
+#[derive(Debug, Encode, Decode)]
+pub enum NetworkMessage {
+    /// Notify of the existence of a new Ethernet interface. No response is expected.
+    RegisterInterface {
+        /// Identifier of the interface, chosen by the sender. Must be unique among the
+        /// interfaces registered by the same process.
+        id: u64,
+        /// MAC address of the interface.
+        mac_address: [u8; 6],
+    },
+
+    /// Removes an interface previously registered with [`NetworkMessage::RegisterInterface`].
+    /// No response is expected.
+    ///
+    /// Interfaces are automatically unregistered when the process that registered them
+    /// terminates.
+    UnregisterInterface(u64),
+
+    /// Notify that an Ethernet frame has been received by the interface. No response is
+    /// expected.
+    InterfaceOnData {
+        /// Identifier of the interface.
+        id: u64,
+        /// Ethernet frame, without the preamble and the frame check sequence.
+        data: Vec<u8>,
+    },
+
+    /// Ask the network manager for the next Ethernet frame to send out on the interface. Must
+    /// answer with a `Vec<u8>` containing the frame, without the preamble and the frame check
+    /// sequence.
+    ///
+    /// The answer is only sent once a frame is ready to be sent out. Drivers are expected to
+    /// only emit this message once they are ready to accept a frame.
+    InterfaceWaitData(u64),
+}
@@ -0,0 +1,102 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Ethernet network interfaces.
+//!
+//! This interface allows drivers of network cards to plug into the network manager, which
+//! implements the TCP/IP stack.
+//!
+//! Use this interface if you're writing a driver for a network card. Call
+//! [`register_interface`] for each network card, then transmit the frames received from the
+//! network with [`NetInterfaceRegistration::packet_from_network`], and send out the frames
+//! returned by [`NetInterfaceRegistration::packet_to_send`].
+
+#![deny(intra_doc_link_resolution_failure)]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use futures::prelude::*;
+
+pub mod ffi;
+
+/// Configuration of a network interface to register.
+#[derive(Debug, Clone)]
+pub struct InterfaceConfig {
+    /// MAC address of the interface.
+    pub mac_address: [u8; 6],
+}
+
+/// Registers a new network interface towards the network manager.
+///
+/// The interface is unregistered when the returned object is dropped.
+pub fn register_interface(config: InterfaceConfig) -> NetInterfaceRegistration {
+    // Identifiers only have to be unique within our process.
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    unsafe {
+        let msg = ffi::NetworkMessage::RegisterInterface {
+            id,
+            mac_address: config.mac_address,
+        };
+        redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg).unwrap();
+    }
+
+    NetInterfaceRegistration { id }
+}
+
+/// Network interface registered towards the network manager.
+pub struct NetInterfaceRegistration {
+    /// Identifier of the interface within our process.
+    id: u64,
+}
+
+impl NetInterfaceRegistration {
+    /// Notifies the network manager that a frame has been received from the network.
+    pub fn packet_from_network(&self, data: impl Into<Vec<u8>>) {
+        unsafe {
+            let msg = ffi::NetworkMessage::InterfaceOnData {
+                id: self.id,
+                data: data.into(),
+            };
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg)
+                .unwrap();
+        }
+    }
+
+    /// Returns the next frame that must be sent out on the network.
+    ///
+    /// Only call this method once ready to send out a frame, as the network manager considers
+    /// the frame sent as soon as it has answered.
+    pub fn packet_to_send(&self) -> impl Future<Output = Vec<u8>> {
+        unsafe {
+            let msg = ffi::NetworkMessage::InterfaceWaitData(self.id);
+            redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+        }
+    }
+}
+
+impl Drop for NetInterfaceRegistration {
+    fn drop(&mut self) {
+        unsafe {
+            let msg = ffi::NetworkMessage::UnregisterInterface(self.id);
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg)
+                .unwrap();
+        }
+    }
+}
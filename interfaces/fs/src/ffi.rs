@@ -0,0 +1,121 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xae, 0x5e, 0xb4, 0x81, 0x76, 0xf3, 0x29, 0x9d, 0xf5, 0x4d, 0xd9, 0xfd, 0xa9, 0x10, 0xbc, 0xc8,
+    0xfd, 0xa4, 0x57, 0xd3, 0x5d, 0x5d, 0x2d, 0xe1, 0xd9, 0xdc, 0xcc, 0x35, 0x42, 0xea, 0x63, 0x23,
+]);
+
+/// Message in destination to the file system handler.
+///
+/// Paths are absolute and made of components separated with `/`, such as `/boot/modules.txt`.
+#[derive(Debug, Encode, Decode)]
+pub enum FsMessage {
+    /// Opens the file at the given path. Must be answered with an [`OpenResponse`].
+    Open(String),
+    /// Reads data from a file opened with [`FsMessage::Open`]. Must be answered with a
+    /// [`ReadResponse`].
+    Read {
+        /// Handle of the file, as returned in the [`OpenResponse`].
+        handle: u64,
+        /// Position in the file of the first byte to read.
+        offset: u64,
+        /// Maximum number of bytes to read.
+        len: u32,
+    },
+    /// Closes a file opened with [`FsMessage::Open`]. No response is expected.
+    ///
+    /// Files are automatically closed when the process that opened them terminates.
+    Close(u64),
+    /// Lists the content of the directory at the given path. Must be answered with a
+    /// [`ReadDirResponse`].
+    ReadDir(String),
+    /// Returns information about the file or directory at the given path. Must be answered
+    /// with a [`StatResponse`].
+    Stat(String),
+}
+
+/// Error that can happen when accessing the file system.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum FsError {
+    /// There is no file or directory at the given path.
+    NotFound,
+    /// A component of the path, or the path itself in the case of [`FsMessage::ReadDir`],
+    /// isn't a directory.
+    NotADirectory,
+    /// The path designates a directory, while a file was expected.
+    IsADirectory,
+    /// The file handle is invalid.
+    InvalidHandle,
+    /// Error while reading the underlying storage device, or the file system is corrupted.
+    Io,
+}
+
+/// Kind of entry in a directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum FileType {
+    File,
+    Directory,
+    /// Symbolic links, devices, and so on. Can't be opened.
+    Other,
+}
+
+/// Information about a file or directory.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct Metadata {
+    /// Kind of entry.
+    pub file_type: FileType,
+    /// Size in bytes of the content of the file. Unspecified for directories.
+    pub size: u64,
+}
+
+/// Entry in a directory.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct DirEntry {
+    /// Name of the entry within the directory.
+    pub name: String,
+    /// Information about the entry.
+    pub metadata: Metadata,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct OpenResponse {
+    /// On success, the handle of the newly-opened file.
+    pub result: Result<u64, FsError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct ReadResponse {
+    /// On success, the data that has been read. Can be shorter than the requested length, for
+    /// example if the end of the file has been reached. Empty only if the end of the file has
+    /// been reached.
+    pub result: Result<Vec<u8>, FsError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct ReadDirResponse {
+    /// On success, the entries of the directory, in no particular order. Doesn't include the
+    /// `.` and `..` entries.
+    pub result: Result<Vec<DirEntry>, FsError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct StatResponse {
+    pub result: Result<Metadata, FsError>,
+}
@@ -0,0 +1,122 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! File systems.
+//!
+//! This interface allows reading files and directories from the file system of a storage
+//! device. The file system is currently read-only.
+//!
+//! Use [`File::open`] to open a file, or [`read`] to read the entire content of a file at once.
+
+#![deny(intra_doc_link_resolution_failure)]
+
+use futures::prelude::*;
+use std::{convert::TryFrom as _, fmt};
+
+pub use ffi::{DirEntry, FileType, FsError, Metadata};
+
+pub mod ffi;
+
+/// Maximum number of bytes requested at once when reading an entire file.
+const READ_CHUNK_LEN: u32 = 64 * 1024;
+
+/// File opened with [`File::open`]. Closed when dropped.
+pub struct File {
+    handle: u64,
+}
+
+impl File {
+    /// Opens the file at the given path.
+    pub fn open(path: &str) -> impl Future<Output = Result<File, FsError>> {
+        let msg = ffi::FsMessage::Open(path.to_owned());
+        let response = unsafe {
+            redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+        };
+
+        response.map(|response: ffi::OpenResponse| {
+            Ok(File {
+                handle: response.result?,
+            })
+        })
+    }
+
+    /// Reads at most `len` bytes of the file, starting at `offset`.
+    ///
+    /// Returns less than `len` bytes if the end of the file is reached, and an empty buffer if
+    /// `offset` is past the end of the file.
+    pub fn read_at(&self, offset: u64, len: u32) -> impl Future<Output = Result<Vec<u8>, FsError>> {
+        let msg = ffi::FsMessage::Read {
+            handle: self.handle,
+            offset,
+            len,
+        };
+        let response = unsafe {
+            redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+        };
+
+        response.map(|response: ffi::ReadResponse| response.result)
+    }
+}
+
+impl fmt::Debug for File {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("File").field(&self.handle).finish()
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe {
+            let msg = ffi::FsMessage::Close(self.handle);
+            let _ =
+                redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg);
+        }
+    }
+}
+
+/// Reads the entire content of the file at the given path.
+pub async fn read(path: &str) -> Result<Vec<u8>, FsError> {
+    let file = File::open(path).await?;
+    let mut out = Vec::new();
+
+    loop {
+        let offset = u64::try_from(out.len()).unwrap();
+        let chunk = file.read_at(offset, READ_CHUNK_LEN).await?;
+        if chunk.is_empty() {
+            return Ok(out);
+        }
+        out.extend_from_slice(&chunk);
+    }
+}
+
+/// Returns the list of entries of the directory at the given path.
+pub fn read_dir(path: &str) -> impl Future<Output = Result<Vec<DirEntry>, FsError>> {
+    let msg = ffi::FsMessage::ReadDir(path.to_owned());
+    let response = unsafe {
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    };
+
+    response.map(|response: ffi::ReadDirResponse| response.result)
+}
+
+/// Returns information about the file or directory at the given path.
+pub fn metadata(path: &str) -> impl Future<Output = Result<Metadata, FsError>> {
+    let msg = ffi::FsMessage::Stat(path.to_owned());
+    let response = unsafe {
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    };
+
+    response.map(|response: ffi::StatResponse| response.result)
+}
@@ -26,19 +26,33 @@ pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
 /// Message in destination to the hardware interface handler.
 #[derive(Debug, Encode, Decode)]
 pub enum HardwareMessage {
-    /// Allocate RAM. Must answer with a `u64`. The value `0` is returned if the allocation is
-    /// too large.
+    /// Allocate RAM. Must answer with a `u64` containing the physical address of the
+    /// allocation. The value `0` is returned if the allocation is too large or if the
+    /// constraints can't be satisfied.
     ///
-    /// This is useful in situations where you want to pass a pointer to a device.
+    /// This is useful in situations where you want to pass a pointer to a device. The allocated
+    /// memory is physically contiguous and initialized with zeroes.
+    ///
+    /// The memory belongs to the process that emitted the message. It is automatically freed
+    /// when that process terminates.
     Malloc {
         /// Size to allocate.
         size: u64,
-        /// Alignment of the pointer to return.
+        /// Alignment of the pointer to return. Must be a power of two.
         ///
         /// The returned value modulo `alignment` must be equal to 0.
-        alignment: u8,
+        alignment: u64,
+        /// If true, the allocation must be entirely located below the 4GiB limit. Necessary for
+        /// devices that only support 32 bits addresses.
+        below_4gib: bool,
+        /// If true, the memory must be coherent with devices' accesses. In other words,
+        /// writes performed by the CPU must be visible by devices and vice-versa, without
+        /// having to manually flush caches.
+        cache_coherent: bool,
     },
-    /// Opposite of malloc.
+    /// Opposite of malloc. No response is expected.
+    ///
+    /// Has no effect if `ptr` wasn't returned by a malloc message emitted by the same process.
     Free {
         /// Value previously returned after a malloc message.
         ptr: u64,
@@ -50,15 +64,128 @@ pub enum HardwareMessage {
     /// If there is at least one memory or port read, the response must be a
     /// `Vec<HardwareAccessResponse>` where each element corresponds to a read. No response is
     /// expected if there are only writes.
+    ///
+    /// Processes can only access the physical memory that they have allocated with
+    /// [`HardwareMessage::Malloc`] or claimed with [`HardwareMessage::ClaimMmio`]. If one of the
+    /// operations accesses any other memory, none of them is performed and the message is
+    /// answered with an error.
     // TODO: should we enforce some limits in the amount of data that can be returned in a response?
     HardwareAccess(Vec<Operation>),
 
-    /// Ask the handler to send back a response when the interrupt with the given number is
-    /// triggered.
+    /// Start recording the interrupts triggered by the IRQ line with the given number on behalf
+    /// of the emitting process. Must answer with a `Result<(), ()>`. An error is returned if the
+    /// IRQ isn't supported by the platform.
+    ///
+    /// Once subscribed, no interrupt can be missed: interrupts that happen while no
+    /// [`HardwareMessage::InterruptWait`] is in progress are reported by the next one.
     ///
-    /// > **Note**: If called with a non-hardware interrupt, no response will ever come back.
-    // TODO: how to not miss any interrupt? we instead need some registration system or something
+    /// Subscribing multiple times to the same IRQ is equivalent to subscribing once.
+    InterruptSubscribe(u32),
+
+    /// Opposite of [`HardwareMessage::InterruptSubscribe`]. No response is expected.
+    ///
+    /// If a [`HardwareMessage::InterruptWait`] is in progress, it is answered with `Err(())`.
+    /// The IRQ is masked once no process is subscribed to it anymore.
+    InterruptUnsubscribe(u32),
+
+    /// Ask the handler to send back a response when the IRQ with the given number is triggered.
+    /// Must answer with a `Result<u64, ()>`. On success, the `u64` indicates the number of times
+    /// the interrupt has been triggered since the subscription or the previous answer. This
+    /// number is always at least 1.
+    ///
+    /// Answers immediately if the interrupt has been triggered since the previous answer.
+    ///
+    /// Answers with `Err(())` if the process isn't subscribed to this IRQ, if a previous
+    /// `InterruptWait` for the same IRQ is still in progress, or if the process unsubscribes
+    /// while waiting.
     InterruptWait(u32),
+
+    /// Request the list of memory regions where the configuration space of PCI Express devices
+    /// is mapped (Enhanced Configuration Access Mechanism). Must answer with a
+    /// `Vec<PciEcamRegion>`, which is empty if the platform doesn't support ECAM or if the
+    /// information isn't available.
+    GetPciEcamRegions,
+
+    /// Request information about the linear framebuffer that the bootloader or the firmware has
+    /// set up, if any. Must answer with an `Option<FramebufferInfo>`.
+    ///
+    /// The framebuffer can then be claimed with [`HardwareMessage::ClaimMmio`] and accessed
+    /// through [`HardwareMessage::HardwareAccess`].
+    GetFramebuffer,
+
+    /// Request exclusive access to a range of physical memory where the registers or the buffers
+    /// of a device are mapped. Must answer with a `Result<(), ()>`.
+    ///
+    /// An error is returned if the range overlaps RAM or a range claimed by a different process.
+    /// Claiming the same range multiple times is equivalent to claiming it once.
+    ///
+    /// The range is released when the process that has claimed it terminates.
+    ClaimMmio {
+        /// Physical memory address of the start of the range.
+        address: u64,
+        /// Length of the range, in bytes.
+        len: u64,
+    },
+}
+
+/// Memory region where the configuration space of PCI Express devices is mapped.
+///
+/// The configuration space of the function `function` of device `device` on bus `bus` is
+/// located at `base_address + ((bus - start_bus) << 20 | device << 15 | function << 12)` and is
+/// 4kiB long.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct PciEcamRegion {
+    /// Physical memory address of the configuration space of bus `start_bus`.
+    pub base_address: u64,
+    /// PCI segment group the region applies to.
+    pub segment_group: u16,
+    /// First bus covered by this region.
+    pub start_bus: u8,
+    /// Last bus covered by this region, inclusive.
+    pub end_bus: u8,
+}
+
+/// Description of a linear framebuffer.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct FramebufferInfo {
+    /// Physical memory address of the top-left pixel.
+    pub address: u64,
+    /// Number of pixels in a line.
+    pub width: u32,
+    /// Number of lines.
+    pub height: u32,
+    /// Number of bytes between the start of a line and the start of the next one.
+    pub pitch: u32,
+    /// Number of bits of each pixel.
+    pub bpp: u8,
+    /// How the pixels are laid out.
+    pub format: FramebufferFormat,
+}
+
+/// How the pixels of a framebuffer are laid out.
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum FramebufferFormat {
+    /// Each pixel contains a red, a green and a blue component.
+    Rgb {
+        red: ColorField,
+        green: ColorField,
+        blue: ColorField,
+    },
+    /// Each pixel is an index within a palette.
+    Indexed,
+    /// The framebuffer is an EGA text mode buffer, where each character is a `u16`. In that
+    /// situation, `width` and `height` are expressed in characters.
+    Text,
+}
+
+/// Location of a color component within a pixel.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct ColorField {
+    /// Position of the least significant bit of the component, starting from the least
+    /// significant bit of the pixel.
+    pub position: u8,
+    /// Number of bits of the component.
+    pub size: u8,
 }
 
 /// Request to perform accesses to physical memory or to ports.
@@ -0,0 +1,100 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Receiving hardware interrupts.
+//!
+//! Subscribe to an IRQ line with [`InterruptSubscription::subscribe`], then call
+//! [`InterruptSubscription::wait`] in order to wait for interrupts to happen.
+
+use crate::ffi;
+
+use alloc::boxed::Box;
+use core::{pin::Pin, task::Poll};
+use futures::prelude::*;
+
+/// Active subscription to an IRQ line. Unsubscribes when dropped.
+pub struct InterruptSubscription {
+    /// Number of the IRQ line.
+    irq: u32,
+    /// `InterruptWait` message that has been sent and whose answer hasn't been received yet.
+    /// Kept across calls to [`InterruptSubscription::wait`], so that dropping the `Future`
+    /// returned by `wait` doesn't lose interrupts.
+    next: Option<Pin<Box<dyn Future<Output = Result<u64, ()>>>>>,
+}
+
+impl InterruptSubscription {
+    /// Subscribes to the interrupts of the given IRQ line.
+    ///
+    /// Returns an error if the IRQ isn't supported by the platform.
+    pub fn subscribe(irq: u32) -> impl Future<Output = Result<Self, ()>> {
+        unsafe {
+            let msg = ffi::HardwareMessage::InterruptSubscribe(irq);
+            redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg)
+                .unwrap()
+                .map(move |result: Result<(), ()>| {
+                    result.map(|()| InterruptSubscription { irq, next: None })
+                })
+        }
+    }
+
+    /// Returns the number of the IRQ line.
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+
+    /// Waits until the interrupt is triggered. Returns the number of times it has been
+    /// triggered since the subscription or the previous time `wait` produced a value.
+    ///
+    /// If the interrupt has been triggered in the meanwhile, the returned `Future` is ready
+    /// immediately. If the `Future` is dropped before being ready, the next call to `wait`
+    /// continues waiting where the previous one stopped, and no interrupt is missed.
+    ///
+    /// Calling this function also indicates that the device has been serviced. Interrupts of
+    /// level-triggered IRQ lines, which stay active until the device is serviced, aren't
+    /// reported again before the next call to `wait`.
+    ///
+    /// Returns an error if the handler of the interface refuses the request, in which case the
+    /// subscription should be considered as no longer active.
+    pub fn wait<'a>(&'a mut self) -> impl Future<Output = Result<u64, ()>> + 'a {
+        future::poll_fn(move |cx| {
+            let irq = self.irq;
+            let next = self.next.get_or_insert_with(|| unsafe {
+                let msg = ffi::HardwareMessage::InterruptWait(irq);
+                Box::pin(
+                    redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg)
+                        .unwrap(),
+                )
+            });
+
+            match next.as_mut().poll(cx) {
+                Poll::Ready(result) => {
+                    self.next = None;
+                    Poll::Ready(result)
+                }
+                Poll::Pending => Poll::Pending,
+            }
+        })
+    }
+}
+
+impl Drop for InterruptSubscription {
+    fn drop(&mut self) {
+        unsafe {
+            let msg = ffi::HardwareMessage::InterruptUnsubscribe(self.irq);
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg)
+                .unwrap();
+        }
+    }
+}
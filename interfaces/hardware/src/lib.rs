@@ -26,6 +26,7 @@ use alloc::{vec, vec::Vec};
 use futures::prelude::*;
 
 pub mod ffi;
+pub mod interrupts;
 pub mod malloc;
 
 /// Builder for write-only hardware operations.
@@ -118,6 +119,38 @@ pub async unsafe fn port_read_u8(port: u32) -> u8 {
     out
 }
 
+/// Returns the memory regions where the configuration space of PCI Express devices is mapped.
+///
+/// Returns an empty list if the platform doesn't support this mechanism.
+pub fn pci_ecam_regions() -> impl Future<Output = Vec<ffi::PciEcamRegion>> {
+    unsafe {
+        let msg = ffi::HardwareMessage::GetPciEcamRegions;
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    }
+}
+
+/// Returns the linear framebuffer that has been set up by the bootloader or the firmware.
+///
+/// Returns `None` if there isn't any, or if the information isn't available.
+pub fn framebuffer() -> impl Future<Output = Option<ffi::FramebufferInfo>> {
+    unsafe {
+        let msg = ffi::HardwareMessage::GetFramebuffer;
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    }
+}
+
+/// Requests exclusive access to the given range of physical memory, where the registers or the
+/// buffers of a device are mapped. Physical memory, apart from the memory allocated through the
+/// [`malloc`] module, must be claimed before being accessed.
+///
+/// Returns an error if the range overlaps RAM or has already been claimed by a different process.
+pub fn claim_mmio(address: u64, len: u64) -> impl Future<Output = Result<(), ()>> {
+    unsafe {
+        let msg = ffi::HardwareMessage::ClaimMmio { address, len };
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    }
+}
+
 /// Builder for read and write hardware operations.
 pub struct HardwareOperationsBuilder<'a> {
     operations: Vec<ffi::Operation>,
@@ -41,7 +41,7 @@ impl<T> PhysicalBuffer<T> {
     ///
     pub fn new(data: T) -> impl Future<Output = Self> {
         let size = u64::try_from(mem::size_of_val(&data)).unwrap();
-        let align = u8::try_from(mem::align_of_val(&data)).unwrap();
+        let align = u64::try_from(mem::align_of_val(&data)).unwrap();
 
         malloc(size, align).map(move |ptr| {
             let buf = PhysicalBuffer {
@@ -125,21 +125,60 @@ impl<T: ?Sized> Drop for PhysicalBuffer<T> {
     }
 }
 
+/// Constraints for an allocation of physical memory.
+#[derive(Debug, Clone, Default)]
+pub struct AllocOptions {
+    /// If true, the allocation must be entirely located below 4GiB. Necessary for devices that
+    /// only support 32 bits addresses.
+    pub below_4gib: bool,
+    /// If true, the memory must be coherent with devices' accesses, without having to manually
+    /// flush caches.
+    pub cache_coherent: bool,
+}
+
 /// Allocates physical memory.
 ///
 /// # Panic
 ///
 /// Panics if the allocation fails, for example if `size` is too large to be acceptable.
 ///
-pub fn malloc(size: u64, alignment: u8) -> impl Future<Output = u64> {
+pub fn malloc(size: u64, alignment: u64) -> impl Future<Output = u64> {
+    malloc_with_options(size, alignment, AllocOptions::default())
+        .map(|result| result.expect("physical memory allocation failed"))
+}
+
+/// Allocates physical memory meeting the given constraints. The memory is physically
+/// contiguous and can be passed to devices for DMA.
+///
+/// Returns an error if the allocation fails or if the platform can't satisfy the constraints.
+///
+/// # Panic
+///
+/// Panics if `alignment` isn't a power of two.
+///
+pub fn malloc_with_options(
+    size: u64,
+    alignment: u64,
+    options: AllocOptions,
+) -> impl Future<Output = Result<u64, ()>> {
+    assert!(alignment.is_power_of_two());
+
     unsafe {
-        let msg = ffi::HardwareMessage::Malloc { size, alignment };
+        let msg = ffi::HardwareMessage::Malloc {
+            size,
+            alignment,
+            below_4gib: options.below_4gib,
+            cache_coherent: options.cache_coherent,
+        };
         redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg)
             .unwrap()
             .map(move |ptr: u64| {
-                assert_ne!(ptr, 0);
-                debug_assert_eq!(ptr % u64::from(alignment), 0);
-                ptr
+                if ptr == 0 {
+                    return Err(());
+                }
+
+                debug_assert_eq!(ptr % alignment, 0);
+                Ok(ptr)
             })
     }
 }
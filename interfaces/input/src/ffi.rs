@@ -0,0 +1,98 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0xce, 0x50, 0x93, 0x17, 0xa5, 0xe7, 0x4d, 0x2b, 0xa4, 0x50, 0x69, 0xe0, 0x15, 0xa5, 0xbc, 0xb7,
+    0xdc, 0x26, 0x9a, 0xff, 0x46, 0xcd, 0xc1, 0x73, 0xff, 0xe6, 0x30, 0xf7, 0x37, 0x5a, 0x65, 0x82,
+]);
+
+/// Message in destination to the handler of input devices.
+///
+/// Input devices are identified by the combination of the process that registered them and an
+/// identifier chosen by this process.
+#[derive(Debug, Encode, Decode)]
+pub enum InputMessage {
+    /// Notify of the existence of a new input device. No response is expected.
+    RegisterDevice {
+        /// Identifier of the device, chosen by the sender. Must be unique among the devices
+        /// registered by the same process.
+        id: u64,
+        /// What kind of events the device produces.
+        kind: DeviceKind,
+    },
+
+    /// Removes a device previously registered with [`InputMessage::RegisterDevice`]. No
+    /// response is expected.
+    ///
+    /// Devices are automatically unregistered when the process that registered them terminates.
+    UnregisterDevice(u64),
+
+    /// Notify that something happened on a device. No response is expected.
+    Event {
+        /// Identifier of the device.
+        id: u64,
+        /// What happened.
+        event: InputEvent,
+    },
+}
+
+/// Kind of input device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum DeviceKind {
+    /// Produces [`InputEvent::Key`] events.
+    Keyboard,
+    /// Produces [`InputEvent::PointerMotion`], [`InputEvent::PointerPosition`],
+    /// [`InputEvent::PointerButton`] and [`InputEvent::PointerWheel`] events.
+    Pointer,
+}
+
+/// Event produced by an input device.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum InputEvent {
+    /// A key has been pressed or released.
+    Key {
+        /// Physical location of the key, as a USB HID usage ID of the "Keyboard/Keypad" usage
+        /// page, whatever the kind of keyboard. Doesn't depend on the keyboard layout.
+        scancode: u32,
+        /// Symbol produced by the key according to the keyboard layout and the state of the
+        /// modifiers, as an X11 keysym. See the [`keys`](crate::keys) module. Can be
+        /// [`NO_SYMBOL`](crate::keys::NO_SYMBOL).
+        keysym: u32,
+        /// True if the key has been pressed, false if it has been released.
+        pressed: bool,
+    },
+    /// The pointer has moved by the given amount, in device-specific units. Positive values
+    /// are towards the right and the bottom.
+    PointerMotion { dx: i32, dy: i32 },
+    /// The pointer has moved to the given absolute position, for example on a touch screen or
+    /// on a tablet. `0` is the left or top edge, and `u16::max_value()` the right or bottom edge.
+    PointerPosition { x: u16, y: u16 },
+    /// A button of the pointer has been pressed or released.
+    PointerButton {
+        /// `0` for the left button, `1` for the right button, and `2` for the middle button.
+        /// Other values are device-specific.
+        button: u8,
+        /// True if the button has been pressed, false if it has been released.
+        pressed: bool,
+    },
+    /// The wheel of the pointer has been rotated by the given number of notches. Positive
+    /// values mean that the wheel has been rotated away from the user, in other words that
+    /// the content should scroll up.
+    PointerWheel { delta: i32 },
+}
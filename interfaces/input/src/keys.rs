@@ -0,0 +1,112 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Identifiers of keys and of the symbols they produce.
+//!
+//! Keys are identified by their USB HID usage ID in the "Keyboard/Keypad" usage page (`0x07`),
+//! whatever the kind of keyboard. Drivers of other kinds of keyboards must translate their
+//! scancodes.
+//!
+//! Symbols are identified by their X11 keysym. Printable ASCII characters have a keysym equal to
+//! their code, while other keys, such as the arrows or the modifiers, have a keysym in the
+//! `0xff00` to `0xffff` range.
+//!
+//! Bibliography:
+//!
+//! - https://www.usb.org/sites/default/files/documents/hut1_12v2.pdf, chapter 10
+//! - https://www.x.org/releases/current/doc/xproto/x11protocol.html#keysym_encoding
+//!
+
+/// Keysym indicating that the key doesn't produce any symbol.
+pub const NO_SYMBOL: u32 = 0;
+
+/// Returns the keysym produced by the given key on a US QWERTY keyboard.
+///
+/// `shift` must be true if a shift key is being held. `caps_lock` must be true if caps lock is
+/// enabled, in which case letters are inverted between lowercase and uppercase.
+///
+/// Returns [`NO_SYMBOL`] if the key is unknown.
+pub fn us_qwerty_keysym(scancode: u32, shift: bool, caps_lock: bool) -> u32 {
+    let printable = |normal: u8, shifted: u8| u32::from(if shift { shifted } else { normal });
+
+    match scancode {
+        // Letters.
+        0x04..=0x1d => {
+            let letter = b'a' + (scancode - 0x04) as u8;
+            if shift != caps_lock {
+                u32::from(letter.to_ascii_uppercase())
+            } else {
+                u32::from(letter)
+            }
+        }
+        // Digits, from 1 to 9 then 0.
+        0x1e..=0x27 => {
+            let index = (scancode - 0x1e) as usize;
+            printable(b"1234567890"[index], b"!@#$%^&*()"[index])
+        }
+        0x28 => 0xff0d, // Return
+        0x29 => 0xff1b, // Escape
+        0x2a => 0xff08, // BackSpace
+        0x2b => 0xff09, // Tab
+        0x2c => u32::from(b' '),
+        0x2d => printable(b'-', b'_'),
+        0x2e => printable(b'=', b'+'),
+        0x2f => printable(b'[', b'{'),
+        0x30 => printable(b']', b'}'),
+        // The "non-US #" key, at 0x32, is located where the backslash is on US keyboards.
+        0x31 | 0x32 => printable(b'\\', b'|'),
+        0x33 => printable(b';', b':'),
+        0x34 => printable(b'\'', b'"'),
+        0x35 => printable(b'`', b'~'),
+        0x36 => printable(b',', b'<'),
+        0x37 => printable(b'.', b'>'),
+        0x38 => printable(b'/', b'?'),
+        0x39 => 0xffe5,                            // Caps_Lock
+        0x3a..=0x45 => 0xffbe + (scancode - 0x3a), // F1 to F12
+        0x46 => 0xff61,                            // Print
+        0x47 => 0xff14,                            // Scroll_Lock
+        0x48 => 0xff13,                            // Pause
+        0x49 => 0xff63,                            // Insert
+        0x4a => 0xff50,                            // Home
+        0x4b => 0xff55,                            // Prior
+        0x4c => 0xffff,                            // Delete
+        0x4d => 0xff57,                            // End
+        0x4e => 0xff56,                            // Next
+        0x4f => 0xff53,                            // Right
+        0x50 => 0xff51,                            // Left
+        0x51 => 0xff54,                            // Down
+        0x52 => 0xff52,                            // Up
+        0x53 => 0xff7f,                            // Num_Lock
+        0x54 => 0xffaf,                            // KP_Divide
+        0x55 => 0xffaa,                            // KP_Multiply
+        0x56 => 0xffad,                            // KP_Subtract
+        0x57 => 0xffab,                            // KP_Add
+        0x58 => 0xff8d,                            // KP_Enter
+        0x59..=0x61 => 0xffb1 + (scancode - 0x59), // KP_1 to KP_9
+        0x62 => 0xffb0,                            // KP_0
+        0x63 => 0xffae,                            // KP_Decimal
+        0x64 => printable(b'\\', b'|'),
+        0x65 => 0xff67, // Menu
+        0xe0 => 0xffe3, // Control_L
+        0xe1 => 0xffe1, // Shift_L
+        0xe2 => 0xffe9, // Alt_L
+        0xe3 => 0xffeb, // Super_L
+        0xe4 => 0xffe4, // Control_R
+        0xe5 => 0xffe2, // Shift_R
+        0xe6 => 0xffea, // Alt_R
+        0xe7 => 0xffec, // Super_R
+        _ => NO_SYMBOL,
+    }
+}
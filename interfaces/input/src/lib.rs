@@ -0,0 +1,82 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Input devices.
+//!
+//! This interface allows drivers of input devices, such as keyboards and mice, to report what
+//! the user is doing to the program that handles this interface, such as a window manager.
+//!
+//! Use this interface if you're writing a driver for an input device. Call
+//! [`register_device`] for each device, then report the events of the device with
+//! [`InputDeviceRegistration::send_event`].
+//!
+//! Messages are only delivered once a program has registered itself as the handler of this
+//! interface. Until then, [`register_device`] and [`InputDeviceRegistration::send_event`] block
+//! the calling thread. Events aren't buffered on the sender's side: a driver that is blocked
+//! stops reading from its device, and it is up to the device to keep or drop the events that
+//! happen in the meantime.
+
+#![deny(intra_doc_link_resolution_failure)]
+#![no_std]
+
+pub use self::ffi::{DeviceKind, InputEvent};
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub mod ffi;
+pub mod keys;
+
+/// Registers a new input device towards the handler.
+///
+/// The device is unregistered when the returned object is dropped.
+pub fn register_device(kind: DeviceKind) -> InputDeviceRegistration {
+    // Identifiers only have to be unique within our process.
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    unsafe {
+        let msg = ffi::InputMessage::RegisterDevice { id, kind };
+        redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg).unwrap();
+    }
+
+    InputDeviceRegistration { id }
+}
+
+/// Input device registered towards the handler.
+pub struct InputDeviceRegistration {
+    /// Identifier of the device within our process.
+    id: u64,
+}
+
+impl InputDeviceRegistration {
+    /// Reports an event that happened on the device.
+    pub fn send_event(&self, event: InputEvent) {
+        unsafe {
+            let msg = ffi::InputMessage::Event { id: self.id, event };
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg)
+                .unwrap();
+        }
+    }
+}
+
+impl Drop for InputDeviceRegistration {
+    fn drop(&mut self) {
+        unsafe {
+            let msg = ffi::InputMessage::UnregisterDevice(self.id);
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg)
+                .unwrap();
+        }
+    }
+}
@@ -13,7 +13,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 use parity_scale_codec::{Decode, Encode};
 use redshirt_syscalls_interface::InterfaceHash;
 
@@ -28,6 +28,37 @@ pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
 pub enum PciMessage {
     /// Request list of PCI devices. Answer with a [`GetDevicesListResponse`].
     GetDevicesList,
+
+    /// Read 32 bits from the configuration space of a device. Answer with a `Result<u32, ()>`.
+    ///
+    /// An error is returned if the device doesn't exist, or if the offset is out of range or
+    /// not aligned to 4 bytes.
+    ReadConfig {
+        /// Device whose configuration space to read.
+        location: PciDeviceLocation,
+        /// Offset within the configuration space. Must be a multiple of 4.
+        offset: u16,
+    },
+
+    /// Write 32 bits to the configuration space of a device. No answer is expected.
+    ///
+    /// The first process to reconfigure a device becomes its owner, until it is destroyed. Has no
+    /// effect if the device doesn't exist, if it is owned by another process, or if the offset
+    /// is out of range or not aligned to 4 bytes.
+    WriteConfig {
+        /// Device whose configuration space to write.
+        location: PciDeviceLocation,
+        /// Offset within the configuration space. Must be a multiple of 4.
+        offset: u16,
+        /// Value to write.
+        value: u32,
+    },
+
+    /// Allow the device to perform DMA. No answer is expected.
+    ///
+    /// Makes the emitter the owner of the device, like [`PciMessage::WriteConfig`]. Has no
+    /// effect if the device is owned by another process.
+    EnableBusMaster(PciDeviceLocation),
 }
 
 /// Response to [`PciMessage::GetDevicesList`].
@@ -37,24 +68,104 @@ pub struct GetDevicesListResponse {
     pub devices: Vec<PciDeviceInfo>,
 }
 
+/// Location of a PCI device function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct PciDeviceLocation {
+    /// Bus the device is connected to.
+    pub bus: u8,
+    /// Device number on the bus. Always inferior to 32.
+    pub device: u8,
+    /// Function of the device. Always inferior to 8.
+    pub function: u8,
+}
+
 /// Description of a single PCI device.
 #[derive(Debug, Clone, Encode, Decode)]
 pub struct PciDeviceInfo {
+    /// Where the device is located.
+    pub location: PciDeviceLocation,
     pub vendor_id: u16,
     pub device_id: u16,
-    pub base_address_registers: Vec<PciBaseAddressRegister>,
-    // TODO: add more fields
+    /// Name of the vendor, if known.
+    pub vendor_name: Option<String>,
+    /// Name of the device, if known.
+    pub device_name: Option<String>,
+    /// Class of the device. Indicates the kind of device (network controller, display
+    /// controller, bridge, ...).
+    pub class_code: u8,
+    /// Subclass of the device. Its meaning depends on the class.
+    pub subclass: u8,
+    /// Programming interface. Its meaning depends on the class and subclass.
+    pub prog_if: u8,
+    pub revision_id: u8,
+    /// Base address registers of the device, indexed by BAR number. An entry is `None` if the
+    /// BAR is unused, or if it is the upper half of a 64 bits memory BAR.
+    pub base_address_registers: Vec<Option<PciBaseAddressRegister>>,
+    /// IRQ line the device's interrupts are routed to, or `None` if the device doesn't use
+    /// interrupts. Can be passed to the `hardware` interface in order to receive interrupts.
+    pub interrupt_line: Option<u8>,
+    /// Interrupt pin used by the device, where `1` is INTA#, `2` is INTB#, and so on. `None` if
+    /// the device doesn't use interrupts.
+    pub interrupt_pin: Option<u8>,
+    /// List of capabilities found in the capabilities list of the device.
+    pub capabilities: Vec<PciCapability>,
 }
 
-/// Description of a single PCI device.
-// TODO: actually figure out PCI and adjust this
+/// Base address register of a PCI device.
 #[derive(Debug, Clone, Encode, Decode)]
 pub enum PciBaseAddressRegister {
+    /// Registers of the device are mapped in physical memory.
     Memory {
-        base_address: u32,
+        base_address: u64,
+        /// Size in bytes of the memory region.
+        size: u64,
+        /// If true, reading the memory has no side effect.
         prefetchable: bool,
+        /// If true, the base address is 64 bits and occupies two BAR slots.
+        is_64bits: bool,
     },
+    /// Registers of the device are accessed through I/O ports.
     Io {
         base_address: u32,
+        /// Number of ports.
+        size: u32,
+    },
+}
+
+/// Capability of a PCI device, as found in its capabilities list.
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum PciCapability {
+    /// Message Signaled Interrupts.
+    Msi {
+        /// Offset of the capability in the configuration space.
+        offset: u8,
+        /// True if the device supports 64 bits message addresses.
+        is_64bits: bool,
+        /// Number of interrupt vectors that the device can use. Always a power of two.
+        max_vectors: u8,
+        /// True if each vector can be individually masked.
+        per_vector_masking: bool,
+    },
+    /// Extended Message Signaled Interrupts.
+    MsiX {
+        /// Offset of the capability in the configuration space.
+        offset: u8,
+        /// Number of entries in the MSI-X table.
+        table_size: u16,
+        /// Index of the BAR containing the MSI-X table.
+        table_bar: u8,
+        /// Offset of the MSI-X table within the BAR.
+        table_offset: u32,
+        /// Index of the BAR containing the pending bits array.
+        pending_bar: u8,
+        /// Offset of the pending bits array within the BAR.
+        pending_offset: u32,
+    },
+    /// Capability not recognized by the PCI handler.
+    Other {
+        /// Identifier of the capability.
+        id: u8,
+        /// Offset of the capability in the configuration space.
+        offset: u8,
     },
 }
@@ -22,7 +22,7 @@
 
 extern crate alloc;
 
-pub use self::ffi::{PciBaseAddressRegister, PciDeviceInfo};
+pub use self::ffi::{PciBaseAddressRegister, PciCapability, PciDeviceInfo, PciDeviceLocation};
 
 use alloc::vec::Vec;
 use futures::prelude::*;
@@ -39,3 +39,43 @@ pub fn get_pci_devices() -> impl Future<Output = Vec<PciDeviceInfo>> {
             .map(|response: ffi::GetDevicesListResponse| response.devices)
     }
 }
+
+/// Reads 32 bits from the configuration space of a device.
+///
+/// Returns an error if the device doesn't exist, or if the offset is out of range or not a
+/// multiple of 4.
+pub fn read_config(
+    location: PciDeviceLocation,
+    offset: u16,
+) -> impl Future<Output = Result<u32, ()>> {
+    unsafe {
+        let msg = ffi::PciMessage::ReadConfig { location, offset };
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    }
+}
+
+/// Writes 32 bits to the configuration space of a device.
+///
+/// # Safety
+///
+/// Reconfiguring a device can break the system in unpredictable ways.
+///
+pub unsafe fn write_config(location: PciDeviceLocation, offset: u16, value: u32) {
+    let msg = ffi::PciMessage::WriteConfig {
+        location,
+        offset,
+        value,
+    };
+    redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg).unwrap();
+}
+
+/// Allows the device to perform DMA.
+///
+/// # Safety
+///
+/// The device can then read and write any location in physical memory.
+///
+pub unsafe fn enable_bus_master(location: PciDeviceLocation) {
+    let msg = ffi::PciMessage::EnableBusMaster(location);
+    redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg).unwrap();
+}
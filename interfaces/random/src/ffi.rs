@@ -31,6 +31,13 @@ pub enum RandomMessage {
     /// errors about the length being too long to fit in memory. Call multiple times to obtain
     /// more.
     Generate { len: u16 },
+
+    /// Provide entropy to the handler, for example coming from a hardware random number
+    /// generator. No answer is expected.
+    ///
+    /// The handler mixes this entropy with its own sources, so that providing predictable data
+    /// can't weaken the random numbers it generates.
+    AddEntropy(Vec<u8>),
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -20,6 +20,7 @@
 
 extern crate alloc;
 
+use alloc::vec::Vec;
 use core::convert::TryFrom;
 
 pub mod ffi;
@@ -50,3 +51,12 @@ pub async fn generate_in(out: &mut [u8]) {
         chunk.copy_from_slice(&rep.result);
     }
 }
+
+/// Provides entropy to the random number generator, for example coming from a hardware random
+/// number generator.
+pub fn add_entropy(entropy: impl Into<Vec<u8>>) {
+    unsafe {
+        let msg = ffi::RandomMessage::AddEntropy(entropy.into());
+        redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use alloc::vec::Vec;
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x3e, 0xc0, 0xd5, 0x11, 0x26, 0x65, 0x12, 0xbb, 0x86, 0x1f, 0x5e, 0x42, 0x34, 0xb9, 0x6d, 0xfc,
+    0x2e, 0xc5, 0x05, 0xa2, 0x86, 0x93, 0xdd, 0xaf, 0x1d, 0xc9, 0xd2, 0xd0, 0x23, 0x6e, 0xbb, 0x23,
+]);
+
+#[derive(Debug, Encode, Decode)]
+pub enum SerialMessage {
+    /// Send bytes on the serial port. No answer.
+    Write(Vec<u8>),
+    /// Wait for bytes to be received on the serial port. The answer, a `Vec<u8>`, contains all
+    /// the bytes that have been received since the previous read, and is never empty.
+    Read,
+    /// Configure the serial port. The answer is a `Result<(), ()>`, which is an error if the
+    /// configuration isn't supported.
+    Configure(SerialConfig),
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct SerialConfig {
+    /// Number of bits per second.
+    pub baud_rate: u32,
+}
@@ -0,0 +1,56 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Serial ports.
+//!
+//! This interface allows sending and receiving raw bytes over a serial port, such as a UART.
+
+#![deny(intra_doc_link_resolution_failure)]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::future::Future;
+
+pub mod ffi;
+
+/// Sends bytes on the serial port.
+pub fn write(data: impl Into<Vec<u8>>) {
+    unsafe {
+        let msg = ffi::SerialMessage::Write(data.into());
+        redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &msg).unwrap();
+    }
+}
+
+/// Waits until bytes are received on the serial port, and returns them.
+///
+/// The returned data is never empty.
+pub fn read() -> impl Future<Output = Vec<u8>> {
+    unsafe {
+        let msg = ffi::SerialMessage::Read;
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    }
+}
+
+/// Sets the baud rate of the serial port.
+///
+/// Returns an error if the baud rate isn't supported.
+pub fn set_baud_rate(baud_rate: u32) -> impl Future<Output = Result<(), ()>> {
+    unsafe {
+        let msg = ffi::SerialMessage::Configure(ffi::SerialConfig { baud_rate });
+        redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, msg).unwrap()
+    }
+}
@@ -25,15 +25,43 @@ pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
 #[derive(Debug, Encode, Decode)]
 pub enum TcpMessage {
     Listen(TcpListen),
+    /// Ask for the next incoming connection of a listening socket. The response contains the
+    /// new socket.
     Accept(TcpAccept),
     Open(TcpOpen),
+    /// Destroys the socket. No response is expected.
     Close(TcpClose),
-    /// Ask to read data from a socket. The response contains the data. For each socket, only one
-    /// read can exist at any given point in time.
+    /// Ask to read data from a socket. The response contains the data. Multiple reads can be in
+    /// progress at the same time, in which case they are answered in order.
     Read(TcpRead),
-    /// Ask to write data to a socket. A response is sent back once written. For each socket, only
-    /// one write can exist at any given point in time.
+    /// Ask to write data to a socket. A response is sent back once the data has been queued for
+    /// sending. Multiple writes can be in progress at the same time, in which case the data is
+    /// sent in order.
     Write(TcpWrite),
+    /// Closes the writing side of the socket, after the writes in progress have been
+    /// processed. A response is sent back once done.
+    Shutdown(TcpShutdown),
+    /// Modifies an option of the socket. No response is expected.
+    SetOption(TcpSetOption),
+}
+
+/// Error that can happen on a socket.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum TcpError {
+    /// The remote refused the connection.
+    ConnectionRefused,
+    /// The connection has been reset by the remote.
+    ConnectionReset,
+    /// The remote didn't respond in time.
+    TimedOut,
+    /// The local address is already in use by another socket.
+    AddrInUse,
+    /// There is no network interface capable of reaching the remote.
+    NetworkUnreachable,
+    /// The socket isn't connected, or its writing side has been shut down.
+    NotConnected,
+    /// The socket ID is invalid, or the operation isn't supported by this kind of socket.
+    InvalidSocket,
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -46,7 +74,7 @@ pub struct TcpListen {
 #[derive(Debug, Encode, Decode)]
 pub struct TcpListenResponse {
     /// On success, the socket ID and the port it's listening on.
-    pub result: Result<(u32, u16), ()>,
+    pub result: Result<(u32, u16), TcpError>,
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -57,7 +85,7 @@ pub struct TcpOpen {
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpOpenResponse {
-    pub result: Result<u32, ()>,
+    pub result: Result<TcpConnection, TcpError>,
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -67,7 +95,15 @@ pub struct TcpAccept {
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpAcceptResponse {
-    pub accepted_socket_id: u32,
+    pub result: Result<TcpConnection, TcpError>,
+}
+
+/// Information about a newly-established connection.
+#[derive(Debug, Encode, Decode)]
+pub struct TcpConnection {
+    pub socket_id: u32,
+    pub local_ip: [u16; 8],
+    pub local_port: u16,
     pub remote_ip: [u16; 8],
     pub remote_port: u16,
 }
@@ -84,7 +120,9 @@ pub struct TcpRead {
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpReadResponse {
-    pub result: Result<Vec<u8>, ()>,
+    /// On success, the data that has been read. An empty buffer indicates that the remote has
+    /// closed its writing side.
+    pub result: Result<Vec<u8>, TcpError>,
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -95,5 +133,31 @@ pub struct TcpWrite {
 
 #[derive(Debug, Encode, Decode)]
 pub struct TcpWriteResponse {
-    pub result: Result<(), ()>,
+    pub result: Result<(), TcpError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TcpShutdown {
+    pub socket_id: u32,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TcpShutdownResponse {
+    pub result: Result<(), TcpError>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct TcpSetOption {
+    pub socket_id: u32,
+    pub option: TcpOption,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub enum TcpOption {
+    /// If true, data is sent out as soon as possible rather than being buffered in order to
+    /// send larger segments.
+    NoDelay(bool),
+    /// If `Some`, sends keep-alive packets at the given interval, in milliseconds, when the
+    /// connection is idle.
+    KeepAlive(Option<u64>),
 }
@@ -17,64 +17,123 @@
 
 #![deny(intra_doc_link_resolution_failure)]
 
-// TODO: everything here is a draft
-
 use futures::{prelude::*, ready};
-use parity_scale_codec::DecodeAll;
-use redshirt_syscalls_interface::{Encode as _, MessageId};
+use redshirt_syscalls_interface::{Decode, Encode as _, MessageResponseFuture};
 use std::{
-    cmp, io, mem, net::Ipv6Addr, net::SocketAddr, pin::Pin, sync::Arc, task::Context, task::Poll,
-    task::Waker,
+    cmp,
+    collections::VecDeque,
+    convert::TryFrom as _,
+    io, mem,
+    net::{Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::Context,
+    task::Poll,
+    time::Duration,
 };
 
+pub use ffi::TcpError;
+
 pub mod ffi;
 
+/// Maximum number of bytes that can be in the process of being written at any given point in
+/// time. Writing more data waits for the writes in progress to finish.
+const MAX_PENDING_WRITE_BYTES: usize = 64 * 1024;
+
 pub struct TcpStream {
     handle: u32,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
     /// Buffer of data that has been read from the socket but not transmitted to the user yet.
     read_buffer: Vec<u8>,
+    /// True if the remote has closed its writing side. No more data will be read.
+    read_closed: bool,
     /// If Some, we have sent out a "read" message and are waiting for a response.
-    // TODO: use strongly typed Future here
-    pending_read: Option<Pin<Box<dyn Future<Output = ffi::TcpReadResponse> + Send>>>,
-    /// If Some, we have sent out a "write" message and are waiting for a response.
-    // TODO: use strongly typed Future here
-    pending_write: Option<Pin<Box<dyn Future<Output = ffi::TcpWriteResponse> + Send>>>,
+    pending_read: Option<MessageResponseFuture<ffi::TcpReadResponse>>,
+    /// List of "write" messages we have sent out and are waiting for a response, with the
+    /// number of bytes of each write.
+    pending_writes: VecDeque<(usize, MessageResponseFuture<ffi::TcpWriteResponse>)>,
+    /// Sum of the number of bytes in [`TcpStream::pending_writes`].
+    pending_writes_bytes: usize,
+    /// If Some, we have sent out a "shutdown" message and are waiting for a response.
+    pending_shutdown: Option<MessageResponseFuture<ffi::TcpShutdownResponse>>,
 }
 
 impl TcpStream {
-    pub fn connect(socket_addr: &SocketAddr) -> impl Future<Output = Result<TcpStream, ()>> {
-        let tcp_open = ffi::TcpMessage::Open(match socket_addr {
-            SocketAddr::V4(addr) => ffi::TcpOpen {
-                ip: addr.ip().to_ipv6_mapped().segments(),
-                port: addr.port(),
-            },
-            SocketAddr::V6(addr) => ffi::TcpOpen {
-                ip: addr.ip().segments(),
-                port: addr.port(),
-            },
-        });
-
-        let msg_id = unsafe {
-            let msg = tcp_open.encode();
-            redshirt_syscalls_interface::MessageBuilder::new()
-                .add_data(&msg)
-                .emit_with_response_raw(&ffi::INTERFACE)
-                .unwrap()
-        };
+    pub fn connect(socket_addr: &SocketAddr) -> impl Future<Output = Result<TcpStream, io::Error>> {
+        let (ip, port) = addr_to_ffi(socket_addr);
+        let response = emit_with_response(ffi::TcpMessage::Open(ffi::TcpOpen { ip, port }));
 
         async move {
-            let message: ffi::TcpOpenResponse =
-                redshirt_syscalls_interface::message_response(msg_id).await;
-            let handle = message.result?;
+            let message: ffi::TcpOpenResponse = response.await;
+            Ok(TcpStream::from_connection(message.result?))
+        }
+    }
 
-            Ok(TcpStream {
-                handle,
-                read_buffer: Vec::new(),
-                pending_read: None,
-                pending_write: None,
-            })
+    fn from_connection(connection: ffi::TcpConnection) -> TcpStream {
+        TcpStream {
+            handle: connection.socket_id,
+            local_addr: addr_from_ffi(connection.local_ip, connection.local_port),
+            peer_addr: addr_from_ffi(connection.remote_ip, connection.remote_port),
+            read_buffer: Vec::new(),
+            read_closed: false,
+            pending_read: None,
+            pending_writes: VecDeque::new(),
+            pending_writes_bytes: 0,
+            pending_shutdown: None,
+        }
+    }
+
+    /// Returns the local address of the connection.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the address of the remote of the connection.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// If true, data is sent out as soon as possible rather than being buffered in order to
+    /// send larger segments.
+    pub fn set_nodelay(&self, nodelay: bool) {
+        self.set_option(ffi::TcpOption::NoDelay(nodelay))
+    }
+
+    /// If `Some`, keep-alive packets are sent at the given interval when the connection is idle.
+    pub fn set_keepalive(&self, interval: Option<Duration>) {
+        let interval = interval.map(|i| u64::try_from(i.as_millis()).unwrap_or(u64::max_value()));
+        self.set_option(ffi::TcpOption::KeepAlive(interval))
+    }
+
+    fn set_option(&self, option: ffi::TcpOption) {
+        unsafe {
+            let tcp_set_option = ffi::TcpMessage::SetOption(ffi::TcpSetOption {
+                socket_id: self.handle,
+                option,
+            });
+
+            redshirt_syscalls_interface::emit_message_without_response(
+                &ffi::INTERFACE,
+                &tcp_set_option,
+            );
         }
     }
+
+    /// Processes the responses to the writes in progress.
+    fn poll_pending_writes(&mut self, cx: &mut Context) -> Result<(), io::Error> {
+        while let Some((len, pending_write)) = self.pending_writes.front_mut() {
+            let result = match Future::poll(Pin::new(pending_write), cx) {
+                Poll::Ready(response) => response.result,
+                Poll::Pending => break,
+            };
+
+            self.pending_writes_bytes -= *len;
+            self.pending_writes.pop_front();
+            result?;
+        }
+
+        Ok(())
+    }
 }
 
 impl AsyncRead for TcpStream {
@@ -84,14 +143,6 @@ impl AsyncRead for TcpStream {
         buf: &mut [u8],
     ) -> Poll<Result<usize, io::Error>> {
         loop {
-            if let Some(pending_read) = self.pending_read.as_mut() {
-                self.read_buffer = match ready!(Future::poll(Pin::new(pending_read), cx)).result {
-                    Ok(d) => d,
-                    Err(_) => return Poll::Ready(Err(io::ErrorKind::Other.into())), // TODO:
-                };
-                self.pending_read = None;
-            }
-
             if !self.read_buffer.is_empty() {
                 let to_copy = cmp::min(self.read_buffer.len(), buf.len());
                 let mut tmp = mem::replace(&mut self.read_buffer, Vec::new());
@@ -100,19 +151,24 @@ impl AsyncRead for TcpStream {
                 return Poll::Ready(Ok(to_copy));
             }
 
-            let tcp_read = ffi::TcpMessage::Read(ffi::TcpRead {
+            if self.read_closed {
+                return Poll::Ready(Ok(0));
+            }
+
+            if let Some(pending_read) = self.pending_read.as_mut() {
+                let result = ready!(Future::poll(Pin::new(pending_read), cx)).result;
+                self.pending_read = None;
+                let data = result?;
+                if data.is_empty() {
+                    self.read_closed = true;
+                }
+                self.read_buffer = data;
+                continue;
+            }
+
+            self.pending_read = Some(emit_with_response(ffi::TcpMessage::Read(ffi::TcpRead {
                 socket_id: self.handle,
-            });
-            let msg_id = unsafe {
-                let msg = tcp_read.encode();
-                redshirt_syscalls_interface::MessageBuilder::new()
-                    .add_data(&msg)
-                    .emit_with_response_raw(&ffi::INTERFACE)
-                    .unwrap()
-            };
-            self.pending_read = Some(Box::pin(redshirt_syscalls_interface::message_response(
-                msg_id,
-            )));
+            })));
         }
     }
 
@@ -125,36 +181,46 @@ impl AsyncWrite for TcpStream {
         cx: &mut Context,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        if let Some(pending_write) = self.pending_write.as_mut() {
-            match ready!(Future::poll(Pin::new(pending_write), cx)).result {
-                Ok(()) => self.pending_write = None,
-                Err(_) => return Poll::Ready(Err(io::ErrorKind::Other.into())), // TODO:
-            }
+        self.poll_pending_writes(cx)?;
+        if self.pending_writes_bytes >= MAX_PENDING_WRITE_BYTES {
+            return Poll::Pending;
         }
 
-        let tcp_write = ffi::TcpMessage::Write(ffi::TcpWrite {
+        let to_write = cmp::min(buf.len(), MAX_PENDING_WRITE_BYTES);
+        let pending_write = emit_with_response(ffi::TcpMessage::Write(ffi::TcpWrite {
             socket_id: self.handle,
-            data: buf.to_vec(),
-        });
-        let msg_id = unsafe {
-            let msg = tcp_write.encode();
-            redshirt_syscalls_interface::MessageBuilder::new()
-                .add_data(&msg)
-                .emit_with_response_raw(&ffi::INTERFACE)
-                .unwrap()
-        };
-        self.pending_write = Some(Box::pin(redshirt_syscalls_interface::message_response(
-            msg_id,
-        )));
-        Poll::Ready(Ok(buf.len()))
+            data: buf[..to_write].to_vec(),
+        }));
+        self.pending_writes.push_back((to_write, pending_write));
+        self.pending_writes_bytes += to_write;
+        Poll::Ready(Ok(to_write))
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), io::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        self.poll_pending_writes(cx)?;
+        if self.pending_writes.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
     }
 
-    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), io::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        // The shutdown is processed after the writes in progress, but we wait for them anyway
+        // in order to report their errors.
+        ready!(AsyncWrite::poll_flush(self.as_mut(), cx))?;
+
+        if self.pending_shutdown.is_none() {
+            self.pending_shutdown = Some(emit_with_response(ffi::TcpMessage::Shutdown(
+                ffi::TcpShutdown {
+                    socket_id: self.handle,
+                },
+            )));
+        }
+
+        let pending_shutdown = self.pending_shutdown.as_mut().unwrap();
+        let result = ready!(Future::poll(Pin::new(pending_shutdown), cx)).result;
+        Poll::Ready(result.map_err(From::from))
     }
 }
 
@@ -202,36 +268,19 @@ pub struct TcpListener {
     handle: u32,
     local_addr: SocketAddr,
     /// If Some, we have sent out an "accept" message and are waiting for a response.
-    // TODO: use strongly typed Future here
-    pending_accept: Option<Pin<Box<dyn Future<Output = ffi::TcpAcceptResponse> + Send>>>,
+    pending_accept: Option<MessageResponseFuture<ffi::TcpAcceptResponse>>,
 }
 
 impl TcpListener {
-    pub fn bind(socket_addr: &SocketAddr) -> impl Future<Output = Result<TcpListener, ()>> {
-        let tcp_listen = ffi::TcpMessage::Listen(match socket_addr {
-            SocketAddr::V4(addr) => ffi::TcpListen {
-                local_ip: addr.ip().to_ipv6_mapped().segments(),
-                port: addr.port(),
-            },
-            SocketAddr::V6(addr) => ffi::TcpListen {
-                local_ip: addr.ip().segments(),
-                port: addr.port(),
-            },
-        });
-
-        let msg_id = unsafe {
-            let msg = tcp_listen.encode();
-            redshirt_syscalls_interface::MessageBuilder::new()
-                .add_data(&msg)
-                .emit_with_response_raw(&ffi::INTERFACE)
-                .unwrap()
-        };
+    pub fn bind(socket_addr: &SocketAddr) -> impl Future<Output = Result<TcpListener, io::Error>> {
+        let (local_ip, port) = addr_to_ffi(socket_addr);
+        let response =
+            emit_with_response(ffi::TcpMessage::Listen(ffi::TcpListen { local_ip, port }));
 
         let mut local_addr = socket_addr.clone();
 
         async move {
-            let message: ffi::TcpListenResponse =
-                redshirt_syscalls_interface::message_response(msg_id).await;
+            let message: ffi::TcpListenResponse = response.await;
             let (handle, local_port) = message.result?;
             local_addr.set_port(local_port);
 
@@ -249,34 +298,20 @@ impl TcpListener {
     }
 
     // TODO: make `&self` instead
-    pub async fn accept(&mut self) -> (TcpStream, SocketAddr) {
+    pub async fn accept(&mut self) -> Result<(TcpStream, SocketAddr), io::Error> {
         loop {
             if let Some(pending_accept) = self.pending_accept.as_mut() {
-                let new_stream = pending_accept.await;
+                let response = pending_accept.await;
                 self.pending_accept = None;
-                let stream = TcpStream {
-                    handle: new_stream.accepted_socket_id,
-                    read_buffer: Vec::new(),
-                    pending_read: None,
-                    pending_write: None,
-                };
-                let remote_ip = Ipv6Addr::from(new_stream.remote_ip);
-                let remote_addr = SocketAddr::from((remote_ip, new_stream.remote_port));
-                return (stream, remote_addr);
+                let stream = TcpStream::from_connection(response.result?);
+                let remote_addr = stream.peer_addr();
+                return Ok((stream, remote_addr));
             }
 
-            let tcp_accept = ffi::TcpMessage::Accept(ffi::TcpAccept {
-                socket_id: self.handle,
-            });
-            let msg_id = unsafe {
-                let msg = tcp_accept.encode();
-                redshirt_syscalls_interface::MessageBuilder::new()
-                    .add_data(&msg)
-                    .emit_with_response_raw(&ffi::INTERFACE)
-                    .unwrap()
-            };
-            self.pending_accept = Some(Box::pin(redshirt_syscalls_interface::message_response(
-                msg_id,
+            self.pending_accept = Some(emit_with_response(ffi::TcpMessage::Accept(
+                ffi::TcpAccept {
+                    socket_id: self.handle,
+                },
             )));
         }
     }
@@ -293,3 +328,45 @@ impl Drop for TcpListener {
         }
     }
 }
+
+impl From<TcpError> for io::Error {
+    fn from(err: TcpError) -> io::Error {
+        let kind = match err {
+            TcpError::ConnectionRefused => io::ErrorKind::ConnectionRefused,
+            TcpError::ConnectionReset => io::ErrorKind::ConnectionReset,
+            TcpError::TimedOut => io::ErrorKind::TimedOut,
+            TcpError::AddrInUse => io::ErrorKind::AddrInUse,
+            TcpError::NetworkUnreachable => io::ErrorKind::Other,
+            TcpError::NotConnected => io::ErrorKind::NotConnected,
+            TcpError::InvalidSocket => io::ErrorKind::InvalidInput,
+        };
+
+        io::Error::new(kind, format!("{:?}", err))
+    }
+}
+
+/// Emits a message on the TCP interface and returns the `Future` of the response.
+fn emit_with_response<T: Decode>(message: ffi::TcpMessage) -> MessageResponseFuture<T> {
+    let msg_id = unsafe {
+        let msg = message.encode();
+        redshirt_syscalls_interface::MessageBuilder::new()
+            .add_data(&msg)
+            .emit_with_response_raw(&ffi::INTERFACE)
+            .unwrap()
+    };
+
+    redshirt_syscalls_interface::message_response(msg_id)
+}
+
+/// Turns a `SocketAddr` into an IPv6 address and a port, as expected by the FFI.
+fn addr_to_ffi(addr: &SocketAddr) -> ([u16; 8], u16) {
+    match addr {
+        SocketAddr::V4(addr) => (addr.ip().to_ipv6_mapped().segments(), addr.port()),
+        SocketAddr::V6(addr) => (addr.ip().segments(), addr.port()),
+    }
+}
+
+/// Turns an address from the FFI into a `SocketAddr`.
+fn addr_from_ffi(ip: [u16; 8], port: u16) -> SocketAddr {
+    SocketAddr::from((Ipv6Addr::from(ip), port))
+}
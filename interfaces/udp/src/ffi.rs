@@ -0,0 +1,87 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use parity_scale_codec::{Decode, Encode};
+use redshirt_syscalls_interface::InterfaceHash;
+
+// TODO: this has been randomly generated; instead should be a hash or something
+pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
+    0x83, 0x21, 0x86, 0x09, 0xd7, 0x05, 0xcc, 0x95, 0x14, 0x16, 0xf5, 0x15, 0x3d, 0x20, 0xb2, 0xf1,
+    0x3f, 0xc0, 0xdc, 0x42, 0x7b, 0xfc, 0x68, 0x84, 0x69, 0x77, 0xa6, 0x33, 0xe4, 0x11, 0x70, 0x52,
+]);
+
+/// IP addresses are always represented as IPv6 addresses. IPv4 addresses are represented as
+/// IPv4-mapped IPv6 addresses.
+#[derive(Debug, Encode, Decode)]
+pub enum UdpMessage {
+    /// Opens a new socket. Must be answered with a [`UdpBindResponse`].
+    Bind(UdpBind),
+    /// Sends a datagram. Must be answered with a [`UdpSendToResponse`] once the datagram has
+    /// been queued for sending.
+    SendTo(UdpSendTo),
+    /// Ask to receive the next datagram. Must be answered with a [`UdpRecvFromResponse`]. For
+    /// each socket, only one receive can exist at any given point in time.
+    RecvFrom(UdpRecvFrom),
+    /// Closes a socket. No response is expected.
+    Close(UdpClose),
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpBind {
+    pub local_ip: [u16; 8],
+    /// Can be 0 for auto-assign.
+    pub port: u16,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpBindResponse {
+    /// On success, the socket ID and the port it's bound to.
+    pub result: Result<(u32, u16), ()>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpSendTo {
+    pub socket_id: u32,
+    pub remote_ip: [u16; 8],
+    pub remote_port: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpSendToResponse {
+    pub result: Result<(), ()>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpRecvFrom {
+    pub socket_id: u32,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpRecvFromResponse {
+    pub result: Result<UdpDatagram, ()>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpDatagram {
+    pub remote_ip: [u16; 8],
+    pub remote_port: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct UdpClose {
+    pub socket_id: u32,
+}
@@ -0,0 +1,137 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! UDP/IP.
+
+#![deny(intra_doc_link_resolution_failure)]
+
+use futures::prelude::*;
+use redshirt_syscalls_interface::Encode as _;
+use std::net::{Ipv6Addr, SocketAddr};
+
+pub mod ffi;
+
+/// Socket that can send and receive datagrams.
+pub struct UdpSocket {
+    handle: u32,
+    local_addr: SocketAddr,
+}
+
+impl UdpSocket {
+    /// Opens a socket bound to the given address. Pass a port of 0 to let the system assign a
+    /// port.
+    pub fn bind(socket_addr: &SocketAddr) -> impl Future<Output = Result<UdpSocket, ()>> {
+        let (local_ip, port) = addr_to_ffi(socket_addr);
+        let udp_bind = ffi::UdpMessage::Bind(ffi::UdpBind { local_ip, port });
+
+        let msg_id = unsafe {
+            let msg = udp_bind.encode();
+            redshirt_syscalls_interface::MessageBuilder::new()
+                .add_data(&msg)
+                .emit_with_response_raw(&ffi::INTERFACE)
+                .unwrap()
+        };
+
+        let mut local_addr = socket_addr.clone();
+
+        async move {
+            let message: ffi::UdpBindResponse =
+                redshirt_syscalls_interface::message_response(msg_id).await;
+            let (handle, local_port) = message.result?;
+            local_addr.set_port(local_port);
+            Ok(UdpSocket { handle, local_addr })
+        }
+    }
+
+    /// Returns the local address of the socket. Useful to determine the port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Sends a datagram to the given target.
+    pub fn send_to(
+        &self,
+        data: &[u8],
+        target: &SocketAddr,
+    ) -> impl Future<Output = Result<(), ()>> {
+        let (remote_ip, remote_port) = addr_to_ffi(target);
+        let udp_send = ffi::UdpMessage::SendTo(ffi::UdpSendTo {
+            socket_id: self.handle,
+            remote_ip,
+            remote_port,
+            data: data.to_vec(),
+        });
+
+        let msg_id = unsafe {
+            let msg = udp_send.encode();
+            redshirt_syscalls_interface::MessageBuilder::new()
+                .add_data(&msg)
+                .emit_with_response_raw(&ffi::INTERFACE)
+                .unwrap()
+        };
+
+        async move {
+            let message: ffi::UdpSendToResponse =
+                redshirt_syscalls_interface::message_response(msg_id).await;
+            message.result
+        }
+    }
+
+    /// Waits for the next datagram. Returns the data and the address of the sender.
+    ///
+    /// Only one call to `recv_from` must be in progress at any given point in time.
+    pub fn recv_from(&self) -> impl Future<Output = Result<(Vec<u8>, SocketAddr), ()>> {
+        let udp_recv = ffi::UdpMessage::RecvFrom(ffi::UdpRecvFrom {
+            socket_id: self.handle,
+        });
+
+        let msg_id = unsafe {
+            let msg = udp_recv.encode();
+            redshirt_syscalls_interface::MessageBuilder::new()
+                .add_data(&msg)
+                .emit_with_response_raw(&ffi::INTERFACE)
+                .unwrap()
+        };
+
+        async move {
+            let message: ffi::UdpRecvFromResponse =
+                redshirt_syscalls_interface::message_response(msg_id).await;
+            let datagram = message.result?;
+            let remote_ip = Ipv6Addr::from(datagram.remote_ip);
+            let remote_addr = SocketAddr::from((remote_ip, datagram.remote_port));
+            Ok((datagram.data, remote_addr))
+        }
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        unsafe {
+            let udp_close = ffi::UdpMessage::Close(ffi::UdpClose {
+                socket_id: self.handle,
+            });
+
+            redshirt_syscalls_interface::emit_message_without_response(&ffi::INTERFACE, &udp_close);
+        }
+    }
+}
+
+/// Turns a `SocketAddr` into an IPv6 address and a port, as expected by the FFI.
+fn addr_to_ffi(addr: &SocketAddr) -> ([u16; 8], u16) {
+    match addr {
+        SocketAddr::V4(addr) => (addr.ip().to_ipv6_mapped().segments(), addr.port()),
+        SocketAddr::V6(addr) => (addr.ip().segments(), addr.port()),
+    }
+}
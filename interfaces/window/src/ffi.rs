@@ -26,17 +26,63 @@ pub const INTERFACE: InterfaceHash = InterfaceHash::from_raw_hash([
 
 #[derive(Debug, Encode, Decode)]
 pub enum WindowMessage {
+    /// Open a new window. Must answer with a [`WindowOpenResponse`].
     Open(WindowOpen),
+    /// Close a window. No answer is expected.
     Close(WindowClose),
-    GetEvents(Vec<WindowEvent>),
+    /// Replace the content of a window with the given framebuffer. Must answer with a
+    /// [`WindowPresentResponse`].
+    Present(WindowPresent),
+    /// Ask for the next events that happened on a window. Must answer with a
+    /// [`WindowGetEventsResponse`] once at least one event is available.
+    GetEvents(WindowGetEvents),
 }
 
 #[derive(Debug, Encode, Decode)]
-pub struct WindowOpen {}
+pub struct WindowOpen {
+    /// Title of the window. Can be ignored by the windowing manager.
+    pub title: String,
+    /// Requested width in pixels. The windowing manager is free to choose another size.
+    pub width: u32,
+    /// Requested height in pixels. The windowing manager is free to choose another size.
+    pub height: u32,
+}
 
 #[derive(Debug, Encode, Decode)]
 pub struct WindowOpenResponse {
-    pub result: Result<u32, ()>,
+    pub result: Result<WindowInfo, ()>,
+}
+
+/// Information about a window that has been opened.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct WindowInfo {
+    /// Identifier of the window, to pass in subsequent messages.
+    pub window_id: u32,
+    /// Actual width of the window in pixels.
+    pub width: u32,
+    /// Actual height of the window in pixels.
+    pub height: u32,
+    /// Layout of the framebuffers to pass to [`WindowMessage::Present`].
+    pub pixel_format: PixelFormat,
+}
+
+/// Layout of a pixel in a framebuffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum PixelFormat {
+    /// Four bytes per pixel: red, green, blue, then alpha.
+    Rgba8,
+    /// Four bytes per pixel: blue, green, red, then alpha.
+    Bgra8,
+}
+
+impl PixelFormat {
+    /// Returns the number of bytes that a single pixel occupies.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::Bgra8 => 4,
+        }
+    }
 }
 
 #[derive(Debug, Encode, Decode)]
@@ -45,9 +91,46 @@ pub struct WindowClose {
 }
 
 #[derive(Debug, Encode, Decode)]
+pub struct WindowPresent {
+    pub window_id: u32,
+    /// Pixels of the window, row by row, starting from the top-left corner. Must contain
+    /// exactly `width * height` pixels in the format indicated in the [`WindowInfo`].
+    pub framebuffer: Vec<u8>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WindowPresentResponse {
+    /// Error if the window doesn't exist or if the framebuffer doesn't have the right size.
+    pub result: Result<(), ()>,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WindowGetEvents {
+    pub window_id: u32,
+}
+
+#[derive(Debug, Encode, Decode)]
+pub struct WindowGetEventsResponse {
+    /// List of events that happened, in chronological order. Never empty if `Ok`.
+    pub result: Result<Vec<WindowEvent>, ()>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub enum WindowEvent {
-    // TODO: it's very hipster to not provide the size of the window, but I don't know how viable that is
-    Resized,
-    // TODO: mouse events
-    // TODO:
+    /// The window has been resized. Subsequent calls to [`WindowMessage::Present`] must use the
+    /// new size.
+    Resized { width: u32, height: u32 },
+    /// The user asked for the window to be closed.
+    CloseRequested,
+    /// A key has been pressed or released.
+    KeyboardInput {
+        /// Platform-specific code of the key.
+        scancode: u32,
+        pressed: bool,
+    },
+    /// The mouse cursor moved within the window. Coordinates are in pixels relative to the
+    /// top-left corner.
+    MouseMoved { x: i32, y: i32 },
+    /// A mouse button has been pressed or released.
+    MouseButton { button: u8, pressed: bool },
 }
@@ -17,33 +17,97 @@
 
 #![deny(intra_doc_link_resolution_failure)]
 
-// TODO: everything here is a draft
-
 pub mod ffi;
 
+pub use self::ffi::{PixelFormat, WindowEvent};
+
+/// Open window. Closes the window on drop.
 pub struct Window {
-    handle: u32,
+    info: ffi::WindowInfo,
 }
 
 impl Window {
-    pub async fn open() -> Result<Window, ()> {
-        let open = ffi::WindowMessage::Open(ffi::WindowOpen {});
+    /// Opens a new window with the given title and requested size.
+    ///
+    /// The actual size of the window might be different from the one requested. Use
+    /// [`Window::width`] and [`Window::height`] to obtain it.
+    pub async fn open(title: &str, width: u32, height: u32) -> Result<Window, ()> {
+        let open = ffi::WindowMessage::Open(ffi::WindowOpen {
+            title: title.to_owned(),
+            width,
+            height,
+        });
         let response: ffi::WindowOpenResponse = unsafe {
             redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, open)
                 .map_err(|_| ())?
                 .await
         };
         Ok(Window {
-            handle: response.result?,
+            info: response.result?,
         })
     }
+
+    /// Returns the current width of the window, in pixels.
+    pub fn width(&self) -> u32 {
+        self.info.width
+    }
+
+    /// Returns the current height of the window, in pixels.
+    pub fn height(&self) -> u32 {
+        self.info.height
+    }
+
+    /// Returns the format of the pixels to pass to [`Window::present`].
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.info.pixel_format
+    }
+
+    /// Replaces the content of the window with the given framebuffer.
+    ///
+    /// The framebuffer must contain `width * height` pixels, row by row, in the format
+    /// returned by [`Window::pixel_format`].
+    pub async fn present(&self, framebuffer: Vec<u8>) -> Result<(), ()> {
+        let present = ffi::WindowMessage::Present(ffi::WindowPresent {
+            window_id: self.info.window_id,
+            framebuffer,
+        });
+        let response: ffi::WindowPresentResponse = unsafe {
+            redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, present)
+                .map_err(|_| ())?
+                .await
+        };
+        response.result
+    }
+
+    /// Waits for the next events that happen on this window.
+    ///
+    /// [`WindowEvent::Resized`] events automatically update the values returned by
+    /// [`Window::width`] and [`Window::height`].
+    pub async fn next_events(&mut self) -> Result<Vec<WindowEvent>, ()> {
+        let get_events = ffi::WindowMessage::GetEvents(ffi::WindowGetEvents {
+            window_id: self.info.window_id,
+        });
+        let response: ffi::WindowGetEventsResponse = unsafe {
+            redshirt_syscalls_interface::emit_message_with_response(&ffi::INTERFACE, get_events)
+                .map_err(|_| ())?
+                .await
+        };
+        let events = response.result?;
+        for event in &events {
+            if let WindowEvent::Resized { width, height } = event {
+                self.info.width = *width;
+                self.info.height = *height;
+            }
+        }
+        Ok(events)
+    }
 }
 
 impl Drop for Window {
     fn drop(&mut self) {
         unsafe {
             let close = ffi::WindowMessage::Close(ffi::WindowClose {
-                window_id: self.handle,
+                window_id: self.info.window_id,
             });
 
             let _ =
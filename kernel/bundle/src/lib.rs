@@ -0,0 +1,223 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Bundle of WASM modules passed to the standalone kernel at boot.
+//!
+//! Rather than embedding modules in the kernel binary, the bootloader loads a bundle in memory
+//! alongside the kernel (as a multiboot2 module on x86_64, or as an initrd on ARM). This makes it
+//! possible to change the set of modules without rebuilding the kernel.
+//!
+//! # Format
+//!
+//! All integers are in little endian.
+//!
+//! - The 8 bytes `RSBNDL01`.
+//! - The number of entries, as a `u32`.
+//! - For each entry:
+//!   - The length of the name of the entry, as a `u32`, followed with the name in UTF-8.
+//!   - Flags, as a `u32`. Bit 0 is set if the module must be started at boot.
+//!   - The length of the module, as a `u32`, followed with the module itself.
+//!
+//! Use a [`BundleBuilder`] to build a bundle, and [`Bundle::parse`] to read it.
+
+#![deny(intra_doc_link_resolution_failure)]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use core::{convert::TryFrom as _, fmt, str};
+
+/// Bytes found at the start of every bundle.
+pub const MAGIC: [u8; 8] = *b"RSBNDL01";
+
+/// Flag set on entries that must be started at boot.
+const FLAG_STARTUP: u32 = 1 << 0;
+
+/// Successfully-parsed bundle.
+#[derive(Debug, Clone)]
+pub struct Bundle<'a> {
+    /// Data following the number of entries.
+    entries: &'a [u8],
+    /// Number of entries in the bundle.
+    num_entries: u32,
+}
+
+/// Entry of a [`Bundle`].
+#[derive(Debug, Clone)]
+pub struct BundleEntry<'a> {
+    /// Name of the module. Informative only.
+    pub name: &'a str,
+    /// True if the module must be started at boot.
+    pub startup: bool,
+    /// The module itself.
+    pub module: &'a [u8],
+}
+
+/// Error that can happen when parsing a bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The data doesn't start with [`MAGIC`].
+    BadMagic,
+    /// The data is shorter than indicated by its content.
+    Truncated,
+    /// The name of an entry isn't valid UTF-8.
+    InvalidName,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::BadMagic => write!(f, "Not a modules bundle"),
+            ParseError::Truncated => write!(f, "Modules bundle is truncated"),
+            ParseError::InvalidName => write!(f, "Invalid module name in bundle"),
+        }
+    }
+}
+
+impl<'a> Bundle<'a> {
+    /// Parses a bundle and checks its validity.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ParseError> {
+        if data.len() < MAGIC.len() || data[..MAGIC.len()] != MAGIC {
+            return Err(ParseError::BadMagic);
+        }
+
+        let mut cursor = &data[MAGIC.len()..];
+        let num_entries = read_u32(&mut cursor)?;
+        let bundle = Bundle {
+            entries: cursor,
+            num_entries,
+        };
+
+        for _ in 0..num_entries {
+            read_entry(&mut cursor)?;
+        }
+
+        Ok(bundle)
+    }
+
+    /// Returns the list of entries in the bundle.
+    pub fn entries(&self) -> impl Iterator<Item = BundleEntry<'a>> {
+        let mut cursor = self.entries;
+        // The entries have been checked in `parse`.
+        (0..self.num_entries).map(move |_| read_entry(&mut cursor).unwrap())
+    }
+}
+
+/// Reads one entry and advances `cursor`.
+fn read_entry<'a>(cursor: &mut &'a [u8]) -> Result<BundleEntry<'a>, ParseError> {
+    let name_len = read_u32(cursor)?;
+    let name = read_bytes(cursor, name_len)?;
+    let name = str::from_utf8(name).map_err(|_| ParseError::InvalidName)?;
+    let flags = read_u32(cursor)?;
+    let module_len = read_u32(cursor)?;
+    let module = read_bytes(cursor, module_len)?;
+
+    Ok(BundleEntry {
+        name,
+        startup: (flags & FLAG_STARTUP) != 0,
+        module,
+    })
+}
+
+fn read_u32(cursor: &mut &[u8]) -> Result<u32, ParseError> {
+    let bytes = read_bytes(cursor, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_bytes<'a>(cursor: &mut &'a [u8], len: u32) -> Result<&'a [u8], ParseError> {
+    let len = usize::try_from(len).map_err(|_| ParseError::Truncated)?;
+    if cursor.len() < len {
+        return Err(ParseError::Truncated);
+    }
+
+    let (bytes, rest) = cursor.split_at(len);
+    *cursor = rest;
+    Ok(bytes)
+}
+
+/// Builds a bundle.
+#[derive(Debug, Default)]
+pub struct BundleBuilder {
+    entries: Vec<(String, bool, Vec<u8>)>,
+}
+
+impl BundleBuilder {
+    /// Initializes a new empty builder.
+    pub fn new() -> Self {
+        BundleBuilder::default()
+    }
+
+    /// Adds a module to the bundle.
+    pub fn add(&mut self, name: impl Into<String>, startup: bool, module: impl Into<Vec<u8>>) {
+        self.entries.push((name.into(), startup, module.into()));
+    }
+
+    /// Turns the builder into the encoded bundle.
+    ///
+    /// # Panic
+    ///
+    /// Panics if a name or module is larger than 4GiB.
+    ///
+    pub fn build(self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&u32::try_from(self.entries.len()).unwrap().to_le_bytes());
+
+        for (name, startup, module) in self.entries {
+            out.extend_from_slice(&u32::try_from(name.len()).unwrap().to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            let flags = if startup { FLAG_STARTUP } else { 0 };
+            out.extend_from_slice(&flags.to_le_bytes());
+            out.extend_from_slice(&u32::try_from(module.len()).unwrap().to_le_bytes());
+            out.extend_from_slice(&module);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bundle, BundleBuilder, ParseError};
+
+    #[test]
+    fn build_and_parse() {
+        let mut builder = BundleBuilder::new();
+        builder.add("foo", true, &b"hello"[..]);
+        builder.add("bar", false, &b""[..]);
+        let encoded = builder.build();
+
+        let bundle = Bundle::parse(&encoded).unwrap();
+        let entries = bundle.entries().collect::<alloc::vec::Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "foo");
+        assert!(entries[0].startup);
+        assert_eq!(entries[0].module, b"hello");
+        assert_eq!(entries[1].name, "bar");
+        assert!(!entries[1].startup);
+        assert!(entries[1].module.is_empty());
+
+        assert_eq!(
+            Bundle::parse(&encoded[..encoded.len() - 1]).unwrap_err(),
+            ParseError::Truncated
+        );
+    }
+
+    #[test]
+    fn bad_magic() {
+        assert_eq!(Bundle::parse(b"hello").unwrap_err(), ParseError::BadMagic);
+    }
+}
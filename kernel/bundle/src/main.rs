@@ -0,0 +1,113 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Builds a bundle of modules to pass to the standalone kernel.
+//!
+//! The content of the bundle is described by a TOML manifest, for example:
+//!
+//! ```toml
+//! [[module]]
+//! path = "../../modules/target/wasm32-unknown-unknown/release/hello-world.wasm"
+//! startup = true
+//! ```
+//!
+//! Paths are relative to the directory containing the manifest.
+
+use serde::Deserialize;
+use std::{fs, path::PathBuf, process};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "redshirt-bundle",
+    about = "Builds a bundle of modules for the standalone kernel."
+)]
+struct CliOptions {
+    /// TOML file describing the modules to put in the bundle.
+    #[structopt(parse(from_os_str))]
+    manifest: PathBuf,
+
+    /// Where to write the bundle.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    module: Vec<ManifestModule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestModule {
+    /// Path to the WASM module.
+    path: PathBuf,
+    /// Name of the module. Defaults to the name of the file.
+    name: Option<String>,
+    /// If true, the module is started when the kernel boots.
+    #[serde(default)]
+    startup: bool,
+}
+
+fn main() {
+    let cli_opts = CliOptions::from_args();
+
+    let manifest: Manifest = match fs::read(&cli_opts.manifest)
+        .map_err(|err| err.to_string())
+        .and_then(|content| toml::from_slice(&content).map_err(|err| err.to_string()))
+    {
+        Ok(m) => m,
+        Err(err) => {
+            eprintln!(
+                "Error while reading {}: {}",
+                cli_opts.manifest.display(),
+                err
+            );
+            process::exit(1);
+        }
+    };
+
+    let base_dir = cli_opts
+        .manifest
+        .parent()
+        .map(|p| p.to_owned())
+        .unwrap_or_default();
+
+    let mut builder = redshirt_bundle::BundleBuilder::new();
+    for module in manifest.module {
+        let path = base_dir.join(&module.path);
+        let data = match fs::read(&path) {
+            Ok(d) => d,
+            Err(err) => {
+                eprintln!("Error while reading {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        };
+
+        let name = module.name.unwrap_or_else(|| {
+            path.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        builder.add(name, module.startup, data);
+    }
+
+    if let Err(err) = fs::write(&cli_opts.output, builder.build()) {
+        eprintln!("Error while writing {}: {}", cli_opts.output.display(), err);
+        process::exit(1);
+    }
+}
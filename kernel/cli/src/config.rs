@@ -0,0 +1,391 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Configuration file describing how to compose a [`System`](redshirt_core::System).
+//!
+//! The configuration is written in TOML. Example:
+//!
+//! ```toml
+//! # Hashes of the programs to load through the `loader` interface after startup.
+//! main-programs = ["8GiGe4Vtkw5yq6eTADn6TiWP7ptp4wjmG6D2K5CtPWEE"]
+//!
+//! # Processes started when the system boots, in order. Paths are relative to the directory
+//! # containing the configuration file.
+//! [[startup-process]]
+//! path = "target/wasm32-wasi/release/p2p-loader.wasm"
+//! # Passed to the `main` function of the program. Optional.
+//! arguments = ["--verbose"]
+//!
+//! # Optional as well.
+//! [startup-process.limits]
+//! # Maximum number of 64kiB pages of memory. The process is killed if it uses more.
+//! max-memory-pages = 1024
+//!
+//! [native-programs]
+//! disk-image = "disk.img"
+//! dns = true
+//! input-script = "input.txt"
+//! stdout = true
+//! time = true
+//! udp = true
+//! window = true
+//! window-dumps = "screenshots"
+//!
+//! # If set, hostnames are resolved using only this table rather than the resolver of the host.
+//! [native-programs.dns-hosts]
+//! "example.com" = ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"]
+//! ```
+
+use redshirt_core::scheduler::ProcessConfig;
+use serde::Deserialize;
+use std::{collections::HashMap, error, fmt, fs, io, net::IpAddr, path::Path, path::PathBuf};
+
+/// Parsed configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Hashes of the programs to load through the `loader` interface, in base58.
+    pub main_programs: Vec<String>,
+    /// Processes to start as part of the startup process.
+    #[serde(rename = "startup-process")]
+    pub startup_processes: Vec<StartupProcess>,
+    /// Which native programs to enable.
+    pub native_programs: NativePrograms,
+}
+
+/// Entry in the list of startup processes.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StartupProcess {
+    /// Path to the WASM file.
+    pub path: PathBuf,
+    /// Arguments passed to the `main` function of the program.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    /// Resources that the process is allowed to use.
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+/// Limits applied to a startup process. No limit is applied by default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Limits {
+    /// Maximum number of 64kiB pages of memory that the process can use.
+    pub max_memory_pages: Option<u32>,
+}
+
+/// Which native programs to enable.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NativePrograms {
+    /// Disk image file to expose as a block device, if any.
+    pub disk_image: Option<PathBuf>,
+    /// Implementation of the `dns` interface, using the resolver of the host.
+    pub dns: bool,
+    /// If set, the `dns` interface resolves hostnames using only this table.
+    pub dns_hosts: Option<HashMap<String, Vec<IpAddr>>>,
+    /// Script describing input events to report through the `input` interface, if any. See the
+    /// `redshirt-input-hosted` crate for the format.
+    pub input_script: Option<PathBuf>,
+    /// Implementation of the `stdout` interface.
+    pub stdout: bool,
+    /// Implementation of the `time` interface.
+    pub time: bool,
+    /// Implementation of the `udp` interface, using the UDP sockets of the host.
+    pub udp: bool,
+    /// Headless implementation of the `window` interface.
+    pub window: bool,
+    /// Directory where to write the content of the windows as PNG files.
+    pub window_dumps: Option<PathBuf>,
+}
+
+/// Error that can happen when loading a configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Error while reading the file.
+    Io(io::Error),
+    /// The file isn't valid TOML or doesn't match the expected format.
+    Parse(toml::de::Error),
+    /// One of the main program hashes is invalid.
+    BadHash(String),
+    /// A directory for the window dumps is set, but the `window` interface is disabled.
+    WindowDumpsWithoutWindow,
+    /// A table of DNS hosts is set, but the `dns` interface is disabled.
+    DnsHostsWithoutDns,
+}
+
+impl Config {
+    /// Loads the configuration from the given file.
+    ///
+    /// Relative paths found in the configuration are turned into paths relative to the
+    /// directory containing the file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let mut config: Config = toml::from_str(&content).map_err(ConfigError::Parse)?;
+
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for process in &mut config.startup_processes {
+            process.path = base.join(&process.path);
+        }
+        if let Some(image) = &mut config.native_programs.disk_image {
+            *image = base.join(&*image);
+        }
+        if let Some(script) = &mut config.native_programs.input_script {
+            *script = base.join(&*script);
+        }
+        if let Some(dumps) = &mut config.native_programs.window_dumps {
+            *dumps = base.join(&*dumps);
+        }
+
+        // Check the hashes right now rather than when building the system.
+        config.main_program_hashes()?;
+        if config.native_programs.window_dumps.is_some() && !config.native_programs.window {
+            return Err(ConfigError::WindowDumpsWithoutWindow);
+        }
+        if config.native_programs.dns_hosts.is_some() && !config.native_programs.dns {
+            return Err(ConfigError::DnsHostsWithoutDns);
+        }
+        Ok(config)
+    }
+
+    /// Returns the list of main programs hashes, decoded.
+    pub fn main_program_hashes(&self) -> Result<Vec<[u8; 32]>, ConfigError> {
+        self.main_programs
+            .iter()
+            .map(|hash| {
+                let decoded = bs58::decode(hash)
+                    .into_vec()
+                    .map_err(|_| ConfigError::BadHash(hash.clone()))?;
+                if decoded.len() != 32 {
+                    return Err(ConfigError::BadHash(hash.clone()));
+                }
+                let mut out = [0; 32];
+                out.copy_from_slice(&decoded);
+                Ok(out)
+            })
+            .collect()
+    }
+}
+
+impl StartupProcess {
+    /// Returns the configuration to pass to the core when starting the process.
+    pub fn process_config(&self) -> ProcessConfig {
+        ProcessConfig {
+            arguments: self.arguments.clone(),
+            max_memory_pages: self.limits.max_memory_pages,
+        }
+    }
+}
+
+impl Default for NativePrograms {
+    fn default() -> Self {
+        NativePrograms {
+            disk_image: None,
+            dns: true,
+            dns_hosts: None,
+            input_script: None,
+            stdout: true,
+            time: true,
+            udp: true,
+            window: true,
+            window_dumps: None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "failed to read configuration: {}", err),
+            ConfigError::Parse(err) => write!(f, "invalid configuration: {}", err),
+            ConfigError::BadHash(hash) => write!(f, "invalid main program hash: {}", hash),
+            ConfigError::WindowDumpsWithoutWindow => {
+                write!(f, "window-dumps can't be set when window is disabled")
+            }
+            ConfigError::DnsHostsWithoutDns => {
+                write!(f, "dns-hosts can't be set when dns is disabled")
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            ConfigError::Parse(err) => Some(err),
+            ConfigError::BadHash(_) => None,
+            ConfigError::WindowDumpsWithoutWindow => None,
+            ConfigError::DnsHostsWithoutDns => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, ConfigError};
+    use std::{
+        env, fs,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        path::Path,
+        process,
+    };
+
+    const EXAMPLE: &str = r#"
+main-programs = ["8GiGe4Vtkw5yq6eTADn6TiWP7ptp4wjmG6D2K5CtPWEE"]
+
+[[startup-process]]
+path = "loader.wasm"
+arguments = ["--verbose", "foo"]
+
+[startup-process.limits]
+max-memory-pages = 1024
+
+[[startup-process]]
+path = "/modules/fs.wasm"
+
+[native-programs]
+disk-image = "disk.img"
+dns = false
+udp = false
+"#;
+
+    #[test]
+    fn defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.main_programs.is_empty());
+        assert!(config.startup_processes.is_empty());
+        assert!(config.native_programs.disk_image.is_none());
+        assert!(config.native_programs.dns);
+        assert!(config.native_programs.dns_hosts.is_none());
+        assert!(config.native_programs.input_script.is_none());
+        assert!(config.native_programs.stdout);
+        assert!(config.native_programs.time);
+        assert!(config.native_programs.udp);
+        assert!(config.native_programs.window);
+        assert!(config.native_programs.window_dumps.is_none());
+    }
+
+    #[test]
+    fn example() {
+        let config: Config = toml::from_str(EXAMPLE).unwrap();
+        assert_eq!(config.main_program_hashes().unwrap().len(), 1);
+
+        assert_eq!(config.startup_processes.len(), 2);
+        let loader = config.startup_processes[0].process_config();
+        assert_eq!(loader.arguments, vec!["--verbose", "foo"]);
+        assert_eq!(loader.max_memory_pages, Some(1024));
+        let fs = config.startup_processes[1].process_config();
+        assert!(fs.arguments.is_empty());
+        assert_eq!(fs.max_memory_pages, None);
+
+        assert_eq!(
+            config.native_programs.disk_image.as_ref().unwrap(),
+            Path::new("disk.img")
+        );
+        assert!(!config.native_programs.dns);
+        assert!(!config.native_programs.udp);
+        assert!(config.native_programs.stdout);
+    }
+
+    #[test]
+    fn dns_hosts() {
+        let content = r#"
+[native-programs.dns-hosts]
+"example.com" = ["10.0.0.1", "::1"]
+"empty.example" = []
+"#;
+        let config: Config = toml::from_str(content).unwrap();
+        let hosts = config.native_programs.dns_hosts.unwrap();
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(
+            hosts["example.com"],
+            vec![
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            ]
+        );
+        assert!(hosts["empty.example"].is_empty());
+
+        let invalid = "[native-programs.dns-hosts]\n\"example.com\" = [\"foo\"]";
+        assert!(toml::from_str::<Config>(invalid).is_err());
+    }
+
+    #[test]
+    fn startup_process_requires_path() {
+        assert!(toml::from_str::<Config>("[[startup-process]]\narguments = []").is_err());
+    }
+
+    #[test]
+    fn unknown_fields_rejected() {
+        for content in &[
+            "foo = 5",
+            "[[startup-process]]\npath = \"a.wasm\"\nfoo = 5",
+            "[[startup-process]]\npath = \"a.wasm\"\n[startup-process.limits]\nfoo = 5",
+            "[native-programs]\nfoo = true",
+        ] {
+            assert!(toml::from_str::<Config>(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn load_joins_paths() {
+        let dir = env::temp_dir().join(format!("redshirt-config-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+
+        fs::write(&path, EXAMPLE).unwrap();
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.startup_processes[0].path, dir.join("loader.wasm"));
+        assert_eq!(
+            config.startup_processes[1].path,
+            Path::new("/modules/fs.wasm")
+        );
+        assert_eq!(
+            config.native_programs.disk_image.unwrap(),
+            dir.join("disk.img")
+        );
+
+        fs::write(&path, "main-programs = [\"foo\"]").unwrap();
+        match Config::load(&path) {
+            Err(ConfigError::BadHash(hash)) => assert_eq!(hash, "foo"),
+            _ => panic!(),
+        }
+
+        fs::write(
+            &path,
+            "[native-programs]\nwindow = false\nwindow-dumps = \"dumps\"",
+        )
+        .unwrap();
+        match Config::load(&path) {
+            Err(ConfigError::WindowDumpsWithoutWindow) => {}
+            _ => panic!(),
+        }
+
+        fs::write(
+            &path,
+            "[native-programs]\ndns = false\n[native-programs.dns-hosts]\nfoo = []",
+        )
+        .unwrap();
+        match Config::load(&path) {
+            Err(ConfigError::DnsHostsWithoutDns) => {}
+            _ => panic!(),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
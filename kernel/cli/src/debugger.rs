@@ -0,0 +1,235 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Interactive debugger reading commands from the standard input.
+//!
+//! Commands are read line by line on a separate thread, then executed against the
+//! [`System`] in between two runs. Type `help` to get the list of commands.
+
+use futures::channel::mpsc;
+use redshirt_core::{Pid, System};
+use std::{
+    io::{self, BufRead as _},
+    thread,
+};
+
+/// Text printed in response to the `help` command.
+const HELP: &str = "\
+Available commands:
+  ps                          List the running processes.
+  threads <pid>               List the threads of a process.
+  pause <pid>                 Pause a process.
+  continue <pid>              Resume a paused process.
+  step <pid>                  Resume a process until it enters the next function.
+  break <pid> <function>      Pause a process when it enters the given function index.
+  delete <pid> <function>     Remove a breakpoint.
+  read <pid> <offset> <len>   Print a range of the memory of a process.
+  write <pid> <offset> <hex>  Write bytes, in hexadecimal, in the memory of a process.
+Breakpoints and stepping only work for programs started with --debug.";
+
+/// Command entered by the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Help,
+    Ps,
+    Threads(Pid),
+    Pause(Pid),
+    Continue(Pid),
+    Step(Pid),
+    Break(Pid, u32),
+    Delete(Pid, u32),
+    Read(Pid, u32, u32),
+    Write(Pid, u32, Vec<u8>),
+}
+
+/// Spawns a background thread that reads lines from the standard input and sends them on the
+/// returned channel. The channel is closed when the standard input is closed.
+pub fn spawn_stdin_reader() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break,
+            };
+            if tx.unbounded_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Parses the given line and executes the corresponding command, printing the result.
+pub fn execute(system: &mut System, line: &str) {
+    let command = match parse(line) {
+        Ok(Some(c)) => c,
+        Ok(None) => return,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+
+    let result = match command {
+        Command::Help => {
+            eprintln!("{}", HELP);
+            Ok(())
+        }
+        Command::Ps => {
+            for pid in system.pids() {
+                eprintln!("{}", u64::from(pid));
+            }
+            Ok(())
+        }
+        Command::Threads(pid) => match system.process_threads(pid) {
+            Some(threads) => {
+                for (thread_id, state) in threads {
+                    eprintln!("{} {:?}", u64::from(thread_id), state);
+                }
+                Ok(())
+            }
+            None => Err(()),
+        },
+        Command::Pause(pid) => system.pause_process(pid),
+        Command::Continue(pid) => system.resume_process(pid),
+        Command::Step(pid) => system.step_process(pid),
+        Command::Break(pid, function) => system.add_breakpoint(pid, function),
+        Command::Delete(pid, function) => system.remove_breakpoint(pid, function),
+        Command::Read(pid, offset, len) => system.read_memory(pid, offset, len).map(|data| {
+            for (n, chunk) in data.chunks(16).enumerate() {
+                let hex = chunk
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                eprintln!("{:08x}  {}", offset as usize + n * 16, hex);
+            }
+        }),
+        Command::Write(pid, offset, data) => system.write_memory(pid, offset, &data),
+    };
+
+    if result.is_err() {
+        eprintln!("Error: no such process or invalid memory range");
+    }
+}
+
+/// Parses a line entered by the user. Returns `Ok(None)` if the line is empty.
+fn parse(line: &str) -> Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let command = match words.next() {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+
+    let args = words.collect::<Vec<_>>();
+    let expect_args = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(format!(
+                "`{}` expects {} argument(s); type `help`",
+                command, n
+            ))
+        }
+    };
+
+    let command = match command {
+        "help" => {
+            expect_args(0)?;
+            Command::Help
+        }
+        "ps" => {
+            expect_args(0)?;
+            Command::Ps
+        }
+        "threads" => {
+            expect_args(1)?;
+            Command::Threads(parse_pid(args[0])?)
+        }
+        "pause" => {
+            expect_args(1)?;
+            Command::Pause(parse_pid(args[0])?)
+        }
+        "continue" => {
+            expect_args(1)?;
+            Command::Continue(parse_pid(args[0])?)
+        }
+        "step" => {
+            expect_args(1)?;
+            Command::Step(parse_pid(args[0])?)
+        }
+        "break" => {
+            expect_args(2)?;
+            Command::Break(parse_pid(args[0])?, parse_number(args[1])?)
+        }
+        "delete" => {
+            expect_args(2)?;
+            Command::Delete(parse_pid(args[0])?, parse_number(args[1])?)
+        }
+        "read" => {
+            expect_args(3)?;
+            Command::Read(
+                parse_pid(args[0])?,
+                parse_number(args[1])?,
+                parse_number(args[2])?,
+            )
+        }
+        "write" => {
+            expect_args(3)?;
+            Command::Write(
+                parse_pid(args[0])?,
+                parse_number(args[1])?,
+                parse_hex(args[2])?,
+            )
+        }
+        other => return Err(format!("Unknown command `{}`; type `help`", other)),
+    };
+
+    Ok(Some(command))
+}
+
+fn parse_pid(word: &str) -> Result<Pid, String> {
+    let word = word.trim_start_matches('#');
+    word.parse::<u64>()
+        .map(Pid::from)
+        .map_err(|_| format!("Invalid process id: {}", word))
+}
+
+/// Parses a number, either in decimal or in hexadecimal with a `0x` prefix.
+fn parse_number(word: &str) -> Result<u32, String> {
+    let result = if word.starts_with("0x") {
+        u32::from_str_radix(&word[2..], 16)
+    } else {
+        word.parse::<u32>()
+    };
+
+    result.map_err(|_| format!("Invalid number: {}", word))
+}
+
+fn parse_hex(word: &str) -> Result<Vec<u8>, String> {
+    if !word.is_ascii() || word.len() % 2 != 0 {
+        return Err(format!("Invalid hexadecimal data: {}", word));
+    }
+
+    (0..word.len())
+        .step_by(2)
+        .map(|n| {
+            u8::from_str_radix(&word[n..n + 2], 16)
+                .map_err(|_| format!("Invalid hexadecimal data: {}", word))
+        })
+        .collect()
+}
@@ -15,15 +15,51 @@
 
 #![deny(intra_doc_link_resolution_failure)]
 
+use futures::prelude::*;
+use redshirt_core::module::{Module, ModuleCache};
 use std::{fs, path::PathBuf, process};
 use structopt::StructOpt;
 
+mod config;
+mod debugger;
+mod module_store;
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "redshirt-cli", about = "Redshirt modules executor.")]
 struct CliOptions {
-    /// WASM file to run.
+    /// WASM file to run. The executor stops when this program ends.
     #[structopt(parse(from_os_str))]
-    wasm_file: PathBuf,
+    wasm_file: Option<PathBuf>,
+
+    /// TOML file describing the startup processes, main programs and native programs to use.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Instrument the WASM programs read from the disk so that breakpoints can be set on their
+    /// functions, and read debugger commands from the standard input. Type `help` for the list
+    /// of commands.
+    #[structopt(long)]
+    debug: bool,
+
+    /// Directory where to write the content of the windows opened by programs, as PNG files.
+    /// Overrides the value in the configuration file, if any.
+    #[structopt(long, parse(from_os_str))]
+    window_dumps: Option<PathBuf>,
+
+    /// Disk image file to expose to the programs as a block device. Overrides the value in the
+    /// configuration file, if any.
+    #[structopt(long, parse(from_os_str))]
+    disk_image: Option<PathBuf>,
+
+    /// Script describing keyboard and mouse events to inject through the `input` interface.
+    /// Overrides the value in the configuration file, if any.
+    #[structopt(long, parse(from_os_str))]
+    input_script: Option<PathBuf>,
+
+    /// Directory where to store the WASM programs that have been loaded, so that they don't
+    /// need to be loaded again the next time. Ignored in debug mode.
+    #[structopt(long, parse(from_os_str))]
+    module_cache: Option<PathBuf>,
 }
 
 fn main() {
@@ -31,25 +67,192 @@ fn main() {
 }
 
 async fn async_main() {
-    let cli_requested_process = {
-        let cli_opts = CliOptions::from_args();
-        let wasm_file_content = fs::read(cli_opts.wasm_file).expect("failed to read input file");
-        redshirt_core::module::Module::from_bytes(&wasm_file_content)
-            .expect("failed to parse input file")
+    let cli_opts = CliOptions::from_args();
+
+    let config = match &cli_opts.config {
+        Some(path) => match config::Config::load(path) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        },
+        None => config::Config::default(),
     };
 
-    let mut system = redshirt_core::system::SystemBuilder::new()
-        .with_native_program(redshirt_time_hosted::TimerHandler::new())
-        .with_native_program(redshirt_stdout_hosted::StdoutHandler::new())
-        .build();
+    if cli_opts.wasm_file.is_none() && cli_opts.config.is_none() {
+        eprintln!("Either a WASM file or a configuration file must be passed");
+        process::exit(1);
+    }
 
-    let cli_pid = system.execute(&cli_requested_process);
+    if cli_opts.window_dumps.is_some() && !config.native_programs.window {
+        eprintln!("--window-dumps can't be passed when the window interface is disabled");
+        process::exit(1);
+    }
+
+    // Instrumented modules have the same hash as the original ones, and therefore must not be
+    // put in the cache.
+    let mut module_cache = match (&cli_opts.module_cache, cli_opts.debug) {
+        (Some(dir), false) => ModuleCache::with_store(module_store::DirectoryStore::new(dir)),
+        _ => ModuleCache::new(),
+    };
+
+    let debug = cli_opts.debug;
+    let mut parse_module = |bytes: &[u8]| {
+        if debug {
+            Module::from_bytes_with_breakpoints(bytes)
+        } else {
+            module_cache.get_or_parse(bytes)
+        }
+    };
+
+    let cli_requested_process = cli_opts.wasm_file.as_ref().map(|wasm_file| {
+        let wasm_file_content = fs::read(wasm_file).expect("failed to read input file");
+        parse_module(&wasm_file_content).expect("failed to parse input file")
+    });
+
+    let mut system_builder = redshirt_core::system::SystemBuilder::new();
+
+    if let Some(path) = cli_opts
+        .disk_image
+        .as_ref()
+        .or(config.native_programs.disk_image.as_ref())
+    {
+        match redshirt_block_hosted::BlockDeviceHandler::open(path, false) {
+            Ok(handler) => system_builder = system_builder.with_native_program(handler),
+            Err(err) => {
+                eprintln!("Failed to open {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = cli_opts
+        .input_script
+        .as_ref()
+        .or(config.native_programs.input_script.as_ref())
+    {
+        let script = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        };
+        match redshirt_input_hosted::ScriptedInput::from_script(&script) {
+            Ok(handler) => system_builder = system_builder.with_native_program(handler),
+            Err(err) => {
+                eprintln!("Invalid input script {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+    if config.native_programs.dns {
+        let handler = redshirt_dns_hosted::DnsHandler::new();
+        let handler = match config.native_programs.dns_hosts.clone() {
+            Some(hosts) => handler.with_static_hosts(hosts),
+            None => handler,
+        };
+        system_builder = system_builder.with_native_program(handler);
+    }
+    if config.native_programs.time {
+        system_builder =
+            system_builder.with_native_program(redshirt_time_hosted::TimerHandler::new());
+    }
+    if config.native_programs.stdout {
+        system_builder =
+            system_builder.with_native_program(redshirt_stdout_hosted::StdoutHandler::new());
+    }
+    if config.native_programs.udp {
+        system_builder = system_builder.with_native_program(redshirt_udp_hosted::UdpHandler::new());
+    }
+    if config.native_programs.window {
+        let handler = redshirt_window_hosted::WindowHandler::new();
+        let handler = match cli_opts
+            .window_dumps
+            .or(config.native_programs.window_dumps.clone())
+        {
+            Some(dir) => handler.with_dump_directory(dir),
+            None => handler,
+        };
+        system_builder = system_builder.with_native_program(handler);
+    }
+
+    for startup_process in &config.startup_processes {
+        let content = match fs::read(&startup_process.path) {
+            Ok(c) => c,
+            Err(err) => {
+                eprintln!("Failed to read {}: {}", startup_process.path.display(), err);
+                process::exit(1);
+            }
+        };
+        match parse_module(&content) {
+            Ok(module) => {
+                system_builder = system_builder
+                    .with_startup_process_config(module, startup_process.process_config())
+            }
+            Err(err) => {
+                eprintln!(
+                    "Failed to parse {}: {}",
+                    startup_process.path.display(),
+                    err
+                );
+                process::exit(1);
+            }
+        }
+    }
+
+    // Hashes have already been checked when loading the configuration.
+    for hash in config.main_program_hashes().unwrap() {
+        system_builder = system_builder.with_main_program(hash);
+    }
+
+    if !cli_opts.debug {
+        system_builder = system_builder.with_module_cache(module_cache);
+    }
+
+    let mut system = system_builder.build();
+
+    let cli_pid = cli_requested_process
+        .as_ref()
+        .map(|module| system.execute(module));
+
+    let mut debugger_commands = if cli_opts.debug {
+        Some(debugger::spawn_stdin_reader())
+    } else {
+        None
+    };
 
     loop {
-        let outcome = system.run().await;
+        let outcome = match &mut debugger_commands {
+            Some(commands) => {
+                let event = {
+                    let run = system.run();
+                    futures::pin_mut!(run);
+                    match future::select(run, commands.next()).await {
+                        future::Either::Left((outcome, _)) => Ok(outcome),
+                        future::Either::Right((command, _)) => Err(command),
+                    }
+                };
+
+                match event {
+                    Ok(outcome) => outcome,
+                    Err(Some(command)) => {
+                        debugger::execute(&mut system, &command);
+                        continue;
+                    }
+                    Err(None) => {
+                        // Standard input has been closed.
+                        debugger_commands = None;
+                        continue;
+                    }
+                }
+            }
+            None => system.run().await,
+        };
+
         match outcome {
             redshirt_core::system::SystemRunOutcome::ProgramFinished { pid, outcome }
-                if pid == cli_pid =>
+                if Some(pid) == cli_pid =>
             {
                 process::exit(match outcome {
                     Ok(_) => 0,
@@ -59,7 +262,23 @@ async fn async_main() {
                     }
                 });
             }
-            _ => panic!(),
+            redshirt_core::system::SystemRunOutcome::ProgramFinished { pid, outcome } => {
+                if let Err(err) = outcome {
+                    eprintln!("{:?} has crashed: {:?}", pid, err);
+                }
+            }
+            redshirt_core::system::SystemRunOutcome::Breakpoint {
+                pid,
+                thread_id,
+                function_index,
+            } => {
+                eprintln!(
+                    "Process {} paused: thread {} entered function {}",
+                    u64::from(pid),
+                    u64::from(thread_id),
+                    function_index
+                );
+            }
         }
     }
 }
@@ -0,0 +1,56 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Persistent storage of modules in a directory of the file system.
+
+use redshirt_core::module::{ModuleHash, ModuleStore};
+use std::{fs, path::PathBuf};
+
+/// Implementation of [`ModuleStore`] that stores each module in a `<hash>.wasm` file.
+pub struct DirectoryStore {
+    directory: PathBuf,
+}
+
+impl DirectoryStore {
+    /// Initializes a store in the given directory. The directory is created when the first
+    /// module gets stored.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        DirectoryStore {
+            directory: directory.into(),
+        }
+    }
+
+    fn path_of(&self, hash: &ModuleHash) -> PathBuf {
+        self.directory.join(format!("{}.wasm", hash))
+    }
+}
+
+impl ModuleStore for DirectoryStore {
+    fn load(&mut self, hash: &ModuleHash) -> Option<Vec<u8>> {
+        fs::read(self.path_of(hash)).ok()
+    }
+
+    fn store(&mut self, hash: &ModuleHash, bytes: &[u8]) {
+        if let Err(err) = fs::create_dir_all(&self.directory) {
+            eprintln!("Failed to create {}: {}", self.directory.display(), err);
+            return;
+        }
+
+        let path = self.path_of(hash);
+        if let Err(err) = fs::write(&path, bytes) {
+            eprintln!("Failed to write {}: {}", path.display(), err);
+        }
+    }
+}
@@ -0,0 +1,810 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Parsing of flattened device trees.
+//!
+//! On ARM platforms, the bootloader passes to the kernel a *device tree* describing the hardware:
+//! where the RAM is located, which peripherals are present and where their registers are mapped,
+//! and so on. The device tree is passed in a binary format named *flattened device tree* (FDT),
+//! or *device tree blob* (DTB).
+//!
+//! The device tree consists of a hierarchy of nodes, each of them having a name and a list of
+//! properties. The value of a property is an opaque array of bytes, whose meaning depends on the
+//! property. Integers are always in big endian.
+//!
+//! Use [`DeviceTree::parse`] to parse a device tree, then [`DeviceTree::root`] or
+//! [`DeviceTree::nodes`] to walk through it.
+//!
+//! See also https://www.devicetree.org/specifications/
+
+#![deny(intra_doc_link_resolution_failure)]
+#![no_std]
+
+use core::{convert::TryFrom as _, fmt, str};
+
+/// Value of the first four bytes of a device tree.
+pub const MAGIC: u32 = 0xd00d_feed;
+
+/// Size of the header of a device tree.
+const HEADER_LEN: usize = 40;
+
+/// Maximum depth of the nodes that [`Nodes`] can walk through.
+const MAX_DEPTH: usize = 16;
+
+/// Tokens of the structure block.
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Successfully-parsed device tree.
+#[derive(Debug, Copy, Clone)]
+pub struct DeviceTree<'a> {
+    /// The entire device tree, including its header.
+    data: &'a [u8],
+    /// Structure block, containing the nodes and their properties.
+    structure: &'a [u8],
+    /// Strings block, containing the names of the properties.
+    strings: &'a [u8],
+    /// Memory reservation block.
+    reservations: &'a [u8],
+}
+
+/// Error that can happen when parsing a device tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The data doesn't start with [`MAGIC`].
+    BadMagic,
+    /// The data is shorter than indicated by its header.
+    Truncated,
+    /// The version of the format isn't supported.
+    UnsupportedVersion,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::BadMagic => write!(f, "Data isn't a device tree"),
+            ParseError::Truncated => write!(f, "Device tree is truncated"),
+            ParseError::UnsupportedVersion => write!(f, "Unsupported device tree version"),
+        }
+    }
+}
+
+impl<'a> DeviceTree<'a> {
+    /// Parses the header of the given device tree.
+    ///
+    /// The structure of the tree itself is only checked while walking through it. Malformed
+    /// nodes and properties are skipped.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ParseError> {
+        if data.len() < HEADER_LEN {
+            return Err(ParseError::Truncated);
+        }
+        if read_u32(data, 0) != Some(MAGIC) {
+            return Err(ParseError::BadMagic);
+        }
+
+        let field = |n: usize| usize::try_from(read_u32(data, 4 * n).unwrap()).unwrap();
+        let total_size = field(1);
+        let off_dt_struct = field(2);
+        let off_dt_strings = field(3);
+        let off_mem_rsvmap = field(4);
+        let last_comp_version = field(6);
+        let size_dt_strings = field(8);
+        let size_dt_struct = field(9);
+
+        // Version 17 is the current one, and is compatible with version 16.
+        if last_comp_version > 17 {
+            return Err(ParseError::UnsupportedVersion);
+        }
+
+        let data = data.get(..total_size).ok_or(ParseError::Truncated)?;
+        let block = |offset: usize, len: usize| {
+            offset
+                .checked_add(len)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(ParseError::Truncated)
+        };
+
+        Ok(DeviceTree {
+            data,
+            structure: block(off_dt_struct, size_dt_struct)?,
+            strings: block(off_dt_strings, size_dt_strings)?,
+            reservations: data.get(off_mem_rsvmap..).ok_or(ParseError::Truncated)?,
+        })
+    }
+
+    /// Parses the device tree found at the given memory location.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to readable memory, at least as long as the size indicated in the
+    /// header if the header is valid.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, ParseError> {
+        let header = core::slice::from_raw_parts(ptr, HEADER_LEN);
+        if read_u32(header, 0) != Some(MAGIC) {
+            return Err(ParseError::BadMagic);
+        }
+
+        let total_size = usize::try_from(read_u32(header, 4).unwrap()).unwrap();
+        DeviceTree::parse(core::slice::from_raw_parts(ptr, total_size.max(HEADER_LEN)))
+    }
+
+    /// Returns the entire device tree, in its binary format.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Returns the root node of the tree.
+    pub fn root(&self) -> Node<'a> {
+        let mut offset = skip_nops(self.structure, 0);
+        if read_u32(self.structure, offset) == Some(FDT_BEGIN_NODE) {
+            offset += 4;
+        }
+        let (name, body) = read_node_name(self.structure, offset).unwrap_or(("", offset));
+        Node {
+            tree: *self,
+            name,
+            body,
+            address_cells: 2,
+            size_cells: 1,
+        }
+    }
+
+    /// Returns an iterator to all the nodes of the tree, in depth-first order, starting with the
+    /// root.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            tree: *self,
+            offset: 0,
+            depth: 0,
+            cells: [(2, 1); MAX_DEPTH + 1],
+        }
+    }
+
+    /// Returns an iterator to all the nodes compatible with the given value. See
+    /// [`Node::is_compatible`].
+    pub fn compatible_nodes<'b>(&self, compatible: &'b str) -> impl Iterator<Item = Node<'a>> + 'b
+    where
+        'a: 'b,
+    {
+        self.nodes().filter(move |n| n.is_compatible(compatible))
+    }
+
+    /// Returns the node with the given path, such as `/chosen` or `/soc/serial@7e201000`.
+    ///
+    /// The unit address (the part after `@`) can be omitted from the path, in which case the
+    /// first node with a matching name is returned.
+    pub fn node_by_path(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = self.root();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| {
+                child.name() == component || child.name().split('@').next() == Some(component)
+            })?;
+        }
+        Some(node)
+    }
+
+    /// Returns the list of memory ranges that must not be used by the kernel, as found in the
+    /// memory reservation block. Each item is an address and a size.
+    pub fn memory_reservations(&self) -> impl Iterator<Item = (u64, u64)> + 'a {
+        let reservations = self.reservations;
+        (0..)
+            .map(move |n| {
+                (
+                    read_u64(reservations, 16 * n),
+                    read_u64(reservations, 16 * n + 8),
+                )
+            })
+            .take_while(|(address, size)| match (address, size) {
+                (Some(a), Some(s)) => *a != 0 || *s != 0,
+                _ => false,
+            })
+            .map(|(address, size)| (address.unwrap(), size.unwrap()))
+    }
+
+    /// Translates an address found in the `reg` property of the given node into a physical
+    /// address, by going through the `ranges` property of all the parents of the node.
+    ///
+    /// Returns `None` if the address can't be translated, for example because one of the parents
+    /// doesn't have a `ranges` property, meaning that its children aren't memory-mapped.
+    pub fn translate_address(&self, node: &Node<'a>, address: u64) -> Option<u64> {
+        // Find the parents of the node, where `parents[n]` is the parent at depth `n`.
+        let mut parents: [Option<Node<'a>>; MAX_DEPTH] = [None; MAX_DEPTH];
+        let mut nodes = self.nodes();
+        let depth = loop {
+            let candidate = nodes.next()?;
+            let depth = nodes.depth - 1;
+            if candidate.body == node.body {
+                break depth;
+            }
+            parents[depth] = Some(candidate);
+        };
+
+        // The addresses of the children of the root node are physical addresses. The `ranges`
+        // property of the root node, if any, is therefore ignored.
+        let mut address = address;
+        for parent in parents.get(1..depth).unwrap_or(&[]).iter().rev() {
+            let parent = parent.as_ref()?;
+            if parent.property("ranges")?.is_empty() {
+                continue;
+            }
+
+            address = parent
+                .ranges()
+                .find(|(child, _, size)| *child <= address && address - *child < *size)
+                .map(|(child, parent, _)| address - child + parent)?;
+        }
+
+        Some(address)
+    }
+
+    /// Returns the name of the property whose name is at the given offset within the strings
+    /// block.
+    fn string(&self, offset: usize) -> Option<&'a str> {
+        let bytes = self.strings.get(offset..)?;
+        let len = bytes.iter().position(|b| *b == 0)?;
+        str::from_utf8(&bytes[..len]).ok()
+    }
+}
+
+/// Node of a [`DeviceTree`].
+#[derive(Debug, Copy, Clone)]
+pub struct Node<'a> {
+    tree: DeviceTree<'a>,
+    /// Name of the node, including its unit address.
+    name: &'a str,
+    /// Offset within the structure block of the first token after the node's name.
+    body: usize,
+    /// Value of the `#address-cells` property of the parent node.
+    address_cells: u32,
+    /// Value of the `#size-cells` property of the parent node.
+    size_cells: u32,
+}
+
+impl<'a> Node<'a> {
+    /// Returns the name of the node, including its unit address. For example `serial@7e201000`.
+    /// The name of the root node is empty.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns the value of the property with the given name, if any.
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        self.properties().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+
+    /// Returns the value of the property with the given name, interpreted as a `u32` or a
+    /// `u64` depending on its length.
+    pub fn property_int(&self, name: &str) -> Option<u64> {
+        let value = self.property(name)?;
+        match value.len() {
+            4 => read_u32(value, 0).map(u64::from),
+            8 => read_u64(value, 0),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the property with the given name, interpreted as a string.
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        let value = self.property(name)?;
+        let value = value.split(|b| *b == 0).next()?;
+        str::from_utf8(value).ok()
+    }
+
+    /// Returns the list of properties of this node, as names and values.
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            tree: self.tree,
+            offset: self.body,
+        }
+    }
+
+    /// Returns the list of direct children of this node.
+    pub fn children(&self) -> Children<'a> {
+        let mut properties = self.properties();
+        while properties.next().is_some() {}
+
+        Children {
+            tree: self.tree,
+            offset: properties.offset,
+            address_cells: self.child_address_cells(),
+            size_cells: self.child_size_cells(),
+        }
+    }
+
+    /// Returns true if the `compatible` property of the node contains the given value. For
+    /// example `arm,pl011`.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        match self.property("compatible") {
+            Some(list) => list
+                .split(|b| *b == 0)
+                .any(|entry| entry == compatible.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// Returns the list of addresses and sizes found in the `reg` property of the node.
+    ///
+    /// The addresses are relative to the address space of the parent node. In practice, this
+    /// is the same as the physical address space if the parent node doesn't have a `ranges`
+    /// property, or if this property is empty.
+    pub fn reg(&self) -> Reg<'a> {
+        Reg {
+            data: self.property("reg").unwrap_or(&[]),
+            address_cells: self.address_cells,
+            size_cells: self.size_cells,
+        }
+    }
+
+    /// Returns the list of translations found in the `ranges` property of the node. Each item is
+    /// an address in the address space of the children of this node, the corresponding address
+    /// in the address space of the parent of this node, and a size.
+    ///
+    /// An empty list means either that the property is missing or that it is empty. In the
+    /// latter case, the address space of the children is the same as the one of the parent.
+    pub fn ranges(&self) -> Ranges<'a> {
+        Ranges {
+            data: self.property("ranges").unwrap_or(&[]),
+            child_address_cells: self.child_address_cells(),
+            parent_address_cells: self.address_cells,
+            size_cells: self.child_size_cells(),
+        }
+    }
+
+    /// Returns the number of 32 bits cells used to encode addresses in the `reg` property of
+    /// the children of this node.
+    pub fn child_address_cells(&self) -> u32 {
+        self.property("#address-cells")
+            .and_then(|v| read_u32(v, 0))
+            .unwrap_or(2)
+    }
+
+    /// Returns the number of 32 bits cells used to encode sizes in the `reg` property of the
+    /// children of this node.
+    pub fn child_size_cells(&self) -> u32 {
+        self.property("#size-cells")
+            .and_then(|v| read_u32(v, 0))
+            .unwrap_or(1)
+    }
+}
+
+/// Iterator to the properties of a [`Node`].
+#[derive(Debug, Clone)]
+pub struct Properties<'a> {
+    tree: DeviceTree<'a>,
+    /// Offset within the structure block of the next token.
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = (&'a str, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let structure = self.tree.structure;
+            self.offset = skip_nops(structure, self.offset);
+            if read_u32(structure, self.offset) != Some(FDT_PROP) {
+                return None;
+            }
+
+            let len = usize::try_from(read_u32(structure, self.offset + 4)?).ok()?;
+            let name_offset = usize::try_from(read_u32(structure, self.offset + 8)?).ok()?;
+            let value_start = self.offset + 12;
+            let value = structure.get(value_start..value_start.checked_add(len)?)?;
+            self.offset = align4(value_start + len);
+
+            // Properties whose name is invalid are skipped.
+            if let Some(name) = self.tree.string(name_offset) {
+                return Some((name, value));
+            }
+        }
+    }
+}
+
+/// Iterator to the children of a [`Node`].
+#[derive(Debug, Clone)]
+pub struct Children<'a> {
+    tree: DeviceTree<'a>,
+    /// Offset within the structure block of the next token.
+    offset: usize,
+    /// Value of the `#address-cells` property of the parent.
+    address_cells: u32,
+    /// Value of the `#size-cells` property of the parent.
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structure = self.tree.structure;
+        self.offset = skip_nops(structure, self.offset);
+        if read_u32(structure, self.offset) != Some(FDT_BEGIN_NODE) {
+            return None;
+        }
+
+        let (name, body) = read_node_name(structure, self.offset + 4)?;
+        let node = Node {
+            tree: self.tree,
+            name,
+            body,
+            address_cells: self.address_cells,
+            size_cells: self.size_cells,
+        };
+
+        // Skip the entire subtree of the node in order to reach its next sibling.
+        let mut depth = 0u32;
+        let mut offset = body;
+        loop {
+            match read_u32(structure, offset)? {
+                FDT_BEGIN_NODE => {
+                    depth += 1;
+                    offset = read_node_name(structure, offset + 4)?.1;
+                }
+                FDT_END_NODE if depth == 0 => {
+                    offset += 4;
+                    break;
+                }
+                FDT_END_NODE => {
+                    depth -= 1;
+                    offset += 4;
+                }
+                FDT_PROP => {
+                    let len = usize::try_from(read_u32(structure, offset + 4)?).ok()?;
+                    offset = align4(offset.checked_add(12)?.checked_add(len)?);
+                }
+                FDT_NOP => offset += 4,
+                _ => return None,
+            }
+        }
+
+        self.offset = offset;
+        Some(node)
+    }
+}
+
+/// Iterator to all the nodes of a [`DeviceTree`]. See [`DeviceTree::nodes`].
+#[derive(Debug, Clone)]
+pub struct Nodes<'a> {
+    tree: DeviceTree<'a>,
+    /// Offset within the structure block of the next token.
+    offset: usize,
+    /// Depth of the next node.
+    depth: usize,
+    /// For each depth, the `#address-cells` and `#size-cells` that apply to the nodes at this
+    /// depth.
+    cells: [(u32, u32); MAX_DEPTH + 1],
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structure = self.tree.structure;
+        loop {
+            match read_u32(structure, self.offset)? {
+                FDT_BEGIN_NODE => {
+                    if self.depth >= MAX_DEPTH {
+                        return None;
+                    }
+
+                    let (name, body) = read_node_name(structure, self.offset + 4)?;
+                    let (address_cells, size_cells) = self.cells[self.depth];
+                    let node = Node {
+                        tree: self.tree,
+                        name,
+                        body,
+                        address_cells,
+                        size_cells,
+                    };
+
+                    // Skip the properties, and prepare for the children.
+                    let mut properties = node.properties();
+                    while properties.next().is_some() {}
+                    self.offset = properties.offset;
+                    self.depth += 1;
+                    self.cells[self.depth] = (node.child_address_cells(), node.child_size_cells());
+                    return Some(node);
+                }
+                FDT_END_NODE => {
+                    self.depth = self.depth.checked_sub(1)?;
+                    self.offset += 4;
+                }
+                FDT_NOP => self.offset += 4,
+                FDT_END => return None,
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Iterator to the content of the `reg` property of a [`Node`]. Yields addresses and sizes.
+#[derive(Debug, Clone)]
+pub struct Reg<'a> {
+    /// Remaining data of the property.
+    data: &'a [u8],
+    /// Number of 32 bits cells of each address.
+    address_cells: u32,
+    /// Number of 32 bits cells of each size.
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Reg<'a> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let address_len = usize::try_from(self.address_cells).ok()? * 4;
+        let size_len = usize::try_from(self.size_cells).ok()? * 4;
+        if self.data.len() < address_len + size_len {
+            return None;
+        }
+
+        let address = read_cells(&self.data[..address_len])?;
+        let size = read_cells(&self.data[address_len..address_len + size_len])?;
+        self.data = &self.data[address_len + size_len..];
+        Some((address, size))
+    }
+}
+
+/// Iterator to the content of the `ranges` property of a [`Node`]. Yields child addresses,
+/// parent addresses, and sizes.
+#[derive(Debug, Clone)]
+pub struct Ranges<'a> {
+    /// Remaining data of the property.
+    data: &'a [u8],
+    /// Number of 32 bits cells of each child address.
+    child_address_cells: u32,
+    /// Number of 32 bits cells of each parent address.
+    parent_address_cells: u32,
+    /// Number of 32 bits cells of each size.
+    size_cells: u32,
+}
+
+impl<'a> Iterator for Ranges<'a> {
+    type Item = (u64, u64, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let child_len = usize::try_from(self.child_address_cells).ok()? * 4;
+        let parent_len = usize::try_from(self.parent_address_cells).ok()? * 4;
+        let size_len = usize::try_from(self.size_cells).ok()? * 4;
+        if self.data.len() < child_len + parent_len + size_len {
+            return None;
+        }
+
+        let child = read_cells(&self.data[..child_len])?;
+        let parent = read_cells(&self.data[child_len..child_len + parent_len])?;
+        let size =
+            read_cells(&self.data[child_len + parent_len..child_len + parent_len + size_len])?;
+        self.data = &self.data[child_len + parent_len + size_len..];
+        Some((child, parent, size))
+    }
+}
+
+/// Reads a big endian number made of one or two cells. Returns `None` if it's larger.
+fn read_cells(data: &[u8]) -> Option<u64> {
+    match data.len() {
+        0 => Some(0),
+        4 => read_u32(data, 0).map(u64::from),
+        8 => read_u64(data, 0),
+        _ => None,
+    }
+}
+
+/// Reads the name of a node starting at `offset`, and returns it along with the offset of the
+/// token that follows.
+fn read_node_name(structure: &[u8], offset: usize) -> Option<(&str, usize)> {
+    let bytes = structure.get(offset..)?;
+    let len = bytes.iter().position(|b| *b == 0)?;
+    let name = str::from_utf8(&bytes[..len]).ok()?;
+    Some((name, align4(offset + len + 1)))
+}
+
+/// Returns the offset of the first token at or after `offset` that isn't a `FDT_NOP`.
+fn skip_nops(structure: &[u8], mut offset: usize) -> usize {
+    while read_u32(structure, offset) == Some(FDT_NOP) {
+        offset += 4;
+    }
+    offset
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(<[u8; 4]>::try_from(bytes).unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_be_bytes(<[u8; 8]>::try_from(bytes).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::{DeviceTree, ParseError};
+    use alloc::vec::Vec;
+
+    /// Builds a device tree equivalent to:
+    ///
+    /// ```notrust
+    /// / {
+    ///     #address-cells = <1>;
+    ///     #size-cells = <1>;
+    ///     memory@0 {
+    ///         device_type = "memory";
+    ///         reg = <0x0 0x1000 0x2000 0x1000>;
+    ///     };
+    ///     soc {
+    ///         #address-cells = <1>;
+    ///         #size-cells = <1>;
+    ///         ranges = <0x7e000000 0x3f000000 0x1000000>;
+    ///         serial@7e201000 {
+    ///             compatible = "brcm,bcm2835-pl011", "arm,pl011";
+    ///             reg = <0x7e201000 0x200>;
+    ///         };
+    ///     };
+    /// };
+    /// ```
+    fn build() -> Vec<u8> {
+        let mut strings = Vec::new();
+        let mut structure = Vec::new();
+
+        let begin_node = |s: &mut Vec<u8>, name: &str| {
+            s.extend_from_slice(&1u32.to_be_bytes());
+            s.extend_from_slice(name.as_bytes());
+            s.push(0);
+            while s.len() % 4 != 0 {
+                s.push(0);
+            }
+        };
+        let end_node = |s: &mut Vec<u8>| s.extend_from_slice(&2u32.to_be_bytes());
+        let mut prop = |s: &mut Vec<u8>, name: &str, value: &[u8]| {
+            let name_offset = strings.len() as u32;
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+            s.extend_from_slice(&3u32.to_be_bytes());
+            s.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            s.extend_from_slice(&name_offset.to_be_bytes());
+            s.extend_from_slice(value);
+            while s.len() % 4 != 0 {
+                s.push(0);
+            }
+        };
+        let cells = |values: &[u32]| {
+            values
+                .iter()
+                .flat_map(|v| v.to_be_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        begin_node(&mut structure, "");
+        prop(&mut structure, "#address-cells", &cells(&[1]));
+        prop(&mut structure, "#size-cells", &cells(&[1]));
+        begin_node(&mut structure, "memory@0");
+        prop(&mut structure, "device_type", b"memory\0");
+        prop(
+            &mut structure,
+            "reg",
+            &cells(&[0x0, 0x1000, 0x2000, 0x1000]),
+        );
+        end_node(&mut structure);
+        begin_node(&mut structure, "soc");
+        prop(&mut structure, "#address-cells", &cells(&[1]));
+        prop(&mut structure, "#size-cells", &cells(&[1]));
+        prop(
+            &mut structure,
+            "ranges",
+            &cells(&[0x7e00_0000, 0x3f00_0000, 0x100_0000]),
+        );
+        begin_node(&mut structure, "serial@7e201000");
+        prop(
+            &mut structure,
+            "compatible",
+            b"brcm,bcm2835-pl011\0arm,pl011\0",
+        );
+        prop(&mut structure, "reg", &cells(&[0x7e20_1000, 0x200]));
+        end_node(&mut structure);
+        end_node(&mut structure);
+        end_node(&mut structure);
+        structure.extend_from_slice(&9u32.to_be_bytes());
+
+        // Header, followed with a memory reservation block containing one entry, then the
+        // structure and strings blocks.
+        let off_mem_rsvmap = 40;
+        let off_dt_struct = off_mem_rsvmap + 32;
+        let off_dt_strings = off_dt_struct + structure.len();
+        let total_size = off_dt_strings + strings.len();
+
+        let mut out = Vec::new();
+        for field in &[
+            super::MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17,
+            16,
+            0,
+            strings.len() as u32,
+            structure.len() as u32,
+        ] {
+            out.extend_from_slice(&field.to_be_bytes());
+        }
+        out.extend_from_slice(&0x8000u64.to_be_bytes());
+        out.extend_from_slice(&0x100u64.to_be_bytes());
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&structure);
+        out.extend_from_slice(&strings);
+        out
+    }
+
+    #[test]
+    fn walk() {
+        let data = build();
+        let tree = DeviceTree::parse(&data).unwrap();
+
+        let names = tree.nodes().map(|n| n.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["", "memory@0", "soc", "serial@7e201000"]);
+
+        let memory = tree.node_by_path("/memory").unwrap();
+        assert_eq!(memory.property_str("device_type"), Some("memory"));
+        assert_eq!(
+            memory.reg().collect::<Vec<_>>(),
+            [(0x0, 0x1000), (0x2000, 0x1000)]
+        );
+
+        let serial = tree.compatible_nodes("arm,pl011").next().unwrap();
+        assert_eq!(serial.name(), "serial@7e201000");
+        assert_eq!(serial.reg().collect::<Vec<_>>(), [(0x7e20_1000, 0x200)]);
+        assert_eq!(
+            tree.translate_address(&serial, 0x7e20_1000),
+            Some(0x3f20_1000)
+        );
+        assert_eq!(tree.translate_address(&serial, 0x1000), None);
+        assert_eq!(tree.translate_address(&memory, 0x2000), Some(0x2000));
+        assert_eq!(
+            tree.node_by_path("/soc")
+                .unwrap()
+                .ranges()
+                .collect::<Vec<_>>(),
+            [(0x7e00_0000, 0x3f00_0000, 0x100_0000)]
+        );
+        assert_eq!(tree.root().ranges().next(), None);
+
+        assert_eq!(
+            tree.memory_reservations().collect::<Vec<_>>(),
+            [(0x8000, 0x100)]
+        );
+    }
+
+    #[test]
+    fn bad_data() {
+        let data = build();
+        assert_eq!(
+            DeviceTree::parse(&data[..data.len() - 1]).unwrap_err(),
+            ParseError::Truncated
+        );
+        assert_eq!(
+            DeviceTree::parse(&[0; 64]).unwrap_err(),
+            ParseError::BadMagic
+        );
+    }
+}
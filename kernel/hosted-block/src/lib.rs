@@ -0,0 +1,331 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Block device backed by a disk image file.
+//!
+//! Registers a block device towards the handler of the `block` interface, and executes the
+//! commands of the handler by reading and writing the file.
+
+use futures::{channel::mpsc, lock::Mutex, prelude::*};
+use redshirt_block_interface::ffi::{BlockCommand, BlockCommandKind, BlockMessage, INTERFACE};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use std::{
+    convert::TryFrom as _,
+    fs,
+    io::{self, Read as _, Seek as _, SeekFrom, Write as _},
+    path::Path,
+    pin::Pin,
+    sync::{atomic, Mutex as SyncMutex},
+};
+
+/// Size of a sector of the device. Unrelated to the block size of the host.
+const SECTOR_SIZE: u32 = 512;
+
+/// Identifier of our device. We only ever register one.
+const DEVICE_ID: u64 = 0;
+
+/// Native program that exposes a disk image file as a block device.
+pub struct BlockDeviceHandler {
+    /// File containing the data of the device.
+    file: SyncMutex<fs::File>,
+    /// Number of sectors of the device.
+    num_sectors: u64,
+    /// If true, the file can't be written to.
+    read_only: bool,
+    /// If true, we have sent the device registration message.
+    registered: atomic::AtomicBool,
+    /// If true, we have asked the handler for the next command and are waiting for the answer.
+    ///
+    /// Only one such message is in flight at any given time, which is why we don't need to
+    /// keep track of its identifier.
+    command_requested: atomic::AtomicBool,
+    /// Sending side of [`BlockDeviceHandler::finished_rx`].
+    finished_tx: mpsc::UnboundedSender<EncodedMessage>,
+    /// Messages to emit to report that commands have finished executing.
+    finished_rx: Mutex<mpsc::UnboundedReceiver<EncodedMessage>>,
+}
+
+impl BlockDeviceHandler {
+    /// Opens the given disk image. The file is opened in read-only mode if `read_only` is true.
+    ///
+    /// Data past the last multiple of the sector size is ignored.
+    pub fn open(path: impl AsRef<Path>, read_only: bool) -> Result<Self, io::Error> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(path)?;
+        let num_sectors = file.metadata()?.len() / u64::from(SECTOR_SIZE);
+        let (finished_tx, finished_rx) = mpsc::unbounded();
+
+        Ok(BlockDeviceHandler {
+            file: SyncMutex::new(file),
+            num_sectors,
+            read_only,
+            registered: atomic::AtomicBool::new(false),
+            command_requested: atomic::AtomicBool::new(false),
+            finished_tx,
+            finished_rx: Mutex::new(finished_rx),
+        })
+    }
+
+    /// Executes the given command on the file.
+    fn execute(&self, command: BlockCommandKind) -> Result<Vec<u8>, ()> {
+        let mut file = self.file.lock().unwrap();
+
+        match command {
+            BlockCommandKind::Read {
+                sector,
+                num_sectors,
+            } => {
+                if sector.saturating_add(u64::from(num_sectors)) > self.num_sectors {
+                    return Err(());
+                }
+
+                let len = num_sectors.checked_mul(SECTOR_SIZE).ok_or(())?;
+                let len = usize::try_from(len).map_err(|_| ())?;
+                let mut data = vec![0; len];
+                file.seek(SeekFrom::Start(sector * u64::from(SECTOR_SIZE)))
+                    .map_err(|_| ())?;
+                file.read_exact(&mut data).map_err(|_| ())?;
+                Ok(data)
+            }
+            BlockCommandKind::Write { sector, data } => {
+                let num_sectors = u64::try_from(data.len()).unwrap() / u64::from(SECTOR_SIZE);
+                if self.read_only
+                    || data.len() % SECTOR_SIZE as usize != 0
+                    || sector.saturating_add(num_sectors) > self.num_sectors
+                {
+                    return Err(());
+                }
+
+                file.seek(SeekFrom::Start(sector * u64::from(SECTOR_SIZE)))
+                    .map_err(|_| ())?;
+                file.write_all(&data).map_err(|_| ())?;
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a BlockDeviceHandler {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: INTERFACE,
+                    message_id_write: None,
+                    message: BlockMessage::RegisterDevice {
+                        id: DEVICE_ID,
+                        sector_size: SECTOR_SIZE,
+                        num_sectors: self.num_sectors,
+                        read_only: self.read_only,
+                    }
+                    .encode(),
+                };
+            }
+
+            // Commands that have finished must be reported before asking for the next one.
+            let mut finished_rx = self.finished_rx.lock().await;
+            if let Ok(Some(message)) = finished_rx.try_next() {
+                return NativeProgramEvent::Emit {
+                    interface: INTERFACE,
+                    message_id_write: None,
+                    message,
+                };
+            }
+
+            if !self.command_requested.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: INTERFACE,
+                    message_id_write: Some(DummyMessageIdWrite),
+                    message: BlockMessage::NextCommand(DEVICE_ID).encode(),
+                };
+            }
+
+            match finished_rx.next().await {
+                Some(message) => NativeProgramEvent::Emit {
+                    interface: INTERFACE,
+                    message_id_write: None,
+                    message,
+                },
+                None => unreachable!(),
+            }
+        })
+    }
+
+    fn interface_message(self, _: InterfaceHash, _: Option<MessageId>, _: Pid, _: EncodedMessage) {
+        unreachable!()
+    }
+
+    fn process_destroyed(self, _: Pid) {}
+
+    fn message_response(self, _: MessageId, response: Result<EncodedMessage, ()>) {
+        let command = match response.map(BlockCommand::decode) {
+            Ok(Ok(command)) => command,
+            // TODO: the handler has rejected our message or sent an invalid answer; we just
+            //       stop asking for commands
+            _ => return,
+        };
+
+        let result = self.execute(command.kind);
+        let message = BlockMessage::CommandFinished {
+            id: DEVICE_ID,
+            command_id: command.command_id,
+            result,
+        }
+        .encode();
+
+        self.finished_tx.unbounded_send(message).unwrap();
+        self.command_requested
+            .store(false, atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockDeviceHandler, DEVICE_ID};
+    use futures::executor::block_on;
+    use redshirt_block_interface::ffi::{BlockCommand, BlockCommandKind, BlockMessage, INTERFACE};
+    use redshirt_core::native::{NativeProgramEvent, NativeProgramRef as _};
+    use redshirt_core::{Decode as _, Encode as _, MessageId};
+    use std::{env, fs, path::PathBuf, process};
+
+    /// Writes a disk image with the given content in a temporary file, and returns its path.
+    fn image(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("redshirt-block-{}-{}", process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn read(sector: u64, num_sectors: u32) -> BlockCommandKind {
+        BlockCommandKind::Read {
+            sector,
+            num_sectors,
+        }
+    }
+
+    /// Returns the next message emitted by the handler, and whether it expects an answer.
+    fn next_message(handler: &BlockDeviceHandler) -> (BlockMessage, bool) {
+        match block_on(handler.next_event()) {
+            NativeProgramEvent::Emit {
+                interface,
+                message_id_write,
+                message,
+            } => {
+                assert_eq!(interface, INTERFACE);
+                let message = BlockMessage::decode(message).unwrap();
+                (message, message_id_write.is_some())
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn read_and_write() {
+        // Data past the last sector is ignored.
+        let content = (0..1100).map(|n| n as u8).collect::<Vec<_>>();
+        let path = image("read-and-write", &content);
+        let handler = BlockDeviceHandler::open(&path, false).unwrap();
+        assert_eq!(handler.num_sectors, 2);
+
+        assert_eq!(handler.execute(read(1, 1)), Ok(content[512..1024].to_vec()));
+        assert!(handler.execute(read(1, 2)).is_err());
+        assert!(handler.execute(read(0, u32::max_value())).is_err());
+        assert!(handler.execute(read(u64::max_value(), 1)).is_err());
+
+        let write = |sector, data| BlockCommandKind::Write { sector, data };
+        assert_eq!(handler.execute(write(1, vec![7; 512])), Ok(Vec::new()));
+        assert_eq!(handler.execute(read(1, 1)), Ok(vec![7; 512]));
+        // Not a multiple of the sector size.
+        assert!(handler.execute(write(0, vec![7; 100])).is_err());
+        assert!(handler.execute(write(2, vec![7; 512])).is_err());
+
+        drop(handler);
+        assert_eq!(fs::read(&path).unwrap()[1024..], content[1024..]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_only() {
+        let path = image("read-only", &[1; 1024]);
+        let handler = BlockDeviceHandler::open(&path, true).unwrap();
+
+        let write = BlockCommandKind::Write {
+            sector: 0,
+            data: vec![7; 512],
+        };
+        assert!(handler.execute(write).is_err());
+        assert_eq!(handler.execute(read(0, 1)), Ok(vec![1; 512]));
+
+        drop(handler);
+        assert_eq!(fs::read(&path).unwrap(), vec![1; 1024]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn commands_loop() {
+        let path = image("commands-loop", &[3; 2048]);
+        let handler = BlockDeviceHandler::open(&path, true).unwrap();
+
+        match next_message(&handler) {
+            (
+                BlockMessage::RegisterDevice {
+                    id: DEVICE_ID,
+                    sector_size: 512,
+                    num_sectors: 4,
+                    read_only: true,
+                },
+                false,
+            ) => {}
+            _ => panic!(),
+        }
+
+        for command_id in 0..2 {
+            match next_message(&handler) {
+                (BlockMessage::NextCommand(DEVICE_ID), true) => {}
+                _ => panic!(),
+            }
+
+            let command = BlockCommand {
+                command_id,
+                kind: read(command_id, 2),
+            };
+            (&handler).message_response(MessageId::from(1), Ok(command.encode()));
+
+            match next_message(&handler) {
+                (
+                    BlockMessage::CommandFinished {
+                        id: DEVICE_ID,
+                        command_id: id,
+                        result: Ok(data),
+                    },
+                    false,
+                ) => {
+                    assert_eq!(id, command_id);
+                    assert_eq!(data, vec![3; 1024]);
+                }
+                _ => panic!(),
+            }
+        }
+
+        drop(handler);
+        fs::remove_file(&path).unwrap();
+    }
+}
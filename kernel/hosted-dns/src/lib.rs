@@ -0,0 +1,236 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the dns interface.
+//!
+//! Hostnames are resolved either using the resolver of the host, or using a static table of
+//! hosts.
+
+use async_std::net::ToSocketAddrs as _;
+use futures::{channel::mpsc, lock::Mutex, prelude::*, stream::FuturesUnordered};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use redshirt_dns_interface::ffi::{DnsMessage, ResolveResponse, INTERFACE};
+use std::{collections::HashMap, net::IpAddr, pin::Pin, sync::atomic, sync::Arc};
+
+/// State machine for `dns` interface messages handling.
+pub struct DnsHandler {
+    /// If true, we have sent the interface registration message.
+    registered: atomic::AtomicBool,
+    /// If `Some`, hostnames are looked up in this table rather than with the host's resolver.
+    hosts: Option<Arc<HashMap<String, Vec<IpAddr>>>>,
+    /// Accessed only by `next_event`.
+    inner: Mutex<DnsHandlerInner>,
+    /// Send on this channel the received interface messages.
+    messages_tx: mpsc::UnboundedSender<(String, MessageId)>,
+}
+
+/// Separate struct behind a mutex.
+struct DnsHandlerInner {
+    /// Resolutions in progress.
+    resolving: FuturesUnordered<Pin<Box<dyn Future<Output = (MessageId, ResolveResponse)> + Send>>>,
+    /// Receiving side of [`DnsHandler::messages_tx`].
+    messages_rx: mpsc::UnboundedReceiver<(String, MessageId)>,
+}
+
+impl DnsHandler {
+    /// Initializes a new state machine that resolves hostnames using the host's resolver.
+    pub fn new() -> Self {
+        let (messages_tx, messages_rx) = mpsc::unbounded();
+
+        DnsHandler {
+            registered: atomic::AtomicBool::new(false),
+            hosts: None,
+            inner: Mutex::new(DnsHandlerInner {
+                resolving: {
+                    let resolving = FuturesUnordered::<
+                        Pin<Box<dyn Future<Output = (MessageId, ResolveResponse)> + Send>>,
+                    >::new();
+                    // TODO: ugh; pushing a never-ending future, otherwise we get a permanent `None` when polling
+                    resolving.push(Box::pin(async move {
+                        loop {
+                            futures::pending!()
+                        }
+                    }));
+                    resolving
+                },
+                messages_rx,
+            }),
+            messages_tx,
+        }
+    }
+
+    /// Resolves hostnames using only the given table instead of the host's resolver. Hostnames
+    /// that aren't in the table fail to resolve.
+    pub fn with_static_hosts(mut self, hosts: HashMap<String, Vec<IpAddr>>) -> Self {
+        self.hosts = Some(Arc::new(hosts));
+        self
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a DnsHandler {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        INTERFACE,
+                    )
+                    .encode(),
+                };
+            }
+
+            let mut inner = self.inner.lock().await;
+            let inner = &mut *inner;
+
+            loop {
+                match future::select(inner.resolving.next(), inner.messages_rx.next()).await {
+                    future::Either::Left((Some((message_id, response)), _)) => {
+                        return NativeProgramEvent::Answer {
+                            message_id,
+                            answer: Ok(response.encode()),
+                        };
+                    }
+                    future::Either::Right((Some((hostname, message_id)), _)) => {
+                        let hosts = self.hosts.clone();
+                        inner.resolving.push(Box::pin(async move {
+                            let result = match hosts {
+                                Some(hosts) => hosts.get(&hostname).cloned().ok_or(()),
+                                None => resolve(&hostname).await,
+                            };
+                            let result = result
+                                .and_then(
+                                    |addrs| if addrs.is_empty() { Err(()) } else { Ok(addrs) },
+                                )
+                                .map(|addrs| addrs.into_iter().map(ip_to_ffi).collect());
+                            (message_id, ResolveResponse { result })
+                        }));
+                    }
+                    future::Either::Left((None, _)) => unreachable!(),
+                    future::Either::Right((None, _)) => unreachable!(),
+                }
+            }
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        _emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        debug_assert_eq!(interface, INTERFACE);
+
+        match (DnsMessage::decode(message), message_id) {
+            (Ok(DnsMessage::Resolve(hostname)), Some(message_id)) => {
+                self.messages_tx
+                    .unbounded_send((hostname, message_id))
+                    .unwrap();
+            }
+            _ => {}
+        }
+    }
+
+    fn process_destroyed(self, _: Pid) {}
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+/// Resolves a hostname using the host's resolver.
+async fn resolve(hostname: &str) -> Result<Vec<IpAddr>, ()> {
+    let addrs = (hostname, 0).to_socket_addrs().await.map_err(|_| ())?;
+    let mut out = Vec::new();
+    for addr in addrs {
+        if !out.contains(&addr.ip()) {
+            out.push(addr.ip());
+        }
+    }
+    Ok(out)
+}
+
+fn ip_to_ffi(ip: IpAddr) -> [u16; 8] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().segments(),
+        IpAddr::V6(ip) => ip.segments(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DnsHandler;
+    use futures::executor::block_on;
+    use redshirt_core::native::{NativeProgramEvent, NativeProgramRef as _};
+    use redshirt_core::{Decode as _, Encode as _, MessageId, Pid};
+    use redshirt_dns_interface::ffi::{DnsMessage, ResolveResponse, INTERFACE};
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    };
+
+    /// Asks the handler to resolve the given hostname, and returns its answer.
+    fn resolve(handler: &DnsHandler, hostname: &str) -> Result<Vec<[u16; 8]>, ()> {
+        let message = DnsMessage::Resolve(hostname.to_owned()).encode();
+        handler.interface_message(INTERFACE, Some(MessageId::from(1)), Pid::from(1), message);
+
+        loop {
+            match block_on(handler.next_event()) {
+                // Registration of the interface.
+                NativeProgramEvent::Emit { .. } => {}
+                NativeProgramEvent::Answer {
+                    message_id,
+                    answer: Ok(answer),
+                } => {
+                    assert_eq!(message_id, MessageId::from(1));
+                    return ResolveResponse::decode(answer).unwrap().result;
+                }
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn static_hosts() {
+        let mut hosts = HashMap::new();
+        hosts.insert(
+            "example.com".to_owned(),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ],
+        );
+        hosts.insert("empty.example".to_owned(), Vec::new());
+        let handler = DnsHandler::new().with_static_hosts(hosts);
+
+        assert_eq!(
+            resolve(&handler, "example.com"),
+            Ok(vec![
+                [0, 0, 0, 0, 0, 0xffff, 0x0a00, 1],
+                [0, 0, 0, 0, 0, 0, 0, 1]
+            ])
+        );
+        assert!(resolve(&handler, "empty.example").is_err());
+        // Hostnames missing from the table aren't resolved with the host's resolver.
+        assert!(resolve(&handler, "localhost").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Injects scripted input events.
+//!
+//! Registers a keyboard and a pointer towards the handler of the `input` interface, and reports
+//! the events described by a script. This is useful in order to test the programs that handle
+//! the user's input without an actual keyboard or mouse. See the [`script`] module for the
+//! format of the script.
+
+pub use self::script::ParseError;
+
+use futures::{lock::Mutex, prelude::*};
+use futures_timer::Delay;
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use redshirt_input_interface::ffi::{DeviceKind, InputMessage, INTERFACE};
+use std::{collections::VecDeque, pin::Pin, time::Duration};
+
+pub mod script;
+
+/// Native program that reports the input events described by a script.
+pub struct ScriptedInput {
+    /// Accessed only by `next_event`.
+    inner: Mutex<ScriptedInputInner>,
+}
+
+/// Separate struct behind a mutex.
+struct ScriptedInputInner {
+    /// What remains to be done, in order.
+    actions: VecDeque<Action>,
+    /// If the first element of `actions` is an [`Action::Wait`], the timer of the wait. Set when
+    /// we start waiting.
+    ///
+    /// The timer is kept here rather than in the future returned by `next_event`, as this future
+    /// might be destroyed before the wait is over, which would cancel the timer.
+    delay: Option<Delay>,
+}
+
+enum Action {
+    Emit(InputMessage),
+    Wait(Duration),
+}
+
+impl ScriptedInput {
+    /// Parses the given script. See the [`script`] module for the format.
+    ///
+    /// The events start being reported when the system runs.
+    pub fn from_script(script: &str) -> Result<Self, ParseError> {
+        let registrations = [
+            (script::KEYBOARD_ID, DeviceKind::Keyboard),
+            (script::POINTER_ID, DeviceKind::Pointer),
+        ];
+
+        let actions = registrations
+            .iter()
+            .map(|(id, kind)| {
+                Action::Emit(InputMessage::RegisterDevice {
+                    id: *id,
+                    kind: *kind,
+                })
+            })
+            .chain(script::parse(script)?.into_iter().map(|step| match step {
+                script::Step::Wait(duration) => Action::Wait(duration),
+                script::Step::Event { device, event } => {
+                    Action::Emit(InputMessage::Event { id: device, event })
+                }
+            }))
+            .collect();
+
+        Ok(ScriptedInput {
+            inner: Mutex::new(ScriptedInputInner {
+                actions,
+                delay: None,
+            }),
+        })
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a ScriptedInput {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            let mut inner = self.inner.lock().await;
+
+            loop {
+                let duration = match inner.actions.front() {
+                    Some(Action::Wait(duration)) => *duration,
+                    Some(Action::Emit(_)) => match inner.actions.pop_front() {
+                        Some(Action::Emit(message)) => {
+                            return NativeProgramEvent::Emit {
+                                interface: INTERFACE,
+                                message_id_write: None,
+                                message: message.encode(),
+                            };
+                        }
+                        _ => unreachable!(),
+                    },
+                    // The script is over. The devices stay registered.
+                    None => future::pending().await,
+                };
+
+                // If this future is destroyed before the wait is over, the next call to
+                // `next_event` resumes waiting on the same timer.
+                inner
+                    .delay
+                    .get_or_insert_with(|| Delay::new(duration))
+                    .await;
+
+                inner.delay = None;
+                inner.actions.pop_front();
+            }
+        })
+    }
+
+    fn interface_message(self, _: InterfaceHash, _: Option<MessageId>, _: Pid, _: EncodedMessage) {
+        unreachable!()
+    }
+
+    fn process_destroyed(self, _: Pid) {}
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptedInput;
+    use futures::{prelude::*, task};
+    use redshirt_core::native::NativeProgramRef as _;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn wait_survives_dropped_future() {
+        let input = ScriptedInput::from_script("wait 20\nkey a").unwrap();
+
+        // The keyboard and the pointer registrations.
+        futures::executor::block_on(async {
+            (&input).next_event().await;
+            (&input).next_event().await;
+        });
+
+        struct Flag(AtomicBool);
+        impl task::ArcWake for Flag {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        // The system polls the future returned by `next_event` once, then destroys it.
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = task::waker(flag.clone());
+        let mut cx = task::Context::from_waker(&waker);
+        assert!((&input).next_event().poll_unpin(&mut cx).is_pending());
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(flag.0.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,430 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Scripts describing the input to inject.
+//!
+//! A script is a text file where each line is a command. Empty lines and lines starting with
+//! `#` are ignored. The following commands are supported:
+//!
+//! - `wait <milliseconds>` waits for the given amount of time before executing the next command.
+//! - `press <key>` and `release <key>` press or release a key of the keyboard. `key <key>`
+//!   presses then immediately releases a key. Keys are designated either by the character they
+//!   produce on a US QWERTY keyboard (for example `a` or `/`), or by a name such as `enter`,
+//!   `left-shift` or `f1`. See [`KEY_NAMES`] for the list of names.
+//! - `type <text>` presses and releases the keys necessary to type the rest of the line,
+//!   including the shift key when necessary.
+//! - `move <dx> <dy>` moves the pointer by the given amount.
+//! - `move-to <x> <y>` moves the pointer to the given absolute position, where `x` and `y` are
+//!   between `0.0` and `1.0`.
+//! - `button-press <button>` and `button-release <button>` press or release a button of the
+//!   pointer. `click <button>` presses then immediately releases a button. Buttons are `left`,
+//!   `right`, `middle`, or a number.
+//! - `wheel <delta>` rotates the wheel of the pointer by the given number of notches. Positive
+//!   values scroll up.
+//!
+//! Example:
+//!
+//! ```text
+//! # Give the programs some time to start.
+//! wait 1000
+//! type Hello world!
+//! key enter
+//! move-to 0.5 0.5
+//! click left
+//! ```
+
+use redshirt_input_interface::{keys, InputEvent};
+use std::{collections::HashSet, error, fmt, time::Duration};
+
+/// Identifier of the keyboard device that emits the key events.
+pub const KEYBOARD_ID: u64 = 0;
+/// Identifier of the pointer device that emits the pointer events.
+pub const POINTER_ID: u64 = 1;
+
+/// Names of the keys that don't produce a printable character, and their USB HID usage ID.
+pub const KEY_NAMES: &[(&str, u32)] = &[
+    ("enter", 0x28),
+    ("escape", 0x29),
+    ("backspace", 0x2a),
+    ("tab", 0x2b),
+    ("space", 0x2c),
+    ("caps-lock", 0x39),
+    ("f1", 0x3a),
+    ("f2", 0x3b),
+    ("f3", 0x3c),
+    ("f4", 0x3d),
+    ("f5", 0x3e),
+    ("f6", 0x3f),
+    ("f7", 0x40),
+    ("f8", 0x41),
+    ("f9", 0x42),
+    ("f10", 0x43),
+    ("f11", 0x44),
+    ("f12", 0x45),
+    ("print-screen", 0x46),
+    ("scroll-lock", 0x47),
+    ("pause", 0x48),
+    ("insert", 0x49),
+    ("home", 0x4a),
+    ("page-up", 0x4b),
+    ("delete", 0x4c),
+    ("end", 0x4d),
+    ("page-down", 0x4e),
+    ("right", 0x4f),
+    ("left", 0x50),
+    ("down", 0x51),
+    ("up", 0x52),
+    ("num-lock", 0x53),
+    ("menu", 0x65),
+    ("left-ctrl", 0xe0),
+    ("left-shift", 0xe1),
+    ("left-alt", 0xe2),
+    ("left-meta", 0xe3),
+    ("right-ctrl", 0xe4),
+    ("right-shift", 0xe5),
+    ("right-alt", 0xe6),
+    ("right-meta", 0xe7),
+];
+
+/// USB HID usage ID of the left shift key.
+const LEFT_SHIFT: u32 = 0xe1;
+/// USB HID usage ID of the right shift key.
+const RIGHT_SHIFT: u32 = 0xe5;
+
+/// Step of a parsed script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Wait for the given amount of time.
+    Wait(Duration),
+    /// Report an event of the device with the given identifier. See [`KEYBOARD_ID`] and
+    /// [`POINTER_ID`].
+    Event { device: u64, event: InputEvent },
+}
+
+/// Error that can happen when parsing a script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line where the error happened, starting from 1.
+    pub line: usize,
+    /// What is wrong.
+    pub message: String,
+}
+
+/// Parses the given script into a list of steps.
+pub fn parse(script: &str) -> Result<Vec<Step>, ParseError> {
+    let mut parser = Parser {
+        steps: Vec::new(),
+        pressed_keys: HashSet::new(),
+    };
+
+    for (line_num, line) in script.lines().enumerate() {
+        parser.parse_line(line).map_err(|message| ParseError {
+            line: line_num + 1,
+            message,
+        })?;
+    }
+
+    Ok(parser.steps)
+}
+
+/// Parsing in progress.
+struct Parser {
+    /// Steps parsed so far.
+    steps: Vec<Step>,
+    /// Keys that are pressed after the steps parsed so far have executed, as USB HID usage IDs.
+    /// Used to determine the keysyms of the keys.
+    pressed_keys: HashSet<u32>,
+}
+
+impl Parser {
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let (command, args) = match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], &line[pos + 1..]),
+            None => (line, ""),
+        };
+
+        match command {
+            "wait" => {
+                let ms = parse_args::<u64>(args, 1)?[0];
+                self.steps.push(Step::Wait(Duration::from_millis(ms)));
+            }
+            "press" => self.key(parse_key(args.trim())?, true),
+            "release" => self.key(parse_key(args.trim())?, false),
+            "key" => {
+                let scancode = parse_key(args.trim())?;
+                self.key(scancode, true);
+                self.key(scancode, false);
+            }
+            "type" => {
+                for chr in args.chars() {
+                    let (scancode, needs_shift) =
+                        find_char(chr).ok_or_else(|| format!("Can't type character {:?}", chr))?;
+                    let shift_held = self.shift_held();
+                    if !needs_shift && shift_held {
+                        return Err(format!("Can't type {:?} while shift is held", chr));
+                    }
+
+                    if needs_shift && !shift_held {
+                        self.key(LEFT_SHIFT, true);
+                    }
+                    self.key(scancode, true);
+                    self.key(scancode, false);
+                    if needs_shift && !shift_held {
+                        self.key(LEFT_SHIFT, false);
+                    }
+                }
+            }
+            "move" => {
+                let delta = parse_args::<i32>(args, 2)?;
+                self.pointer(InputEvent::PointerMotion {
+                    dx: delta[0],
+                    dy: delta[1],
+                });
+            }
+            "move-to" => {
+                let position = parse_args::<f64>(args, 2)?;
+                let convert = |val: f64| {
+                    if (0.0..=1.0).contains(&val) {
+                        Ok((val * f64::from(u16::max_value())).round() as u16)
+                    } else {
+                        Err(format!("Position out of range: {}", val))
+                    }
+                };
+                self.pointer(InputEvent::PointerPosition {
+                    x: convert(position[0])?,
+                    y: convert(position[1])?,
+                });
+            }
+            "button-press" => self.button(parse_button(args.trim())?, true),
+            "button-release" => self.button(parse_button(args.trim())?, false),
+            "click" => {
+                let button = parse_button(args.trim())?;
+                self.button(button, true);
+                self.button(button, false);
+            }
+            "wheel" => {
+                let delta = parse_args::<i32>(args, 1)?[0];
+                self.pointer(InputEvent::PointerWheel { delta });
+            }
+            _ => return Err(format!("Unknown command: {}", command)),
+        }
+
+        Ok(())
+    }
+
+    fn shift_held(&self) -> bool {
+        self.pressed_keys.contains(&LEFT_SHIFT) || self.pressed_keys.contains(&RIGHT_SHIFT)
+    }
+
+    fn key(&mut self, scancode: u32, pressed: bool) {
+        let keysym = keys::us_qwerty_keysym(scancode, self.shift_held(), false);
+        if pressed {
+            self.pressed_keys.insert(scancode);
+        } else {
+            self.pressed_keys.remove(&scancode);
+        }
+
+        self.steps.push(Step::Event {
+            device: KEYBOARD_ID,
+            event: InputEvent::Key {
+                scancode,
+                keysym,
+                pressed,
+            },
+        });
+    }
+
+    fn button(&mut self, button: u8, pressed: bool) {
+        self.pointer(InputEvent::PointerButton { button, pressed });
+    }
+
+    fn pointer(&mut self, event: InputEvent) {
+        self.steps.push(Step::Event {
+            device: POINTER_ID,
+            event,
+        });
+    }
+}
+
+/// Parses exactly `num` whitespace-separated arguments.
+fn parse_args<T: std::str::FromStr>(args: &str, num: usize) -> Result<Vec<T>, String> {
+    let args = args
+        .split_whitespace()
+        .map(|arg| {
+            arg.parse()
+                .map_err(|_| format!("Invalid argument: {}", arg))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if args.len() != num {
+        return Err(format!("Expected {} argument(s), got {}", num, args.len()));
+    }
+
+    Ok(args)
+}
+
+/// Turns a key name or character into a USB HID usage ID.
+fn parse_key(key: &str) -> Result<u32, String> {
+    if let Some((_, scancode)) = KEY_NAMES.iter().find(|(name, _)| *name == key) {
+        return Ok(*scancode);
+    }
+
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(chr), None) => find_char(chr)
+            .map(|(scancode, _)| scancode)
+            .ok_or_else(|| format!("Unknown key: {}", key)),
+        _ => Err(format!("Unknown key: {}", key)),
+    }
+}
+
+/// Finds the key that produces the given character on a US QWERTY keyboard. Returns the USB
+/// HID usage ID of the key, and whether shift must be held.
+fn find_char(chr: char) -> Option<(u32, bool)> {
+    let keysym = u32::from(chr);
+    if keysym == keys::NO_SYMBOL {
+        return None;
+    }
+
+    for &shift in &[false, true] {
+        for scancode in 0..0x100 {
+            if keys::us_qwerty_keysym(scancode, shift, false) == keysym {
+                return Some((scancode, shift));
+            }
+        }
+    }
+
+    None
+}
+
+/// Turns a button name or number into the number used by the input interface.
+fn parse_button(button: &str) -> Result<u8, String> {
+    match button {
+        "left" => Ok(0),
+        "right" => Ok(1),
+        "middle" => Ok(2),
+        _ => button
+            .parse()
+            .map_err(|_| format!("Unknown button: {}", button)),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Step, KEYBOARD_ID, POINTER_ID};
+    use redshirt_input_interface::InputEvent;
+    use std::time::Duration;
+
+    fn key(scancode: u32, keysym: u32, pressed: bool) -> Step {
+        Step::Event {
+            device: KEYBOARD_ID,
+            event: InputEvent::Key {
+                scancode,
+                keysym,
+                pressed,
+            },
+        }
+    }
+
+    #[test]
+    fn comments_and_wait() {
+        let steps = parse("# comment\n\n  wait 250\n").unwrap();
+        assert_eq!(steps, vec![Step::Wait(Duration::from_millis(250))]);
+    }
+
+    #[test]
+    fn type_with_shift() {
+        let steps = parse("type aB").unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                key(0x04, u32::from(b'a'), true),
+                key(0x04, u32::from(b'a'), false),
+                key(0xe1, 0xffe1, true),
+                key(0x05, u32::from(b'B'), true),
+                key(0x05, u32::from(b'B'), false),
+                key(0xe1, 0xffe1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn held_shift_changes_keysym() {
+        let steps = parse("press left-shift\nkey 1\nrelease left-shift").unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                key(0xe1, 0xffe1, true),
+                key(0x1e, u32::from(b'!'), true),
+                key(0x1e, u32::from(b'!'), false),
+                key(0xe1, 0xffe1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn pointer() {
+        let steps = parse("move 5 -3\nmove-to 1.0 0\nclick right\nwheel -2").unwrap();
+        let events = steps
+            .into_iter()
+            .map(|step| match step {
+                Step::Event { device, event } => {
+                    assert_eq!(device, POINTER_ID);
+                    event
+                }
+                Step::Wait(_) => panic!(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::PointerMotion { dx: 5, dy: -3 },
+                InputEvent::PointerPosition { x: 0xffff, y: 0 },
+                InputEvent::PointerButton {
+                    button: 1,
+                    pressed: true
+                },
+                InputEvent::PointerButton {
+                    button: 1,
+                    pressed: false
+                },
+                InputEvent::PointerWheel { delta: -2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("wait 10\njump").unwrap_err().line, 2);
+        assert!(parse("key nonexistent").is_err());
+        assert!(parse("move 1").is_err());
+        assert!(parse("move-to 2.0 0.5").is_err());
+        assert!(parse("type é").is_err());
+    }
+}
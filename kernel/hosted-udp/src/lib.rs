@@ -0,0 +1,420 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the udp interface using the UDP sockets of the host.
+
+use async_std::net::UdpSocket;
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+    stream::FuturesUnordered,
+};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use redshirt_udp_interface::ffi::{self, UdpMessage, INTERFACE};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{atomic, Arc},
+};
+
+/// Maximum size of a UDP payload.
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// State machine for `udp` interface messages handling.
+pub struct UdpHandler {
+    /// If true, we have sent the interface registration message.
+    registered: atomic::AtomicBool,
+    /// Accessed only by `next_event`.
+    inner: Mutex<UdpHandlerInner>,
+    /// List of open sockets.
+    sockets: Arc<std::sync::Mutex<HashMap<u32, Socket>>>,
+    /// ID to assign to the next socket.
+    next_socket_id: atomic::AtomicU32,
+    /// Send on this channel the received interface messages.
+    messages_tx: mpsc::UnboundedSender<(UdpMessage, Option<MessageId>, Pid)>,
+}
+
+/// Open socket.
+struct Socket {
+    /// Process that owns the socket.
+    owner: Pid,
+    socket: Arc<UdpSocket>,
+    /// Resolves when the `Socket` is destroyed, which interrupts the receives in progress.
+    /// Otherwise they would keep the port bound until a datagram arrives.
+    closed: future::Shared<oneshot::Receiver<()>>,
+    /// Sending side of `closed`. Never used, and only dropped.
+    _closed_tx: oneshot::Sender<()>,
+}
+
+/// Separate struct behind a mutex.
+struct UdpHandlerInner {
+    /// Operations in progress, and the answer to send back once they finish.
+    pending: FuturesUnordered<Pin<Box<dyn Future<Output = (MessageId, EncodedMessage)> + Send>>>,
+    /// Receiving side of [`UdpHandler::messages_tx`].
+    messages_rx: mpsc::UnboundedReceiver<(UdpMessage, Option<MessageId>, Pid)>,
+}
+
+impl UdpHandler {
+    /// Initializes the new state machine for UDP sockets.
+    pub fn new() -> Self {
+        let (messages_tx, messages_rx) = mpsc::unbounded();
+
+        UdpHandler {
+            registered: atomic::AtomicBool::new(false),
+            inner: Mutex::new(UdpHandlerInner {
+                pending: {
+                    let pending = FuturesUnordered::<
+                        Pin<Box<dyn Future<Output = (MessageId, EncodedMessage)> + Send>>,
+                    >::new();
+                    // TODO: ugh; pushing a never-ending future, otherwise we get a permanent `None` when polling
+                    pending.push(Box::pin(async move {
+                        loop {
+                            futures::pending!()
+                        }
+                    }));
+                    pending
+                },
+                messages_rx,
+            }),
+            sockets: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            next_socket_id: atomic::AtomicU32::new(1),
+            messages_tx,
+        }
+    }
+
+    /// Returns the socket with the given ID if it is owned by `pid`, and a `Future` that
+    /// resolves when the socket is closed.
+    fn socket(
+        &self,
+        socket_id: u32,
+        pid: Pid,
+    ) -> Option<(Arc<UdpSocket>, future::Shared<oneshot::Receiver<()>>)> {
+        let sockets = self.sockets.lock().unwrap();
+        match sockets.get(&socket_id) {
+            Some(socket) if socket.owner == pid => {
+                Some((socket.socket.clone(), socket.closed.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Starts processing the given message. Returns a `Future` yielding the answer to send
+    /// back, if any.
+    fn start_operation(
+        &self,
+        message: UdpMessage,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+    ) -> Option<Pin<Box<dyn Future<Output = (MessageId, EncodedMessage)> + Send>>> {
+        match message {
+            UdpMessage::Bind(ffi::UdpBind { local_ip, port }) => {
+                let message_id = message_id?;
+                let socket_id = self.next_socket_id.fetch_add(1, atomic::Ordering::Relaxed);
+                let sockets = self.sockets.clone();
+                let addr = SocketAddr::new(ip_from_ffi(local_ip), port);
+                Some(Box::pin(async move {
+                    let result = match UdpSocket::bind(addr).await {
+                        Ok(socket) => match socket.local_addr() {
+                            Ok(local_addr) => {
+                                let (closed_tx, closed) = oneshot::channel();
+                                let socket = Socket {
+                                    owner: emitter_pid,
+                                    socket: Arc::new(socket),
+                                    closed: closed.shared(),
+                                    _closed_tx: closed_tx,
+                                };
+                                sockets.lock().unwrap().insert(socket_id, socket);
+                                Ok((socket_id, local_addr.port()))
+                            }
+                            Err(_) => Err(()),
+                        },
+                        Err(_) => Err(()),
+                    };
+                    (message_id, ffi::UdpBindResponse { result }.encode())
+                }))
+            }
+            UdpMessage::SendTo(ffi::UdpSendTo {
+                socket_id,
+                remote_ip,
+                remote_port,
+                data,
+            }) => {
+                let message_id = message_id?;
+                let socket = self.socket(socket_id, emitter_pid);
+                let target = SocketAddr::new(ip_from_ffi(remote_ip), remote_port);
+                Some(Box::pin(async move {
+                    let result = match socket {
+                        Some((socket, _)) => socket
+                            .send_to(&data, target)
+                            .await
+                            .map(|_| ())
+                            .map_err(|_| ()),
+                        None => Err(()),
+                    };
+                    (message_id, ffi::UdpSendToResponse { result }.encode())
+                }))
+            }
+            UdpMessage::RecvFrom(ffi::UdpRecvFrom { socket_id }) => {
+                let message_id = message_id?;
+                let socket = self.socket(socket_id, emitter_pid);
+                Some(Box::pin(async move {
+                    let result = match socket {
+                        Some((socket, closed)) => {
+                            let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+                            let received = {
+                                let recv = socket.recv_from(&mut buf);
+                                futures::pin_mut!(recv);
+                                match future::select(recv, closed).await {
+                                    future::Either::Left((result, _)) => result.map_err(|_| ()),
+                                    future::Either::Right(_) => Err(()),
+                                }
+                            };
+                            match received {
+                                Ok((len, remote)) => {
+                                    buf.truncate(len);
+                                    let remote_ip = match remote.ip() {
+                                        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                                        IpAddr::V6(ip) => ip,
+                                    };
+                                    Ok(ffi::UdpDatagram {
+                                        remote_ip: remote_ip.segments(),
+                                        remote_port: remote.port(),
+                                        data: buf,
+                                    })
+                                }
+                                Err(_) => Err(()),
+                            }
+                        }
+                        None => Err(()),
+                    };
+                    (message_id, ffi::UdpRecvFromResponse { result }.encode())
+                }))
+            }
+            UdpMessage::Close(ffi::UdpClose { socket_id }) => {
+                let mut sockets = self.sockets.lock().unwrap();
+                if let Some(socket) = sockets.get(&socket_id) {
+                    if socket.owner == emitter_pid {
+                        sockets.remove(&socket_id);
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a UdpHandler {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        INTERFACE,
+                    )
+                    .encode(),
+                };
+            }
+
+            let mut inner = self.inner.lock().await;
+            let inner = &mut *inner;
+
+            loop {
+                match future::select(inner.pending.next(), inner.messages_rx.next()).await {
+                    future::Either::Left((Some((message_id, answer)), _)) => {
+                        return NativeProgramEvent::Answer {
+                            message_id,
+                            answer: Ok(answer),
+                        };
+                    }
+                    future::Either::Right((Some((message, message_id, emitter_pid)), _)) => {
+                        if let Some(operation) =
+                            self.start_operation(message, message_id, emitter_pid)
+                        {
+                            inner.pending.push(operation);
+                        }
+                    }
+                    future::Either::Left((None, _)) => unreachable!(),
+                    future::Either::Right((None, _)) => unreachable!(),
+                }
+            }
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        debug_assert_eq!(interface, INTERFACE);
+
+        match UdpMessage::decode(message) {
+            Ok(msg) => {
+                self.messages_tx
+                    .unbounded_send((msg, message_id, emitter_pid))
+                    .unwrap();
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn process_destroyed(self, pid: Pid) {
+        let mut sockets = self.sockets.lock().unwrap();
+        sockets.retain(|_, socket| socket.owner != pid);
+    }
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+/// Converts an IP address from the FFI. IPv4-mapped IPv6 addresses are turned into IPv4
+/// addresses.
+fn ip_from_ffi(ip: [u16; 8]) -> IpAddr {
+    let ip = Ipv6Addr::from(ip);
+    let segments = ip.segments();
+    if segments[..5].iter().all(|s| *s == 0) && segments[5] == 0xffff {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    } else {
+        IpAddr::V6(ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UdpHandler;
+    use futures::executor::block_on;
+    use redshirt_core::native::{NativeProgramEvent, NativeProgramRef as _};
+    use redshirt_core::{Decode as _, Encode as _, EncodedMessage, MessageId, Pid};
+    use redshirt_udp_interface::ffi::{self, UdpMessage, INTERFACE};
+
+    /// 127.0.0.1, as an IPv4-mapped IPv6 address.
+    const LOCALHOST: [u16; 8] = [0, 0, 0, 0, 0, 0xffff, 0x7f00, 1];
+
+    fn send(handler: &UdpHandler, message_id: Option<u64>, message: UdpMessage) {
+        handler.interface_message(
+            INTERFACE,
+            message_id.map(MessageId::from),
+            Pid::from(1),
+            message.encode(),
+        );
+    }
+
+    /// Returns the next answer sent back by the handler.
+    fn next_answer(handler: &UdpHandler) -> (MessageId, EncodedMessage) {
+        match block_on(handler.next_event()) {
+            NativeProgramEvent::Answer {
+                message_id,
+                answer: Ok(answer),
+            } => (message_id, answer),
+            _ => panic!(),
+        }
+    }
+
+    fn bind(handler: &UdpHandler, port: u16) -> Result<(u32, u16), ()> {
+        let message = UdpMessage::Bind(ffi::UdpBind {
+            local_ip: LOCALHOST,
+            port,
+        });
+        send(handler, Some(1), message);
+        let (message_id, answer) = next_answer(handler);
+        assert_eq!(message_id, MessageId::from(1));
+        ffi::UdpBindResponse::decode(answer).unwrap().result
+    }
+
+    #[test]
+    fn loopback_send_recv_close() {
+        let handler = UdpHandler::new();
+        match block_on(handler.next_event()) {
+            NativeProgramEvent::Emit { .. } => {}
+            _ => panic!(),
+        }
+
+        let (receiver, receiver_port) = bind(&handler, 0).unwrap();
+        let (sender, sender_port) = bind(&handler, 0).unwrap();
+
+        send(
+            &handler,
+            Some(2),
+            UdpMessage::RecvFrom(ffi::UdpRecvFrom {
+                socket_id: receiver,
+            }),
+        );
+        send(
+            &handler,
+            Some(3),
+            UdpMessage::SendTo(ffi::UdpSendTo {
+                socket_id: sender,
+                remote_ip: LOCALHOST,
+                remote_port: receiver_port,
+                data: b"hello".to_vec(),
+            }),
+        );
+
+        // The answers can arrive in any order.
+        for _ in 0..2 {
+            let (message_id, answer) = next_answer(&handler);
+            if message_id == MessageId::from(2) {
+                let datagram = ffi::UdpRecvFromResponse::decode(answer)
+                    .unwrap()
+                    .result
+                    .unwrap();
+                assert_eq!(datagram.remote_ip, LOCALHOST);
+                assert_eq!(datagram.remote_port, sender_port);
+                assert_eq!(datagram.data, b"hello");
+            } else {
+                assert_eq!(message_id, MessageId::from(3));
+                let response = ffi::UdpSendToResponse::decode(answer).unwrap();
+                assert_eq!(response.result, Ok(()));
+            }
+        }
+
+        // Closing the socket interrupts the receive in progress and releases the port.
+        send(
+            &handler,
+            Some(4),
+            UdpMessage::RecvFrom(ffi::UdpRecvFrom {
+                socket_id: receiver,
+            }),
+        );
+        send(
+            &handler,
+            None,
+            UdpMessage::Close(ffi::UdpClose {
+                socket_id: receiver,
+            }),
+        );
+        let (message_id, answer) = next_answer(&handler);
+        assert_eq!(message_id, MessageId::from(4));
+        assert!(ffi::UdpRecvFromResponse::decode(answer)
+            .unwrap()
+            .result
+            .is_err());
+
+        assert!(bind(&handler, receiver_port).is_ok());
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use redshirt_window_interface::ffi::PixelFormat;
+use std::{convert::TryFrom as _, io};
+
+/// In-memory content of a window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixel_format: PixelFormat,
+    data: Vec<u8>,
+}
+
+impl Framebuffer {
+    /// Initializes a new framebuffer filled with black pixels.
+    pub fn new(width: u32, height: u32, pixel_format: PixelFormat) -> Framebuffer {
+        let len = Framebuffer::expected_len(width, height, pixel_format);
+        let mut data = vec![0; len];
+        // Make the alpha channel opaque.
+        for pixel in data.chunks_mut(pixel_format.bytes_per_pixel()) {
+            pixel[3] = 0xff;
+        }
+
+        Framebuffer {
+            width,
+            height,
+            pixel_format,
+            data,
+        }
+    }
+
+    /// Returns the width of the framebuffer in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height of the framebuffer in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the format of the pixels in [`Framebuffer::data`].
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// Returns the raw pixels, row by row, starting from the top-left corner.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Replaces the content of the framebuffer.
+    ///
+    /// Returns an error if `data` doesn't have the right length.
+    pub fn set_data(&mut self, data: Vec<u8>) -> Result<(), ()> {
+        if data.len() != self.data.len() {
+            return Err(());
+        }
+
+        self.data = data;
+        Ok(())
+    }
+
+    /// Encodes the content of the framebuffer as a PNG image.
+    pub fn write_png(&self, writer: impl io::Write) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+
+        match self.pixel_format {
+            PixelFormat::Rgba8 => writer.write_image_data(&self.data),
+            PixelFormat::Bgra8 => {
+                let mut rgba = self.data.clone();
+                for pixel in rgba.chunks_mut(4) {
+                    pixel.swap(0, 2);
+                }
+                writer.write_image_data(&rgba)
+            }
+        }
+    }
+
+    fn expected_len(width: u32, height: u32, pixel_format: PixelFormat) -> usize {
+        usize::try_from(width).unwrap()
+            * usize::try_from(height).unwrap()
+            * pixel_format.bytes_per_pixel()
+    }
+}
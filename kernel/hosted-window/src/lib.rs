@@ -0,0 +1,487 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the window interface.
+//!
+//! Windows aren't displayed on the screen. Instead, each window is backed by an in-memory
+//! framebuffer that can be inspected with [`WindowHandler::framebuffer`] or dumped to PNG files.
+//! This makes it possible to run graphical programs in headless environments, such as a CI.
+
+use futures::{channel::mpsc, lock::Mutex as AsyncMutex, prelude::*};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use redshirt_window_interface::ffi::{
+    self, PixelFormat, WindowEvent, WindowInfo, WindowMessage, INTERFACE,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{atomic, Mutex},
+};
+
+pub use self::framebuffer::Framebuffer;
+
+mod framebuffer;
+
+/// Maximum width or height of a window, to avoid allocating absurd amounts of memory.
+const MAX_DIMENSION: u32 = 16384;
+
+/// State machine for `window` interface messages handling.
+pub struct WindowHandler {
+    /// If true, we have sent the interface registration message.
+    registered: atomic::AtomicBool,
+    /// If `Some`, every time a window is presented we write its content as a PNG file in this
+    /// directory.
+    dump_directory: Option<PathBuf>,
+    /// List of open windows, and identifier to assign to the next window.
+    windows: Mutex<(HashMap<u32, WindowState>, u32)>,
+    /// Sending side of the answers to deliver. Pushed to by the various methods.
+    answers_tx: mpsc::UnboundedSender<(MessageId, Result<EncodedMessage, ()>)>,
+    /// Receiving side of [`WindowHandler::answers_tx`]. Accessed only by `next_event`.
+    answers_rx: AsyncMutex<mpsc::UnboundedReceiver<(MessageId, Result<EncodedMessage, ()>)>>,
+}
+
+/// State of a single window.
+struct WindowState {
+    /// Process that has opened the window.
+    owner: Pid,
+    /// Current content of the window.
+    framebuffer: Framebuffer,
+    /// Events that haven't been delivered yet.
+    pending_events: VecDeque<WindowEvent>,
+    /// If `Some`, the owner is waiting for events and this message must be answered.
+    get_events_message: Option<MessageId>,
+}
+
+impl WindowHandler {
+    /// Initializes the new state machine for windows.
+    pub fn new() -> Self {
+        let (answers_tx, answers_rx) = mpsc::unbounded();
+
+        WindowHandler {
+            registered: atomic::AtomicBool::new(false),
+            dump_directory: None,
+            windows: Mutex::new((HashMap::new(), 0)),
+            answers_tx,
+            answers_rx: AsyncMutex::new(answers_rx),
+        }
+    }
+
+    /// Writes the content of windows as `window-<id>.png` files in the given directory every
+    /// time they're presented.
+    pub fn with_dump_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.dump_directory = Some(directory.into());
+        self
+    }
+
+    /// Returns a copy of the current content of the given window, or `None` if there is no
+    /// window with that identifier.
+    pub fn framebuffer(&self, window_id: u32) -> Option<Framebuffer> {
+        let windows = self.windows.lock().unwrap();
+        windows.0.get(&window_id).map(|w| w.framebuffer.clone())
+    }
+
+    /// Simulates an event happening on the given window, such as a key press.
+    ///
+    /// Returns an error if there is no window with that identifier, or if the event is a resize
+    /// to a size that couldn't have been passed when opening the window.
+    pub fn inject_event(&self, window_id: u32, event: WindowEvent) -> Result<(), ()> {
+        let mut windows = self.windows.lock().unwrap();
+        let window = match windows.0.get_mut(&window_id) {
+            Some(w) => w,
+            None => return Err(()),
+        };
+
+        if let WindowEvent::Resized { width, height } = event {
+            if !is_valid_size(width, height) {
+                return Err(());
+            }
+
+            window.framebuffer = Framebuffer::new(width, height, window.framebuffer.pixel_format());
+        }
+
+        window.pending_events.push_back(event);
+        self.flush_events(window);
+        Ok(())
+    }
+
+    /// If the owner of the window is waiting for events and events are available, delivers them.
+    fn flush_events(&self, window: &mut WindowState) {
+        if window.pending_events.is_empty() {
+            return;
+        }
+
+        if let Some(message_id) = window.get_events_message.take() {
+            let events = window.pending_events.drain(..).collect::<Vec<_>>();
+            let response = ffi::WindowGetEventsResponse { result: Ok(events) };
+            self.answer(message_id, response.encode());
+        }
+    }
+
+    fn answer(&self, message_id: MessageId, answer: EncodedMessage) {
+        self.answers_tx
+            .unbounded_send((message_id, Ok(answer)))
+            .unwrap();
+    }
+
+    fn dump_png(&self, directory: &Path, window_id: u32, framebuffer: &Framebuffer) {
+        let path = directory.join(format!("window-{}.png", window_id));
+        // Errors are ignored; failing to write a dump mustn't affect the programs being run.
+        if let Ok(file) = fs::File::create(path) {
+            let _ = framebuffer.write_png(file);
+        }
+    }
+}
+
+/// Returns true if a window can have the given dimensions.
+fn is_valid_size(width: u32, height: u32) -> bool {
+    width != 0 && height != 0 && width <= MAX_DIMENSION && height <= MAX_DIMENSION
+}
+
+impl<'a> NativeProgramRef<'a> for &'a WindowHandler {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        Box::pin(async move {
+            if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+                return NativeProgramEvent::Emit {
+                    interface: redshirt_interface_interface::ffi::INTERFACE,
+                    message_id_write: None,
+                    message: redshirt_interface_interface::ffi::InterfaceMessage::Register(
+                        INTERFACE,
+                    )
+                    .encode(),
+                };
+            }
+
+            let mut answers_rx = self.answers_rx.lock().await;
+            match answers_rx.next().await {
+                Some((message_id, answer)) => NativeProgramEvent::Answer { message_id, answer },
+                // `self` holds the sending side of the channel.
+                None => unreachable!(),
+            }
+        })
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        debug_assert_eq!(interface, INTERFACE);
+
+        let mut windows = self.windows.lock().unwrap();
+        let (windows, next_window_id) = &mut *windows;
+
+        match (WindowMessage::decode(message), message_id) {
+            (Ok(WindowMessage::Open(open)), Some(message_id)) => {
+                let result = if !is_valid_size(open.width, open.height) {
+                    Err(())
+                } else {
+                    let window_id = *next_window_id;
+                    *next_window_id = next_window_id.wrapping_add(1);
+                    let pixel_format = PixelFormat::Rgba8;
+                    windows.insert(
+                        window_id,
+                        WindowState {
+                            owner: emitter_pid,
+                            framebuffer: Framebuffer::new(open.width, open.height, pixel_format),
+                            pending_events: VecDeque::new(),
+                            get_events_message: None,
+                        },
+                    );
+                    Ok(WindowInfo {
+                        window_id,
+                        width: open.width,
+                        height: open.height,
+                        pixel_format,
+                    })
+                };
+
+                let response = ffi::WindowOpenResponse { result };
+                self.answer(message_id, response.encode());
+            }
+            (Ok(WindowMessage::Close(close)), _) => {
+                let is_owner = windows
+                    .get(&close.window_id)
+                    .map_or(false, |w| w.owner == emitter_pid);
+                if is_owner {
+                    let window = windows.remove(&close.window_id).unwrap();
+                    if let Some(message_id) = window.get_events_message {
+                        let response = ffi::WindowGetEventsResponse { result: Err(()) };
+                        self.answer(message_id, response.encode());
+                    }
+                }
+            }
+            (Ok(WindowMessage::Present(present)), Some(message_id)) => {
+                let result = match windows.get_mut(&present.window_id) {
+                    Some(w) if w.owner == emitter_pid => {
+                        let result = w.framebuffer.set_data(present.framebuffer);
+                        if let (Ok(()), Some(dir)) = (&result, &self.dump_directory) {
+                            self.dump_png(dir, present.window_id, &w.framebuffer);
+                        }
+                        result
+                    }
+                    _ => Err(()),
+                };
+
+                let response = ffi::WindowPresentResponse { result };
+                self.answer(message_id, response.encode());
+            }
+            (Ok(WindowMessage::GetEvents(get_events)), Some(message_id)) => {
+                match windows.get_mut(&get_events.window_id) {
+                    Some(w) if w.owner == emitter_pid && w.get_events_message.is_none() => {
+                        w.get_events_message = Some(message_id);
+                        self.flush_events(w);
+                    }
+                    _ => {
+                        let response = ffi::WindowGetEventsResponse { result: Err(()) };
+                        self.answer(message_id, response.encode());
+                    }
+                }
+            }
+            (_, Some(message_id)) => {
+                self.answers_tx
+                    .unbounded_send((message_id, Err(())))
+                    .unwrap();
+            }
+            (_, None) => {}
+        }
+    }
+
+    fn process_destroyed(self, pid: Pid) {
+        let mut windows = self.windows.lock().unwrap();
+        windows.0.retain(|_, w| w.owner != pid);
+    }
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WindowHandler, MAX_DIMENSION};
+    use futures::executor::block_on;
+    use redshirt_core::native::{NativeProgramEvent, NativeProgramRef as _};
+    use redshirt_core::{Decode, Encode as _, EncodedMessage, MessageId, Pid};
+    use redshirt_window_interface::ffi::{
+        self, PixelFormat, WindowEvent, WindowInfo, WindowMessage, INTERFACE,
+    };
+    use std::{env, fs, process};
+
+    /// Builds a handler and pulls the interface registration out of it.
+    fn handler() -> WindowHandler {
+        let handler = WindowHandler::new();
+        match block_on((&handler).next_event()) {
+            NativeProgramEvent::Emit { .. } => {}
+            _ => panic!(),
+        }
+        handler
+    }
+
+    fn send(handler: &WindowHandler, pid: u64, message_id: u64, message: WindowMessage) {
+        handler.interface_message(
+            INTERFACE,
+            Some(MessageId::from(message_id)),
+            Pid::from(pid),
+            message.encode(),
+        );
+    }
+
+    /// Returns the next answer emitted by the handler, decoded as `T`.
+    fn answer<T: Decode>(handler: &WindowHandler) -> (MessageId, T) {
+        match block_on(handler.next_event()) {
+            NativeProgramEvent::Answer {
+                message_id,
+                answer: Ok(answer),
+            } => (message_id, T::decode(answer).unwrap()),
+            _ => panic!(),
+        }
+    }
+
+    fn open(handler: &WindowHandler, pid: u64, width: u32, height: u32) -> Result<WindowInfo, ()> {
+        let message = WindowMessage::Open(ffi::WindowOpen {
+            title: "test".to_owned(),
+            width,
+            height,
+        });
+        send(handler, pid, 1, message);
+        answer::<ffi::WindowOpenResponse>(handler).1.result
+    }
+
+    fn present(handler: &WindowHandler, pid: u64, window_id: u32, data: Vec<u8>) -> Result<(), ()> {
+        let message = WindowMessage::Present(ffi::WindowPresent {
+            window_id,
+            framebuffer: data,
+        });
+        send(handler, pid, 2, message);
+        answer::<ffi::WindowPresentResponse>(handler).1.result
+    }
+
+    #[test]
+    fn open_and_close() {
+        let handler = handler();
+
+        assert!(open(&handler, 1, 0, 10).is_err());
+        assert!(open(&handler, 1, 10, MAX_DIMENSION + 1).is_err());
+
+        let info = open(&handler, 1, 3, 2).unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!(info.pixel_format, PixelFormat::Rgba8);
+        let framebuffer = handler.framebuffer(info.window_id).unwrap();
+        assert_eq!(framebuffer.data(), &[0, 0, 0, 0xff].repeat(6)[..]);
+
+        let close = |pid| {
+            let message = WindowMessage::Close(ffi::WindowClose {
+                window_id: info.window_id,
+            });
+            (&handler).interface_message(INTERFACE, None, Pid::from(pid), message.encode());
+        };
+
+        // Only the owner can close the window.
+        close(2);
+        assert!(handler.framebuffer(info.window_id).is_some());
+        close(1);
+        assert!(handler.framebuffer(info.window_id).is_none());
+    }
+
+    #[test]
+    fn present_and_process_destroyed() {
+        let handler = handler();
+        let info = open(&handler, 1, 2, 2).unwrap();
+
+        assert!(present(&handler, 1, info.window_id, vec![5; 15]).is_err());
+        assert!(present(&handler, 2, info.window_id, vec![5; 16]).is_err());
+        assert!(present(&handler, 1, info.window_id + 1, vec![5; 16]).is_err());
+
+        let data = (0..16).collect::<Vec<u8>>();
+        assert!(present(&handler, 1, info.window_id, data.clone()).is_ok());
+        assert_eq!(
+            handler.framebuffer(info.window_id).unwrap().data(),
+            &data[..]
+        );
+
+        // The windows of a destroyed process are closed.
+        (&handler).process_destroyed(Pid::from(1));
+        assert!(handler.framebuffer(info.window_id).is_none());
+    }
+
+    #[test]
+    fn events() {
+        let handler = handler();
+        let info = open(&handler, 1, 2, 2).unwrap();
+        let get_events = |message_id| {
+            let message = WindowMessage::GetEvents(ffi::WindowGetEvents {
+                window_id: info.window_id,
+            });
+            send(&handler, 1, message_id, message);
+        };
+
+        let key = WindowEvent::KeyboardInput {
+            scancode: 30,
+            pressed: true,
+        };
+        assert!(handler
+            .inject_event(info.window_id + 1, key.clone())
+            .is_err());
+
+        // Events are buffered until the owner asks for them.
+        assert!(handler.inject_event(info.window_id, key.clone()).is_ok());
+        assert!(handler
+            .inject_event(info.window_id, WindowEvent::CloseRequested)
+            .is_ok());
+        get_events(10);
+        let (message_id, response) = answer::<ffi::WindowGetEventsResponse>(&handler);
+        assert_eq!(message_id, MessageId::from(10));
+        assert_eq!(response.result, Ok(vec![key, WindowEvent::CloseRequested]));
+
+        // Resizes must respect the same bounds as opening a window.
+        for (width, height) in &[(0, 4), (4, MAX_DIMENSION + 1)] {
+            let resize = WindowEvent::Resized {
+                width: *width,
+                height: *height,
+            };
+            assert!(handler.inject_event(info.window_id, resize).is_err());
+        }
+
+        get_events(11);
+        let resize = WindowEvent::Resized {
+            width: 4,
+            height: 1,
+        };
+        assert!(handler.inject_event(info.window_id, resize.clone()).is_ok());
+        let (message_id, response) = answer::<ffi::WindowGetEventsResponse>(&handler);
+        assert_eq!(message_id, MessageId::from(11));
+        assert_eq!(response.result, Ok(vec![resize]));
+        let framebuffer = handler.framebuffer(info.window_id).unwrap();
+        assert_eq!((framebuffer.width(), framebuffer.height()), (4, 1));
+        assert!(present(&handler, 1, info.window_id, vec![0; 16]).is_ok());
+
+        // Asking for events twice at the same time is an error.
+        get_events(12);
+        get_events(13);
+        let (message_id, response) = answer::<ffi::WindowGetEventsResponse>(&handler);
+        assert_eq!(message_id, MessageId::from(13));
+        assert!(response.result.is_err());
+    }
+
+    #[test]
+    fn png_dump() {
+        let directory = env::temp_dir().join(format!("redshirt-window-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let handler = WindowHandler::new().with_dump_directory(&directory);
+        block_on((&handler).next_event());
+
+        let info = open(&handler, 1, 2, 1).unwrap();
+        let data = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        assert!(present(&handler, 1, info.window_id, data.clone()).is_ok());
+
+        let path = directory.join(format!("window-{}.png", info.window_id));
+        let decoder = png::Decoder::new(fs::File::open(&path).unwrap());
+        let (png_info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!((png_info.width, png_info.height), (2, 1));
+        assert_eq!(png_info.color_type, png::ColorType::RGBA);
+        let mut decoded = vec![0; png_info.buffer_size()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn unknown_message() {
+        let handler = handler();
+        (&handler).interface_message(
+            INTERFACE,
+            Some(MessageId::from(4)),
+            Pid::from(1),
+            EncodedMessage(vec![0xff]),
+        );
+        match block_on((&handler).next_event()) {
+            NativeProgramEvent::Answer {
+                message_id,
+                answer: Err(()),
+            } => assert_eq!(message_id, MessageId::from(4)),
+            _ => panic!(),
+        }
+    }
+}
@@ -13,10 +13,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod aarch64;
 mod arm;
+mod pl011;
 mod x86_64;
 
+#[cfg(target_arch = "aarch64")]
+pub use self::aarch64::*; // TODO: remove
 #[cfg(target_arch = "x86_64")]
 pub use self::x86_64::*; // TODO: remove
-#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+#[cfg(target_arch = "arm")]
 pub use arm::*; // TODO: remove
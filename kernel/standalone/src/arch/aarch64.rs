@@ -0,0 +1,392 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(target_arch = "aarch64")]
+
+//! Support for 64-bits ARM platforms.
+//!
+//! The kernel is booted using the Linux arm64 boot protocol (see `aarch64-freestanding.ld`),
+//! which is supported by QEMU and by the firmware of the Raspberry Pi 3 and 4. All the
+//! information about the hardware, such as the location of the RAM and of the UART, is found in
+//! the device tree passed by the bootloader.
+
+use alloc::vec::Vec;
+use core::{ops::Range, slice, task::Waker};
+use redshirt_fdt::DeviceTree;
+use spin::Once;
+
+use super::pl011;
+
+mod clock;
+mod exceptions;
+
+pub use self::clock::{monotonic_clock, set_timer_waker, system_clock, wake_expired_timer};
+pub use super::pl011::write as write_uart;
+
+/// Size of the stack of the kernel.
+const STACK_SIZE: usize = 0x40_0000;
+
+/// Ranges of physical memory containing RAM, as found in the device tree.
+static RAM: Once<Vec<Range<u64>>> = Once::new();
+
+/// This is the main entry point of the kernel for 64-bits ARM architectures.
+#[no_mangle]
+#[naked]
+unsafe extern "C" fn _start() -> ! {
+    // The bootloader passes the address of the device tree in `x0`. We save it in `x19`, which
+    // isn't modified by the code below.
+    asm!("mov x19, x0" :::: "volatile");
+
+    // Only the first CPU continues. The other CPUs are normally held by the bootloader, but
+    // some bootloaders start all of them.
+    asm!(r#"
+    mrs x1, mpidr_el1
+    and x1, x1, #0xff
+    cbz x1, 2f
+1:  wfe
+    b 1b
+2:
+    "#:::"x1":"volatile");
+
+    // If we have been started in EL2, switch to EL1.
+    asm!(r#"
+    mrs x1, CurrentEL
+    lsr x1, x1, #2
+    cmp x1, #2
+    b.ne 1f
+
+    // EL1 runs in AArch64 mode.
+    mov x1, #(1 << 31)
+    msr hcr_el2, x1
+    // Give EL1 access to the physical counter and timer.
+    mrs x1, cnthctl_el2
+    orr x1, x1, #3
+    msr cnthctl_el2, x1
+    msr cntvoff_el2, xzr
+    // MMU and caches disabled, with the reserved bits set to 1.
+    mov x1, #0x0800
+    movk x1, #0x30d0, lsl #16
+    msr sctlr_el1, x1
+    // Return to EL1, using SP_EL1, with all interrupts masked.
+    mov x1, #0x3c5
+    msr spsr_el2, x1
+    adr x1, 1f
+    msr elr_el2, x1
+    eret
+1:
+    "#:::"x1":"volatile");
+
+    // Apply the relocations of the kernel, which is linked at address 0. This must be done
+    // before running any code that relies on absolute addresses, which includes all the Rust
+    // code. Only relocations of type `R_AARCH64_RELATIVE` (1027) are emitted when linking a
+    // position-independent executable.
+    asm!(r#"
+    adrp x1, __image_start
+    add x1, x1, :lo12:__image_start
+    adrp x2, __rela_start
+    add x2, x2, :lo12:__rela_start
+    adrp x3, __rela_end
+    add x3, x3, :lo12:__rela_end
+1:  cmp x2, x3
+    b.hs 2f
+    ldp x4, x5, [x2]
+    ldr x6, [x2, #16]
+    add x2, x2, #24
+    cmp w5, #1027
+    b.ne 1b
+    add x6, x6, x1
+    str x6, [x1, x4]
+    b 1b
+2:
+    "#:::"x1","x2","x3","x4","x5","x6","memory":"volatile");
+
+    // Zero the BSS section. The bootloader only loads the content of the image file, which
+    // doesn't include the BSS.
+    asm!(r#"
+    adrp x1, __bss_start
+    add x1, x1, :lo12:__bss_start
+    adrp x2, __bss_end
+    add x2, x2, :lo12:__bss_end
+1:  cmp x1, x2
+    b.hs 2f
+    stp xzr, xzr, [x1], #16
+    b 1b
+2:
+    "#:::"x1","x2","memory":"volatile");
+
+    // Set up the stack, enable the floating point registers, and install the exception vectors.
+    asm!(r#"
+    .pushsection .bss
+    .balign 16
+    stack:
+    .skip 0x400000
+    stack_top:
+    .popsection
+    adrp x1, stack_top
+    add x1, x1, :lo12:stack_top
+    mov sp, x1
+
+    mov x1, #(3 << 20)
+    msr cpacr_el1, x1
+
+    adrp x1, exception_vectors
+    add x1, x1, :lo12:exception_vectors
+    msr vbar_el1, x1
+    isb
+    "#:::"x1","memory":"volatile");
+
+    // Exception vectors. There are 16 vectors, each 0x80 bytes long, and the table must be
+    // aligned on 2kiB. Each vector passes its index and information about the exception to
+    // `exception_handler`.
+    asm!(r#"
+    .pushsection .text.exception_vectors, "ax"
+    .balign 0x800
+    exception_vectors:
+    .irp n, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+    .balign 0x80
+    mov x0, #\n
+    b exception_entry
+    .endr
+    exception_entry:
+    mrs x1, esr_el1
+    mrs x2, far_el1
+    mrs x3, elr_el1
+    b exception_handler
+    .popsection
+    "#:::"x0","x1","x2","x3":"volatile");
+
+    asm!(r#"
+    mov x0, x19
+    b cpu_enter
+    "#:::"volatile");
+    core::hint::unreachable_unchecked()
+}
+
+/// Main Rust entry point. The parameter is the physical address of the device tree.
+#[no_mangle]
+fn cpu_enter(device_tree: usize) -> ! {
+    // If the device tree is invalid, the panic message can't be printed, since the UART is
+    // found in the device tree.
+    let device_tree = match unsafe { DeviceTree::from_ptr(device_tree as *const u8) } {
+        Ok(dt) => dt,
+        Err(err) => panic!("Invalid device tree: {}", err),
+    };
+
+    if let Some(base) = device_tree
+        .compatible_nodes("arm,pl011")
+        .filter_map(|n| device_tree.translate_address(&n, n.reg().next()?.0))
+        .next()
+    {
+        unsafe {
+            pl011::init(base as usize);
+        }
+    }
+
+    let initrd = find_initrd(&device_tree);
+
+    unsafe {
+        extern "C" {
+            // Defined in `aarch64-freestanding.ld`.
+            static __image_start: u8;
+            static __image_end: u8;
+        }
+
+        let image = &__image_start as *const u8 as usize..&__image_end as *const u8 as usize;
+        let dtb = {
+            let start = device_tree.as_bytes().as_ptr() as usize;
+            start..start + device_tree.as_bytes().len()
+        };
+
+        let mut reserved = [image, dtb, 0..0];
+        if let Some(initrd) = &initrd {
+            reserved[2] = initrd.clone();
+        }
+
+        crate::mem_alloc::initialize(free_memory_ranges(&device_tree, &reserved));
+    }
+
+    RAM.call_once(|| memory_ranges(&device_tree).collect());
+
+    let timer_frequency = device_tree
+        .compatible_nodes("arm,armv8-timer")
+        .filter_map(|n| n.property_int("clock-frequency"))
+        .next();
+    unsafe {
+        clock::init(timer_frequency);
+    }
+
+    let modules_bundle = match initrd {
+        Some(initrd) => unsafe {
+            slice::from_raw_parts(initrd.start as *const u8, initrd.end - initrd.start)
+        },
+        None => &[],
+    };
+
+    let kernel = crate::kernel::Kernel::init(crate::kernel::KernelConfig {
+        num_cpus: 1,
+        modules_bundle,
+        device_tree: Some(device_tree.as_bytes()),
+        ..Default::default()
+    });
+
+    kernel.run()
+}
+
+/// Returns the ranges of RAM indicated in the device tree.
+fn memory_ranges<'a>(device_tree: &DeviceTree<'a>) -> impl Iterator<Item = Range<u64>> + 'a {
+    device_tree
+        .nodes()
+        .filter(|n| n.property_str("device_type") == Some("memory"))
+        .flat_map(|n| n.reg())
+        .filter_map(|(start, size)| Some(start..start.checked_add(size)?))
+}
+
+/// Returns the location of the initial RAM disk, which contains the modules bundle, as
+/// indicated in the `/chosen` node of the device tree.
+fn find_initrd(device_tree: &DeviceTree) -> Option<Range<usize>> {
+    let chosen = device_tree.node_by_path("/chosen")?;
+    let start = chosen.property_int("linux,initrd-start")?;
+    let end = chosen.property_int("linux,initrd-end")?;
+    if start >= end {
+        return None;
+    }
+    Some(start as usize..end as usize)
+}
+
+/// Returns the ranges of RAM that can be used as a heap, in other words the ranges of RAM
+/// indicated in the device tree, minus the reserved ranges.
+///
+/// Must be called before the heap is initialized, and therefore doesn't allocate.
+fn free_memory_ranges(
+    device_tree: &DeviceTree,
+    reserved: &[Range<usize>],
+) -> impl Iterator<Item = Range<usize>> {
+    const MAX_RANGES: usize = 32;
+    let mut ranges: [Range<usize>; MAX_RANGES] = Default::default();
+    let mut num_ranges = 0;
+
+    for range in memory_ranges(device_tree) {
+        if num_ranges < MAX_RANGES {
+            ranges[num_ranges] = range.start as usize..range.end as usize;
+            num_ranges += 1;
+        }
+    }
+
+    // The address 0 can't be returned by an allocator.
+    let reservations = device_tree
+        .memory_reservations()
+        .map(|(start, size)| start as usize..start.saturating_add(size) as usize)
+        .chain(reserved.iter().cloned())
+        .chain(core::iter::once(0..1));
+
+    for reservation in reservations {
+        for n in 0..num_ranges {
+            let range = ranges[n].clone();
+            if reservation.end <= range.start || reservation.start >= range.end {
+                continue;
+            }
+
+            ranges[n] = range.start..reservation.start.max(range.start);
+            if reservation.end < range.end && num_ranges < MAX_RANGES {
+                ranges[num_ranges] = reservation.end..range.end;
+                num_ranges += 1;
+            }
+        }
+    }
+
+    (0..num_ranges)
+        .map(move |n| ranges[n].clone())
+        .filter(|r| r.start < r.end)
+}
+
+// TODO: no_mangle and naked because it's called at initialization; attributes should eventually be removed
+#[no_mangle]
+#[naked]
+// TODO: define the semantics of that
+pub fn halt() -> ! {
+    unsafe {
+        loop {
+            asm!(r#"wfe"#);
+        }
+    }
+}
+
+// TODO: only one CPU is started on aarch64 at the moment
+pub fn current_cpu() -> u32 {
+    0
+}
+
+pub fn wake_cpu(cpu: u32) {}
+
+/// Makes sure that the given range of physical memory, corresponding to a memory-mapped device,
+/// is accessible.
+///
+/// Returns an error if the range overlaps RAM.
+// TODO: the MMU isn't enabled, meaning that all the memory is already accessible
+pub fn map_mmio(range: Range<u64>) -> Result<(), ()> {
+    let overlaps_ram = RAM.r#try().map_or(false, |ram| {
+        ram.iter()
+            .any(|r| r.start < range.end && range.start < r.end)
+    });
+    if overlaps_ram {
+        Err(())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn virtual_to_physical(ptr: usize) -> u64 {
+    // The MMU is disabled.
+    ptr as u64
+}
+
+// TODO: the MMU is disabled, meaning that the caches are disabled as well; this should be
+//       revisited once the MMU is enabled
+pub fn dma_cache_coherent() -> bool {
+    true
+}
+
+// TODO: interrupts aren't supported on aarch64 yet
+pub fn enable_irq(irq: u32) -> Result<(), ()> {
+    Err(())
+}
+
+pub fn disable_irq(irq: u32) {}
+
+pub fn unmask_irq(irq: u32) {}
+
+pub fn irq_count(irq: u32) -> Option<u64> {
+    None
+}
+
+pub fn set_irq_waker(irq: u32, waker: &Waker) {}
+
+pub unsafe fn write_port_u8(port: u32, data: u8) {}
+
+pub unsafe fn write_port_u16(port: u32, data: u16) {}
+
+pub unsafe fn write_port_u32(port: u32, data: u32) {}
+
+pub unsafe fn read_port_u8(port: u32) -> u8 {
+    0
+}
+
+pub unsafe fn read_port_u16(port: u32) -> u16 {
+    0
+}
+
+pub unsafe fn read_port_u32(port: u32) -> u32 {
+    0
+}
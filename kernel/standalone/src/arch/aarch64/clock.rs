@@ -0,0 +1,132 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Monotonic clock and timers, based on the ARM generic timer.
+//!
+//! The generic timer provides a 64 bits counter incremented at a fixed frequency, indicated by
+//! the `CNTFRQ_EL0` register.
+//!
+//! Since interrupts aren't supported on aarch64 yet, timers are implemented by enabling the event
+//! stream of the generic timer, which periodically wakes up the CPU if it is sleeping in a `wfe`
+//! instruction. After waking up, [`wake_expired_timer`] must be called.
+
+use core::{
+    convert::TryFrom as _,
+    sync::atomic::{AtomicU32, Ordering},
+    task::Waker,
+    time::Duration,
+};
+use spin::Mutex;
+
+/// Frequency to assume if the `CNTFRQ_EL0` register hasn't been set by the firmware, and the
+/// device tree doesn't indicate it either.
+const DEFAULT_FREQUENCY_HZ: u64 = 62_500_000;
+
+/// Index of the bit of the counter that triggers an event when it flips. An event is generated
+/// every `2^(EVENT_STREAM_BIT + 1)` ticks, which is approximately every millisecond on common
+/// hardware.
+const EVENT_STREAM_BIT: u64 = 14;
+
+/// Deadline and `Waker` registered with [`set_timer_waker`].
+static TIMER: Mutex<Option<(Duration, Waker)>> = Mutex::new(None);
+
+/// Frequency of the counter as indicated by the device tree. 0 if unknown.
+static DEVICE_TREE_FREQUENCY_HZ: AtomicU32 = AtomicU32::new(0);
+
+/// Enables the event stream of the generic timer.
+///
+/// `frequency_hz` is the frequency of the counter as indicated by the `clock-frequency` property
+/// of the timer in the device tree, if any. It is only used if the `CNTFRQ_EL0` register hasn't been
+/// set by the firmware.
+///
+/// # Safety
+///
+/// Must only be called once.
+pub unsafe fn init(frequency_hz: Option<u64>) {
+    if let Some(frequency_hz) = frequency_hz.and_then(|f| u32::try_from(f).ok()) {
+        DEVICE_TREE_FREQUENCY_HZ.store(frequency_hz, Ordering::Relaxed);
+    }
+
+    let mut cntkctl: u64;
+    asm!("mrs $0, cntkctl_el1" : "=r"(cntkctl) ::: "volatile");
+    // Bits 4 to 7 contain the bit to watch, and bit 2 enables the event stream.
+    cntkctl = (cntkctl & !0xf0) | (EVENT_STREAM_BIT << 4) | (1 << 2);
+    asm!("msr cntkctl_el1, $0" :: "r"(cntkctl) :: "volatile");
+}
+
+/// Returns the amount of time that has elapsed since an undeterminate moment in time.
+pub fn monotonic_clock() -> Duration {
+    let ticks: u64;
+    unsafe {
+        // The `isb` prevents the counter from being read ahead of time.
+        asm!("isb; mrs $0, cntpct_el0" : "=r"(ticks) ::: "volatile");
+    }
+
+    let nanos = u128::from(ticks) * 1_000_000_000 / u128::from(counter_frequency());
+    Duration::new(
+        u64::try_from(nanos / 1_000_000_000).unwrap(),
+        u32::try_from(nanos % 1_000_000_000).unwrap(),
+    )
+}
+
+/// Returns the amount of time that has elapsed since the UNIX epoch.
+// TODO: we return the time since boot; this should be read from the real-time clock if there is
+//       one, or eventually be provided by the network
+pub fn system_clock() -> Duration {
+    monotonic_clock()
+}
+
+/// Registers a `Waker` to wake up when the monotonic clock reaches `deadline`.
+///
+/// Only the latest registered `Waker` and deadline are taken into account.
+pub fn set_timer_waker(deadline: Duration, waker: &Waker) {
+    if deadline <= monotonic_clock() {
+        waker.wake_by_ref();
+        return;
+    }
+
+    *TIMER.lock() = Some((deadline, waker.clone()));
+}
+
+/// Wakes up the `Waker` registered with [`set_timer_waker`] if its deadline has been reached.
+pub fn wake_expired_timer() {
+    let mut timer = TIMER.lock();
+    let expired = match &*timer {
+        Some((deadline, _)) => *deadline <= monotonic_clock(),
+        None => false,
+    };
+
+    if expired {
+        timer.take().unwrap().1.wake();
+    }
+}
+
+/// Returns the frequency of the counter, in Hz.
+fn counter_frequency() -> u64 {
+    let frequency: u64;
+    unsafe {
+        asm!("mrs $0, cntfrq_el0" : "=r"(frequency) ::: "volatile");
+    }
+
+    // Only the lowest 32 bits are meaningful.
+    match (
+        frequency & 0xffff_ffff,
+        DEVICE_TREE_FREQUENCY_HZ.load(Ordering::Relaxed),
+    ) {
+        (0, 0) => DEFAULT_FREQUENCY_HZ,
+        (0, from_device_tree) => u64::from(from_device_tree),
+        (f, _) => f,
+    }
+}
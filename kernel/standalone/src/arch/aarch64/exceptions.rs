@@ -0,0 +1,43 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Exceptions handling.
+//!
+//! The exception vectors, defined in the `_start` function, jump to [`exception_handler`] for
+//! every exception, including interrupts. Since interrupts are never unmasked, any exception
+//! indicates a bug, and the handler panics.
+//!
+//! See chapter D1.10 of the ARM® Architecture Reference Manual (ARMv8, for ARMv8-A architecture
+//! profile).
+
+/// Called by the exception vectors.
+///
+/// `vector` is the index of the vector within the table (0 to 15), `esr` the value of the
+/// `ESR_EL1` register (syndrome), `far` the value of `FAR_EL1` (faulting address), and `elr` the
+/// value of `ELR_EL1` (address of the faulting instruction).
+#[no_mangle]
+extern "C" fn exception_handler(vector: u64, esr: u64, far: u64, elr: u64) -> ! {
+    let kind = match vector % 4 {
+        0 => "synchronous exception",
+        1 => "IRQ",
+        2 => "FIQ",
+        _ => "SError",
+    };
+
+    panic!(
+        "Unexpected {} (vector: {}, ESR: {:#x}, FAR: {:#x}, ELR: {:#x})",
+        kind, vector, esr, far, elr
+    )
+}
@@ -13,11 +13,21 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-#![cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+#![cfg(target_arch = "arm")]
 
-use core::iter;
+use core::{slice, task::Waker};
 
+mod atags;
+mod clock;
 mod misc;
+mod paging;
+mod platform;
+
+use super::pl011;
+
+pub use self::clock::{monotonic_clock, set_timer_waker, system_clock, wake_expired_timer};
+pub use self::paging::map_mmio;
+pub use super::pl011::write as write_uart;
 
 // TODO: always fails :-/
 /*#[cfg(not(any(target_feature = "armv7-a", target_feature = "armv7-r")))]
@@ -43,9 +53,17 @@ unsafe extern "C" fn _start() -> ! {
 
     // Only one CPU reaches here.
 
-    // Set up the stack.
+    // Set up the stack. The stack is preceded with a guard page that is left unmapped once the
+    // MMU is enabled.
     asm!(r#"
-    .comm stack, 0x400000, 8
+    .pushsection .bss
+    .balign 4096
+    .global stack_guard
+    stack_guard:
+    .skip 4096
+    stack:
+    .skip 0x400000
+    .popsection
     ldr sp, =stack+0x400000"#:::"memory":"volatile");
 
     // On ARM platforms, the `r0`, `r1` and `r2` registers are used to pass the first three
@@ -63,28 +81,45 @@ unsafe extern "C" fn _start() -> ! {
 /// Main Rust entry point. The three parameters are the values of the `r0`, `r1` and `r2`
 /// registers as they were when we entered the kernel.
 #[no_mangle]
-fn cpu_enter(_r0: u32, _r1: u32, _r2: u32) -> ! {
-    unsafe {
-        // TODO: RAM starts at 0, but we start later to avoid the kernel
-        // TODO: make this is a cleaner way
-        crate::mem_alloc::initialize(iter::once(0xa000000..0x40000000));
+fn cpu_enter(_r0: u32, _r1: u32, r2: u32) -> ! {
+    // The `r2` parameter is set by the bootloader, and points either to ATAGS or a DTB (device
+    // tree) indicating what the hardware supports.
+    let platform = unsafe { platform::Platform::from_boot_parameters(r2 as usize) };
+
+    if let Some(uart) = platform.uart {
+        unsafe {
+            pl011::init(uart as usize);
+        }
     }
 
-    // TODO: The `r0`, `r1` and `r2` parameters are supposedly set by the bootloader, and `r2`
-    // points either to ATAGS or a DTB (device tree) indicating what the hardware supports. This
-    // is unfortunately not supported by QEMU as of the writing of this comment.
+    unsafe {
+        crate::mem_alloc::initialize(platform.free_memory());
+    }
 
-    // Initialize performance counters.
-    // TODO: do that properly and well isolated
-    // TODO: also, we just assume that counters are supported
     unsafe {
-        asm!("mcr p15, 0, $0, c9, c12, 0"::"r"(0b111u32)::"volatile");
-        asm!("mcr p15, 0, $0, c9, c12, 1"::"r"(0x8000000fu32)::"volatile");
-        asm!("mcr p15, 0, $0, c9, c12, 3"::"r"(0x8000000fu32)::"volatile");
+        paging::init(platform.ram.clone(), platform.peripherals.clone());
+        clock::init(platform.timer_frequency);
     }
 
+    // TODO: interrupts aren't supported on ARM yet; for now we only make sure that the registers
+    //       of the interrupt controller are accessible
+    if let Some(interrupt_controller) = &platform.interrupt_controller {
+        let _ = paging::map_mmio(
+            u64::from(interrupt_controller.start)..u64::from(interrupt_controller.end),
+        );
+    }
+
+    let modules_bundle = match &platform.initrd {
+        Some(initrd) => unsafe {
+            slice::from_raw_parts(initrd.start as *const u8, initrd.end - initrd.start)
+        },
+        None => &[],
+    };
+
     let kernel = crate::kernel::Kernel::init(crate::kernel::KernelConfig {
         num_cpus: 1,
+        modules_bundle,
+        device_tree: platform.device_tree.map(|dt| dt.as_bytes()),
         ..Default::default()
     });
 
@@ -103,6 +138,38 @@ pub fn halt() -> ! {
     }
 }
 
+// TODO: only one CPU is started on ARM at the moment
+pub fn current_cpu() -> u32 {
+    0
+}
+
+pub fn wake_cpu(cpu: u32) {}
+
+pub fn virtual_to_physical(ptr: usize) -> u64 {
+    // The memory is identity-mapped.
+    ptr as u64
+}
+
+// TODO: caches aren't coherent with DMA on ARM; we should map allocations as uncached
+pub fn dma_cache_coherent() -> bool {
+    false
+}
+
+// TODO: interrupts aren't supported on ARM yet
+pub fn enable_irq(irq: u32) -> Result<(), ()> {
+    Err(())
+}
+
+pub fn disable_irq(irq: u32) {}
+
+pub fn unmask_irq(irq: u32) {}
+
+pub fn irq_count(irq: u32) -> Option<u64> {
+    None
+}
+
+pub fn set_irq_waker(irq: u32, waker: &Waker) {}
+
 pub unsafe fn write_port_u8(port: u32, data: u8) {}
 
 pub unsafe fn write_port_u16(port: u32, data: u16) {}
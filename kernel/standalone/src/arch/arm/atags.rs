@@ -0,0 +1,67 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Parsing of the ATAGS list passed by the bootloader.
+//!
+//! The ATAGS list is a sequence of tags describing the hardware and the boot parameters, and is
+//! the ancestor of the device tree. Each tag starts with its size in 32 bits words, followed with
+//! its type.
+//!
+//! See also http://www.simtec.co.uk/products/SWLINUX/files/booting_article.html#appendix_tag_reference
+
+use core::ops::Range;
+
+const ATAG_NONE: u32 = 0x0000_0000;
+const ATAG_CORE: u32 = 0x5441_0001;
+const ATAG_INITRD2: u32 = 0x5442_0005;
+
+/// Looks for the initial RAM disk in the ATAGS list found at `ptr`, and returns its location in
+/// physical memory.
+///
+/// Returns `None` if `ptr` doesn't point to an ATAGS list, or if the list doesn't mention any
+/// initial RAM disk.
+///
+/// # Safety
+///
+/// `ptr` must either be null or point to readable memory.
+pub unsafe fn find_initrd(ptr: usize) -> Option<Range<usize>> {
+    let mut ptr = ptr as *const u32;
+    if ptr.is_null() {
+        return None;
+    }
+
+    // The list always starts with an `ATAG_CORE` tag. If that's not the case, then `ptr` points
+    // to something else, such as a device tree.
+    if ptr.add(1).read_volatile() != ATAG_CORE {
+        return None;
+    }
+
+    loop {
+        let size = ptr.read_volatile();
+        let tag = ptr.add(1).read_volatile();
+
+        if tag == ATAG_NONE || size < 2 {
+            return None;
+        }
+
+        if tag == ATAG_INITRD2 && size >= 4 {
+            let start = ptr.add(2).read_volatile() as usize;
+            let len = ptr.add(3).read_volatile() as usize;
+            return Some(start..start + len);
+        }
+
+        ptr = ptr.add(size as usize);
+    }
+}
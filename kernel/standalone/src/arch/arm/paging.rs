@@ -0,0 +1,264 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Translation tables management.
+//!
+//! The bootloader hands over control with the MMU disabled. [`init`] builds translation tables,
+//! using the short-descriptor format of ARMv7, and enables the MMU. In these tables:
+//!
+//! - The RAM is mapped as normal cacheable memory, with 1MiB sections.
+//! - The guard page below the kernel stack is left unmapped, so that a stack overflow triggers a
+//!   fault rather than silently corrupting memory.
+//! - Memory-mapped devices are mapped on demand as device memory through [`map_mmio`].
+//! - Everything else is left unmapped.
+//!
+//! The memory stays identity-mapped, in other words virtual addresses are always equal to
+//! physical addresses.
+//!
+//! See chapter B3 of the ARM® Architecture Reference Manual (ARMv7-A and ARMv7-R edition).
+
+use alloc::vec::Vec;
+use core::{convert::TryFrom as _, ops::Range};
+use spin::Mutex;
+
+/// Size of a section, as mapped by a first-level descriptor.
+const SECTION_SIZE: u32 = 0x10_0000;
+/// Size of a small page, as mapped by a second-level descriptor.
+const PAGE_SIZE: u32 = 0x1000;
+
+/// Alignment and size of the first-level table.
+const L1_TABLE_SIZE: usize = 0x4000;
+/// Alignment and size of a second-level table.
+const L2_TABLE_SIZE: usize = 0x400;
+
+/// First-level descriptor bits.
+const L1_PAGE_TABLE: u32 = 0b01;
+const L1_SECTION: u32 = 0b10;
+const L1_SECTION_NORMAL: u32 = (0b001 << 12) | (1 << 3) | (1 << 2);
+const L1_SECTION_DEVICE: u32 = 1 << 2;
+const L1_SECTION_FULL_ACCESS: u32 = 0b11 << 10;
+
+/// Second-level descriptor bits.
+const L2_SMALL_PAGE: u32 = 0b10;
+const L2_SMALL_PAGE_NORMAL: u32 = (0b001 << 6) | (1 << 3) | (1 << 2);
+const L2_SMALL_PAGE_DEVICE: u32 = 1 << 2;
+const L2_SMALL_PAGE_FULL_ACCESS: u32 = 0b11 << 4;
+
+/// Kind of memory that a range of addresses is mapped to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MemoryKind {
+    /// RAM. Cached.
+    Ram,
+    /// Memory-mapped device. Uncached.
+    Mmio,
+}
+
+/// Translation tables managed by the kernel. `None` until [`init`] is called.
+static PAGE_TABLES: Mutex<Option<PageTables>> = Mutex::new(None);
+
+struct PageTables {
+    /// Physical address of the first-level table.
+    l1: u32,
+    /// List of ranges that are currently mapped.
+    mapped: Vec<(Range<u32>, MemoryKind)>,
+}
+
+/// Builds the kernel's translation tables and enables the MMU.
+///
+/// `ram` and `peripherals` must be aligned on 1MiB boundaries.
+///
+/// # Safety
+///
+/// Must only be called once, from the CPU that booted. The heap must have been initialized.
+pub unsafe fn init(ram: Range<u32>, peripherals: Range<u32>) {
+    extern "C" {
+        // Defined in the `_start` function.
+        static stack_guard: u8;
+    }
+
+    let guard = &stack_guard as *const u8 as u32;
+
+    let mut tables = PageTables::new();
+    if ram.start <= guard && guard < ram.end {
+        tables.map(ram.start..guard, MemoryKind::Ram);
+        tables.map(guard + PAGE_SIZE..ram.end, MemoryKind::Ram);
+    } else {
+        tables.map(ram, MemoryKind::Ram);
+    }
+    // TODO: peripherals are mapped upfront because the panic handler writes to the UART without
+    // going through `map_mmio`
+    tables.map(peripherals, MemoryKind::Mmio);
+
+    // TTBCR = 0, so that TTBR0 is used for the whole address space.
+    asm!("mcr p15, 0, $0, c2, c0, 2" :: "r"(0u32) :: "volatile");
+    // TTBR0
+    asm!("mcr p15, 0, $0, c2, c0, 0" :: "r"(tables.l1) :: "volatile");
+    // DACR: domain 0 is a client, meaning that the access permissions of the descriptors are
+    // checked.
+    asm!("mcr p15, 0, $0, c3, c0, 0" :: "r"(1u32) :: "volatile");
+    // TLBIALL
+    asm!("mcr p15, 0, $0, c8, c7, 0" :: "r"(0u32) :: "volatile");
+    asm!("dsb; isb" ::: "memory" : "volatile");
+
+    // Set the M bit of SCTLR.
+    let sctlr: u32;
+    asm!("mrc p15, 0, $0, c1, c0, 0" : "=r"(sctlr) ::: "volatile");
+    asm!("mcr p15, 0, $0, c1, c0, 0" :: "r"(sctlr | 1) :: "volatile");
+    asm!("isb" ::: "memory" : "volatile");
+
+    *PAGE_TABLES.lock() = Some(tables);
+}
+
+/// Makes sure that the given range of physical memory, corresponding to a memory-mapped device,
+/// is mapped as device memory.
+///
+/// Returns an error if the range overlaps RAM or is out of the 32-bits address space, in which
+/// case nothing is mapped.
+///
+/// Before [`init`] has been called, everything is considered mapped.
+pub fn map_mmio(range: Range<u64>) -> Result<(), ()> {
+    let mut page_tables = PAGE_TABLES.lock();
+    let tables = match page_tables.as_mut() {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+
+    let start =
+        u32::try_from(range.start / u64::from(PAGE_SIZE) * u64::from(PAGE_SIZE)).map_err(|_| ())?;
+    let end = u32::try_from(
+        range.end.checked_add(u64::from(PAGE_SIZE) - 1).ok_or(())? / u64::from(PAGE_SIZE)
+            * u64::from(PAGE_SIZE),
+    )
+    .map_err(|_| ())?;
+
+    if tables
+        .mapped
+        .iter()
+        .any(|(r, kind)| *kind == MemoryKind::Ram && r.start < end && start < r.end)
+    {
+        return Err(());
+    }
+
+    // Map the pages that aren't mapped yet.
+    let mut cursor = start;
+    while cursor < end {
+        match tables
+            .mapped
+            .iter()
+            .find(|(r, _)| r.start <= cursor && cursor < r.end)
+        {
+            Some((r, _)) => cursor = r.end,
+            None => {
+                let next_mapped = tables
+                    .mapped
+                    .iter()
+                    .map(|(r, _)| r.start)
+                    .filter(|s| *s > cursor)
+                    .min()
+                    .unwrap_or(end)
+                    .min(end);
+                tables.map(cursor..next_mapped, MemoryKind::Mmio);
+                cursor = next_mapped;
+            }
+        }
+    }
+
+    // Make sure that the table walk sees the new descriptors. Descriptors that generate a
+    // translation fault are never cached in the TLB, so no invalidation is necessary.
+    unsafe {
+        asm!("dsb; isb" ::: "memory" : "volatile");
+    }
+
+    Ok(())
+}
+
+impl PageTables {
+    fn new() -> Self {
+        PageTables {
+            l1: allocate_table(L1_TABLE_SIZE),
+            mapped: Vec::new(),
+        }
+    }
+
+    /// Identity-maps the given range, whose bounds must be aligned on pages boundaries. Uses
+    /// sections where possible.
+    ///
+    /// The range must not be mapped already.
+    fn map(&mut self, range: Range<u32>, kind: MemoryKind) {
+        debug_assert_eq!(range.start % PAGE_SIZE, 0);
+        debug_assert_eq!(range.end % PAGE_SIZE, 0);
+        if range.start >= range.end {
+            return;
+        }
+
+        let (section_flags, page_flags) = match kind {
+            MemoryKind::Ram => (
+                L1_SECTION | L1_SECTION_FULL_ACCESS | L1_SECTION_NORMAL,
+                L2_SMALL_PAGE | L2_SMALL_PAGE_FULL_ACCESS | L2_SMALL_PAGE_NORMAL,
+            ),
+            MemoryKind::Mmio => (
+                L1_SECTION | L1_SECTION_FULL_ACCESS | L1_SECTION_DEVICE,
+                L2_SMALL_PAGE | L2_SMALL_PAGE_FULL_ACCESS | L2_SMALL_PAGE_DEVICE,
+            ),
+        };
+
+        let mut address = range.start;
+        while address < range.end {
+            if address % SECTION_SIZE == 0 && address + SECTION_SIZE <= range.end {
+                unsafe {
+                    *l1_entry(self.l1, address) = address | section_flags;
+                }
+                address += SECTION_SIZE;
+            } else {
+                let l2 = self.l2_table_for(address);
+                unsafe {
+                    *l2_entry(l2, address) = address | page_flags;
+                }
+                address += PAGE_SIZE;
+            }
+        }
+
+        self.mapped.push((range, kind));
+    }
+
+    /// Returns the physical address of the second-level table that contains the entry for
+    /// `address`. Creates it if necessary.
+    fn l2_table_for(&mut self, address: u32) -> u32 {
+        let entry = unsafe { &mut *l1_entry(self.l1, address) };
+        if (*entry & 0b11) == 0 {
+            *entry = allocate_table(L2_TABLE_SIZE) | L1_PAGE_TABLE;
+        }
+        debug_assert_eq!(*entry & 0b11, L1_PAGE_TABLE);
+        *entry & !0x3ff
+    }
+}
+
+/// Returns a pointer to the first-level descriptor corresponding to `address`.
+fn l1_entry(l1: u32, address: u32) -> *mut u32 {
+    (l1 as usize as *mut u32).wrapping_add((address >> 20) as usize)
+}
+
+/// Returns a pointer to the descriptor corresponding to `address` in the second-level table
+/// at `l2`.
+fn l2_entry(l2: u32, address: u32) -> *mut u32 {
+    (l2 as usize as *mut u32).wrapping_add(((address >> 12) & 0xff) as usize)
+}
+
+/// Allocates a new empty translation table and returns its physical address.
+fn allocate_table(size: usize) -> u32 {
+    let table = crate::mem_alloc::allocate_frames(size, size)
+        .expect("out of memory while allocating translation tables");
+    u32::try_from(super::virtual_to_physical(table.as_ptr() as usize)).unwrap()
+}
@@ -0,0 +1,212 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Discovery of the hardware.
+//!
+//! The bootloader passes to the kernel, in the `r2` register, a pointer to either a device tree
+//! or an ATAGS list. Device trees describe the hardware, while ATAGS lists only indicate the boot
+//! parameters, in which case we assume that we are running on a Raspberry Pi 2.
+
+use super::atags;
+
+use core::{convert::TryFrom as _, ops::Range};
+use redshirt_fdt::DeviceTree;
+
+/// Information about the hardware we are running on.
+#[derive(Debug)]
+pub struct Platform {
+    /// Range of physical memory containing RAM. Aligned on 1MiB.
+    pub ram: Range<u32>,
+    /// Range of physical memory containing the memory-mapped peripherals that must be mapped
+    /// when the MMU is enabled. Aligned on 1MiB.
+    pub peripherals: Range<u32>,
+    /// Physical address of the registers of the PL011 UART, if any.
+    pub uart: Option<u32>,
+    /// Frequency of the generic timer, if indicated in the device tree.
+    pub timer_frequency: Option<u64>,
+    /// Range of physical memory containing the registers of the interrupt controller, if known.
+    pub interrupt_controller: Option<Range<u32>>,
+    /// Location in physical memory of the initial RAM disk, if any.
+    pub initrd: Option<Range<usize>>,
+    /// Device tree passed by the bootloader, if any.
+    pub device_tree: Option<DeviceTree<'static>>,
+}
+
+/// Size of a section, which is the granularity of [`Platform::ram`] and
+/// [`Platform::peripherals`].
+const SECTION_SIZE: u32 = 0x10_0000;
+
+impl Platform {
+    /// Discovers the hardware from the device tree or ATAGS list pointed to by `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the value of `r2` passed by the bootloader.
+    pub unsafe fn from_boot_parameters(ptr: usize) -> Platform {
+        match DeviceTree::from_ptr(ptr as *const u8) {
+            Ok(device_tree) => Platform::from_device_tree(device_tree),
+            Err(_) => Platform {
+                initrd: atags::find_initrd(ptr),
+                ..Platform::raspberry_pi_2()
+            },
+        }
+    }
+
+    /// Returns the ranges of RAM that can be used as a heap.
+    pub fn free_memory(&self) -> impl Iterator<Item = Range<usize>> {
+        extern "C" {
+            // Defined in `arm-freestanding.ld`.
+            static __image_end: u8;
+        }
+
+        // The kernel is loaded at the start of the RAM, and the heap starts after it.
+        let image_end = unsafe { &__image_end as *const u8 as usize };
+        let heap = (self.ram.start as usize).max(image_end)..self.ram.end as usize;
+
+        let device_tree = self.device_tree.map(|dt| {
+            let start = dt.as_bytes().as_ptr() as usize;
+            start..start + dt.as_bytes().len()
+        });
+
+        let mut ranges: [Range<usize>; 3] = [heap, 0..0, 0..0];
+        let mut num_ranges = 1;
+        for reserved in self.initrd.iter().cloned().chain(device_tree) {
+            for n in 0..num_ranges {
+                let range = ranges[n].clone();
+                if reserved.end <= range.start || reserved.start >= range.end {
+                    continue;
+                }
+
+                ranges[n] = range.start..reserved.start.max(range.start);
+                if reserved.end < range.end {
+                    ranges[num_ranges] = reserved.end..range.end;
+                    num_ranges += 1;
+                }
+            }
+        }
+
+        (0..num_ranges)
+            .map(move |n| ranges[n].clone())
+            .filter(|r| r.start < r.end)
+    }
+
+    fn from_device_tree(device_tree: DeviceTree<'static>) -> Platform {
+        let fallback = Platform::raspberry_pi_2();
+
+        // Registers of the first device compatible with one of the given values, translated to
+        // physical memory.
+        let find_device = |compatible: &[&str]| {
+            device_tree
+                .nodes()
+                .filter(|n| compatible.iter().any(|c| n.is_compatible(c)))
+                .filter_map(|n| {
+                    let (address, size) = n.reg().next()?;
+                    let start = device_tree.translate_address(&n, address)?;
+                    let end = start.checked_add(size)?;
+                    Some(u32::try_from(start).ok()?..u32::try_from(end).ok()?)
+                })
+                .next()
+        };
+
+        // We only support a single range of RAM, as the kernel is linked at a fixed address.
+        // TODO: support multiple ranges
+        let ram = device_tree
+            .nodes()
+            .filter(|n| n.property_str("device_type") == Some("memory"))
+            .filter_map(|n| n.reg().next())
+            .filter_map(|(start, size)| {
+                let start = u32::try_from(start).ok()?;
+                let end = u32::try_from(u64::from(start) + size).unwrap_or(u32::max_value());
+                Some(align_up(start)..(end / SECTION_SIZE * SECTION_SIZE))
+            })
+            .find(|r| r.start < r.end)
+            .unwrap_or(fallback.ram);
+
+        let uart = find_device(&["arm,pl011"]).map(|r| r.start);
+        let interrupt_controller = find_device(&[
+            "brcm,bcm2836-armctrl-ic",
+            "brcm,bcm2835-armctrl-ic",
+            "arm,cortex-a15-gic",
+            "arm,cortex-a7-gic",
+        ]);
+
+        // On the Raspberry Pi, the peripherals are children of the `/soc` node. Otherwise, we
+        // only map the UART upfront, and the rest is mapped on demand.
+        // TODO: the UART is mapped upfront because the panic handler needs it
+        let peripherals = device_tree
+            .node_by_path("/soc")
+            .into_iter()
+            .flat_map(|soc| soc.ranges())
+            .filter_map(|(_, start, size)| {
+                let end = start.checked_add(size)?;
+                Some(u32::try_from(start).ok()?..u32::try_from(end).ok()?)
+            })
+            .chain(uart.map(|uart| uart..uart + 1))
+            .fold(None, |hull: Option<Range<u32>>, r| match hull {
+                Some(hull) => Some(hull.start.min(r.start)..hull.end.max(r.end)),
+                None => Some(r),
+            })
+            .map(|r| (r.start / SECTION_SIZE * SECTION_SIZE)..align_up(r.end))
+            .filter(|r| r.end <= ram.start || r.start >= ram.end)
+            .unwrap_or(fallback.peripherals);
+
+        let initrd = device_tree.node_by_path("/chosen").and_then(|chosen| {
+            let start = chosen.property_int("linux,initrd-start")?;
+            let end = chosen.property_int("linux,initrd-end")?;
+            if start >= end {
+                return None;
+            }
+            Some(usize::try_from(start).ok()?..usize::try_from(end).ok()?)
+        });
+
+        let timer_frequency = device_tree
+            .nodes()
+            .filter(|n| n.is_compatible("arm,armv7-timer") || n.is_compatible("arm,armv8-timer"))
+            .filter_map(|n| n.property_int("clock-frequency"))
+            .next();
+
+        Platform {
+            ram,
+            peripherals,
+            uart,
+            timer_frequency,
+            interrupt_controller,
+            initrd,
+            device_tree: Some(device_tree),
+        }
+    }
+
+    /// Returns the hardware of the Raspberry Pi 2.
+    fn raspberry_pi_2() -> Platform {
+        Platform {
+            ram: 0..0x3f00_0000,
+            peripherals: 0x3f00_0000..0x4010_0000,
+            uart: Some(0x3f20_1000),
+            timer_frequency: None,
+            interrupt_controller: Some(0x3f00_b200..0x3f00_b400),
+            initrd: None,
+            device_tree: None,
+        }
+    }
+}
+
+/// Rounds up the given address to a multiple of [`SECTION_SIZE`], saturating at the top of the
+/// address space.
+fn align_up(address: u32) -> u32 {
+    match address.checked_add(SECTION_SIZE - 1) {
+        Some(a) => a / SECTION_SIZE * SECTION_SIZE,
+        None => u32::max_value() / SECTION_SIZE * SECTION_SIZE,
+    }
+}
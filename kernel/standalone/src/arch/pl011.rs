@@ -0,0 +1,68 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+
+//! Output through a PL011 UART.
+//!
+//! The PL011 is the UART found in QEMU's `virt` machine and in the Raspberry Pi. Its location is
+//! found in the device tree.
+//!
+//! The baud rate is left to whatever the firmware has configured.
+//!
+//! See the ARM PrimeCell UART (PL011) Technical Reference Manual.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Offsets of the registers.
+const DR: usize = 0x0;
+const FR: usize = 0x18;
+const LCRH: usize = 0x2c;
+const CR: usize = 0x30;
+
+/// Base address of the registers of the UART. 0 if [`init`] hasn't been called.
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Configures the UART whose registers are at the given address, for 8 data bits, no parity,
+/// one stop bit.
+///
+/// # Safety
+///
+/// `base` must be the address of the registers of a PL011.
+pub unsafe fn init(base: usize) {
+    // The UART must be disabled while being configured.
+    ((base + CR) as *mut u32).write_volatile(0);
+    // 8 bits words, FIFOs enabled.
+    ((base + LCRH) as *mut u32).write_volatile((0b11 << 5) | (1 << 4));
+    // Enable the UART, transmission and reception.
+    ((base + CR) as *mut u32).write_volatile((1 << 0) | (1 << 8) | (1 << 9));
+    BASE.store(base, Ordering::SeqCst);
+}
+
+/// Writes data to the UART. Does nothing if [`init`] hasn't been called.
+pub fn write(data: &[u8]) {
+    let base = BASE.load(Ordering::SeqCst);
+    if base == 0 {
+        return;
+    }
+
+    for byte in data {
+        unsafe {
+            // Wait for the transmit FIFO to have some space.
+            while (((base + FR) as *mut u32).read_volatile() & (1 << 5)) != 0 {}
+            ((base + DR) as *mut u32).write_volatile(u32::from(*byte));
+        }
+    }
+}
@@ -15,13 +15,21 @@
 
 #![cfg(target_arch = "x86_64")]
 
-use core::{convert::TryFrom as _, ops::Range};
-use x86_64::registers::model_specific::Msr;
+use alloc::boxed::Box;
+use core::{convert::TryFrom as _, ops::Range, slice, task::Waker};
 use x86_64::structures::port::{PortRead as _, PortWrite as _};
 
 mod acpi;
+mod ap_boot;
+mod apic;
 mod boot_link;
+mod clock;
+mod gdt;
 mod interrupts;
+mod paging;
+mod rtc;
+
+pub use self::clock::{monotonic_clock, set_timer_waker, system_clock};
 
 /// Called by `boot.S` after basic set up has been performed.
 ///
@@ -39,14 +47,58 @@ extern "C" fn after_boot(multiboot_header: usize) -> ! {
 
         // TODO: panics in BOCHS
         //let acpi = acpi::load_acpi_tables(&multiboot_info);
+        let pci_ecam_regions = acpi::find_pci_ecam_regions(&multiboot_info);
+        let processors = acpi::find_processors(&multiboot_info);
+        let io_apic = acpi::find_io_apic(&multiboot_info);
+        let modules_bundle = find_modules_bundle(&multiboot_info);
+        let framebuffer = find_framebuffer(&multiboot_info);
+
+        paging::init(&multiboot_info);
 
-        init_pic_apic();
+        // The framebuffer is mapped now rather than when panicking, as the page tables might be
+        // locked at that point.
+        if let Some(framebuffer) = &framebuffer {
+            let len = u64::from(framebuffer.pitch) * u64::from(framebuffer.height);
+            if map_mmio(framebuffer.address..framebuffer.address + len).is_ok() {
+                crate::panic::set_framebuffer(framebuffer.clone());
+            } else {
+                klog!("Can't map the framebuffer at {:#x}", framebuffer.address);
+            }
+        }
+
+        gdt::init();
+        init_pic_apic(io_apic);
+        clock::init();
         interrupts::init();
 
-        let kernel = crate::kernel::Kernel::init(crate::kernel::KernelConfig {
-            num_cpus: 1,
-            ..Default::default()
-        });
+        let kernel: &'static _ = Box::leak(Box::new(crate::kernel::Kernel::init(
+            crate::kernel::KernelConfig {
+                num_cpus: u32::try_from(processors.len().max(1)).unwrap(),
+                pci_ecam_regions,
+                modules_bundle,
+                framebuffer,
+                ..Default::default()
+            },
+        )));
+
+        // Start the other CPUs, and make them run the kernel as well. For now, only the current
+        // CPU runs programs, and the other CPUs stay halted. See the `kernel` module.
+        let local_apic_id = apic::current_apic_id();
+        for apic_id in processors.into_iter().filter(|id| *id != local_apic_id) {
+            let result = ap_boot::start_application_processor(
+                apic_id,
+                Box::new(move || {
+                    gdt::init();
+                    apic::init_local_apic();
+                    interrupts::init();
+                    kernel.run()
+                }),
+            );
+
+            if result.is_err() {
+                break;
+            }
+        }
 
         kernel.run()
     }
@@ -76,40 +128,57 @@ fn find_free_memory_ranges<'a>(
         let mut area_end = area.end_address();
         debug_assert!(area_start <= area_end);
 
+        // The first MiB is left untouched. Amongst other things, it contains the address 0,
+        // which can't be returned by an allocator.
+        area_start = area_start.max(0x10_0000);
+        if area_start >= area_end {
+            return None;
+        }
+
         // The kernel has probably been loaded into RAM, so we have to remove ELF sections
-        // from the portion of memory that we use.
-        for section in elf_sections.sections() {
-            if section.start_address() >= area_start && section.end_address() <= area_end {
+        // from the portion of memory that we use. The same goes for the modules loaded by the
+        // bootloader.
+        let reserved = elf_sections
+            .sections()
+            .map(|s| (s.start_address(), s.end_address()))
+            .chain(
+                multiboot_info
+                    .module_tags()
+                    .map(|m| (u64::from(m.start_address()), u64::from(m.end_address()))),
+            );
+
+        for (section_start, section_end) in reserved {
+            if section_start >= area_start && section_end <= area_end {
                 /*         ↓ section_start    section_end ↓
                 ==================================================
                     ↑ area_start                      area_end ↑
                 */
-                let off_bef = section.start_address() - area_start;
-                let off_aft = area_end - section.end_address();
+                let off_bef = section_start - area_start;
+                let off_aft = area_end - section_end;
                 if off_bef > off_aft {
-                    area_end = section.start_address();
+                    area_end = section_start;
                 } else {
-                    area_start = section.end_address();
+                    area_start = section_end;
                 }
-            } else if section.start_address() < area_start && section.end_address() > area_end {
+            } else if section_start < area_start && section_end > area_end {
                 /*    ↓ section_start             section_end ↓
                 ==================================================
                         ↑ area_start         area_end ↑
                 */
                 // We have no memory available!
                 return None;
-            } else if section.start_address() <= area_start && section.end_address() > area_start {
+            } else if section_start <= area_start && section_end > area_start {
                 /*    ↓ section_start     section_end ↓
                 ==================================================
                         ↑ area_start                 area_end ↑
                 */
-                area_start = section.end_address();
-            } else if section.start_address() < area_end && section.end_address() >= area_end {
+                area_start = section_end;
+            } else if section_start < area_end && section_end >= area_end {
                 /*         ↓ section_start      section_end ↓
                 ==================================================
                     ↑ area_start         area_end ↑
                 */
-                area_end = section.start_address();
+                area_end = section_start;
             }
         }
 
@@ -119,7 +188,61 @@ fn find_free_memory_ranges<'a>(
     })
 }
 
-unsafe fn init_pic_apic() {
+/// Returns the bundle of modules loaded by the bootloader as the first multiboot2 module, or an
+/// empty slice if there isn't any.
+///
+/// The memory of the module is excluded from the heap by [`find_free_memory_ranges`].
+fn find_modules_bundle(multiboot_info: &multiboot2::BootInformation) -> &'static [u8] {
+    let module = match multiboot_info.module_tags().next() {
+        Some(m) => m,
+        None => return &[],
+    };
+
+    let start = usize::try_from(module.start_address()).unwrap();
+    let len = usize::try_from(module.end_address() - module.start_address()).unwrap();
+    // The memory is identity-mapped. See `boot.S`.
+    unsafe { slice::from_raw_parts(start as *const u8, len) }
+}
+
+/// Returns the framebuffer that the bootloader has set up, if any.
+///
+/// See the framebuffer request tag in `x86_64-multiboot2.ld`.
+fn find_framebuffer(
+    multiboot_info: &multiboot2::BootInformation,
+) -> Option<redshirt_hardware_interface::ffi::FramebufferInfo> {
+    use redshirt_hardware_interface::ffi::{ColorField, FramebufferFormat, FramebufferInfo};
+
+    let tag = multiboot_info.framebuffer_tag()?;
+    let format = match tag.buffer_type {
+        multiboot2::FramebufferType::RGB { red, green, blue } => FramebufferFormat::Rgb {
+            red: ColorField {
+                position: red.position,
+                size: red.size,
+            },
+            green: ColorField {
+                position: green.position,
+                size: green.size,
+            },
+            blue: ColorField {
+                position: blue.position,
+                size: blue.size,
+            },
+        },
+        multiboot2::FramebufferType::Indexed { .. } => FramebufferFormat::Indexed,
+        multiboot2::FramebufferType::Text => FramebufferFormat::Text,
+    };
+
+    Some(FramebufferInfo {
+        address: tag.address,
+        width: tag.width,
+        height: tag.height,
+        pitch: tag.pitch,
+        bpp: tag.bpp,
+        format,
+    })
+}
+
+unsafe fn init_pic_apic(io_apic: Option<acpi::IoApicDescription>) {
     // Remap and disable the PIC.
     //
     // The PIC (Programmable Interrupt Controller) is the old chip responsible for triggering
@@ -147,19 +270,80 @@ unsafe fn init_pic_apic() {
     u8::write_to_port(0xa1, 0xff);
     u8::write_to_port(0x21, 0xff);
 
-    // Set up the APIC.
-    let apic_base_addr = {
-        const APIC_BASE_MSR: Msr = Msr::new(0x1b);
-        let base_addr = APIC_BASE_MSR.read() & !0xfff;
-        APIC_BASE_MSR.write(base_addr | 0x800); // Enable the APIC.
-        base_addr
-    };
+    apic::init_local_apic();
+    apic::init_io_apic(io_apic);
+}
 
-    // Enable spurious interrupts.
-    {
-        let svr_addr = usize::try_from(apic_base_addr + 0xf0).unwrap() as *mut u32;
-        let val = svr_addr.read_volatile();
-        svr_addr.write_volatile(val | 0x100); // Enable spurious interrupts.
+/// Returns an identifier of the CPU that calls this function.
+pub fn current_cpu() -> u32 {
+    u32::from(apic::current_apic_id())
+}
+
+/// Wakes up the given CPU, as returned by [`current_cpu`], if it is halted.
+pub fn wake_cpu(cpu: u32) {
+    if let Ok(apic_id) = u8::try_from(cpu) {
+        apic::send_wake_ipi(apic_id);
+    }
+}
+
+/// Makes sure that the given range of physical memory, corresponding to a memory-mapped device,
+/// is accessible with caching disabled.
+///
+/// Returns an error if the range overlaps RAM.
+pub fn map_mmio(range: Range<u64>) -> Result<(), ()> {
+    paging::map_mmio(range)
+}
+
+/// Returns the physical memory address corresponding to the given pointer.
+pub fn virtual_to_physical(ptr: usize) -> u64 {
+    // The memory is identity-mapped. See `boot.S`.
+    u64::try_from(ptr).unwrap()
+}
+
+/// Returns true if devices performing DMA see the same memory as the CPU caches, in which case
+/// there is no need to flush the caches.
+pub fn dma_cache_coherent() -> bool {
+    // On x86 and x86_64, the caches snoop the bus and are always coherent with DMA.
+    true
+}
+
+/// Starts delivering the interrupts of the given IRQ line.
+///
+/// Returns an error if the IRQ isn't supported.
+pub fn enable_irq(irq: u32) -> Result<(), ()> {
+    let irq = u8::try_from(irq).map_err(|_| ())?;
+    unsafe { apic::enable_irq(irq) }
+}
+
+/// Stops delivering the interrupts of the given IRQ line.
+pub fn disable_irq(irq: u32) {
+    if let Ok(irq) = u8::try_from(irq) {
+        apic::disable_irq(irq);
+    }
+}
+
+/// Indicates that the interrupts of the given IRQ have been handled, and that the next ones can
+/// be delivered.
+pub fn unmask_irq(irq: u32) {
+    if let Ok(irq) = u8::try_from(irq) {
+        apic::unmask_irq(irq);
+    }
+}
+
+/// Returns the number of times the given IRQ has been triggered since boot, or `None` if the
+/// IRQ isn't supported.
+pub fn irq_count(irq: u32) -> Option<u64> {
+    interrupts::irq_count(usize::try_from(irq).ok()?)
+}
+
+/// Registers a `Waker` to wake up when the given IRQ is triggered.
+///
+/// Has the same semantics as [`interrupts::set_interrupt_waker`].
+pub fn set_irq_waker(irq: u32, waker: &Waker) {
+    if let Ok(irq) = u8::try_from(irq) {
+        if usize::from(irq) < apic::MAX_IRQS {
+            interrupts::set_interrupt_waker(apic::IRQ_VECTORS_START + irq, waker);
+        }
     }
 }
 
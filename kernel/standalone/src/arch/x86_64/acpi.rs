@@ -14,6 +14,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use acpi::handler::PhysicalMapping;
+use alloc::vec::Vec;
 use core::ptr::NonNull;
 
 /// Loads ACPI tables from physical memory.
@@ -76,3 +77,243 @@ impl acpi::handler::AcpiHandler for DummyHandler {
 
     fn unmap_physical_region<T>(&mut self, region: PhysicalMapping<T>) {}
 }
+
+/// Finds the MCFG ACPI table and returns the list of PCI Express configuration space regions
+/// it contains.
+///
+/// Contrary to [`load_acpi_tables`], the tables are parsed manually and this function never
+/// panics. Returns an empty list if the tables can't be found, are invalid, or if there is no
+/// MCFG table.
+pub fn find_pci_ecam_regions(
+    multiboot_info: &multiboot2::BootInformation,
+) -> Vec<redshirt_hardware_interface::ffi::PciEcamRegion> {
+    let (table, table_len) = match find_table(multiboot_info, *b"MCFG") {
+        Some(t) => t,
+        None => return Vec::new(),
+    };
+
+    // The MCFG table contains the standard header, 8 reserved bytes, then a list of 16 bytes
+    // entries.
+    let mut out = Vec::new();
+    let mut region = table + SDT_HEADER_LEN + 8;
+    while region + 16 <= table + table_len {
+        unsafe {
+            out.push(redshirt_hardware_interface::ffi::PciEcamRegion {
+                base_address: (region as *const u64).read_unaligned(),
+                segment_group: ((region + 8) as *const u16).read_unaligned(),
+                start_bus: ((region + 10) as *const u8).read(),
+                end_bus: ((region + 11) as *const u8).read(),
+            });
+        }
+        region += 16;
+    }
+    out
+}
+
+/// Finds the MADT ACPI table and returns the local APIC IDs of the processors it contains,
+/// including the current one.
+///
+/// Just like [`find_pci_ecam_regions`], the tables are parsed manually and this function never
+/// panics. Returns an empty list if the tables can't be found, are invalid, or if there is no
+/// MADT.
+pub fn find_processors(multiboot_info: &multiboot2::BootInformation) -> Vec<u8> {
+    let (table, table_len) = match find_table(multiboot_info, *b"APIC") {
+        Some(t) => t,
+        None => return Vec::new(),
+    };
+
+    // The MADT contains the standard header, the address of the local APIC (4 bytes), some
+    // flags (4 bytes), then a list of variable-length entries. Each entry starts with its type
+    // and its length.
+    let mut out = Vec::new();
+    let mut entry = table + SDT_HEADER_LEN + 8;
+    while entry + 2 <= table + table_len {
+        let (ty, len) = unsafe {
+            (
+                (entry as *const u8).read(),
+                usize::from(((entry + 1) as *const u8).read()),
+            )
+        };
+        if len < 2 || entry + len > table + table_len {
+            break;
+        }
+
+        // Entries of type 0 describe a processor and its local APIC. They contain the ACPI
+        // processor ID (1 byte), the local APIC ID (1 byte), then flags (4 bytes) whose bit 0
+        // indicates whether the processor is enabled.
+        if ty == 0 && len >= 8 {
+            let (apic_id, flags) = unsafe {
+                (
+                    ((entry + 3) as *const u8).read(),
+                    ((entry + 4) as *const u32).read_unaligned(),
+                )
+            };
+            if (flags & 0x1) != 0 {
+                out.push(apic_id);
+            }
+        }
+
+        entry += len;
+    }
+    out
+}
+
+/// Description of the I/O APIC, as found in the MADT.
+#[derive(Debug)]
+pub struct IoApicDescription {
+    /// Physical memory address of the registers of the I/O APIC.
+    pub address: usize,
+    /// List of ISA IRQs that aren't connected to the pin of the same number, or that aren't
+    /// edge-triggered and active high.
+    pub overrides: Vec<InterruptSourceOverride>,
+}
+
+/// Entry of [`IoApicDescription::overrides`].
+#[derive(Debug, Copy, Clone)]
+pub struct InterruptSourceOverride {
+    /// ISA IRQ being overridden.
+    pub isa_irq: u8,
+    /// Pin of the I/O APIC the IRQ is connected to.
+    pub pin: u32,
+    /// True if the interrupt is signalled by the line staying at a certain level, rather than
+    /// by a change of level.
+    pub level_triggered: bool,
+    /// True if the line is active when low.
+    pub active_low: bool,
+}
+
+/// Finds the MADT ACPI table and returns the description of the I/O APIC whose pins start at
+/// global system interrupt 0.
+///
+/// Just like [`find_pci_ecam_regions`], the tables are parsed manually and this function never
+/// panics. Returns `None` if the tables can't be found, are invalid, or if there is no MADT or
+/// no such I/O APIC in it.
+pub fn find_io_apic(multiboot_info: &multiboot2::BootInformation) -> Option<IoApicDescription> {
+    let (table, table_len) = find_table(multiboot_info, *b"APIC")?;
+
+    let mut address = None;
+    let mut overrides = Vec::new();
+
+    // See `find_processors` for the layout of the MADT.
+    let mut entry = table + SDT_HEADER_LEN + 8;
+    while entry + 2 <= table + table_len {
+        let (ty, len) = unsafe {
+            (
+                (entry as *const u8).read(),
+                usize::from(((entry + 1) as *const u8).read()),
+            )
+        };
+        if len < 2 || entry + len > table + table_len {
+            break;
+        }
+
+        // Entries of type 1 describe an I/O APIC. They contain its ID (1 byte), a reserved
+        // byte, the address of its registers (4 bytes), then the global system interrupt
+        // corresponding to its first pin (4 bytes).
+        if ty == 1 && len >= 12 {
+            let (io_apic_address, gsi_base) = unsafe {
+                (
+                    ((entry + 4) as *const u32).read_unaligned(),
+                    ((entry + 8) as *const u32).read_unaligned(),
+                )
+            };
+            if gsi_base == 0 {
+                address = Some(io_apic_address as usize);
+            }
+        }
+
+        // Entries of type 2 are interrupt source overrides. They contain the bus (1 byte, always
+        // 0 for ISA), the IRQ on that bus (1 byte), the global system interrupt it is connected
+        // to (4 bytes), then flags (2 bytes). Bits 0 and 1 of the flags indicate the polarity,
+        // and bits 2 and 3 the trigger mode. A value of 0 means that the ISA default applies,
+        // which is active high and edge-triggered.
+        if ty == 2 && len >= 10 {
+            let (bus, isa_irq, gsi, flags) = unsafe {
+                (
+                    ((entry + 2) as *const u8).read(),
+                    ((entry + 3) as *const u8).read(),
+                    ((entry + 4) as *const u32).read_unaligned(),
+                    ((entry + 8) as *const u16).read_unaligned(),
+                )
+            };
+            if bus == 0 {
+                overrides.push(InterruptSourceOverride {
+                    isa_irq,
+                    pin: gsi,
+                    level_triggered: (flags >> 2) & 0x3 == 0x3,
+                    active_low: flags & 0x3 == 0x3,
+                });
+            }
+        }
+
+        entry += len;
+    }
+
+    Some(IoApicDescription {
+        address: address?,
+        overrides,
+    })
+}
+
+/// Finds the ACPI table with the given signature, and returns its address and length, including
+/// its header.
+///
+/// Returns `None` if there is no such table, or if the root table or the table itself are
+/// invalid.
+fn find_table(
+    multiboot_info: &multiboot2::BootInformation,
+    signature: [u8; 4],
+) -> Option<(usize, usize)> {
+    // The XSDT contains 64 bits pointers, while the RSDT contains 32 bits pointers.
+    let (root_table, entry_size) = if let Some(rsdp_v2) = multiboot_info.rsdp_v2_tag() {
+        (rsdp_v2.xsdt_address(), 8)
+    } else if let Some(rsdp_v1) = multiboot_info.rsdp_v1_tag() {
+        (rsdp_v1.rsdt_address(), 4)
+    } else {
+        return None;
+    };
+
+    unsafe {
+        let root_len = table_length(root_table)?;
+
+        let mut entry = root_table + SDT_HEADER_LEN;
+        while entry + entry_size <= root_table + root_len {
+            let table = if entry_size == 8 {
+                (entry as *const u64).read_unaligned() as usize
+            } else {
+                (entry as *const u32).read_unaligned() as usize
+            };
+            entry += entry_size;
+
+            if (table as *const [u8; 4]).read_unaligned() != signature {
+                continue;
+            }
+
+            if let Some(table_len) = table_length(table) {
+                return Some((table, table_len));
+            }
+        }
+    }
+
+    None
+}
+
+/// Size of the header common to all ACPI tables.
+const SDT_HEADER_LEN: usize = 36;
+
+/// Returns the length of the ACPI table at the given address, including its header, after
+/// verifying its checksum. Returns `None` if the table is invalid.
+unsafe fn table_length(table: usize) -> Option<usize> {
+    let len = ((table + 4) as *const u32).read_unaligned() as usize;
+    if len < SDT_HEADER_LEN {
+        return None;
+    }
+
+    // The sum of all the bytes of the table, including the checksum field, must be 0.
+    let bytes = core::slice::from_raw_parts(table as *const u8, len);
+    if bytes.iter().fold(0u8, |a, b| a.wrapping_add(*b)) != 0 {
+        return None;
+    }
+
+    Some(len)
+}
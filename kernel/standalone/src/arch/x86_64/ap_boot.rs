@@ -0,0 +1,171 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Starting the application processors.
+//!
+//! When the machine boots, only one CPU, named the bootstrap processor, starts running. The
+//! other CPUs, named application processors, have to be started by sending them an INIT
+//! inter-processor interrupt followed with a SIPI (Startup Inter-Processor Interrupt).
+//!
+//! After the SIPI, the processor starts executing code in real mode at an address below 1MiB.
+//! The trampoline found in `ap_boot.S` is copied to this address. It switches the processor to
+//! long mode and calls [`ap_after_boot`], which runs the function passed to
+//! [`start_application_processor`].
+//!
+//! See also https://wiki.osdev.org/Symmetric_Multiprocessing
+
+use super::apic;
+
+use alloc::boxed::Box;
+use core::{
+    convert::TryFrom as _,
+    mem, ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use spin::Mutex;
+use x86_64::registers::control::Cr3;
+
+/// Physical memory address where the trampoline is copied. Must be kept in sync with
+/// `ap_boot.S`.
+///
+/// Must be aligned on a page boundary and below 1MiB. The memory below 1MiB is never used by
+/// the heap.
+const TRAMPOLINE_ADDR: usize = 0x8000;
+
+/// Size of the stack of each application processor.
+const STACK_SIZE: usize = 0x10_0000;
+
+/// Parameters read by the trampoline. Must be kept in sync with `ap_boot.S`.
+#[repr(C)]
+struct TrampolineParams {
+    /// Value to load in the CR3 register.
+    cr3: u64,
+    /// Initial value of the stack pointer.
+    stack_top: u64,
+    /// Function to call.
+    entry: u64,
+    /// Parameter to pass to `entry`.
+    argument: u64,
+}
+
+extern "C" {
+    // Defined in `ap_boot.S`.
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_boot_params: u8;
+}
+
+/// Shared between the bootstrap processor and the application processor being started.
+struct ApStart {
+    /// Function to run on the application processor. Extracted by the application processor
+    /// when it starts.
+    entry: Mutex<Option<Box<dyn FnOnce() -> ! + Send>>>,
+    /// Set to true once the application processor has extracted `entry`, after which it never
+    /// accesses this struct again.
+    started: AtomicBool,
+}
+
+/// Starts the application processor whose local APIC has the given ID, and makes it run
+/// `entry`.
+///
+/// Blocks until the processor has started. Returns an error if the processor didn't start in
+/// time, in which case the other application processors shouldn't be started, as the processor
+/// might still start later and access the parameters of the trampoline.
+///
+/// The function runs on a newly-allocated stack, with interrupts disabled, and with the same
+/// page tables as the current processor. It is responsible for initializing the GDT, the
+/// local APIC and the IDT of the processor.
+///
+/// # Safety
+///
+/// Must only be called from the bootstrap processor, after the clock and the local APIC have
+/// been initialized. Must not be called multiple times for the same processor.
+pub unsafe fn start_application_processor(
+    apic_id: u8,
+    entry: Box<dyn FnOnce() -> ! + Send>,
+) -> Result<(), ()> {
+    let trampoline_start = &ap_trampoline_start as *const u8 as usize;
+    let trampoline_len = &ap_trampoline_end as *const u8 as usize - trampoline_start;
+    let params_offset = &ap_boot_params as *const u8 as usize - trampoline_start;
+
+    let stack = crate::mem_alloc::allocate_frames(STACK_SIZE, 0x1000).ok_or(())?;
+    // TODO: the stack has no guard page
+
+    let start = Box::new(ApStart {
+        entry: Mutex::new(Some(entry)),
+        started: AtomicBool::new(false),
+    });
+
+    ptr::copy_nonoverlapping(
+        trampoline_start as *const u8,
+        TRAMPOLINE_ADDR as *mut u8,
+        trampoline_len,
+    );
+    ((TRAMPOLINE_ADDR + params_offset) as *mut TrampolineParams).write_volatile(TrampolineParams {
+        cr3: Cr3::read().0.start_address().as_u64(),
+        stack_top: u64::try_from(stack.as_ptr() as usize + STACK_SIZE).unwrap(),
+        entry: u64::try_from(ap_after_boot as usize).unwrap(),
+        argument: u64::try_from(&*start as *const ApStart as usize).unwrap(),
+    });
+
+    // The sequence below is the one recommended by Intel: INIT, wait 10ms, SIPI, wait 200µs,
+    // and a second SIPI if the processor hasn't started yet.
+    apic::send_init_ipi(apic_id);
+    busy_wait(Duration::from_millis(10));
+
+    let page = u8::try_from(TRAMPOLINE_ADDR / 0x1000).unwrap();
+    for timeout in &[Duration::from_micros(200), Duration::from_secs(1)] {
+        apic::send_startup_ipi(apic_id, page);
+        if wait_started(&start.started, *timeout) {
+            return Ok(());
+        }
+    }
+
+    // The processor might still start later and access `start`, so we leak it.
+    mem::forget(start);
+    Err(())
+}
+
+/// Called by the trampoline once the application processor is in long mode.
+extern "C" fn ap_after_boot(start: usize) -> ! {
+    let entry = {
+        let start = unsafe { &*(start as *const ApStart) };
+        let entry = start.entry.lock().take().unwrap();
+        start.started.store(true, Ordering::Release);
+        entry
+    };
+
+    entry()
+}
+
+/// Waits until `started` is true, or until `timeout` has elapsed. Returns the value of
+/// `started`.
+fn wait_started(started: &AtomicBool, timeout: Duration) -> bool {
+    let deadline = super::monotonic_clock() + timeout;
+    while super::monotonic_clock() < deadline {
+        if started.load(Ordering::Acquire) {
+            return true;
+        }
+    }
+
+    started.load(Ordering::Acquire)
+}
+
+/// Busy-waits for the given duration.
+fn busy_wait(duration: Duration) {
+    let deadline = super::monotonic_clock() + duration;
+    while super::monotonic_clock() < deadline {}
+}
@@ -0,0 +1,390 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Local APIC and I/O APIC management.
+//!
+//! The local APIC is the per-CPU component that receives interrupts and delivers them to the CPU.
+//! The I/O APIC is the component that receives interrupts from the hardware (keyboard, network
+//! cards, ...) and forwards them to one or more local APICs.
+//!
+//! Hardware interrupts are identified by their IRQ number. IRQs 0 to 15 are the ones of the
+//! legacy ISA bus, and the ACPI tables indicate which pin of the I/O APIC they are connected to.
+//! The other IRQs correspond to the pin of the same number. IRQ `n` is delivered on interrupt
+//! vector [`IRQ_VECTORS_START`]` + n`.
+//!
+//! See also https://wiki.osdev.org/APIC and https://wiki.osdev.org/IOAPIC
+
+use super::acpi::{InterruptSourceOverride, IoApicDescription};
+use alloc::vec::Vec;
+use core::{
+    convert::TryFrom as _,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use spin::Mutex;
+use x86_64::{instructions::interrupts, registers::model_specific::Msr};
+
+/// Interrupt vector corresponding to IRQ 0.
+pub const IRQ_VECTORS_START: u8 = 32;
+
+/// Maximum number of IRQs that we support.
+pub const MAX_IRQS: usize = 24;
+
+/// Interrupt vector triggered by the local APIC timer.
+pub const TIMER_VECTOR: u8 = IRQ_VECTORS_START + MAX_IRQS as u8;
+
+/// Interrupt vector of the inter-processor interrupts sent by [`send_wake_ipi`].
+pub const WAKE_VECTOR: u8 = TIMER_VECTOR + 1;
+
+/// Interrupt vector triggered by the local APIC when an interrupt disappears before the CPU
+/// could acknowledge it. Contrary to the other interrupts, spurious interrupts must not be
+/// followed with a call to [`end_of_interrupt`].
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// Physical memory address of the I/O APIC, if the ACPI tables don't indicate it.
+const DEFAULT_IO_APIC_BASE: usize = 0xfec0_0000;
+
+/// Physical memory address of the local APIC. Set to a non-zero value by [`init_local_apic`].
+static LOCAL_APIC_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// State of the I/O APIC. Set by [`init_io_apic`].
+///
+/// Also accessed from interrupt handlers. Interrupts must therefore be disabled while the lock
+/// is held.
+static IO_APIC: Mutex<Option<IoApic>> = Mutex::new(None);
+
+struct IoApic {
+    /// Physical memory address of the registers of the I/O APIC.
+    base: usize,
+    /// How the ISA IRQs differ from the default.
+    overrides: Vec<InterruptSourceOverride>,
+    /// For each IRQ, if it has been enabled and is level-triggered, the pin of the I/O APIC it
+    /// is connected to.
+    level_triggered_pins: [Option<u8>; MAX_IRQS],
+}
+
+/// Enables the local APIC of the current CPU.
+///
+/// # Safety
+///
+/// Must only be called once per CPU, after the legacy PIC has been disabled.
+pub unsafe fn init_local_apic() {
+    let apic_base_addr = {
+        const APIC_BASE_MSR: Msr = Msr::new(0x1b);
+        let base_addr = APIC_BASE_MSR.read() & !0xfff;
+        APIC_BASE_MSR.write(base_addr | 0x800); // Enable the APIC.
+        usize::try_from(base_addr).unwrap()
+    };
+
+    // Make sure that the registers of the local APIC are accessible.
+    {
+        let base = u64::try_from(apic_base_addr).unwrap();
+        super::paging::map_mmio(base..base + 0x1000).unwrap();
+    }
+
+    LOCAL_APIC_BASE.store(apic_base_addr, Ordering::Release);
+
+    // Enable spurious interrupts. The low 8 bits of the register contain the vector to use.
+    {
+        let svr_addr = (apic_base_addr + 0xf0) as *mut u32;
+        let val = svr_addr.read_volatile();
+        svr_addr.write_volatile((val & !0xff) | 0x100 | u32::from(SPURIOUS_VECTOR));
+    }
+}
+
+/// Signals to the local APIC that the interrupt currently being handled is finished.
+///
+/// Must be called at the end of each interrupt handler of an interrupt delivered by the APIC,
+/// otherwise no further interrupt of the same or lower priority will be delivered.
+pub fn end_of_interrupt() {
+    let base = LOCAL_APIC_BASE.load(Ordering::Acquire);
+    if base == 0 {
+        return;
+    }
+
+    unsafe {
+        ((base + 0xb0) as *mut u32).write_volatile(0);
+    }
+}
+
+/// Starts the local APIC timer of the current CPU in one-shot mode. The timer counts down from
+/// `initial_count`, decrementing once every 16 cycles of the bus clock.
+///
+/// If `interrupt` is true, the interrupt vector [`TIMER_VECTOR`] is triggered when the count
+/// reaches zero. Any previously-started countdown is overwritten.
+///
+/// # Safety
+///
+/// The local APIC must have been initialized with [`init_local_apic`]. If `interrupt` is true,
+/// the interrupt vector must have a handler.
+pub unsafe fn set_timer(initial_count: u32, interrupt: bool) {
+    let base = LOCAL_APIC_BASE.load(Ordering::Acquire);
+    debug_assert_ne!(base, 0);
+
+    // Divide configuration register. A value of 3 means "divide by 16".
+    ((base + 0x3e0) as *mut u32).write_volatile(0x3);
+    // Local vector table entry of the timer. Bits 17 and 18 are zero, meaning one-shot mode.
+    // Bit 16 masks the interrupt.
+    let lvt = u32::from(TIMER_VECTOR) | if interrupt { 0 } else { 1 << 16 };
+    ((base + 0x320) as *mut u32).write_volatile(lvt);
+    // Writing the initial count starts the countdown.
+    ((base + 0x380) as *mut u32).write_volatile(initial_count);
+}
+
+/// Returns the current value of the countdown of the local APIC timer of the current CPU.
+pub fn timer_current_count() -> u32 {
+    let base = LOCAL_APIC_BASE.load(Ordering::Acquire);
+    if base == 0 {
+        return 0;
+    }
+
+    unsafe { ((base + 0x390) as *const u32).read_volatile() }
+}
+
+/// Initializes the I/O APIC, using the description found in the ACPI tables if any.
+///
+/// # Safety
+///
+/// Must only be called once.
+pub unsafe fn init_io_apic(description: Option<IoApicDescription>) {
+    let (base, overrides) = match description {
+        Some(d) => (d.address, d.overrides),
+        None => (DEFAULT_IO_APIC_BASE, Vec::new()),
+    };
+
+    // Make sure that the registers of the I/O APIC are accessible.
+    {
+        let base = u64::try_from(base).unwrap();
+        super::paging::map_mmio(base..base + 0x1000).unwrap();
+    }
+
+    interrupts::without_interrupts(|| {
+        *IO_APIC.lock() = Some(IoApic {
+            base,
+            overrides,
+            level_triggered_pins: [None; MAX_IRQS],
+        });
+    });
+}
+
+/// Configures the I/O APIC so that the given IRQ is delivered to the current CPU on the vector
+/// `IRQ_VECTORS_START + irq`.
+///
+/// Returns an error if the IRQ isn't supported by the I/O APIC.
+///
+/// If the IRQ is level-triggered, the device keeps the line active until its driver has
+/// handled the interrupt. Such an IRQ is masked by [`mask_if_level_triggered`] when an
+/// interrupt happens, and must be unmasked with [`unmask_irq`].
+///
+/// # Safety
+///
+/// The local APIC and the I/O APIC must have been initialized with [`init_local_apic`] and
+/// [`init_io_apic`], and the interrupt vector must have a handler.
+pub unsafe fn enable_irq(irq: u8) -> Result<(), ()> {
+    if usize::from(irq) >= MAX_IRQS {
+        return Err(());
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut io_apic = IO_APIC.lock();
+        let io_apic = io_apic.as_mut().ok_or(())?;
+
+        let (pin, level_triggered, active_low) = io_apic.irq_pin(irq).ok_or(())?;
+
+        let local_apic_id = u32::from(current_apic_id());
+
+        // Fixed delivery, physical destination mode, unmasked. Bit 13 indicates the polarity,
+        // and bit 15 the trigger mode.
+        let mut low = u32::from(IRQ_VECTORS_START + irq);
+        if active_low {
+            low |= 1 << 13;
+        }
+        if level_triggered {
+            low |= 1 << 15;
+        }
+        let high = local_apic_id << 24;
+
+        io_apic.write(redirection_register(pin) + 1, high);
+        io_apic.write(redirection_register(pin), low);
+        io_apic.level_triggered_pins[usize::from(irq)] =
+            if level_triggered { Some(pin) } else { None };
+        Ok(())
+    })
+}
+
+/// Must be called when an interrupt of the given IRQ is received. Masks the IRQ if it is
+/// level-triggered, as the interrupt would otherwise be triggered again as soon as it is
+/// finished.
+///
+/// Must be called from within the interrupt handler.
+pub fn mask_if_level_triggered(irq: u8) {
+    if let Some(io_apic) = IO_APIC.lock().as_ref() {
+        if let Some(Some(pin)) = io_apic.level_triggered_pins.get(usize::from(irq)) {
+            unsafe {
+                let value = io_apic.read(redirection_register(*pin));
+                io_apic.write(redirection_register(*pin), value | (1 << 16));
+            }
+        }
+    }
+}
+
+/// Unmasks the given IRQ after it has been masked by [`mask_if_level_triggered`]. Must be
+/// called once the driver of the device has handled the interrupts.
+///
+/// Does nothing if the IRQ isn't level-triggered.
+pub fn unmask_irq(irq: u8) {
+    interrupts::without_interrupts(|| {
+        if let Some(io_apic) = IO_APIC.lock().as_ref() {
+            if let Some(Some(pin)) = io_apic.level_triggered_pins.get(usize::from(irq)) {
+                unsafe {
+                    let value = io_apic.read(redirection_register(*pin));
+                    io_apic.write(redirection_register(*pin), value & !(1 << 16));
+                }
+            }
+        }
+    })
+}
+
+/// Masks the given IRQ, so that its interrupts are no longer delivered. The IRQ can be enabled
+/// again with [`enable_irq`].
+///
+/// Does nothing if the IRQ isn't supported.
+pub fn disable_irq(irq: u8) {
+    if usize::from(irq) >= MAX_IRQS {
+        return;
+    }
+
+    interrupts::without_interrupts(|| {
+        if let Some(io_apic) = IO_APIC.lock().as_mut() {
+            if let Some((pin, _, _)) = io_apic.irq_pin(irq) {
+                unsafe {
+                    let value = io_apic.read(redirection_register(pin));
+                    io_apic.write(redirection_register(pin), value | (1 << 16));
+                }
+            }
+            io_apic.level_triggered_pins[usize::from(irq)] = None;
+        }
+    })
+}
+
+/// Returns the index of the register containing the low 32 bits of the redirection entry of
+/// the given pin of the I/O APIC. The next register contains the high 32 bits.
+fn redirection_register(pin: u8) -> u32 {
+    0x10 + 2 * u32::from(pin)
+}
+
+/// Returns the ID of the local APIC of the current CPU.
+///
+/// Returns 0 if the local APIC hasn't been initialized yet.
+pub fn current_apic_id() -> u8 {
+    let base = LOCAL_APIC_BASE.load(Ordering::Acquire);
+    if base == 0 {
+        return 0;
+    }
+
+    // Bits 24 to 31 of the local APIC ID register contain the ID.
+    unsafe { u8::try_from(((base + 0x20) as *const u32).read_volatile() >> 24).unwrap() }
+}
+
+/// Sends an INIT inter-processor interrupt to the given local APIC, resetting its CPU.
+///
+/// # Safety
+///
+/// The local APIC must have been initialized with [`init_local_apic`]. The target CPU gets
+/// reset, and must not be running anything.
+pub unsafe fn send_init_ipi(target_apic_id: u8) {
+    // Delivery mode INIT, level assert.
+    send_ipi(target_apic_id, (0b101 << 8) | (1 << 14));
+}
+
+/// Sends a startup inter-processor interrupt (SIPI) to the given local APIC. If its CPU is
+/// waiting for such an interrupt after an INIT, it starts executing code in real mode at the
+/// physical address `page * 0x1000`.
+///
+/// # Safety
+///
+/// The local APIC must have been initialized with [`init_local_apic`]. The given page must
+/// contain valid code.
+pub unsafe fn send_startup_ipi(target_apic_id: u8, page: u8) {
+    // Delivery mode startup, level assert.
+    send_ipi(target_apic_id, (0b110 << 8) | (1 << 14) | u32::from(page));
+}
+
+/// Sends an inter-processor interrupt on the [`WAKE_VECTOR`] to the given local APIC. This
+/// wakes up its CPU if it is halted.
+///
+/// Does nothing if the local APIC hasn't been initialized yet.
+pub fn send_wake_ipi(target_apic_id: u8) {
+    if LOCAL_APIC_BASE.load(Ordering::Acquire) == 0 {
+        return;
+    }
+
+    // Delivery mode fixed, level assert.
+    unsafe { send_ipi(target_apic_id, (1 << 14) | u32::from(WAKE_VECTOR)) }
+}
+
+/// Writes the interrupt command register of the local APIC, which sends an inter-processor
+/// interrupt to the given local APIC. `command` is the value of the low 32 bits of the
+/// register.
+unsafe fn send_ipi(target_apic_id: u8, command: u32) {
+    let base = LOCAL_APIC_BASE.load(Ordering::Acquire);
+    debug_assert_ne!(base, 0);
+
+    // Only one inter-processor interrupt can be sent at a time by a local APIC. Since each CPU
+    // has its own local APIC, disabling interrupts is enough to prevent concurrent sends.
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // Bit 12 is set as long as the previous interrupt hasn't been delivered.
+        while (((base + 0x300) as *const u32).read_volatile() & (1 << 12)) != 0 {}
+        ((base + 0x310) as *mut u32).write_volatile(u32::from(target_apic_id) << 24);
+        // Writing the low 32 bits sends the interrupt.
+        ((base + 0x300) as *mut u32).write_volatile(command);
+    });
+}
+
+impl IoApic {
+    /// Returns the pin of the I/O APIC the given IRQ is connected to, whether the IRQ is
+    /// level-triggered, and whether it is active low. Returns `None` if the I/O APIC doesn't
+    /// have this pin.
+    fn irq_pin(&self, irq: u8) -> Option<(u8, bool, bool)> {
+        // ISA IRQs are edge-triggered and active high, and are connected to the pin of the same
+        // number, unless the ACPI tables indicate otherwise. The other IRQs are the ones of PCI
+        // devices, which are level-triggered and active low.
+        let (pin, level_triggered, active_low) =
+            match self.overrides.iter().find(|o| o.isa_irq == irq) {
+                Some(o) => (o.pin, o.level_triggered, o.active_low),
+                None if irq < 16 => (u32::from(irq), false, false),
+                None => (u32::from(irq), true, true),
+            };
+
+        // Bits 16 to 23 of the version register contain the index of the last redirection entry.
+        let max_redirection_entry = unsafe { (self.read(0x1) >> 16) & 0xff };
+        if pin > max_redirection_entry {
+            return None;
+        }
+
+        Some((u8::try_from(pin).unwrap(), level_triggered, active_low))
+    }
+
+    /// Reads a register of the I/O APIC.
+    unsafe fn read(&self, register: u32) -> u32 {
+        (self.base as *mut u32).write_volatile(register);
+        ((self.base + 0x10) as *const u32).read_volatile()
+    }
+
+    /// Writes a register of the I/O APIC.
+    unsafe fn write(&self, register: u32, value: u32) {
+        (self.base as *mut u32).write_volatile(register);
+        ((self.base + 0x10) as *mut u32).write_volatile(value);
+    }
+}
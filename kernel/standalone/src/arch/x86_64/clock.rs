@@ -0,0 +1,145 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Monotonic clock and timers.
+//!
+//! The monotonic clock is based on the TSC (Time Stamp Counter), a counter incremented at a
+//! fixed rate by the CPU. Timers are implemented using the local APIC timer in one-shot mode.
+//!
+//! The frequencies of both the TSC and the local APIC timer aren't known in advance, and are
+//! calibrated during [`init`] against the PIT (Programmable Interval Timer), whose frequency is
+//! fixed.
+//!
+//! See also https://wiki.osdev.org/TSC and https://wiki.osdev.org/APIC_timer
+
+use super::{apic, interrupts, rtc};
+
+use core::{
+    convert::TryFrom as _,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Waker,
+    time::Duration,
+};
+use x86_64::structures::port::{PortRead as _, PortWrite as _};
+
+/// Frequency of the PIT, in Hz.
+const PIT_FREQUENCY_HZ: u64 = 1_193_182;
+
+/// Number of PIT ticks to wait for during the calibration. Corresponds to approximately 20ms.
+const CALIBRATION_PIT_TICKS: u16 = 23864;
+
+/// Frequency of the TSC, in Hz. Zero if not calibrated yet.
+static TSC_FREQUENCY_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Frequency at which the local APIC timer counts down, in Hz. Zero if not calibrated yet.
+static APIC_TIMER_FREQUENCY_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Value to add to the monotonic clock in order to obtain the number of nanoseconds since the
+/// UNIX epoch.
+static SYSTEM_CLOCK_OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Calibrates the TSC and the local APIC timer, and reads the real-time clock.
+///
+/// # Safety
+///
+/// Must only be called once, after the local APIC has been initialized. Assumes that nothing
+/// else uses the channel 2 of the PIT.
+pub unsafe fn init() {
+    // We use the channel 2 of the PIT, whose gate can be controlled by software through bit 0
+    // of port 0x61, and whose output can be read through bit 5 of the same port.
+    // Enable the gate, and disable the PC speaker which is also connected to this channel.
+    let port61 = u8::read_from_port(0x61);
+    u8::write_to_port(0x61, (port61 & !0x2) | 0x1);
+
+    // Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary.
+    u8::write_to_port(0x43, 0b1011_0000);
+    u8::write_to_port(0x42, CALIBRATION_PIT_TICKS.to_le_bytes()[0]);
+    u8::write_to_port(0x42, CALIBRATION_PIT_TICKS.to_le_bytes()[1]);
+
+    // Restart the countdown by lowering then raising the gate, and start measuring.
+    let port61 = u8::read_from_port(0x61) & !0x1;
+    u8::write_to_port(0x61, port61);
+    u8::write_to_port(0x61, port61 | 0x1);
+    apic::set_timer(u32::max_value(), false);
+    let tsc_start = core::arch::x86_64::_rdtsc();
+
+    // Bit 5 becomes 1 when the countdown reaches zero.
+    while (u8::read_from_port(0x61) & 0x20) == 0 {}
+
+    let tsc_end = core::arch::x86_64::_rdtsc();
+    let apic_ticks = u32::max_value() - apic::timer_current_count();
+    apic::set_timer(0, false);
+
+    let tsc_frequency = (tsc_end - tsc_start) * PIT_FREQUENCY_HZ / u64::from(CALIBRATION_PIT_TICKS);
+    let apic_frequency =
+        u64::from(apic_ticks) * PIT_FREQUENCY_HZ / u64::from(CALIBRATION_PIT_TICKS);
+    TSC_FREQUENCY_HZ.store(tsc_frequency, Ordering::Release);
+    APIC_TIMER_FREQUENCY_HZ.store(apic_frequency, Ordering::Release);
+
+    let unix_time = rtc::read_unix_time();
+    let offset = unix_time.checked_sub(monotonic_clock()).unwrap_or_default();
+    SYSTEM_CLOCK_OFFSET_NS.store(
+        u64::try_from(offset.as_nanos()).unwrap_or(u64::max_value()),
+        Ordering::Release,
+    );
+}
+
+/// Returns the amount of time that has elapsed since an undeterminate moment in time.
+// TODO: assumes that the TSC is invariant, which is the case on all modern CPUs but should be
+//       checked with CPUID
+pub fn monotonic_clock() -> Duration {
+    let ticks = unsafe { core::arch::x86_64::_rdtsc() };
+
+    let frequency = TSC_FREQUENCY_HZ.load(Ordering::Acquire);
+    if frequency == 0 {
+        // Not calibrated yet. Since this function is also used as a source of entropy, we
+        // return something that changes over time rather than a constant.
+        return Duration::from_nanos(ticks);
+    }
+
+    let nanos = u128::from(ticks) * 1_000_000_000 / u128::from(frequency);
+    Duration::new(
+        u64::try_from(nanos / 1_000_000_000).unwrap(),
+        u32::try_from(nanos % 1_000_000_000).unwrap(),
+    )
+}
+
+/// Returns the amount of time that has elapsed since the UNIX epoch.
+pub fn system_clock() -> Duration {
+    monotonic_clock() + Duration::from_nanos(SYSTEM_CLOCK_OFFSET_NS.load(Ordering::Acquire))
+}
+
+/// Registers a `Waker` to wake up when the monotonic clock reaches `deadline`.
+///
+/// Only the latest registered `Waker` and deadline are taken into account. The `Waker` might be
+/// woken up spuriously or before the deadline, in which case it should be registered again.
+pub fn set_timer_waker(deadline: Duration, waker: &Waker) {
+    interrupts::set_interrupt_waker(apic::TIMER_VECTOR, waker);
+
+    let now = monotonic_clock();
+    if deadline <= now {
+        waker.wake_by_ref();
+        return;
+    }
+
+    let frequency = APIC_TIMER_FREQUENCY_HZ.load(Ordering::Acquire);
+    let ticks = (deadline - now).as_nanos() * u128::from(frequency) / 1_000_000_000;
+    // If the deadline is too far away, the timer fires early and the `Waker` gets registered
+    // again.
+    let ticks = u32::try_from(ticks).unwrap_or(u32::max_value()).max(1);
+    unsafe {
+        apic::set_timer(ticks, true);
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Global descriptor table and task state segment.
+//!
+//! `boot.S` loads a minimal GDT in order to jump to long mode. This module replaces it with one
+//! that also contains a TSS (Task State Segment), which is necessary in order to handle
+//! interrupts on a separate stack.
+//!
+//! In particular, double faults are handled on a separate stack. A kernel stack overflow hits a
+//! guard page and triggers a page fault, which can't be handled on the overflowing stack and
+//! turns into a double fault.
+
+use alloc::{boxed::Box, vec};
+use x86_64::{
+    instructions::{segmentation, tables},
+    structures::{
+        gdt::{Descriptor, GlobalDescriptorTable},
+        tss::TaskStateSegment,
+    },
+    VirtAddr,
+};
+
+/// Index within the interrupt stack table of the stack used to handle double faults.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+/// Size of the stack used to handle double faults.
+const DOUBLE_FAULT_STACK_SIZE: usize = 0x4000;
+
+/// Builds and loads a GDT and a TSS for the current CPU.
+///
+/// Each CPU has its own TSS, and thus its own stack to handle double faults. Since a TSS can't
+/// be loaded by multiple CPUs, each CPU also has its own GDT.
+///
+/// # Safety
+///
+/// Must only be called once per CPU. The heap must have been initialized.
+pub unsafe fn init() {
+    let double_fault_stack = Box::leak(vec![0u8; DOUBLE_FAULT_STACK_SIZE].into_boxed_slice());
+
+    let tss = Box::leak(Box::new(TaskStateSegment::new()));
+    // Stacks grow downwards, so we pass the end of the stack.
+    tss.interrupt_stack_table[usize::from(DOUBLE_FAULT_IST_INDEX)] =
+        VirtAddr::from_ptr(double_fault_stack.as_ptr()) + DOUBLE_FAULT_STACK_SIZE as u64;
+
+    let gdt = Box::leak(Box::new(GlobalDescriptorTable::new()));
+    let code = gdt.add_entry(Descriptor::kernel_code_segment());
+    let tss = gdt.add_entry(Descriptor::tss_segment(tss));
+
+    gdt.load();
+    segmentation::set_cs(code);
+    tables::load_tss(tss);
+}
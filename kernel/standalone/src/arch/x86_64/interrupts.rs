@@ -27,9 +27,16 @@
 //! has happened. By re-registering a `Waker` before looking for the interrupt reason, there is no
 //! risk of losing information.
 //!
+//! In addition to wakers, a counter is maintained for each hardware interrupt (IRQ). See
+//! [`irq_count`]. This makes it possible to know whether an interrupt has happened while no
+//! `Waker` was registered.
+//!
 
 // TODO: init() has to be called; this isn't great
 
+use super::apic;
+use core::convert::TryFrom as _;
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::task::Waker;
 use futures::task::AtomicWaker;
 use x86_64::structures::idt;
@@ -47,9 +54,22 @@ pub fn set_interrupt_waker(interrupt: u8, waker: &Waker) {
     WAKERS[usize::from(interrupt)].register(waker);
 }
 
-/// Initializes the interrupts system.
+/// Returns the number of times the given IRQ has been triggered since boot, or `None` if the IRQ
+/// isn't supported.
+///
+/// Contrary to wakers, the counter never gets reset. Comparing the values returned by two calls
+/// to this function makes it possible to know whether interrupts happened in between.
+pub fn irq_count(irq: usize) -> Option<u64> {
+    IRQ_COUNTERS.get(irq).map(|c| c.load(Ordering::Acquire))
+}
+
+/// Initializes the interrupts system of the current CPU.
+///
+/// Must be called once per CPU. Before this is called, the waker passed to
+/// [`set_interrupt_waker`] will never work.
 ///
-/// Before this is called, the waker passed to [`set_interrupt_waker`] will never work.
+/// Must be called after [`gdt::init`](super::gdt::init), as handling double faults relies on
+/// the task state segment.
 pub unsafe fn init() {
     IDT.load();
     x86_64::instructions::interrupts::enable();
@@ -65,7 +85,7 @@ lazy_static::lazy_static! {
             }};
             ($entry:expr, $n:expr) => {{
                 extern "x86-interrupt" fn handler(_: &mut idt::InterruptStackFrame) {
-                    WAKERS[$n].wake();
+                    on_interrupt($n);
                 }
                 $entry.set_handler_fn(handler)
                     .disable_interrupts(false);
@@ -86,11 +106,13 @@ lazy_static::lazy_static! {
                 $entry.set_handler_fn(handler);
             }};
             ($entry:expr, $n:expr, with-pf-err) => {{
+                // The kernel never expects page faults to happen. They can be caused for example
+                // by a kernel stack overflow hitting a guard page.
                 extern "x86-interrupt" fn handler(_: &mut idt::InterruptStackFrame, _: idt::PageFaultErrorCode) {
-                    WAKERS[$n].wake();
+                    let address = x86_64::registers::control::Cr2::read();
+                    panic!("page fault at {:?}", address)
                 }
-                $entry.set_handler_fn(handler)
-                    .disable_interrupts(false);
+                $entry.set_handler_fn(handler);
             }};
             ($entry:expr, $n:expr, diverging) => {{
                 extern "x86-interrupt" fn handler(_: &mut idt::InterruptStackFrame) -> ! {
@@ -102,7 +124,12 @@ lazy_static::lazy_static! {
                 extern "x86-interrupt" fn handler(_: &mut idt::InterruptStackFrame, _: u64) -> ! {
                     panic!("double fault!") // TODO: well, this is supposedly a generic diverging interrupt handler
                 }
-                $entry.set_handler_fn(handler);
+                // Double faults are handled on a separate stack, as the current stack might be
+                // the reason of the fault.
+                unsafe {
+                    $entry.set_handler_fn(handler)
+                        .set_stack_index(super::gdt::DOUBLE_FAULT_IST_INDEX);
+                }
             }};
         }
 
@@ -367,6 +394,48 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Called by the handler of the interrupt vector `n`, except for exceptions with an error code.
+fn on_interrupt(n: usize) {
+    let irq_vectors_start = usize::from(apic::IRQ_VECTORS_START);
+    if n >= irq_vectors_start && n != usize::from(apic::SPURIOUS_VECTOR) {
+        if let Some(counter) = IRQ_COUNTERS.get(n - irq_vectors_start) {
+            counter.fetch_add(1, Ordering::AcqRel);
+            apic::mask_if_level_triggered(u8::try_from(n - irq_vectors_start).unwrap());
+        }
+        apic::end_of_interrupt();
+    }
+
+    WAKERS[n].wake();
+}
+
+/// For each IRQ, number of times it has been triggered.
+static IRQ_COUNTERS: [AtomicU64; apic::MAX_IRQS] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
 /// For each interrupt vector, a [`Waker`](core::task::Waker) that must be waken up when that
 /// interrupt happens.
 static WAKERS: [AtomicWaker; 256] = [
@@ -0,0 +1,246 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Page tables management.
+//!
+//! `boot.S` identity-maps the first 512GiB of memory with 1GiB pages, without any distinction
+//! between RAM and memory-mapped devices. [`init`] replaces these page tables with ones managed
+//! by the kernel, where:
+//!
+//! - The RAM reported by the bootloader is mapped with caching enabled.
+//! - The guard pages surrounding the kernel stack are left unmapped, so that a stack overflow
+//!   triggers a fault rather than silently corrupting memory.
+//! - Memory-mapped devices are mapped on demand, with caching disabled, through [`map_mmio`].
+//! - Everything else is left unmapped.
+//!
+//! The memory stays identity-mapped, in other words virtual addresses are always equal to
+//! physical addresses.
+
+use alloc::vec::Vec;
+use core::{convert::TryFrom as _, ops::Range};
+use spin::Mutex;
+use x86_64::{
+    registers::control::{Cr3, Cr3Flags},
+    structures::paging::PhysFrame,
+    PhysAddr,
+};
+
+/// Size of a small page.
+const PAGE_SIZE: u64 = 0x1000;
+/// Size of a large page, as mapped by a page directory entry.
+const LARGE_PAGE_SIZE: u64 = 0x20_0000;
+
+/// Page table entry flags.
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const WRITE_THROUGH: u64 = 1 << 3;
+const CACHE_DISABLE: u64 = 1 << 4;
+const LARGE_PAGE: u64 = 1 << 7;
+/// Bits of a page table entry containing the physical address.
+const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Kind of memory that a range of addresses is mapped to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MemoryKind {
+    /// RAM. Cached.
+    Ram,
+    /// Memory-mapped device. Uncached.
+    Mmio,
+}
+
+/// Page tables managed by the kernel. `None` until [`init`] is called.
+static PAGE_TABLES: Mutex<Option<PageTables>> = Mutex::new(None);
+
+struct PageTables {
+    /// Physical address of the PML4.
+    pml4: u64,
+    /// List of ranges that are currently mapped.
+    mapped: Vec<(Range<u64>, MemoryKind)>,
+}
+
+/// Builds the kernel's page tables and switches to them.
+///
+/// # Safety
+///
+/// Must only be called once, from the CPU that booted. The heap must have been initialized.
+pub unsafe fn init(multiboot_info: &multiboot2::BootInformation) {
+    extern "C" {
+        // Defined in `boot.S`.
+        static stack_guard_bottom: u8;
+        static stack_guard_top: u8;
+    }
+
+    let guards = [
+        &stack_guard_bottom as *const u8 as u64,
+        &stack_guard_top as *const u8 as u64,
+    ];
+
+    let mut tables = PageTables::new();
+
+    // The first MiB contains, amongst other things, the BIOS data area and the VGA text buffer.
+    tables.map(0..0xa_0000, MemoryKind::Ram);
+    tables.map(0xa_0000..0x10_0000, MemoryKind::Mmio);
+
+    for area in multiboot_info.memory_map_tag().unwrap().memory_areas() {
+        // Areas aren't necessarily aligned on pages boundaries.
+        let start = (area.start_address() + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let end = area.end_address() / PAGE_SIZE * PAGE_SIZE;
+        let start = start.max(0x10_0000);
+        if start >= end {
+            continue;
+        }
+
+        // Map everything but the guard pages.
+        let mut cursor = start;
+        for guard in guards.iter().filter(|g| **g >= start && **g < end) {
+            tables.map(cursor..*guard, MemoryKind::Ram);
+            cursor = *guard + PAGE_SIZE;
+        }
+        tables.map(cursor..end, MemoryKind::Ram);
+    }
+
+    let pml4 = PhysFrame::from_start_address(PhysAddr::new(tables.pml4)).unwrap();
+    Cr3::write(pml4, Cr3Flags::empty());
+
+    *PAGE_TABLES.lock() = Some(tables);
+}
+
+/// Makes sure that the given range of physical memory, corresponding to a memory-mapped device,
+/// is mapped with caching disabled.
+///
+/// Returns an error if the range overlaps RAM, in which case nothing is mapped.
+///
+/// Before [`init`] has been called, everything is considered mapped.
+pub fn map_mmio(range: Range<u64>) -> Result<(), ()> {
+    let mut page_tables = PAGE_TABLES.lock();
+    let tables = match page_tables.as_mut() {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+
+    let start = range.start / PAGE_SIZE * PAGE_SIZE;
+    let end = range.end.checked_add(PAGE_SIZE - 1).ok_or(())? / PAGE_SIZE * PAGE_SIZE;
+
+    if tables
+        .mapped
+        .iter()
+        .any(|(r, kind)| *kind == MemoryKind::Ram && r.start < end && start < r.end)
+    {
+        return Err(());
+    }
+
+    // Map the pages that aren't mapped yet.
+    let mut cursor = start;
+    while cursor < end {
+        match tables
+            .mapped
+            .iter()
+            .find(|(r, _)| r.start <= cursor && cursor < r.end)
+        {
+            Some((r, _)) => cursor = r.end,
+            None => {
+                let next_mapped = tables
+                    .mapped
+                    .iter()
+                    .map(|(r, _)| r.start)
+                    .filter(|s| *s > cursor)
+                    .min()
+                    .unwrap_or(end)
+                    .min(end);
+                tables.map(cursor..next_mapped, MemoryKind::Mmio);
+                cursor = next_mapped;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl PageTables {
+    fn new() -> Self {
+        PageTables {
+            pml4: allocate_table(),
+            mapped: Vec::new(),
+        }
+    }
+
+    /// Identity-maps the given range, whose bounds must be aligned on pages boundaries. Uses
+    /// large pages where possible.
+    ///
+    /// The range must not be mapped already.
+    fn map(&mut self, range: Range<u64>, kind: MemoryKind) {
+        debug_assert_eq!(range.start % PAGE_SIZE, 0);
+        debug_assert_eq!(range.end % PAGE_SIZE, 0);
+        if range.start >= range.end {
+            return;
+        }
+
+        let flags = match kind {
+            MemoryKind::Ram => PRESENT | WRITABLE,
+            MemoryKind::Mmio => PRESENT | WRITABLE | WRITE_THROUGH | CACHE_DISABLE,
+        };
+
+        let mut address = range.start;
+        while address < range.end {
+            if address % LARGE_PAGE_SIZE == 0 && address + LARGE_PAGE_SIZE <= range.end {
+                let page_directory = self.table_for(address, 2);
+                unsafe {
+                    *entry(page_directory, address, 1) = address | flags | LARGE_PAGE;
+                }
+                address += LARGE_PAGE_SIZE;
+            } else {
+                let page_table = self.table_for(address, 3);
+                unsafe {
+                    *entry(page_table, address, 0) = address | flags;
+                }
+                address += PAGE_SIZE;
+            }
+        }
+
+        // Note that we don't need to flush the TLB, as entries that weren't present can't be
+        // cached.
+        self.mapped.push((range, kind));
+    }
+
+    /// Walks the page tables starting from the PML4, going down `depth` levels, and returns the
+    /// physical address of the table that contains the entry for `address`. Creates the
+    /// intermediary tables if necessary.
+    fn table_for(&mut self, address: u64, depth: u32) -> u64 {
+        let mut table = self.pml4;
+        for level in 0..depth {
+            let entry = unsafe { &mut *entry(table, address, 3 - level) };
+            if (*entry & PRESENT) == 0 {
+                *entry = allocate_table() | PRESENT | WRITABLE;
+            }
+            debug_assert_eq!(*entry & LARGE_PAGE, 0);
+            table = *entry & ADDRESS_MASK;
+        }
+        table
+    }
+}
+
+/// Returns a pointer to the entry corresponding to `address` in the table at `table`.
+/// `level` is 3 for the PML4, 2 for a PDPT, 1 for a page directory, and 0 for a page table.
+fn entry(table: u64, address: u64, level: u32) -> *mut u64 {
+    let index = (address >> (12 + 9 * level)) & 0x1ff;
+    (usize::try_from(table).unwrap() as *mut u64).wrapping_add(usize::try_from(index).unwrap())
+}
+
+/// Allocates a new empty page table and returns its physical address.
+fn allocate_table() -> u64 {
+    let table = crate::mem_alloc::allocate_frames(PAGE_SIZE as usize, PAGE_SIZE as usize)
+        .expect("out of memory while allocating page tables");
+    super::virtual_to_physical(table.as_ptr() as usize)
+}
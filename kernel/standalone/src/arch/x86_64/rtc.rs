@@ -0,0 +1,116 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reading the CMOS real-time clock.
+//!
+//! The real-time clock is a battery-powered chip that keeps track of the date and time, even
+//! while the machine is turned off. It is accessed through the CMOS registers.
+//!
+//! See also https://wiki.osdev.org/CMOS
+
+use core::{convert::TryFrom as _, time::Duration};
+use x86_64::structures::port::{PortRead as _, PortWrite as _};
+
+/// Reads the current date and time from the real-time clock, and returns the amount of time
+/// elapsed since the UNIX epoch.
+///
+/// The real-time clock is assumed to be in UTC.
+///
+/// # Safety
+///
+/// Must not be called concurrently with anything else that accesses the CMOS registers.
+pub unsafe fn read_unix_time() -> Duration {
+    // The values might be modified by the chip while we read them. To avoid inconsistencies, we
+    // read until we get the same values twice in a row.
+    let mut values = read_raw_values();
+    loop {
+        let new_values = read_raw_values();
+        if new_values == values {
+            break;
+        }
+        values = new_values;
+    }
+
+    let [mut second, mut minute, mut hour, mut day, mut month, mut year] = values;
+
+    // Bit 2 of the status register B indicates whether values are in binary or in BCD.
+    // Bit 1 indicates whether the hours are in 24 hours or 12 hours format.
+    let status_b = read_register(0x0b);
+    let is_pm = (hour & 0x80) != 0;
+    hour &= 0x7f;
+
+    if (status_b & 0x4) == 0 {
+        second = from_bcd(second);
+        minute = from_bcd(minute);
+        hour = from_bcd(hour);
+        day = from_bcd(day);
+        month = from_bcd(month);
+        year = from_bcd(year);
+    }
+
+    if (status_b & 0x2) == 0 {
+        hour %= 12;
+        if is_pm {
+            hour += 12;
+        }
+    }
+
+    // TODO: the century register can be found through the ACPI FADT; we assume 21st century
+    let year = 2000 + i64::from(year);
+
+    let days = days_from_civil(year, u32::from(month), u32::from(day));
+    let seconds =
+        days * 86400 + i64::from(hour) * 3600 + i64::from(minute) * 60 + i64::from(second);
+    Duration::from_secs(u64::try_from(seconds).unwrap_or(0))
+}
+
+/// Reads the second, minute, hour, day of month, month and year registers, in that order.
+unsafe fn read_raw_values() -> [u8; 6] {
+    // Bit 7 of the status register A is set while an update is in progress.
+    while (read_register(0x0a) & 0x80) != 0 {}
+
+    [
+        read_register(0x00),
+        read_register(0x02),
+        read_register(0x04),
+        read_register(0x07),
+        read_register(0x08),
+        read_register(0x09),
+    ]
+}
+
+/// Reads one of the CMOS registers.
+unsafe fn read_register(register: u8) -> u8 {
+    u8::write_to_port(0x70, register);
+    u8::read_from_port(0x71)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0xf) + (value >> 4) * 10
+}
+
+/// Returns the number of days between the UNIX epoch and the given date.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,113 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Native program that handles the `devicetree` interface.
+
+use alloc::boxed::Box;
+use core::{pin::Pin, sync::atomic, task::Poll};
+use crossbeam_queue::SegQueue;
+use futures::{prelude::*, task::AtomicWaker};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use redshirt_devicetree_interface::ffi::{DeviceTreeMessage, GetDeviceTreeResponse, INTERFACE};
+
+/// State machine for `devicetree` interface messages handling.
+pub struct DeviceTreeNativeProgram {
+    /// If true, we have sent the interface registration message.
+    registered: atomic::AtomicBool,
+    /// Device tree passed by the bootloader, if any.
+    device_tree: Option<&'static [u8]>,
+    /// Message responses waiting to be emitted.
+    pending_messages: SegQueue<(MessageId, Result<EncodedMessage, ()>)>,
+    /// Waker to wake up when a new message is pushed to `pending_messages`.
+    pending_messages_waker: AtomicWaker,
+}
+
+impl DeviceTreeNativeProgram {
+    /// Initializes the new state machine for device tree messages handling.
+    ///
+    /// `device_tree` is the device tree passed by the bootloader, in its binary format.
+    pub fn new(device_tree: Option<&'static [u8]>) -> Self {
+        DeviceTreeNativeProgram {
+            registered: atomic::AtomicBool::new(false),
+            device_tree,
+            pending_messages: SegQueue::new(),
+            pending_messages_waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Pushes a message to answer to `pending_messages` and wakes up the task.
+    fn push_answer(&self, message_id: MessageId, answer: Result<EncodedMessage, ()>) {
+        self.pending_messages.push((message_id, answer));
+        self.pending_messages_waker.wake();
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a DeviceTreeNativeProgram {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+            return Box::pin(future::ready(NativeProgramEvent::Emit {
+                interface: redshirt_interface_interface::ffi::INTERFACE,
+                message_id_write: None,
+                message: redshirt_interface_interface::ffi::InterfaceMessage::Register(INTERFACE)
+                    .encode(),
+            }));
+        }
+
+        Box::pin(future::poll_fn(move |cx| {
+            self.pending_messages_waker.register(cx.waker());
+            if let Ok((message_id, answer)) = self.pending_messages.pop() {
+                Poll::Ready(NativeProgramEvent::Answer { message_id, answer })
+            } else {
+                Poll::Pending
+            }
+        }))
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        _emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        debug_assert_eq!(interface, INTERFACE);
+
+        let message_id = match message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        match DeviceTreeMessage::decode(message) {
+            Ok(DeviceTreeMessage::GetDeviceTree) => {
+                let response = GetDeviceTreeResponse {
+                    device_tree: self.device_tree.map(|dt| dt.to_vec()),
+                };
+                self.push_answer(message_id, Ok(response.encode()));
+            }
+            Err(_) => self.push_answer(message_id, Err(())),
+        }
+    }
+
+    fn process_destroyed(self, _: Pid) {}
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
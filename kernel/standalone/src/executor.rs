@@ -15,8 +15,6 @@
 
 //! Futures executor that works on bare metal.
 
-// TODO: only works because we're single-CPU'ed at the moment
-
 use alloc::sync::Arc;
 use core::future::Future;
 use core::sync::atomic;
@@ -30,6 +28,7 @@ pub fn block_on<R>(future: impl Future<Output = R>) -> R {
     futures::pin_mut!(future);
 
     let local_wake = Arc::new(LocalWake {
+        cpu: crate::arch::current_cpu(),
         woken_up: atomic::AtomicBool::new(false),
     });
 
@@ -46,13 +45,19 @@ pub fn block_on<R>(future: impl Future<Output = R>) -> R {
 }
 
 struct LocalWake {
+    /// CPU that runs the [`block_on`] function, as returned by
+    /// [`current_cpu`](crate::arch::current_cpu).
+    cpu: u32,
+    /// Set to true when the `Waker` is woken up.
     woken_up: atomic::AtomicBool,
 }
 
 impl ArcWake for LocalWake {
     fn wake_by_ref(arc_self: &Arc<Self>) {
         arc_self.woken_up.store(true, atomic::Ordering::Release);
-        // TODO: wake up original CPU, once we're multi-CPU'ed
+        if crate::arch::current_cpu() != arc_self.cpu {
+            crate::arch::wake_cpu(arc_self.cpu);
+        }
     }
 }
 
@@ -93,5 +98,9 @@ fn wait_for_true(atomic: &atomic::AtomicBool) {
 
         // TODO: this is a draft; I don't really know well how ARM interrupts work
         unsafe { asm!("wfe" :::: "volatile") }
+
+        // Interrupts aren't supported yet, and timers rely on the CPU being periodically woken
+        // up from `wfe`.
+        crate::arch::wake_expired_timer();
     }
 }
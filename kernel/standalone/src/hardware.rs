@@ -20,15 +20,15 @@
 
 use crate::arch;
 
-use alloc::{boxed::Box, vec::Vec};
-use core::{convert::TryFrom as _, pin::Pin, sync::atomic};
+use alloc::{alloc::Layout, boxed::Box, vec::Vec};
+use core::{convert::TryFrom as _, ops::Range, pin::Pin, ptr::NonNull, sync::atomic, task::Poll};
 use crossbeam_queue::SegQueue;
-use futures::prelude::*;
+use futures::{prelude::*, task::AtomicWaker};
 use hashbrown::HashMap;
 use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
 use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
 use redshirt_hardware_interface::ffi::{
-    HardwareAccessResponse, HardwareMessage, Operation, INTERFACE,
+    FramebufferInfo, HardwareAccessResponse, HardwareMessage, Operation, PciEcamRegion, INTERFACE,
 };
 use spin::Mutex;
 
@@ -36,11 +36,90 @@ use spin::Mutex;
 pub struct HardwareHandler {
     /// If true, we have sent the interface registration message.
     registered: atomic::AtomicBool,
-    /// For each PID, a list of memory allocations.
+    /// For each PID, a list of memory allocations. Allocations are freed when the process that
+    /// owns them is destroyed.
     // TODO: optimize
-    allocations: Mutex<HashMap<Pid, Vec<Vec<u8>>>>,
+    allocations: Mutex<HashMap<Pid, Vec<DmaAllocation>>>,
+    /// Ranges of physical memory claimed by processes, and the process that owns them. Released
+    /// when the process is destroyed.
+    mmio_claims: Mutex<Vec<(Pid, Range<u64>)>>,
+    /// Regions where the PCI Express configuration space is mapped.
+    pci_ecam_regions: Vec<PciEcamRegion>,
+    /// Linear framebuffer set up by the bootloader, if any.
+    framebuffer: Option<FramebufferInfo>,
+    /// For each PID and IRQ number, the state of the interrupts subscription.
+    interrupts: Mutex<HashMap<(Pid, u32), InterruptSubscription>>,
     /// List of messages waiting to be emitted with `next_event`.
     pending_messages: SegQueue<(MessageId, Result<EncodedMessage, ()>)>,
+    /// Waker to wake up when a new message is pushed to `pending_messages` or a new
+    /// `InterruptWait` message is received.
+    pending_messages_waker: AtomicWaker,
+}
+
+/// Memory allocated on behalf of a process. Freed on drop.
+struct DmaAllocation {
+    /// Pointer to the allocated memory, as seen by the kernel.
+    ptr: NonNull<u8>,
+    /// Layout that was used to allocate the memory.
+    layout: Layout,
+    /// Physical address of `ptr`, as reported to the process.
+    physical_address: u64,
+}
+
+// The memory is only ever accessed through physical addresses.
+unsafe impl Send for DmaAllocation {}
+
+impl DmaAllocation {
+    /// Allocates memory according to the given parameters. Returns `None` if the allocation
+    /// fails or if the constraints can't be satisfied.
+    fn new(size: u64, alignment: u64, below_4gib: bool, cache_coherent: bool) -> Option<Self> {
+        if cache_coherent && !arch::dma_cache_coherent() {
+            return None;
+        }
+
+        // Zero-sized allocations are forbidden by the allocator.
+        let size = usize::try_from(size).ok()?.max(1);
+        let alignment = usize::try_from(alignment).ok()?.max(1);
+        let layout = Layout::from_size_align(size, alignment).ok()?;
+
+        let ptr = if below_4gib {
+            // On 32 bits platforms, all the memory is below 4GiB anyway.
+            let max_address = usize::try_from(0x1_0000_0000u64).unwrap_or(usize::max_value());
+            crate::mem_alloc::allocate_below(layout, max_address)?
+        } else {
+            NonNull::new(unsafe { alloc::alloc::alloc_zeroed(layout) })?
+        };
+        let allocation = DmaAllocation {
+            ptr,
+            layout,
+            physical_address: arch::virtual_to_physical(ptr.as_ptr() as usize),
+        };
+
+        let end = allocation
+            .physical_address
+            .checked_add(u64::try_from(size).ok()?)?;
+        if below_4gib && end > 0x1_0000_0000 {
+            return None;
+        }
+
+        Some(allocation)
+    }
+}
+
+impl Drop for DmaAllocation {
+    fn drop(&mut self) {
+        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+/// State of a subscription of a process to an IRQ.
+#[derive(Debug)]
+struct InterruptSubscription {
+    /// Value of [`arch::irq_count`] the last time we answered an `InterruptWait` message, or
+    /// at the time of the subscription.
+    last_seen_count: u64,
+    /// `InterruptWait` message waiting to be answered.
+    waiting: Option<MessageId>,
 }
 
 impl HardwareHandler {
@@ -49,8 +128,71 @@ impl HardwareHandler {
         HardwareHandler {
             registered: atomic::AtomicBool::new(false),
             allocations: Mutex::new(HashMap::new()),
+            mmio_claims: Mutex::new(Vec::new()),
+            pci_ecam_regions: Vec::new(),
+            framebuffer: None,
+            interrupts: Mutex::new(HashMap::new()),
             pending_messages: SegQueue::new(),
+            pending_messages_waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Sets the list of regions where the configuration space of PCI Express devices is mapped.
+    /// Reported to processes that ask for them.
+    pub fn with_pci_ecam_regions(mut self, regions: Vec<PciEcamRegion>) -> Self {
+        self.pci_ecam_regions = regions;
+        self
+    }
+
+    /// Sets the linear framebuffer set up by the bootloader. Reported to processes that ask
+    /// for it.
+    pub fn with_framebuffer(mut self, framebuffer: Option<FramebufferInfo>) -> Self {
+        self.framebuffer = framebuffer;
+        self
+    }
+
+    /// Returns true if the given process is allowed to access the given range of physical
+    /// memory.
+    ///
+    /// Processes can access the memory they have allocated themselves, and the ranges they have
+    /// claimed.
+    fn may_access_memory(&self, pid: Pid, range: &Range<u64>) -> bool {
+        let own_allocation = self.allocations.lock().get(&pid).map_or(false, |list| {
+            list.iter().any(|a| {
+                a.physical_address <= range.start
+                    && range.end <= a.physical_address + u64::try_from(a.layout.size()).unwrap()
+            })
+        });
+
+        own_allocation
+            || self.mmio_claims.lock().iter().any(|(owner, claim)| {
+                *owner == pid && claim.start <= range.start && range.end <= claim.end
+            })
+    }
+
+    /// Grants the given process exclusive access to the given range of physical memory, and maps
+    /// it with caching disabled.
+    ///
+    /// Returns an error if the range overlaps RAM or a range claimed by another process.
+    fn claim_mmio(&self, pid: Pid, address: u64, len: u64) -> Result<(), ()> {
+        let range = address..address.checked_add(len).ok_or(())?;
+
+        let mut claims = self.mmio_claims.lock();
+        if claims.iter().any(|(owner, claim)| {
+            *owner != pid && claim.start < range.end && range.start < claim.end
+        }) {
+            return Err(());
         }
+
+        arch::map_mmio(range.clone())?;
+        claims.push((pid, range));
+        Ok(())
+    }
+
+    /// Pushes a message to answer to `pending_messages` and wakes up the task.
+    fn push_answer(&self, message_id: MessageId, answer: Result<EncodedMessage, ()>) {
+        self.pending_messages.push((message_id, answer));
+        self.pending_messages_waker.wake();
     }
 }
 
@@ -69,15 +211,34 @@ impl<'a> NativeProgramRef<'a> for &'a HardwareHandler {
             }));
         }
 
-        // TODO: wrong; if a message gets pushed, we don't wake up the task
-        if let Ok((message_id, answer)) = self.pending_messages.pop() {
-            Box::pin(future::ready(NativeProgramEvent::Answer {
-                message_id,
-                answer,
-            }))
-        } else {
-            Box::pin(future::pending())
-        }
+        Box::pin(future::poll_fn(move |cx| {
+            self.pending_messages_waker.register(cx.waker());
+            if let Ok((message_id, answer)) = self.pending_messages.pop() {
+                return Poll::Ready(NativeProgramEvent::Answer { message_id, answer });
+            }
+
+            let mut interrupts = self.interrupts.lock();
+            for ((_, irq), subscription) in interrupts.iter_mut() {
+                if subscription.waiting.is_none() {
+                    continue;
+                }
+
+                // The waker is registered before reading the counter, so that we can't miss
+                // an interrupt that happens in between.
+                arch::set_irq_waker(*irq, cx.waker());
+                let count = arch::irq_count(*irq).unwrap_or(0);
+                if count > subscription.last_seen_count {
+                    let num_interrupts = count - subscription.last_seen_count;
+                    subscription.last_seen_count = count;
+                    return Poll::Ready(NativeProgramEvent::Answer {
+                        message_id: subscription.waiting.take().unwrap(),
+                        answer: Ok(Ok::<_, ()>(num_interrupts).encode()),
+                    });
+                }
+            }
+
+            Poll::Pending
+        }))
     }
 
     fn interface_message(
@@ -91,59 +252,140 @@ impl<'a> NativeProgramRef<'a> for &'a HardwareHandler {
 
         match HardwareMessage::decode(message) {
             Ok(HardwareMessage::HardwareAccess(operations)) => {
+                // Operations are checked upfront, so that we never perform only part of them.
+                let allowed = operations.iter().all(|operation| {
+                    memory_range(operation)
+                        .map_or(true, |range| self.may_access_memory(emitter_pid, &range))
+                });
+                if !allowed {
+                    if let Some(message_id) = message_id {
+                        self.push_answer(message_id, Err(()));
+                    }
+                    return;
+                }
+
                 let mut response = Vec::with_capacity(operations.len());
                 for operation in operations {
-                    unsafe {
-                        if let Some(outcome) = perform_operation(operation) {
-                            response.push(outcome);
-                        }
+                    if let Some(outcome) = unsafe { perform_operation(operation) } {
+                        response.push(outcome);
                     }
                 }
 
                 if let Some(message_id) = message_id {
                     if !response.is_empty() {
-                        self.pending_messages
-                            .push((message_id, Ok(response.encode())));
+                        self.push_answer(message_id, Ok(response.encode()));
                     }
                 }
             }
-            Ok(HardwareMessage::Malloc { size, alignment }) => {
-                // TODO: this is obviously badly written
-                let size = match usize::try_from(size) {
-                    Ok(s) => s,
-                    Err(_) => panic!(),
-                };
-                let buffer = Vec::with_capacity(size + usize::from(alignment) - 1);
-                let mut ptr = match u64::try_from(buffer.as_ptr() as usize) {
-                    Ok(p) => p,
-                    Err(_) => panic!(),
+            Ok(HardwareMessage::Malloc {
+                size,
+                alignment,
+                below_4gib,
+                cache_coherent,
+            }) => {
+                let ptr = match DmaAllocation::new(size, alignment, below_4gib, cache_coherent) {
+                    Some(allocation) => {
+                        let ptr = allocation.physical_address;
+                        let mut allocations = self.allocations.lock();
+                        allocations.entry(emitter_pid).or_default().push(allocation);
+                        ptr
+                    }
+                    None => 0,
                 };
-                while ptr % u64::from(alignment) != 0 {
-                    ptr += 1;
-                }
 
+                if let Some(message_id) = message_id {
+                    self.push_answer(message_id, Ok(ptr.encode()));
+                }
+            }
+            Ok(HardwareMessage::Free { ptr }) => {
+                // Processes can only free the memory they have allocated themselves.
                 let mut allocations = self.allocations.lock();
-                allocations.entry(emitter_pid).or_default().push(buffer);
+                if let Some(list) = allocations.get_mut(&emitter_pid) {
+                    if let Some(pos) = list.iter().position(|a| a.physical_address == ptr) {
+                        list.remove(pos);
+                    }
+                    if list.is_empty() {
+                        allocations.remove(&emitter_pid);
+                    }
+                }
+            }
+            Ok(HardwareMessage::InterruptSubscribe(irq)) => {
+                let result = {
+                    let mut interrupts = self.interrupts.lock();
+                    if interrupts.contains_key(&(emitter_pid, irq)) {
+                        Ok(())
+                    } else {
+                        match (arch::irq_count(irq), arch::enable_irq(irq)) {
+                            (Some(count), Ok(())) => {
+                                interrupts.insert(
+                                    (emitter_pid, irq),
+                                    InterruptSubscription {
+                                        last_seen_count: count,
+                                        waiting: None,
+                                    },
+                                );
+                                Ok(())
+                            }
+                            _ => Err(()),
+                        }
+                    }
+                };
 
                 if let Some(message_id) = message_id {
-                    self.pending_messages.push((message_id, Ok(ptr.encode())));
+                    self.push_answer(message_id, Ok(result.encode()));
                 }
             }
-            Ok(HardwareMessage::Free { ptr }) => {
-                if let Ok(ptr) = usize::try_from(ptr) {
-                    let mut allocations = self.allocations.lock();
-                    if let Some(list) = allocations.get_mut(&emitter_pid) {
-                        // Since we adjust the returned pointer to match the alignment.
-                        list.retain(|e| {
-                            ptr < e.as_ptr() as usize || ptr >= (e.as_ptr() as usize) + e.len()
-                        });
+            Ok(HardwareMessage::InterruptUnsubscribe(irq)) => {
+                let removed = {
+                    let mut interrupts = self.interrupts.lock();
+                    let removed = interrupts.remove(&(emitter_pid, irq));
+                    if removed.is_some() && !interrupts.keys().any(|(_, i)| *i == irq) {
+                        arch::disable_irq(irq);
+                    }
+                    removed
+                };
+
+                if let Some(InterruptSubscription {
+                    waiting: Some(waiting),
+                    ..
+                }) = removed
+                {
+                    self.push_answer(waiting, Ok(Err::<u64, _>(()).encode()));
+                }
+            }
+            Ok(HardwareMessage::InterruptWait(irq)) => {
+                if let Some(message_id) = message_id {
+                    let mut interrupts = self.interrupts.lock();
+                    match interrupts.get_mut(&(emitter_pid, irq)) {
+                        Some(subscription) if subscription.waiting.is_none() => {
+                            subscription.waiting = Some(message_id);
+                            // The process is ready to handle the next interrupts.
+                            arch::unmask_irq(irq);
+                            self.pending_messages_waker.wake();
+                        }
+                        _ => self.push_answer(message_id, Ok(Err::<u64, _>(()).encode())),
                     }
                 }
             }
-            Ok(HardwareMessage::InterruptWait(_int_id)) => unimplemented!(), // TODO:
+            Ok(HardwareMessage::GetPciEcamRegions) => {
+                if let Some(message_id) = message_id {
+                    self.push_answer(message_id, Ok(self.pci_ecam_regions.clone().encode()));
+                }
+            }
+            Ok(HardwareMessage::GetFramebuffer) => {
+                if let Some(message_id) = message_id {
+                    self.push_answer(message_id, Ok(self.framebuffer.clone().encode()));
+                }
+            }
+            Ok(HardwareMessage::ClaimMmio { address, len }) => {
+                let result = self.claim_mmio(emitter_pid, address, len);
+                if let Some(message_id) = message_id {
+                    self.push_answer(message_id, Ok(result.encode()));
+                }
+            }
             Err(_) => {
                 if let Some(message_id) = message_id {
-                    self.pending_messages.push((message_id, Err(())))
+                    self.push_answer(message_id, Err(()))
                 }
             }
         }
@@ -151,6 +393,23 @@ impl<'a> NativeProgramRef<'a> for &'a HardwareHandler {
 
     fn process_destroyed(self, pid: Pid) {
         self.allocations.lock().remove(&pid);
+        self.mmio_claims.lock().retain(|(owner, _)| *owner != pid);
+
+        let mut interrupts = self.interrupts.lock();
+        let mut unsubscribed = Vec::new();
+        interrupts.retain(|(subscriber, irq), _| {
+            if *subscriber == pid {
+                unsubscribed.push(*irq);
+                false
+            } else {
+                true
+            }
+        });
+        for irq in unsubscribed {
+            if !interrupts.keys().any(|(_, i)| *i == irq) {
+                arch::disable_irq(irq);
+            }
+        }
     }
 
     fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
@@ -158,6 +417,23 @@ impl<'a> NativeProgramRef<'a> for &'a HardwareHandler {
     }
 }
 
+/// Returns the range of physical memory accessed by the given operation, or `None` if the
+/// operation doesn't access physical memory. Out of range addresses yield a range that
+/// extends to the end of the address space.
+fn memory_range(operation: &Operation) -> Option<Range<u64>> {
+    let (address, len) = match operation {
+        Operation::PhysicalMemoryWriteU8 { address, data } => (*address, data.len() as u64),
+        Operation::PhysicalMemoryWriteU16 { address, data } => (*address, 2 * data.len() as u64),
+        Operation::PhysicalMemoryWriteU32 { address, data } => (*address, 4 * data.len() as u64),
+        Operation::PhysicalMemoryReadU8 { address, len } => (*address, u64::from(*len)),
+        Operation::PhysicalMemoryReadU16 { address, len } => (*address, 2 * u64::from(*len)),
+        Operation::PhysicalMemoryReadU32 { address, len } => (*address, 4 * u64::from(*len)),
+        _ => return None,
+    };
+
+    Some(address..address.checked_add(len).unwrap_or(u64::max_value()))
+}
+
 unsafe fn perform_operation(operation: Operation) -> Option<HardwareAccessResponse> {
     match operation {
         Operation::PhysicalMemoryWriteU8 { address, data } => {
@@ -21,13 +21,26 @@
 //! - From one CPU, create a [`Kernel`] with [`Kernel::init`].
 //! - Share the newly-created [`Kernel`] between CPUs, and call [`Kernel::run`] once for each CPU.
 //!
+//! Only the CPU that has called [`Kernel::init`] runs programs. The other CPUs stay halted, and
+//! only wake up in order to handle interrupts.
+//!
 
-use core::sync::atomic::{AtomicBool, Ordering};
+use alloc::vec::Vec;
+use redshirt_core::system::{System, SystemRunOutcome};
+use redshirt_hardware_interface::ffi::{FramebufferInfo, PciEcamRegion};
+use spin::Mutex;
 
 /// Main struct of this crate. Runs everything.
 pub struct Kernel {
-    /// If true, the kernel has started running from a different thread already.
-    running: AtomicBool,
+    /// CPU that has called [`Kernel::init`], as returned by
+    /// [`current_cpu`](crate::arch::current_cpu).
+    init_cpu: u32,
+    /// Configuration passed to [`Kernel::init`]. Extracted by the [`Kernel::run`] function of the
+    /// initial CPU.
+    ///
+    /// The [`System`] isn't thread-safe, as the WASM interpreter uses reference-counted pointers
+    /// internally. It is therefore built by the CPU that runs it, and never leaves that CPU.
+    config: Mutex<Option<KernelConfig>>,
 }
 
 /// Configuration for creating a [`Kernel`].
@@ -36,88 +49,116 @@ pub struct Kernel {
 pub struct KernelConfig {
     /// Number of times the [`Kernel::run`] function might be called.
     pub num_cpus: u32,
+
+    /// Memory regions where the configuration space of PCI Express devices is mapped, as found
+    /// in the ACPI tables. Empty if unknown.
+    pub pci_ecam_regions: Vec<PciEcamRegion>,
+
+    /// Linear framebuffer set up by the bootloader, if any. Made available to programs through
+    /// the `hardware` interface.
+    pub framebuffer: Option<FramebufferInfo>,
+
+    /// Bundle of modules loaded in memory by the bootloader. See the `redshirt-bundle` crate.
+    /// The modules marked as such in the bundle are started when the kernel runs. Empty if the
+    /// bootloader hasn't loaded any bundle.
+    pub modules_bundle: &'static [u8],
+
+    /// Device tree passed by the bootloader, in its binary format, if any. Made available to
+    /// programs through the `devicetree` interface.
+    pub device_tree: Option<&'static [u8]>,
 }
 
 impl Kernel {
     /// Initializes a new `Kernel`.
-    pub fn init(_cfg: KernelConfig) -> Self {
+    pub fn init(cfg: KernelConfig) -> Self {
         Kernel {
-            running: AtomicBool::new(false),
+            init_cpu: crate::arch::current_cpu(),
+            config: Mutex::new(Some(cfg)),
         }
     }
 
     /// Run the kernel. Must be called once per CPU.
     pub fn run(&self) -> ! {
-        // We only want a single CPU to run for now.
-        if self.running.swap(true, Ordering::SeqCst) {
-            crate::arch::halt();
+        // TODO: the system can only be run by one CPU; the other CPUs stay idle
+        if crate::arch::current_cpu() != self.init_cpu {
+            crate::executor::block_on(futures::future::pending::<()>());
+            unreachable!()
         }
 
-        let hello_module = redshirt_core::module::Module::from_bytes(
-            &include_bytes!(
-                "../../../modules/target/wasm32-unknown-unknown/release/hello-world.wasm"
-            )[..],
-        )
-        .unwrap();
-
-        // TODO: use a better system than cfgs
-        #[cfg(target_arch = "x86_64")]
-        let stdout_module = redshirt_core::module::Module::from_bytes(
-            &include_bytes!(
-                "../../../modules/target/wasm32-unknown-unknown/release/x86-stdout.wasm"
-            )[..],
-        )
-        .unwrap();
-        #[cfg(target_arch = "arm")]
-        let stdout_module = redshirt_core::module::Module::from_bytes(
-            &include_bytes!(
-                "../../../modules/target/wasm32-unknown-unknown/release/arm-stdout.wasm"
-            )[..],
-        )
-        .unwrap();
-
-        // TODO: use a better system than cfgs
-        #[cfg(target_arch = "x86_64")]
-        let pci_module = redshirt_core::module::Module::from_bytes(
-            &include_bytes!("../../../modules/target/wasm32-unknown-unknown/release/x86-pci.wasm")
-                [..],
-        )
-        .unwrap();
+        let cfg = match self.config.lock().take() {
+            Some(cfg) => cfg,
+            None => panic!("Kernel::run called multiple times on the same CPU"),
+        };
 
-        // TODO: use a better system than cfgs
-        #[cfg(target_arch = "x86_64")]
-        let ne2000_module = redshirt_core::module::Module::from_bytes(
-            &include_bytes!("../../../modules/target/wasm32-unknown-unknown/debug/ne2000.wasm")[..],
-        )
-        .unwrap();
-
-        let mut system_builder = redshirt_core::system::SystemBuilder::new()
-            .with_native_program(crate::hardware::HardwareHandler::new())
-            .with_native_program(crate::random::native::RandomNativeProgram::new())
-            .with_startup_process(stdout_module)
-            .with_startup_process(hello_module);
-
-        // TODO: use a better system than cfgs
-        #[cfg(target_arch = "x86_64")]
-        {
-            system_builder = system_builder
-                .with_startup_process(pci_module)
-                .with_startup_process(ne2000_module)
-        }
-
-        let mut system = system_builder
-            .with_main_program([0; 32]) // TODO: just a test
-            .build();
+        let mut system = build_system(cfg);
 
         loop {
             // TODO: ideally the entire function would be async, and this would be an `await`,
             // but async functions don't work on no_std yet
             match crate::executor::block_on(system.run()) {
-                redshirt_core::system::SystemRunOutcome::ProgramFinished { pid, outcome } => {
+                SystemRunOutcome::ProgramFinished { pid, outcome } => {
                     //console.write(&format!("Program finished {:?} => {:?}\n", pid, outcome));
                 }
-                _ => panic!(),
+                // The kernel neither sets breakpoints nor instruments modules, so this isn't
+                // supposed to happen. Don't leave the process paused forever if it does.
+                SystemRunOutcome::Breakpoint {
+                    pid,
+                    function_index,
+                    ..
+                } => {
+                    klog!(
+                        "Process {:?} paused at function {}; resuming",
+                        pid,
+                        function_index
+                    );
+                    let _ = system.resume_process(pid);
+                }
             }
         }
     }
 }
+
+/// Builds the [`System`] that the kernel runs.
+fn build_system(cfg: KernelConfig) -> System {
+    // Without a bundle, the kernel still runs, but only with its native programs.
+    let bundle = if cfg.modules_bundle.is_empty() {
+        klog!("No modules bundle passed by the bootloader");
+        None
+    } else {
+        match redshirt_bundle::Bundle::parse(cfg.modules_bundle) {
+            Ok(b) => Some(b),
+            Err(err) => {
+                klog!("Failed to load the modules bundle: {}", err);
+                None
+            }
+        }
+    };
+
+    let mut system_builder = redshirt_core::system::SystemBuilder::new()
+        .with_native_program(
+            crate::hardware::HardwareHandler::new()
+                .with_pci_ecam_regions(cfg.pci_ecam_regions)
+                .with_framebuffer(cfg.framebuffer),
+        )
+        .with_native_program(crate::devicetree::DeviceTreeNativeProgram::new(
+            cfg.device_tree,
+        ))
+        .with_native_program(crate::random::native::RandomNativeProgram::new())
+        .with_native_program(crate::time::native::TimeNativeProgram::new());
+
+    for entry in bundle
+        .iter()
+        .flat_map(|b| b.entries())
+        .filter(|e| e.startup)
+    {
+        let module = match redshirt_core::module::Module::from_bytes(entry.module) {
+            Ok(m) => m,
+            Err(_) => panic!("Failed to parse module {:?}", entry.name),
+        };
+        system_builder = system_builder.with_startup_process(module);
+    }
+
+    system_builder
+        .with_main_program([0; 32]) // TODO: just a test
+        .build()
+}
@@ -0,0 +1,114 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel logs.
+//!
+//! Logs are written to the serial output of the platform: the COM1 port on x86_64, and the UART
+//! found at boot on ARM. Contrary to panic messages, they aren't printed on the screen, as the
+//! screen belongs to the programs.
+
+use core::fmt::{self, Write as _};
+
+/// Writes a line to the kernel logs. Accepts the same parameters as `format!`.
+macro_rules! klog {
+    ($($arg:tt)*) => {
+        $crate::klog::write_line(format_args!($($arg)*))
+    };
+}
+
+/// Writes a line to the kernel logs. Prefer the `klog!` macro.
+pub fn write_line(message: fmt::Arguments) {
+    let mut output = SerialOutput::default();
+    let _ = output.write_fmt(message);
+    let _ = output.write_str("\n");
+}
+
+/// Writer to the serial output of the platform.
+pub struct SerialOutput {
+    _private: (),
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for SerialOutput {
+    fn default() -> SerialOutput {
+        use core::sync::atomic::{AtomicBool, Ordering};
+        static INITIALIZED: AtomicBool = AtomicBool::new(false);
+        if !INITIALIZED.swap(true, Ordering::Relaxed) {
+            init_com1();
+        }
+
+        SerialOutput { _private: () }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl fmt::Write for SerialOutput {
+    fn write_str(&mut self, message: &str) -> fmt::Result {
+        for byte in message.as_bytes() {
+            if *byte == b'\n' {
+                write_com1(b'\r');
+            }
+            write_com1(*byte);
+        }
+
+        Ok(())
+    }
+}
+
+// On ARM, the UART has been found in the device tree and initialized at boot.
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+impl Default for SerialOutput {
+    fn default() -> SerialOutput {
+        SerialOutput { _private: () }
+    }
+}
+
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+impl fmt::Write for SerialOutput {
+    fn write_str(&mut self, message: &str) -> fmt::Result {
+        crate::arch::write_uart(message.as_bytes());
+        Ok(())
+    }
+}
+
+/// Base I/O port of the COM1 serial port.
+#[cfg(target_arch = "x86_64")]
+const COM1_PORT: u32 = 0x3f8;
+
+/// Initializes the COM1 serial port at 115200 bauds, 8 data bits, no parity, one stop bit.
+#[cfg(target_arch = "x86_64")]
+fn init_com1() {
+    unsafe {
+        // Disable interrupts, as we're polling.
+        crate::arch::write_port_u8(COM1_PORT + 1, 0x0);
+        // Set the divisor latch to 1.
+        crate::arch::write_port_u8(COM1_PORT + 3, 0x80);
+        crate::arch::write_port_u8(COM1_PORT + 0, 0x1);
+        crate::arch::write_port_u8(COM1_PORT + 1, 0x0);
+        crate::arch::write_port_u8(COM1_PORT + 3, 0x3);
+        // Enable and clear the FIFOs.
+        crate::arch::write_port_u8(COM1_PORT + 2, 0xc7);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn write_com1(byte: u8) {
+    unsafe {
+        // Wait for the transmit buffer to be empty. If there's no serial port, reading the port
+        // returns 0xff and we don't block.
+        while (crate::arch::read_port_u8(COM1_PORT + 5) & (1 << 5)) == 0 {}
+        crate::arch::write_port_u8(COM1_PORT + 0, byte);
+    }
+}
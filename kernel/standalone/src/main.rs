@@ -27,7 +27,11 @@
 extern crate alloc;
 extern crate compiler_builtins;
 
+#[macro_use]
+mod klog;
+
 mod arch;
+mod devicetree;
 mod executor;
 mod hardware;
 mod kernel;
@@ -13,11 +13,28 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use core::ops::Range;
+//! Memory allocation.
+//!
+//! The kernel heap spans all the memory ranges passed to [`initialize`]. Since the memory is
+//! identity-mapped, the heap also serves as the allocator of physical memory. See
+//! [`allocate_frames`].
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem,
+    ops::Range,
+    ptr::{self, NonNull},
+};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+/// Size of a page of memory.
+pub const PAGE_SIZE: usize = 0x1000;
 
 /// Initialize the memory allocator.
 ///
-/// Pass to this function a list of memory ranges that are available for use.
+/// Pass to this function a list of memory ranges that are available for use. Ranges too small to
+/// be useful are ignored. There is no limit to the number of ranges.
 ///
 /// After this function returns, you can use heap allocations.
 ///
@@ -32,29 +49,127 @@ use core::ops::Range;
 /// allocator) afterwards.
 ///
 pub unsafe fn initialize(ranges: impl Iterator<Item = Range<usize>>) {
-    // We choose the largest range.
-    let range = ranges.max_by_key(|r| {
-        assert!(r.end >= r.start);
-        r.end - r.start
-    });
-
-    let range = match range {
-        Some(r) => r,
-        // If the iterator was empty, return with initializing the allocator.
-        None => return,
-    };
-
-    // Don't initialize the allocator if all the ranges were 0.
-    if range.start == range.end {
-        return;
+    let mut heaps = ALLOCATOR.heaps.lock();
+
+    // Find the end of the list, in order to preserve the order of the ranges.
+    let mut tail: &mut Option<&'static mut RangeHeap> = &mut *heaps;
+    while let Some(node) = tail {
+        tail = &mut node.next;
+    }
+
+    for range in ranges {
+        assert!(range.end >= range.start);
+
+        // The `RangeHeap` is stored at the start of the range itself.
+        let node_align = mem::align_of::<RangeHeap>();
+        let node_start = match range.start.checked_add(node_align - 1) {
+            Some(s) => s & !(node_align - 1),
+            None => continue,
+        };
+        let heap_start = node_start.saturating_add(mem::size_of::<RangeHeap>());
+        if heap_start >= range.end || range.end - heap_start < PAGE_SIZE {
+            continue;
+        }
+
+        let node = node_start as *mut RangeHeap;
+        node.write(RangeHeap {
+            heap: Heap::new(heap_start, range.end - heap_start),
+            next: None,
+        });
+
+        *tail = Some(&mut *node);
+        tail = &mut tail.as_mut().unwrap().next;
+    }
+}
+
+/// Allocates physical memory that is never freed, such as page tables. The memory is zeroed.
+///
+/// Since the memory is identity-mapped, the returned pointer is also the physical address of the
+/// memory. Returns `None` if there isn't enough memory available.
+pub fn allocate_frames(size: usize, alignment: usize) -> Option<NonNull<u8>> {
+    let layout = Layout::from_size_align(size, alignment).ok()?;
+    NonNull::new(unsafe { alloc::alloc::alloc_zeroed(layout) })
+}
+
+/// Allocates memory that ends at or below `max_address`. The memory is zeroed.
+///
+/// Since the memory is identity-mapped, the returned pointer is also the physical address of the
+/// memory. Returns `None` if there isn't enough memory available below `max_address`.
+///
+/// The memory can be freed with [`alloc::alloc::dealloc`].
+pub fn allocate_below(layout: Layout, max_address: usize) -> Option<NonNull<u8>> {
+    let mut heaps = ALLOCATOR.heaps.lock();
+
+    let mut node = heaps.as_mut();
+    while let Some(n) = node {
+        if n.heap.bottom() < max_address {
+            // Holes are sorted by address, and `allocate_first_fit` returns the lowest one that
+            // fits. If it is too high, there is no suitable space in this heap.
+            if let Ok(ptr) = n.heap.allocate_first_fit(layout) {
+                let end = (ptr.as_ptr() as usize).checked_add(layout.size());
+                if end.map_or(false, |end| end <= max_address) {
+                    unsafe { ptr::write_bytes(ptr.as_ptr(), 0, layout.size()) };
+                    return Some(ptr);
+                }
+                unsafe { n.heap.deallocate(ptr, layout) };
+            }
+        }
+
+        node = n.next.as_mut();
     }
 
-    assert!(range.end >= range.start);
-    ALLOCATOR.lock().init(range.start, range.end - range.start);
+    None
 }
 
 #[global_allocator]
-static ALLOCATOR: linked_list_allocator::LockedHeap = linked_list_allocator::LockedHeap::empty();
+static ALLOCATOR: Allocator = Allocator {
+    heaps: Mutex::new(None),
+};
+
+/// Allocator spanning multiple non-contiguous memory ranges, each with its own heap.
+struct Allocator {
+    /// Linked list of heaps, one per memory range, in the order the ranges have been passed to
+    /// [`initialize`].
+    heaps: Mutex<Option<&'static mut RangeHeap>>,
+}
+
+/// Heap covering one of the memory ranges. Stored at the start of the range that it covers.
+struct RangeHeap {
+    /// Heap covering the rest of the range.
+    heap: Heap,
+    /// Next element in the list.
+    next: Option<&'static mut RangeHeap>,
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heaps = self.heaps.lock();
+
+        let mut node = heaps.as_mut();
+        while let Some(n) = node {
+            if let Ok(ptr) = n.heap.allocate_first_fit(layout) {
+                return ptr.as_ptr();
+            }
+            node = n.next.as_mut();
+        }
+
+        ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut heaps = self.heaps.lock();
+        let addr = ptr as usize;
+
+        let mut node = heaps.as_mut();
+        while let Some(n) = node {
+            if addr >= n.heap.bottom() && addr < n.heap.top() {
+                n.heap.deallocate(NonNull::new_unchecked(ptr), layout);
+                return;
+            }
+            node = n.next.as_mut();
+        }
+    }
+}
 
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
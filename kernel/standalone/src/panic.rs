@@ -13,13 +13,36 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::klog::SerialOutput;
 use alloc::string::String;
 use core::fmt::{self, Write};
 
+#[cfg(target_arch = "x86_64")]
+use core::{convert::TryFrom as _, ptr};
+#[cfg(target_arch = "x86_64")]
+use redshirt_hardware_interface::ffi::{ColorField, FramebufferFormat, FramebufferInfo};
+
+#[cfg(target_arch = "x86_64")]
+#[path = "../../../modules/framebuffer-console/src/font.rs"]
+mod font;
+
+/// Linear framebuffer on which to draw the panic messages. If `None`, the messages are written
+/// in the VGA text buffer.
+#[cfg(target_arch = "x86_64")]
+static FRAMEBUFFER: spin::Once<FramebufferInfo> = spin::Once::new();
+
+/// Registers the linear framebuffer set up by the bootloader, so that panic messages are drawn
+/// on it rather than in the VGA text buffer.
+///
+/// The memory of the framebuffer must be accessible. Framebuffers that aren't in the RGB format
+/// are ignored.
+#[cfg(target_arch = "x86_64")]
+pub fn set_framebuffer(framebuffer: FramebufferInfo) {
+    FRAMEBUFFER.call_once(|| framebuffer);
+}
+
 #[panic_handler]
 fn panic(panic_info: &core::panic::PanicInfo) -> ! {
-    // TODO: switch back to text mode somehow?
-
     let mut console = Console::default();
 
     if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
@@ -50,139 +73,259 @@ fn panic(panic_info: &core::panic::PanicInfo) -> ! {
     crate::arch::halt();
 }
 
-// State machine for the standard text console.
-#[cfg(target_arch = "x86_64")]
-#[derive(Default)]
+// The panic messages are written to the serial output, and on x86_64 also on the screen.
 struct Console {
-    cursor_x: u8,
-    cursor_y: u8,
-}
-
-// State machine for the standard text console.
-#[cfg(target_arch = "arm")]
-struct Console {}
-
-#[cfg(target_arch = "x86_64")]
-impl fmt::Write for Console {
-    fn write_str(&mut self, message: &str) -> fmt::Result {
-        unsafe {
-            for chr in message.chars() {
-                if !chr.is_ascii() {
-                    continue;
-                }
-
-                if chr == '\n' {
-                    self.cursor_x = 0;
-                    self.cursor_y += 1;
-                    if self.cursor_y == 25 {
-                        self.cursor_y -= 1;
-                        line_up();
-                    }
-                    continue;
-                }
-
-                let chr = chr as u8;
-                ptr_of(self.cursor_x, self.cursor_y).write_volatile(u16::from(chr) | 0xf00);
-
-                debug_assert!(self.cursor_x < 80);
-                self.cursor_x += 1;
-                if self.cursor_x == 80 {
-                    self.cursor_x = 0;
-                    debug_assert!(self.cursor_y < 25);
-                    self.cursor_y += 1;
-                    if self.cursor_y == 25 {
-                        self.cursor_y -= 1;
-                        line_up(); // TODO: no?
-                    }
-                }
-            }
-        }
-
-        Ok(())
-    }
+    serial: SerialOutput,
+    #[cfg(target_arch = "x86_64")]
+    screen: Screen,
 }
 
-// State machine for the standard text console.
-#[cfg(target_arch = "arm")]
 impl Default for Console {
     fn default() -> Console {
-        init_uart();
-        Console {}
+        Console {
+            serial: SerialOutput::default(),
+            #[cfg(target_arch = "x86_64")]
+            screen: Screen::new(),
+        }
     }
 }
 
-#[cfg(target_arch = "arm")]
 impl fmt::Write for Console {
     fn write_str(&mut self, message: &str) -> fmt::Result {
-        for byte in message.as_bytes() {
-            write_uart(*byte);
-        }
+        let _ = self.serial.write_str(message);
+        #[cfg(target_arch = "x86_64")]
+        self.screen.write_str(message);
         Ok(())
     }
 }
 
-fn ptr_of(x: u8, y: u8) -> *mut u16 {
-    assert!(x < 80);
-    assert!(y < 25);
+// State machine for the screen. The screen is cleared when it is created, as the programs might
+// have drawn on it.
+#[cfg(target_arch = "x86_64")]
+struct Screen {
+    output: ScreenOutput,
+    columns: u32,
+    rows: u32,
+    cursor_x: u32,
+    cursor_y: u32,
+}
 
-    unsafe {
-        let offset = isize::from(y) * 80 + isize::from(x);
-        (0xb8000 as *mut u16).offset(offset)
-    }
+#[cfg(target_arch = "x86_64")]
+enum ScreenOutput {
+    /// Linear framebuffer set up by the bootloader. Characters are drawn with the same font as
+    /// the `framebuffer-console` program.
+    Framebuffer {
+        framebuffer: &'static FramebufferInfo,
+        /// Encoded value of a white pixel.
+        foreground: u32,
+    },
+    /// Standard VGA text buffer.
+    Text,
 }
 
-fn line_up() {
-    unsafe {
-        for y in 1..25 {
-            for x in 0..80 {
-                let val = ptr_of(x, y).read_volatile();
-                ptr_of(x, y - 1).write_volatile(val);
+#[cfg(target_arch = "x86_64")]
+impl Screen {
+    fn new() -> Screen {
+        let framebuffer = FRAMEBUFFER.r#try().and_then(|framebuffer| {
+            if framebuffer.width < font::CHAR_WIDTH || framebuffer.height < font::CHAR_HEIGHT {
+                return None;
+            }
+
+            match &framebuffer.format {
+                FramebufferFormat::Rgb { red, green, blue } => Some((
+                    framebuffer,
+                    encode_component(red) | encode_component(green) | encode_component(blue),
+                )),
+                FramebufferFormat::Indexed | FramebufferFormat::Text => None,
+            }
+        });
+
+        let screen = if let Some((framebuffer, foreground)) = framebuffer {
+            Screen {
+                output: ScreenOutput::Framebuffer {
+                    framebuffer,
+                    foreground,
+                },
+                columns: framebuffer.width / font::CHAR_WIDTH,
+                rows: framebuffer.height / font::CHAR_HEIGHT,
+                cursor_x: 0,
+                cursor_y: 0,
+            }
+        } else {
+            Screen {
+                output: ScreenOutput::Text,
+                columns: 80,
+                rows: 25,
+                cursor_x: 0,
+                cursor_y: 0,
+            }
+        };
+
+        screen.clear();
+        screen
+    }
+
+    fn write_str(&mut self, message: &str) {
+        for chr in message.chars() {
+            if chr == '\n' {
+                self.new_line();
+                continue;
+            }
+
+            let chr = if chr.is_ascii() && !chr.is_ascii_control() {
+                chr
+            } else {
+                '?'
+            };
+
+            self.draw_char(self.cursor_x, self.cursor_y, chr);
+            self.cursor_x += 1;
+            if self.cursor_x == self.columns {
+                self.new_line();
             }
         }
+    }
 
-        for x in 0..80 {
-            ptr_of(x, 24).write_volatile(0);
+    fn new_line(&mut self) {
+        self.cursor_x = 0;
+        self.cursor_y += 1;
+        if self.cursor_y == self.rows {
+            self.cursor_y -= 1;
+            self.line_up();
         }
     }
-}
 
-const GPIO_BASE: usize = 0x3F200000;
-const UART0_BASE: usize = 0x3F201000;
+    fn draw_char(&self, x: u32, y: u32, chr: char) {
+        debug_assert!(x < self.columns);
+        debug_assert!(y < self.rows);
 
-fn init_uart() {
-    unsafe {
-        ((UART0_BASE + 0x30) as *mut u32).write_volatile(0x0);
-        ((GPIO_BASE + 0x94) as *mut u32).write_volatile(0x0);
-        delay(150);
+        match self.output {
+            ScreenOutput::Framebuffer {
+                framebuffer,
+                foreground,
+            } => {
+                let glyph = font::glyph(chr).unwrap_or_else(|| font::glyph('?').unwrap());
+                for (line_num, line) in glyph.iter().enumerate() {
+                    let pixel_y = y * font::CHAR_HEIGHT + u32::try_from(line_num).unwrap();
+                    for column in 0..font::CHAR_WIDTH {
+                        let lit = (line & (0x80 >> column)) != 0;
+                        let pixel_x = x * font::CHAR_WIDTH + column;
+                        let value = if lit { foreground } else { 0 };
+                        write_pixel(framebuffer, pixel_x, pixel_y, value);
+                    }
+                }
+            }
+            ScreenOutput::Text => unsafe {
+                text_ptr_of(x, y).write_volatile(u16::from(chr as u8) | 0xf00);
+            },
+        }
+    }
 
-        ((GPIO_BASE + 0x98) as *mut u32).write_volatile((1 << 14) | (1 << 15));
-        delay(150);
+    fn line_up(&self) {
+        match self.output {
+            ScreenOutput::Framebuffer { framebuffer, .. } => unsafe {
+                let row_len = framebuffer_row_len(framebuffer);
+                let base = framebuffer_base(framebuffer);
+                ptr::copy(
+                    base.add(row_len),
+                    base,
+                    row_len * usize::try_from(self.rows - 1).unwrap(),
+                );
+            },
+            ScreenOutput::Text => unsafe {
+                for y in 1..self.rows {
+                    for x in 0..self.columns {
+                        let val = text_ptr_of(x, y).read_volatile();
+                        text_ptr_of(x, y - 1).write_volatile(val);
+                    }
+                }
+            },
+        }
 
-        ((GPIO_BASE + 0x98) as *mut u32).write_volatile(0x0);
+        self.clear_row(self.rows - 1);
+    }
 
-        ((UART0_BASE + 0x44) as *mut u32).write_volatile(0x7FF);
+    fn clear(&self) {
+        match self.output {
+            ScreenOutput::Framebuffer { framebuffer, .. } => unsafe {
+                let len = framebuffer.pitch * framebuffer.height;
+                ptr::write_bytes(
+                    framebuffer_base(framebuffer),
+                    0,
+                    usize::try_from(len).unwrap(),
+                );
+            },
+            ScreenOutput::Text => {
+                for y in 0..self.rows {
+                    self.clear_row(y);
+                }
+            }
+        }
+    }
 
-        ((UART0_BASE + 0x24) as *mut u32).write_volatile(1);
-        ((UART0_BASE + 0x28) as *mut u32).write_volatile(40);
+    fn clear_row(&self, y: u32) {
+        debug_assert!(y < self.rows);
 
-        ((UART0_BASE + 0x2C) as *mut u32).write_volatile((1 << 4) | (1 << 5) | (1 << 6));
+        match self.output {
+            ScreenOutput::Framebuffer { framebuffer, .. } => unsafe {
+                let row_len = framebuffer_row_len(framebuffer);
+                let base = framebuffer_base(framebuffer);
+                ptr::write_bytes(base.add(row_len * usize::try_from(y).unwrap()), 0, row_len);
+            },
+            ScreenOutput::Text => unsafe {
+                for x in 0..self.columns {
+                    text_ptr_of(x, y).write_volatile(0);
+                }
+            },
+        }
+    }
+}
 
-        ((UART0_BASE + 0x38) as *mut u32).write_volatile(
-            (1 << 1) | (1 << 4) | (1 << 5) | (1 << 6) | (1 << 7) | (1 << 8) | (1 << 9) | (1 << 10),
-        );
+/// Returns the value of the given color component at its maximum intensity.
+#[cfg(target_arch = "x86_64")]
+fn encode_component(field: &ColorField) -> u32 {
+    let max = 1u32
+        .checked_shl(u32::from(field.size))
+        .map_or(!0, |v| v - 1);
+    max.checked_shl(u32::from(field.position)).unwrap_or(0)
+}
 
-        ((UART0_BASE + 0x30) as *mut u32).write_volatile((1 << 0) | (1 << 8) | (1 << 9));
-    }
+#[cfg(target_arch = "x86_64")]
+fn framebuffer_base(framebuffer: &FramebufferInfo) -> *mut u8 {
+    // The memory is identity-mapped. See `boot.S`.
+    usize::try_from(framebuffer.address).unwrap() as *mut u8
+}
+
+/// Returns the number of bytes of a row of characters in the framebuffer.
+#[cfg(target_arch = "x86_64")]
+fn framebuffer_row_len(framebuffer: &FramebufferInfo) -> usize {
+    usize::try_from(framebuffer.pitch * font::CHAR_HEIGHT).unwrap()
 }
 
-fn write_uart(byte: u8) {
+#[cfg(target_arch = "x86_64")]
+fn write_pixel(framebuffer: &FramebufferInfo, x: u32, y: u32, value: u32) {
+    debug_assert!(x < framebuffer.width);
+    debug_assert!(y < framebuffer.height);
+
+    let bytes_per_pixel = (usize::from(framebuffer.bpp) + 7) / 8;
+    let offset = usize::try_from(y * framebuffer.pitch).unwrap()
+        + usize::try_from(x).unwrap() * bytes_per_pixel;
+
     unsafe {
-        // Wait for UART to become ready to transmit.
-        while (((UART0_BASE + 0x18) as *mut u32).read_volatile() & (1 << 5)) != 0 {}
-        ((UART0_BASE + 0x0) as *mut u32).write_volatile(u32::from(byte));
+        let pixel = framebuffer_base(framebuffer).add(offset);
+        for (n, byte) in value.to_le_bytes().iter().take(bytes_per_pixel).enumerate() {
+            pixel.add(n).write_volatile(*byte);
+        }
     }
 }
 
-fn delay(count: i32) {
-    // TODO: asm!("__delay_%=: subs %[count], %[count], #1; bne __delay_%=\n" : "=r"(count): [count]"0"(count) : "cc");
+#[cfg(target_arch = "x86_64")]
+fn text_ptr_of(x: u32, y: u32) -> *mut u16 {
+    assert!(x < 80);
+    assert!(y < 25);
+
+    unsafe {
+        let offset = usize::try_from(y * 80 + x).unwrap();
+        (0xb8000 as *mut u16).add(offset)
+    }
 }
@@ -17,7 +17,7 @@
 
 use crate::random::rng::KernelRng;
 
-use alloc::{boxed::Box, vec};
+use alloc::{boxed::Box, vec, vec::Vec};
 use core::{pin::Pin, sync::atomic};
 use crossbeam_queue::SegQueue;
 use futures::prelude::*;
@@ -25,6 +25,11 @@ use rand_core::RngCore as _;
 use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
 use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
 use redshirt_random_interface::ffi::{GenerateResponse, RandomMessage, INTERFACE};
+use sha2::{
+    digest::{FixedOutput as _, Input as _},
+    Sha512Trunc256,
+};
+use spin::Mutex;
 
 /// State machine for `random` interface messages handling.
 pub struct RandomNativeProgram {
@@ -32,6 +37,9 @@ pub struct RandomNativeProgram {
     registered: atomic::AtomicBool,
     /// Queue of random number generators. If it is empty, we generate a new one.
     rngs: SegQueue<KernelRng>,
+    /// Hash of all the entropy provided through `AddEntropy` messages so far. Mixed into newly
+    /// generated random number generators. `None` if no entropy has been provided.
+    added_entropy: Mutex<Option<[u8; 32]>>,
     /// Message responses waiting to be emitted.
     pending_messages: SegQueue<(MessageId, Result<EncodedMessage, ()>)>,
 }
@@ -42,9 +50,35 @@ impl RandomNativeProgram {
         RandomNativeProgram {
             registered: atomic::AtomicBool::new(false),
             rngs: SegQueue::new(),
+            added_entropy: Mutex::new(None),
             pending_messages: SegQueue::new(),
         }
     }
+
+    /// Mixes the given entropy into all the existing random number generators, and into the
+    /// ones that will be generated in the future.
+    fn add_entropy(&self, entropy: &[u8]) {
+        {
+            let mut added_entropy = self.added_entropy.lock();
+            let mut sha2 = Sha512Trunc256::default();
+            if let Some(previous) = &*added_entropy {
+                sha2.input(&previous[..]);
+            }
+            sha2.input(entropy);
+            let mut hash = [0; 32];
+            hash.copy_from_slice(&sha2.fixed_result());
+            *added_entropy = Some(hash);
+        }
+
+        let mut rngs = Vec::new();
+        while let Ok(rng) = self.rngs.pop() {
+            rngs.push(rng);
+        }
+        for mut rng in rngs {
+            rng.add_entropy(entropy);
+            self.rngs.push(rng);
+        }
+    }
 }
 
 impl<'a> NativeProgramRef<'a> for &'a RandomNativeProgram {
@@ -81,19 +115,28 @@ impl<'a> NativeProgramRef<'a> for &'a RandomNativeProgram {
     ) {
         debug_assert_eq!(interface, INTERFACE);
 
+        let message = RandomMessage::decode(message);
+        if let Ok(RandomMessage::AddEntropy(entropy)) = &message {
+            self.add_entropy(entropy);
+        }
+
         let message_id = match message_id {
             Some(m) => m,
             None => return,
         };
 
-        match RandomMessage::decode(message) {
+        match message {
             Ok(RandomMessage::Generate { len }) => {
                 let mut out = vec![0; usize::from(len)];
 
                 let mut rng = if let Ok(rng) = self.rngs.pop() {
                     rng
                 } else {
-                    KernelRng::new()
+                    let mut rng = KernelRng::new();
+                    if let Some(entropy) = &*self.added_entropy.lock() {
+                        rng.add_entropy(entropy);
+                    }
+                    rng
                 };
 
                 rng.fill_bytes(&mut out);
@@ -102,6 +145,7 @@ impl<'a> NativeProgramRef<'a> for &'a RandomNativeProgram {
                 self.pending_messages
                     .push((message_id, Ok(response.encode())));
             }
+            Ok(RandomMessage::AddEntropy(_)) => {}
             Err(_) => self.pending_messages.push((message_id, Err(()))),
         }
     }
@@ -95,6 +95,25 @@ impl KernelRng {
     }
 }
 
+impl KernelRng {
+    /// Mixes additional entropy into the generator, for example coming from a hardware random
+    /// number generator.
+    ///
+    /// The generator is reseeded with a hash of its own output and of `entropy`. Consequently,
+    /// passing predictable data doesn't weaken it.
+    pub fn add_entropy(&mut self, entropy: &[u8]) {
+        let mut sha2 = Sha512Trunc256::default();
+        let mut current = [0; 32];
+        self.rng.fill_bytes(&mut current);
+        sha2.input(&current[..]);
+        sha2.input(entropy);
+
+        let mut chacha_seed = [0; 32];
+        chacha_seed.copy_from_slice(&sha2.fixed_result());
+        self.rng = From::from(ChaCha20Core::from_seed(chacha_seed));
+    }
+}
+
 impl RngCore for KernelRng {
     fn next_u32(&mut self) -> u32 {
         self.rng.next_u32()
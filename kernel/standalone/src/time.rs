@@ -15,22 +15,14 @@
 
 use core::time::Duration;
 
+pub mod native;
+
 /// Returns the amount of time that has elapsed since an undeterminate moment in time.
-#[cfg(target_arch = "x86_64")]
 pub fn monotonic_clock() -> Duration {
-    // TODO: wrong unit
-    let ns = unsafe { core::arch::x86_64::_rdtsc() };
-    Duration::from_nanos(ns)
+    crate::arch::monotonic_clock()
 }
 
-/// Returns the amount of time that has elapsed since an undeterminate moment in time.
-#[cfg(target_arch = "arm")]
-pub fn monotonic_clock() -> Duration {
-    // TODO: ugh
-    // TODO: assumes that performance counters are supported and enabled
-    let reg: u32;
-    unsafe {
-        asm!("mrc p15, 0, $0, c9, c13, 0" : "=r"(reg) ::: "volatile");
-    }
-    Duration::from_nanos(u64::from(reg))
+/// Returns the amount of time that has elapsed since the UNIX epoch.
+pub fn system_clock() -> Duration {
+    crate::arch::system_clock()
 }
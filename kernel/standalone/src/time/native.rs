@@ -0,0 +1,145 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Native program that handles the `time` interface.
+
+use crate::arch;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::{convert::TryFrom as _, pin::Pin, sync::atomic, task::Poll, time::Duration};
+use crossbeam_queue::SegQueue;
+use futures::{prelude::*, task::AtomicWaker};
+use redshirt_core::native::{DummyMessageIdWrite, NativeProgramEvent, NativeProgramRef};
+use redshirt_core::{Decode as _, Encode as _, EncodedMessage, InterfaceHash, MessageId, Pid};
+use redshirt_time_interface::ffi::{TimeMessage, INTERFACE};
+use spin::Mutex;
+
+/// State machine for `time` interface messages handling.
+pub struct TimeNativeProgram {
+    /// If true, we have sent the interface registration message.
+    registered: atomic::AtomicBool,
+    /// List of `WaitMonotonic` messages, with the value of the monotonic clock in nanoseconds
+    /// after which they must be answered.
+    timers: Mutex<Vec<(u128, MessageId)>>,
+    /// Message responses waiting to be emitted.
+    pending_messages: SegQueue<(MessageId, Result<EncodedMessage, ()>)>,
+    /// Waker to wake up when a new message is pushed to `pending_messages` or a new timer is
+    /// pushed to `timers`.
+    pending_messages_waker: AtomicWaker,
+}
+
+impl TimeNativeProgram {
+    /// Initializes the new state machine for time messages handling.
+    pub fn new() -> Self {
+        TimeNativeProgram {
+            registered: atomic::AtomicBool::new(false),
+            timers: Mutex::new(Vec::new()),
+            pending_messages: SegQueue::new(),
+            pending_messages_waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Pushes a message to answer to `pending_messages` and wakes up the task.
+    fn push_answer(&self, message_id: MessageId, answer: Result<EncodedMessage, ()>) {
+        self.pending_messages.push((message_id, answer));
+        self.pending_messages_waker.wake();
+    }
+}
+
+impl<'a> NativeProgramRef<'a> for &'a TimeNativeProgram {
+    type Future =
+        Pin<Box<dyn Future<Output = NativeProgramEvent<Self::MessageIdWrite>> + Send + 'a>>;
+    type MessageIdWrite = DummyMessageIdWrite;
+
+    fn next_event(self) -> Self::Future {
+        if !self.registered.swap(true, atomic::Ordering::Relaxed) {
+            return Box::pin(future::ready(NativeProgramEvent::Emit {
+                interface: redshirt_interface_interface::ffi::INTERFACE,
+                message_id_write: None,
+                message: redshirt_interface_interface::ffi::InterfaceMessage::Register(INTERFACE)
+                    .encode(),
+            }));
+        }
+
+        Box::pin(future::poll_fn(move |cx| {
+            self.pending_messages_waker.register(cx.waker());
+            if let Ok((message_id, answer)) = self.pending_messages.pop() {
+                return Poll::Ready(NativeProgramEvent::Answer { message_id, answer });
+            }
+
+            let mut timers = self.timers.lock();
+            let now = crate::time::monotonic_clock().as_nanos();
+            if let Some(pos) = timers.iter().position(|(when, _)| *when <= now) {
+                let (_, message_id) = timers.swap_remove(pos);
+                return Poll::Ready(NativeProgramEvent::Answer {
+                    message_id,
+                    answer: Ok(().encode()),
+                });
+            }
+
+            // Arm the hardware timer for the earliest deadline.
+            if let Some(earliest) = timers.iter().map(|(when, _)| *when).min() {
+                arch::set_timer_waker(nanos_to_duration(earliest), cx.waker());
+            }
+
+            Poll::Pending
+        }))
+    }
+
+    fn interface_message(
+        self,
+        interface: InterfaceHash,
+        message_id: Option<MessageId>,
+        _emitter_pid: Pid,
+        message: EncodedMessage,
+    ) {
+        debug_assert_eq!(interface, INTERFACE);
+
+        let message_id = match message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        match TimeMessage::decode(message) {
+            Ok(TimeMessage::GetMonotonic) => {
+                let now = crate::time::monotonic_clock().as_nanos();
+                self.push_answer(message_id, Ok(now.encode()));
+            }
+            Ok(TimeMessage::GetSystem) => {
+                let now = crate::time::system_clock().as_nanos();
+                self.push_answer(message_id, Ok(now.encode()));
+            }
+            Ok(TimeMessage::WaitMonotonic(until)) => {
+                self.timers.lock().push((until, message_id));
+                self.pending_messages_waker.wake();
+            }
+            Err(_) => self.push_answer(message_id, Err(())),
+        }
+    }
+
+    fn process_destroyed(self, _: Pid) {}
+
+    fn message_response(self, _: MessageId, _: Result<EncodedMessage, ()>) {
+        unreachable!()
+    }
+}
+
+/// Turns a number of nanoseconds into a `Duration`, saturating if it is too large.
+fn nanos_to_duration(nanos: u128) -> Duration {
+    match u64::try_from(nanos / 1_000_000_000) {
+        Ok(secs) => Duration::new(secs, u32::try_from(nanos % 1_000_000_000).unwrap()),
+        Err(_) => Duration::new(u64::max_value(), 999_999_999),
+    }
+}
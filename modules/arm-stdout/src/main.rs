@@ -26,7 +26,9 @@ fn main() {
 async fn async_main() -> ! {
     redshirt_interface_interface::register_interface(redshirt_stdout_interface::ffi::INTERFACE)
         .await.unwrap();
-    init_uart();
+    let uart = find_uart().await;
+    claim_registers(&uart).await.unwrap();
+    init_uart(&uart);
 
     loop {
         let msg = match redshirt_syscalls_interface::next_interface_message().await {
@@ -38,56 +40,115 @@ async fn async_main() -> ! {
         let redshirt_stdout_interface::ffi::StdoutMessage::Message(message) =
             DecodeAll::decode_all(&msg.actual_data).unwrap();       // TODO: don't unwrap
         for byte in message.as_bytes() {
-            write_uart(*byte).await;
+            write_uart(&uart, *byte).await;
         }
     }
 }
 
-const GPIO_BASE: u64 = 0x3F200000;
-const UART0_BASE: u64 = 0x3F201000;
+/// Locations of the UART and GPIO registers of the Raspberry Pi 2 and 3, used if the kernel
+/// doesn't provide any device tree.
+const DEFAULT_GPIO_BASE: u64 = 0x3F200000;
+const DEFAULT_UART0_BASE: u64 = 0x3F201000;
+
+/// Physical addresses of the registers we need.
+struct Uart {
+    /// Base address of the PL011 UART registers.
+    base: u64,
+    /// Base address of the GPIO registers of the BCM2835, if any. Used to route the UART to the
+    /// GPIO pins.
+    gpio_base: Option<u64>,
+}
 
-fn init_uart() {
+/// Finds the UART in the device tree.
+///
+/// Falls back to the addresses of the Raspberry Pi if there is no device tree, if it is invalid,
+/// or if it doesn't contain any PL011 UART.
+async fn find_uart() -> Uart {
+    let default = Uart {
+        base: DEFAULT_UART0_BASE,
+        gpio_base: Some(DEFAULT_GPIO_BASE),
+    };
+
+    let device_tree = match redshirt_devicetree_interface::get_device_tree().await {
+        Some(dt) => dt,
+        None => return default,
+    };
+
+    let device_tree = match redshirt_fdt::DeviceTree::parse(&device_tree) {
+        Ok(dt) => dt,
+        Err(_) => return default,
+    };
+    let find_device = |compatible| {
+        device_tree
+            .compatible_nodes(compatible)
+            .filter_map(|n| device_tree.translate_address(&n, n.reg().next()?.0))
+            .next()
+    };
+
+    match find_device("arm,pl011") {
+        Some(base) => Uart {
+            base,
+            gpio_base: find_device("brcm,bcm2835-gpio"),
+        },
+        None => default,
+    }
+}
+
+/// Claims the registers of the UART and of the GPIO controller.
+async fn claim_registers(uart: &Uart) -> Result<(), ()> {
+    // The registers we use are all within the first 4kiB of each device.
+    redshirt_hardware_interface::claim_mmio(uart.base, 0x1000).await?;
+    if let Some(gpio_base) = uart.gpio_base {
+        redshirt_hardware_interface::claim_mmio(gpio_base, 0x1000).await?;
+    }
+    Ok(())
+}
+
+fn init_uart(uart: &Uart) {
     unsafe {
         let mut ops = redshirt_hardware_interface::HardwareWriteOperationsBuilder::new();
 
-        ops.write_one_u32(UART0_BASE + 0x30, 0x0);
-        ops.write_one_u32(GPIO_BASE + 0x94, 0x0);
-        delay(150);
+        ops.write_one_u32(uart.base + 0x30, 0x0);
 
-        ops.write_one_u32(GPIO_BASE + 0x98, (1 << 14) | (1 << 15));
-        delay(150);
+        if let Some(gpio_base) = uart.gpio_base {
+            ops.write_one_u32(gpio_base + 0x94, 0x0);
+            delay(150);
 
-        ops.write_one_u32(GPIO_BASE + 0x98, 0x0);
+            ops.write_one_u32(gpio_base + 0x98, (1 << 14) | (1 << 15));
+            delay(150);
+
+            ops.write_one_u32(gpio_base + 0x98, 0x0);
+        }
 
-        ops.write_one_u32(UART0_BASE + 0x44, 0x7FF);
+        ops.write_one_u32(uart.base + 0x44, 0x7FF);
 
-        ops.write_one_u32(UART0_BASE + 0x24, 1);
-        ops.write_one_u32(UART0_BASE + 0x28, 40);
+        ops.write_one_u32(uart.base + 0x24, 1);
+        ops.write_one_u32(uart.base + 0x28, 40);
 
-        ops.write_one_u32(UART0_BASE + 0x2C, (1 << 4) | (1 << 5) | (1 << 6));
+        ops.write_one_u32(uart.base + 0x2C, (1 << 4) | (1 << 5) | (1 << 6));
 
-        ops.write_one_u32(UART0_BASE + 0x38, 
+        ops.write_one_u32(uart.base + 0x38, 
             (1 << 1) | (1 << 4) | (1 << 5) | (1 << 6) | (1 << 7) | (1 << 8) | (1 << 9) | (1 << 10)
         );
 
-        ops.write_one_u32(UART0_BASE + 0x30, (1 << 0) | (1 << 8) | (1 << 9));
+        ops.write_one_u32(uart.base + 0x30, (1 << 0) | (1 << 8) | (1 << 9));
         ops.send();
     }
 }
 
-async fn write_uart(byte: u8) {
+async fn write_uart(uart: &Uart, byte: u8) {
     unsafe {
         // Wait for UART to become ready to transmit.
         loop {
             // TODO: add shortcut in hardware-interface
             let mut read = redshirt_hardware_interface::HardwareOperationsBuilder::new();
             let mut out = [0];
-            read.read_u32(UART0_BASE + 0x18, &mut out);
+            read.read_u32(uart.base + 0x18, &mut out);
             read.send().await;
             if out[0] & (1 << 5) == 0 { break; }
         }
 
-        redshirt_hardware_interface::write_one_u32(UART0_BASE + 0x0, u32::from(byte));
+        redshirt_hardware_interface::write_one_u32(uart.base + 0x0, u32::from(byte));
     }
 }
 
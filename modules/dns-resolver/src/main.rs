@@ -0,0 +1,184 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! DNS stub resolver.
+//!
+//! Implements the `dns` interface by sending queries to DNS servers through the `udp`
+//! interface.
+
+use futures::{prelude::*, stream::FuturesUnordered};
+use parity_scale_codec::DecodeAll;
+use redshirt_dns_interface::ffi::{DnsMessage, ResolveResponse};
+use redshirt_syscalls_interface::{InterfaceOrDestroyed, MessageId};
+use redshirt_udp_interface::UdpSocket;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    time::Duration,
+};
+
+mod packet;
+
+/// DNS servers to query, in order.
+// TODO: use the servers reported by DHCP instead
+const SERVERS: &[[u8; 4]] = &[[8, 8, 8, 8], [1, 1, 1, 1]];
+
+/// Time after which we give up waiting for a server to answer.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    redshirt_interface_interface::register_interface(redshirt_dns_interface::ffi::INTERFACE)
+        .await
+        .unwrap();
+
+    let mut resolving =
+        FuturesUnordered::<Pin<Box<dyn Future<Output = (MessageId, ResolveResponse)>>>>::new();
+    // Pushing a never-ending future, otherwise we get a permanent `None` when polling.
+    resolving.push(Box::pin(future::pending()));
+
+    loop {
+        let event = match future::select(
+            redshirt_syscalls_interface::next_interface_message(),
+            resolving.next(),
+        )
+        .await
+        {
+            future::Either::Left((message, _)) => future::Either::Left(message),
+            future::Either::Right((resolved, _)) => future::Either::Right(resolved),
+        };
+
+        match event {
+            future::Either::Left(InterfaceOrDestroyed::Interface(msg)) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => continue,
+                };
+
+                match DecodeAll::decode_all(&msg.actual_data) {
+                    Ok(DnsMessage::Resolve(hostname)) => {
+                        resolving.push(Box::pin(async move {
+                            let result = match resolve(&hostname).await {
+                                Ok(addrs) if !addrs.is_empty() => {
+                                    Ok(addrs.into_iter().map(ip_to_ffi).collect())
+                                }
+                                _ => Err(()),
+                            };
+                            (message_id, ResolveResponse { result })
+                        }));
+                    }
+                    Err(_) => redshirt_syscalls_interface::emit_message_error(message_id),
+                }
+            }
+            future::Either::Left(InterfaceOrDestroyed::ProcessDestroyed(_)) => {}
+            future::Either::Right(Some((message_id, response))) => {
+                redshirt_syscalls_interface::emit_answer(message_id, &response);
+            }
+            future::Either::Right(None) => unreachable!(),
+        }
+    }
+}
+
+/// Resolves the given hostname into a list of addresses.
+async fn resolve(hostname: &str) -> Result<Vec<IpAddr>, ()> {
+    if let Ok(ip) = hostname.parse::<IpAddr>() {
+        return Ok(vec![ip]);
+    }
+
+    if hostname.eq_ignore_ascii_case("localhost") {
+        return Ok(vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()]);
+    }
+
+    for server in SERVERS {
+        let server = Ipv4Addr::from(*server);
+        if let Ok(addrs) = query_server(hostname, server).await {
+            return Ok(addrs);
+        }
+    }
+
+    Err(())
+}
+
+/// Asks the given server for the IPv4 and IPv6 addresses of a hostname.
+///
+/// Returns an error if the server didn't answer in time. Returns an empty list if the hostname
+/// doesn't exist.
+async fn query_server(hostname: &str, server: Ipv4Addr) -> Result<Vec<IpAddr>, ()> {
+    let server_addr = SocketAddr::from((server, 53));
+    let socket = UdpSocket::bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+
+    let id = {
+        let mut id = [0; 2];
+        redshirt_random_interface::generate_in(&mut id).await;
+        u16::from_be_bytes(id)
+    };
+
+    // Both queries use the same ID. Since we merge the answers, we don't need to distinguish
+    // between the two responses.
+    socket
+        .send_to(
+            &packet::build_query(id, hostname, packet::TYPE_A)?,
+            &server_addr,
+        )
+        .await?;
+    socket
+        .send_to(
+            &packet::build_query(id, hostname, packet::TYPE_AAAA)?,
+            &server_addr,
+        )
+        .await?;
+
+    let mut timeout = Box::pin(redshirt_time_interface::monotonic_wait(QUERY_TIMEOUT));
+    let mut num_responses = 0;
+    let mut out = Vec::new();
+
+    while num_responses < 2 {
+        let (data, from) =
+            match future::select(Box::pin(socket.recv_from()), timeout.as_mut()).await {
+                future::Either::Left((result, _)) => result?,
+                future::Either::Right(((), _)) => break,
+            };
+
+        // IPv4 addresses are reported as IPv4-mapped IPv6 addresses.
+        let from_server = match from {
+            SocketAddr::V4(from) => *from.ip() == server,
+            SocketAddr::V6(from) => from.ip().to_ipv4() == Some(server),
+        };
+        if !from_server || from.port() != 53 {
+            continue;
+        }
+
+        if let Ok(addrs) = packet::parse_response(id, &data) {
+            num_responses += 1;
+            out.extend(addrs);
+        }
+    }
+
+    if num_responses == 0 {
+        Err(())
+    } else {
+        Ok(out)
+    }
+}
+
+fn ip_to_ffi(ip: IpAddr) -> [u16; 8] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().segments(),
+        IpAddr::V6(ip) => ip.segments(),
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Encoding and decoding of DNS messages.
+//!
+//! See RFC 1035, section 4.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Type of record containing an IPv4 address.
+pub const TYPE_A: u16 = 1;
+/// Type of record containing an IPv6 address.
+pub const TYPE_AAAA: u16 = 28;
+
+/// Class of the records of the Internet.
+const CLASS_IN: u16 = 1;
+
+/// Builds a query asking for the records of the given type.
+///
+/// Returns an error if the hostname isn't a valid domain name.
+pub fn build_query(id: u16, hostname: &str, ty: u16) -> Result<Vec<u8>, ()> {
+    let mut out = Vec::with_capacity(hostname.len() + 18);
+    out.extend_from_slice(&id.to_be_bytes());
+    // Flags: standard query, recursion desired.
+    out.extend_from_slice(&0x0100u16.to_be_bytes());
+    // Number of questions, answers, authority records and additional records.
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    let hostname = hostname.trim_end_matches('.');
+    if hostname.is_empty() || hostname.len() > 253 {
+        return Err(());
+    }
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(());
+        }
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+
+    out.extend_from_slice(&ty.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(out)
+}
+
+/// Decodes the response to the query with the given ID, and returns the addresses it contains.
+///
+/// Returns an error if the message is malformed, isn't a response to the query, or if the
+/// server reports an error other than the name not existing.
+pub fn parse_response(id: u16, message: &[u8]) -> Result<Vec<IpAddr>, ()> {
+    if message.len() < 12 || u16::from_be_bytes([message[0], message[1]]) != id {
+        return Err(());
+    }
+
+    let flags = u16::from_be_bytes([message[2], message[3]]);
+    if flags & 0x8000 == 0 {
+        return Err(());
+    }
+    match flags & 0xf {
+        0 => {}
+        // The name doesn't exist.
+        3 => return Ok(Vec::new()),
+        _ => return Err(()),
+    }
+
+    let num_questions = u16::from_be_bytes([message[4], message[5]]);
+    let num_answers = u16::from_be_bytes([message[6], message[7]]);
+
+    let mut cursor = 12;
+    for _ in 0..num_questions {
+        cursor = skip_name(message, cursor)?;
+        // Type and class.
+        cursor += 4;
+    }
+
+    let mut out = Vec::new();
+    for _ in 0..num_answers {
+        cursor = skip_name(message, cursor)?;
+        let header = message.get(cursor..cursor + 10).ok_or(())?;
+        let ty = u16::from_be_bytes([header[0], header[1]]);
+        let class = u16::from_be_bytes([header[2], header[3]]);
+        let data_len = usize::from(u16::from_be_bytes([header[8], header[9]]));
+        cursor += 10;
+        let data = message.get(cursor..cursor + data_len).ok_or(())?;
+        cursor += data_len;
+
+        if class != CLASS_IN {
+            continue;
+        }
+
+        match (ty, data.len()) {
+            (TYPE_A, 4) => out.push(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            ))),
+            (TYPE_AAAA, 16) => {
+                let mut bytes = [0; 16];
+                bytes.copy_from_slice(data);
+                out.push(IpAddr::V6(Ipv6Addr::from(bytes)))
+            }
+            // Other records, such as CNAME, are ignored. Servers are expected to also include
+            // the records the alias points to.
+            _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+/// Returns the position of the end of the domain name starting at `cursor`.
+fn skip_name(message: &[u8], mut cursor: usize) -> Result<usize, ()> {
+    loop {
+        let len = *message.get(cursor).ok_or(())?;
+        if len == 0 {
+            return Ok(cursor + 1);
+        }
+        // Compression pointer, which always ends the name.
+        if len & 0xc0 == 0xc0 {
+            return Ok(cursor + 2);
+        }
+        cursor += 1 + usize::from(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_query, parse_response, TYPE_A, TYPE_AAAA};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    /// Builds a response to the query with ID 0x1234 for `example.com`, with the given response
+    /// code and the given answers. Each answer is a name, a type, and the record data.
+    fn response(rcode: u16, answers: &[(&[u8], u16, &[u8])]) -> Vec<u8> {
+        let mut out = build_query(0x1234, "example.com", TYPE_A).unwrap();
+        out[2..4].copy_from_slice(&(0x8180 | rcode).to_be_bytes());
+        out[6..8].copy_from_slice(&(answers.len() as u16).to_be_bytes());
+        for (name, ty, data) in answers {
+            out.extend_from_slice(name);
+            out.extend_from_slice(&ty.to_be_bytes());
+            // Class and TTL.
+            out.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+            out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    /// Compression pointer to the name in the question.
+    const QUESTION_NAME: &[u8] = &[0xc0, 12];
+
+    #[test]
+    fn query_encoding() {
+        let query = build_query(0xabcd, "example.com.", TYPE_AAAA).unwrap();
+        assert_eq!(
+            query,
+            &b"\xab\xcd\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+               \x07example\x03com\x00\x00\x1c\x00\x01"[..]
+        );
+
+        assert!(build_query(0, "", TYPE_A).is_err());
+        assert!(build_query(0, "example..com", TYPE_A).is_err());
+        assert!(build_query(0, &"a".repeat(64), TYPE_A).is_err());
+    }
+
+    #[test]
+    fn addresses_with_compressed_names() {
+        let message = response(
+            0,
+            &[
+                (QUESTION_NAME, TYPE_A, &[93, 184, 216, 34]),
+                (
+                    QUESTION_NAME,
+                    TYPE_AAAA,
+                    &[
+                        0x26, 0x06, 0x28, 0, 0x02, 0x20, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1,
+                    ],
+                ),
+            ],
+        );
+
+        assert_eq!(
+            parse_response(0x1234, &message).unwrap(),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34)),
+                IpAddr::V6(Ipv6Addr::new(0x2606, 0x2800, 0x220, 0x1, 0, 0, 0, 0x1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn cname_is_skipped() {
+        // The alias is a label followed by a compression pointer to `com`.
+        let message = response(
+            0,
+            &[
+                (QUESTION_NAME, 5, b"\x03www\xc0\x14"),
+                (b"\x03www\xc0\x14", TYPE_A, &[10, 0, 0, 1]),
+            ],
+        );
+
+        assert_eq!(
+            parse_response(0x1234, &message).unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]
+        );
+    }
+
+    #[test]
+    fn nxdomain() {
+        let message = response(3, &[]);
+        assert!(parse_response(0x1234, &message).unwrap().is_empty());
+    }
+
+    #[test]
+    fn server_failure() {
+        let message = response(2, &[]);
+        assert!(parse_response(0x1234, &message).is_err());
+    }
+
+    #[test]
+    fn wrong_id_or_not_a_response() {
+        let message = response(0, &[(QUESTION_NAME, TYPE_A, &[10, 0, 0, 1])]);
+        assert!(parse_response(0x4321, &message).is_err());
+
+        let query = build_query(0x1234, "example.com", TYPE_A).unwrap();
+        assert!(parse_response(0x1234, &query).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let message = response(
+            0,
+            &[
+                (QUESTION_NAME, TYPE_A, &[10, 0, 0, 1]),
+                (b"\x03www\xc0\x0c", TYPE_A, &[10, 0, 0, 2]),
+            ],
+        );
+        assert_eq!(parse_response(0x1234, &message).unwrap().len(), 2);
+
+        for len in 0..message.len() {
+            assert!(parse_response(0x1234, &message[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn name_past_the_end() {
+        // Label length pointing past the end of the message.
+        let mut message = response(0, &[]);
+        message[6..8].copy_from_slice(&1u16.to_be_bytes());
+        message.extend_from_slice(&[0x3f, b'a', b'b']);
+        assert!(parse_response(0x1234, &message).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the stdout interface by drawing text on the linear framebuffer that has been set
+//! up by the bootloader.
+//!
+//! This is the counterpart of the `x86-stdout` module for machines that have been booted in a
+//! graphical mode, for example through UEFI, where there is no text mode to write to. If no such
+//! framebuffer is available, this program stops immediately.
+//!
+//! Text is rendered with the bitmap font found in the `font` module.
+// TODO: also implement the `window` interface, once it is possible to share the screen between
+//       the console and windows
+
+mod font;
+
+use parity_scale_codec::DecodeAll;
+use redshirt_hardware_interface::ffi::{ColorField, FramebufferFormat, FramebufferInfo};
+use redshirt_stdout_interface::ffi::{StdoutMessage, INTERFACE};
+use std::{convert::TryFrom as _, ops::Range};
+
+/// Color of the text, as red, green and blue components.
+const FOREGROUND: [u8; 3] = [0xff, 0xff, 0xff];
+/// Color of the rest of the screen, as red, green and blue components.
+const BACKGROUND: [u8; 3] = [0x00, 0x00, 0x00];
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    let mut console = match redshirt_hardware_interface::framebuffer().await {
+        Some(framebuffer) => match Console::new(framebuffer) {
+            Ok(c) => c,
+            Err(()) => return,
+        },
+        None => return,
+    };
+
+    let framebuffer_len = u64::from(console.pitch) * u64::from(console.height);
+    if redshirt_hardware_interface::claim_mmio(console.address, framebuffer_len)
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    redshirt_interface_interface::register_interface(INTERFACE)
+        .await
+        .unwrap();
+
+    console.clear_screen();
+
+    loop {
+        let msg = match redshirt_syscalls_interface::next_interface_message().await {
+            redshirt_syscalls_interface::InterfaceOrDestroyed::Interface(m) => m,
+            redshirt_syscalls_interface::InterfaceOrDestroyed::ProcessDestroyed(_) => continue,
+        };
+        assert_eq!(msg.interface, INTERFACE);
+        match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(StdoutMessage::Message(message)) => console.write(&message),
+            Err(_) => continue,
+        }
+    }
+}
+
+/// State machine of the console.
+///
+/// The characters that are on the screen are kept in memory. Whenever some text is written,
+/// the lines of characters that have changed are rendered again and sent to the framebuffer.
+struct Console {
+    /// Physical memory address of the top-left pixel of the framebuffer.
+    address: u64,
+    /// Number of bytes between the start of a line of pixels and the start of the next one.
+    pitch: u32,
+    /// Number of pixels in a line of the framebuffer.
+    width: u32,
+    /// Number of lines of pixels of the framebuffer.
+    height: u32,
+    /// Value of a pixel of text, as it must be written to the framebuffer.
+    foreground: Vec<u8>,
+    /// Value of a pixel of background, as it must be written to the framebuffer.
+    background: Vec<u8>,
+    /// Number of characters in a line of text.
+    columns: u32,
+    /// Number of lines of text.
+    rows: u32,
+    /// Characters on the screen, line by line. Always contains `columns * rows` elements.
+    text: Vec<u8>,
+    /// Column where the next character will be written.
+    cursor_x: u32,
+    /// Line where the next character will be written.
+    cursor_y: u32,
+    /// Lines of text that have been modified since they have last been rendered.
+    dirty_rows: Option<Range<u32>>,
+}
+
+impl Console {
+    /// Initializes the console. Doesn't modify the content of the screen.
+    ///
+    /// Returns an error if the layout of the framebuffer isn't supported, or if it is too small
+    /// to contain a single character.
+    fn new(framebuffer: FramebufferInfo) -> Result<Console, ()> {
+        let (red, green, blue) = match &framebuffer.format {
+            FramebufferFormat::Rgb { red, green, blue } => (red, green, blue),
+            FramebufferFormat::Indexed | FramebufferFormat::Text => return Err(()),
+        };
+
+        if framebuffer.bpp == 0 || framebuffer.bpp % 8 != 0 || framebuffer.bpp > 32 {
+            return Err(());
+        }
+        let bytes_per_pixel = usize::from(framebuffer.bpp / 8);
+
+        let min_pitch = framebuffer
+            .width
+            .checked_mul(u32::from(framebuffer.bpp / 8))
+            .ok_or(())?;
+        if framebuffer.pitch < min_pitch {
+            return Err(());
+        }
+
+        let columns = framebuffer.width / font::CHAR_WIDTH;
+        let rows = framebuffer.height / font::CHAR_HEIGHT;
+        if columns == 0 || rows == 0 {
+            return Err(());
+        }
+
+        let encode = |color: [u8; 3]| {
+            let value = encode_component(color[0], red)
+                | encode_component(color[1], green)
+                | encode_component(color[2], blue);
+            value.to_le_bytes()[..bytes_per_pixel].to_vec()
+        };
+
+        Ok(Console {
+            address: framebuffer.address,
+            pitch: framebuffer.pitch,
+            width: framebuffer.width,
+            height: framebuffer.height,
+            foreground: encode(FOREGROUND),
+            background: encode(BACKGROUND),
+            columns,
+            rows,
+            text: vec![b' '; usize::try_from(columns * rows).unwrap()],
+            cursor_x: 0,
+            cursor_y: 0,
+            dirty_rows: None,
+        })
+    }
+
+    /// Fills the entire framebuffer with the background color.
+    fn clear_screen(&mut self) {
+        let line = self.background_line();
+        let mut data = Vec::with_capacity(line.len() * usize::try_from(self.height).unwrap());
+        for _ in 0..self.height {
+            data.extend_from_slice(&line);
+        }
+
+        unsafe {
+            redshirt_hardware_interface::write(self.address, data);
+        }
+    }
+
+    /// Writes a message on the console.
+    fn write(&mut self, message: &str) {
+        for chr in message.chars() {
+            match chr {
+                '\n' => self.new_line(),
+                // See the documentation of the stdout interface.
+                '\r' => {}
+                chr if font::glyph(chr).is_some() => {
+                    let index = self.cursor_y * self.columns + self.cursor_x;
+                    self.text[usize::try_from(index).unwrap()] = chr as u8;
+                    self.mark_dirty(self.cursor_y);
+
+                    self.cursor_x += 1;
+                    if self.cursor_x == self.columns {
+                        self.new_line();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.flush();
+    }
+
+    /// Moves the cursor to the start of the next line, scrolling the text up if necessary.
+    fn new_line(&mut self) {
+        self.cursor_x = 0;
+
+        if self.cursor_y + 1 < self.rows {
+            self.cursor_y += 1;
+            return;
+        }
+
+        let columns = usize::try_from(self.columns).unwrap();
+        self.text.drain(..columns);
+        self.text.extend((0..columns).map(|_| b' '));
+        self.dirty_rows = Some(0..self.rows);
+    }
+
+    /// Marks the given line of text as needing to be rendered again.
+    fn mark_dirty(&mut self, row: u32) {
+        self.dirty_rows = Some(match self.dirty_rows.take() {
+            Some(r) => r.start.min(row)..r.end.max(row + 1),
+            None => row..row + 1,
+        });
+    }
+
+    /// Renders the lines of text that have been modified and sends them to the framebuffer.
+    fn flush(&mut self) {
+        let dirty_rows = match self.dirty_rows.take() {
+            Some(r) => r,
+            None => return,
+        };
+
+        let pitch = usize::try_from(self.pitch).unwrap();
+        let bytes_per_pixel = self.foreground.len();
+        let columns = usize::try_from(self.columns).unwrap();
+        let char_width = usize::try_from(font::CHAR_WIDTH).unwrap();
+        let char_height = usize::try_from(font::CHAR_HEIGHT).unwrap();
+        let background_line = self.background_line();
+
+        let num_rows = usize::try_from(dirty_rows.end - dirty_rows.start).unwrap();
+        let mut data = Vec::with_capacity(pitch * char_height * num_rows);
+
+        for row in dirty_rows.clone() {
+            let row_start = usize::try_from(row).unwrap() * columns;
+            let text = &self.text[row_start..row_start + columns];
+
+            for glyph_line in 0..char_height {
+                let line_start = data.len();
+                data.extend_from_slice(&background_line);
+
+                for (column, chr) in text.iter().enumerate() {
+                    let bits = match font::glyph(char::from(*chr)) {
+                        Some(glyph) => glyph[glyph_line],
+                        None => continue,
+                    };
+
+                    for bit in 0..char_width {
+                        if bits & (0x80 >> bit) == 0 {
+                            continue;
+                        }
+
+                        let x = column * char_width + bit;
+                        let offset = line_start + x * bytes_per_pixel;
+                        data[offset..offset + bytes_per_pixel].copy_from_slice(&self.foreground);
+                    }
+                }
+            }
+        }
+
+        let offset =
+            u64::from(dirty_rows.start) * u64::from(font::CHAR_HEIGHT) * u64::from(self.pitch);
+        unsafe {
+            redshirt_hardware_interface::write(self.address + offset, data);
+        }
+    }
+
+    /// Returns a line of pixels, including the padding up to `pitch`, filled with the
+    /// background color.
+    fn background_line(&self) -> Vec<u8> {
+        let pitch = usize::try_from(self.pitch).unwrap();
+        let mut line = Vec::with_capacity(pitch);
+        for _ in 0..self.width {
+            line.extend_from_slice(&self.background);
+        }
+        line.resize(pitch, 0);
+        line
+    }
+}
+
+/// Converts a color component between 0 and 255 to its representation within a pixel.
+///
+/// Bits that don't fit in a `u32` are discarded.
+fn encode_component(value: u8, field: &ColorField) -> u32 {
+    let value = u32::from(value);
+    let value = if field.size >= 8 {
+        value.checked_shl(u32::from(field.size - 8)).unwrap_or(0)
+    } else {
+        value >> (8 - field.size)
+    };
+
+    value.checked_shl(u32::from(field.position)).unwrap_or(0)
+}
@@ -0,0 +1,369 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Block devices registered by drivers through the `block` interface.
+//!
+//! A [`BlockDevice`] turns reads of arbitrary ranges of bytes into commands for the driver.
+//! Commands are queued until the driver asks for one, and the outcome of each command is
+//! reported back asynchronously.
+
+use futures::{channel::oneshot, prelude::*};
+use redshirt_block_interface::{BlockCommand, BlockCommandKind};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    convert::TryFrom as _,
+};
+
+/// Maximum number of sectors read by a single command.
+const MAX_SECTORS_PER_COMMAND: u64 = 128;
+
+/// Reads of at most this number of sectors are cached. Larger reads are typically the content
+/// of files and are unlikely to be read again soon.
+const MAX_CACHED_READ_SECTORS: u64 = 8;
+
+/// Maximum number of sectors in the cache.
+const CACHE_CAPACITY: usize = 1024;
+
+/// Block device registered by a driver.
+pub struct BlockDevice {
+    /// Size in bytes of a sector. Never 0.
+    sector_size: u32,
+    /// Number of sectors of the device.
+    num_sectors: u64,
+    /// Mutable state of the device.
+    inner: RefCell<Inner>,
+}
+
+struct Inner {
+    /// If the driver is waiting for a command, function that sends a command to it.
+    waiting_driver: Option<Box<dyn FnOnce(BlockCommand)>>,
+    /// Commands that haven't been sent to the driver yet.
+    queued: VecDeque<BlockCommand>,
+    /// Where to send the outcome of each command, either queued or sent to the driver.
+    pending: HashMap<u64, oneshot::Sender<Result<Vec<u8>, ()>>>,
+    /// Identifier to assign to the next command.
+    next_command_id: u64,
+    /// Content of recently-read sectors.
+    cache: HashMap<u64, Vec<u8>>,
+    /// Sectors in the cache, from the oldest to the most recently inserted.
+    cache_order: VecDeque<u64>,
+    /// If true, the driver has unregistered the device. All reads fail.
+    unregistered: bool,
+}
+
+impl BlockDevice {
+    /// Initializes a newly-registered device.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `sector_size` is 0.
+    ///
+    pub fn new(sector_size: u32, num_sectors: u64) -> BlockDevice {
+        assert_ne!(sector_size, 0);
+
+        BlockDevice {
+            sector_size,
+            num_sectors,
+            inner: RefCell::new(Inner {
+                waiting_driver: None,
+                queued: VecDeque::new(),
+                pending: HashMap::new(),
+                next_command_id: 0,
+                cache: HashMap::new(),
+                cache_order: VecDeque::new(),
+                unregistered: false,
+            }),
+        }
+    }
+
+    /// Call when the driver asks for the next command. `send` is called with the command once
+    /// one is available, which might be immediately.
+    pub fn on_next_command(&self, send: impl FnOnce(BlockCommand) + 'static) {
+        let mut inner = self.inner.borrow_mut();
+        debug_assert!(inner.waiting_driver.is_none());
+        match inner.queued.pop_front() {
+            Some(command) => send(command),
+            None => inner.waiting_driver = Some(Box::new(send)),
+        }
+    }
+
+    /// Call when the driver reports the outcome of a command.
+    pub fn on_command_finished(&self, command_id: u64, result: Result<Vec<u8>, ()>) {
+        let sender = self.inner.borrow_mut().pending.remove(&command_id);
+        if let Some(sender) = sender {
+            let _ = sender.send(result);
+        }
+    }
+
+    /// Call when the driver has unregistered the device. All the reads in progress and future
+    /// reads fail.
+    pub fn on_unregistered(&self) {
+        let mut inner = self.inner.borrow_mut();
+        inner.unregistered = true;
+        inner.waiting_driver = None;
+        inner.queued.clear();
+        // Dropping the senders makes the reads fail.
+        inner.pending.clear();
+    }
+
+    /// Returns the size in bytes of the device.
+    pub fn len(&self) -> u64 {
+        self.num_sectors.saturating_mul(u64::from(self.sector_size))
+    }
+
+    /// Reads `len` bytes from the device, starting at `offset`.
+    ///
+    /// Returns an error if the range is out of the bounds of the device, or if the driver
+    /// reports an error.
+    pub async fn read(&self, offset: u64, len: usize) -> Result<Vec<u8>, ()> {
+        let len_u64 = u64::try_from(len).map_err(|_| ())?;
+        let end = offset.checked_add(len_u64).ok_or(())?;
+        if end > self.len() {
+            return Err(());
+        }
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+
+        let sector_size = u64::from(self.sector_size);
+        let first_sector = offset / sector_size;
+        let end_sector = (end + sector_size - 1) / sector_size;
+
+        let mut data = Vec::with_capacity(
+            usize::try_from((end_sector - first_sector) * sector_size).map_err(|_| ())?,
+        );
+        let mut sector = first_sector;
+        while sector < end_sector {
+            let num_sectors = (end_sector - sector).min(MAX_SECTORS_PER_COMMAND);
+            data.extend(self.read_sectors(sector, num_sectors).await?);
+            sector += num_sectors;
+        }
+
+        let start = usize::try_from(offset - first_sector * sector_size).unwrap();
+        Ok(data[start..start + len].to_vec())
+    }
+
+    /// Reads `num_sectors` sectors starting at `first_sector`, using the cache if possible.
+    async fn read_sectors(&self, first_sector: u64, num_sectors: u64) -> Result<Vec<u8>, ()> {
+        let cacheable = num_sectors <= MAX_CACHED_READ_SECTORS;
+
+        if cacheable {
+            let inner = self.inner.borrow();
+            let mut out = Vec::new();
+            for sector in first_sector..first_sector + num_sectors {
+                match inner.cache.get(&sector) {
+                    Some(data) => out.extend_from_slice(data),
+                    None => break,
+                }
+            }
+            if out.len() as u64 == num_sectors * u64::from(self.sector_size) {
+                return Ok(out);
+            }
+        }
+
+        let data = self
+            .execute(BlockCommandKind::Read {
+                sector: first_sector,
+                num_sectors: u32::try_from(num_sectors).unwrap(),
+            })
+            .await?;
+        if data.len() as u64 != num_sectors * u64::from(self.sector_size) {
+            return Err(());
+        }
+
+        if cacheable {
+            let mut inner = self.inner.borrow_mut();
+            for (n, sector_data) in data.chunks(self.sector_size as usize).enumerate() {
+                let sector = first_sector + n as u64;
+                if inner.cache.insert(sector, sector_data.to_vec()).is_none() {
+                    inner.cache_order.push_back(sector);
+                }
+            }
+            while inner.cache_order.len() > CACHE_CAPACITY {
+                let oldest = inner.cache_order.pop_front().unwrap();
+                inner.cache.remove(&oldest);
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Queues a command for the driver and waits for its outcome.
+    fn execute(&self, kind: BlockCommandKind) -> impl Future<Output = Result<Vec<u8>, ()>> {
+        let mut inner = self.inner.borrow_mut();
+        let (tx, rx) = oneshot::channel();
+
+        if !inner.unregistered {
+            let command_id = inner.next_command_id;
+            inner.next_command_id += 1;
+            inner.pending.insert(command_id, tx);
+
+            let command = BlockCommand { command_id, kind };
+            match inner.waiting_driver.take() {
+                Some(send) => send(command),
+                None => inner.queued.push_back(command),
+            }
+        }
+
+        // If the device is unregistered, `tx` is dropped and the read fails.
+        rx.map(|result| result.unwrap_or(Err(())))
+    }
+}
+
+#[cfg(test)]
+impl BlockDevice {
+    /// Runs `future` to completion, executing the commands sent to the device on `image`, as
+    /// a driver would.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the future is waiting for something else than the device.
+    ///
+    pub fn run_with_image<T>(&self, image: &[u8], future: impl Future<Output = T>) -> T {
+        futures::pin_mut!(future);
+
+        loop {
+            if let Some(output) = future.as_mut().now_or_never() {
+                return output;
+            }
+
+            let commands = self.inner.borrow_mut().queued.drain(..).collect::<Vec<_>>();
+            assert!(!commands.is_empty());
+
+            for command in commands {
+                let result = match command.kind {
+                    BlockCommandKind::Read {
+                        sector,
+                        num_sectors,
+                    } => {
+                        let sector_size = self.sector_size as usize;
+                        let start = usize::try_from(sector).unwrap() * sector_size;
+                        let len = usize::try_from(num_sectors).unwrap() * sector_size;
+                        image.get(start..start + len).map(|d| d.to_vec()).ok_or(())
+                    }
+                    BlockCommandKind::Write { .. } => Err(()),
+                };
+                self.on_command_finished(command.command_id, result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockDevice;
+    use futures::prelude::*;
+    use redshirt_block_interface::BlockCommandKind;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|n| (n % 251) as u8).collect()
+    }
+
+    #[test]
+    fn unaligned_read() {
+        let image = image(4096);
+        let device = BlockDevice::new(512, 8);
+        let data = device.run_with_image(&image, device.read(100, 1000));
+        assert_eq!(data.unwrap(), &image[100..1100]);
+    }
+
+    #[test]
+    fn large_read_is_split() {
+        let image = image(512 * 300);
+        let device = BlockDevice::new(512, 300);
+        let data = device.run_with_image(&image, device.read(0, 512 * 300));
+        assert_eq!(data.unwrap(), image);
+        assert_eq!(device.inner.borrow().next_command_id, 3);
+    }
+
+    #[test]
+    fn small_reads_are_cached() {
+        let image = image(4096);
+        let device = BlockDevice::new(512, 8);
+        let first = device.run_with_image(&image, device.read(10, 20));
+        let second = device.run_with_image(&image, device.read(0, 512));
+        assert_eq!(first.unwrap(), &image[10..30]);
+        assert_eq!(second.unwrap(), &image[..512]);
+        assert_eq!(device.inner.borrow().next_command_id, 1);
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let image = image(4096);
+        let device = BlockDevice::new(512, 8);
+        assert!(device
+            .run_with_image(&image, device.read(4000, 200))
+            .is_err());
+        assert!(device
+            .run_with_image(&image, device.read(u64::max_value(), 1))
+            .is_err());
+        assert_eq!(
+            device.run_with_image(&image, device.read(4096, 0)),
+            Ok(Vec::new())
+        );
+        assert_eq!(device.inner.borrow().next_command_id, 0);
+    }
+
+    #[test]
+    fn driver_error() {
+        // The device pretends to be larger than the image, so the driver fails to read the end.
+        let image = image(4096);
+        let device = BlockDevice::new(512, 16);
+        assert!(device
+            .run_with_image(&image, device.read(5000, 10))
+            .is_err());
+    }
+
+    #[test]
+    fn waiting_driver() {
+        let device = BlockDevice::new(512, 8);
+        let sent = Rc::new(RefCell::new(Vec::new()));
+        device.on_next_command({
+            let sent = sent.clone();
+            move |command| sent.borrow_mut().push(command)
+        });
+
+        let read = device.read(512, 10);
+        futures::pin_mut!(read);
+        assert!(read.as_mut().now_or_never().is_none());
+
+        let command = sent.borrow_mut().pop().unwrap();
+        match command.kind {
+            BlockCommandKind::Read {
+                sector: 1,
+                num_sectors: 1,
+            } => {}
+            _ => panic!(),
+        }
+
+        device.on_command_finished(command.command_id, Ok(vec![7; 512]));
+        assert_eq!(read.now_or_never(), Some(Ok(vec![7; 10])));
+    }
+
+    #[test]
+    fn unregistered() {
+        let device = BlockDevice::new(512, 8);
+
+        let read = device.read(0, 512);
+        futures::pin_mut!(read);
+        assert!(read.as_mut().now_or_never().is_none());
+
+        device.on_unregistered();
+        assert_eq!(read.now_or_never(), Some(Err(())));
+        assert_eq!(device.read(0, 512).now_or_never(), Some(Err(())));
+    }
+}
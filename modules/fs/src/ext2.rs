@@ -0,0 +1,654 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! ext2 file systems.
+//!
+//! The device is divided in blocks, themselves grouped in block groups. The superblock, located
+//! at offset 1024, describes the layout of the file system. It is followed by the table of
+//! block group descriptors, which indicates where the inode table of each group is located.
+//!
+//! Each file or directory is described by an inode, which contains the list of blocks of its
+//! content, either directly or through indirect blocks. Directories are files containing a list
+//! of entries associating names to inode numbers. The root directory is always inode 2.
+//!
+//! Bibliography:
+//!
+//! - https://wiki.osdev.org/Ext2
+//! - https://www.nongnu.org/ext2-doc/ext2.html
+//!
+
+use crate::device::BlockDevice;
+
+use redshirt_fs_interface::{FileType, FsError, Metadata};
+use std::{convert::TryFrom as _, rc::Rc};
+
+/// Offset in bytes of the superblock.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+/// Value of the magic field of the superblock.
+const MAGIC: u16 = 0xef53;
+
+/// Directory entries contain the type of the entry. The only incompatible feature we support.
+const INCOMPAT_FILETYPE: u32 = 0x2;
+
+/// Inode of the root directory.
+const ROOT_INODE: u32 = 2;
+
+/// Size of a block group descriptor.
+const GROUP_DESCRIPTOR_LEN: u64 = 32;
+/// Number of bytes of an inode that we need to read.
+const INODE_LEN: u64 = 128;
+
+/// Mask of the type in the mode of an inode, and possible values.
+const MODE_TYPE_MASK: u16 = 0xf000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_REGULAR: u16 = 0x8000;
+
+/// Number of block pointers in an inode that point directly to data blocks.
+const NUM_DIRECT_BLOCKS: u64 = 12;
+
+/// Directories larger than this are considered corrupted, as their content is entirely loaded
+/// in memory.
+const MAX_DIRECTORY_LEN: u64 = 16 * 1024 * 1024;
+
+/// Mounted ext2 file system.
+pub struct Ext2 {
+    device: Rc<BlockDevice>,
+    /// Size in bytes of a block.
+    block_size: u64,
+    /// Number of inodes in each block group.
+    inodes_per_group: u32,
+    /// Number of block groups.
+    num_groups: u32,
+    /// Size in bytes of an entry in the inode tables.
+    inode_size: u64,
+    /// Offset in bytes of the block group descriptor table.
+    group_descriptors_offset: u64,
+    /// If true, the `size` field of regular files is 64 bits.
+    large_files: bool,
+}
+
+/// File or directory in an ext2 file system.
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// Type and permissions of the file.
+    mode: u16,
+    /// Size of the file in bytes.
+    size: u64,
+    /// Pointers to the blocks of the content of the file. The first 12 point to data blocks,
+    /// and the last three to singly, doubly, and triply indirect blocks.
+    blocks: [u32; 15],
+}
+
+impl Ext2 {
+    /// Tries to mount the device as an ext2 file system.
+    ///
+    /// Returns `Ok(None)` if the device doesn't contain an ext2 file system, or if this file
+    /// system uses features that we don't support.
+    pub async fn mount(device: Rc<BlockDevice>) -> Result<Option<Ext2>, FsError> {
+        if device.len() < SUPERBLOCK_OFFSET + 1024 {
+            return Ok(None);
+        }
+
+        let superblock = device
+            .read(SUPERBLOCK_OFFSET, 1024)
+            .await
+            .map_err(|_| FsError::Io)?;
+        if read_u16(&superblock, 56) != MAGIC {
+            return Ok(None);
+        }
+
+        let inodes_count = read_u32(&superblock, 0);
+        let first_data_block = u64::from(read_u32(&superblock, 20));
+        let log_block_size = read_u32(&superblock, 24);
+        let inodes_per_group = read_u32(&superblock, 40);
+        let revision = read_u32(&superblock, 76);
+
+        // The block size is `1024 << log_block_size`, and is at most 64kiB.
+        if log_block_size > 6 || inodes_per_group == 0 {
+            return Ok(None);
+        }
+        let block_size = 1024 << log_block_size;
+
+        let (inode_size, incompat_features, ro_compat_features) = if revision == 0 {
+            (INODE_LEN, 0, 0)
+        } else {
+            (
+                u64::from(read_u16(&superblock, 88)),
+                read_u32(&superblock, 96),
+                read_u32(&superblock, 100),
+            )
+        };
+
+        // TODO: report the unsupported features somehow
+        if inode_size < INODE_LEN || incompat_features & !INCOMPAT_FILETYPE != 0 {
+            return Ok(None);
+        }
+
+        let num_groups = (u64::from(inodes_count) + u64::from(inodes_per_group) - 1)
+            / u64::from(inodes_per_group);
+
+        Ok(Some(Ext2 {
+            device,
+            block_size,
+            inodes_per_group,
+            num_groups: u32::try_from(num_groups).unwrap(),
+            inode_size,
+            group_descriptors_offset: (first_data_block + 1) * block_size,
+            // `RO_COMPAT_LARGE_FILE`
+            large_files: ro_compat_features & 0x2 != 0,
+        }))
+    }
+
+    /// Returns the root directory.
+    pub async fn root(&self) -> Result<Node, FsError> {
+        self.read_inode(ROOT_INODE).await
+    }
+
+    /// Returns the entries of the given directory.
+    pub async fn read_dir(&self, dir: &Node) -> Result<Vec<(String, Node)>, FsError> {
+        debug_assert_eq!(dir.mode & MODE_TYPE_MASK, MODE_DIRECTORY);
+
+        if dir.size > MAX_DIRECTORY_LEN {
+            return Err(FsError::Io);
+        }
+        let len = u32::try_from(dir.size).map_err(|_| FsError::Io)?;
+        let data = self.read(dir, 0, len).await?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= data.len() {
+            let inode = read_u32(&data, offset);
+            let record_len = usize::from(read_u16(&data, offset + 4));
+            // Without the `FILETYPE` feature, the name length is 16 bits. The maximum length
+            // is 255 anyway, so we can always ignore the second byte.
+            let name_len = usize::from(data[offset + 6]);
+
+            if record_len < 8 || offset + 8 + name_len > data.len() {
+                return Err(FsError::Io);
+            }
+
+            // Entries with an inode of 0 are unused.
+            if inode != 0 {
+                let name =
+                    String::from_utf8_lossy(&data[offset + 8..offset + 8 + name_len]).into_owned();
+                if name != "." && name != ".." {
+                    let node = self.read_inode(inode).await?;
+                    entries.push((name, node));
+                }
+            }
+
+            offset += record_len;
+        }
+
+        Ok(entries)
+    }
+
+    /// Reads at most `len` bytes of the given file, starting at `offset`.
+    pub async fn read(&self, file: &Node, offset: u64, len: u32) -> Result<Vec<u8>, FsError> {
+        if offset >= file.size {
+            return Ok(Vec::new());
+        }
+        let end = file.size.min(offset.saturating_add(u64::from(len)));
+
+        let mut out = Vec::with_capacity(usize::try_from(end - offset).unwrap());
+        let mut position = offset;
+        while position < end {
+            let in_block = position % self.block_size;
+            let chunk_len = (self.block_size - in_block).min(end - position);
+            let chunk_len_usize = usize::try_from(chunk_len).unwrap();
+
+            match self.block_number(file, position / self.block_size).await? {
+                // Block 0 indicates a hole in a sparse file.
+                0 => out.resize(out.len() + chunk_len_usize, 0),
+                block => {
+                    let data = self
+                        .device
+                        .read(
+                            u64::from(block) * self.block_size + in_block,
+                            chunk_len_usize,
+                        )
+                        .await
+                        .map_err(|_| FsError::Io)?;
+                    out.extend_from_slice(&data);
+                }
+            }
+
+            position += chunk_len;
+        }
+
+        Ok(out)
+    }
+
+    /// Reads the inode with the given number.
+    async fn read_inode(&self, inode: u32) -> Result<Node, FsError> {
+        // Inode numbers start at 1.
+        let index = inode.checked_sub(1).ok_or(FsError::Io)?;
+        let group = index / self.inodes_per_group;
+        if group >= self.num_groups {
+            return Err(FsError::Io);
+        }
+
+        let descriptor = self
+            .device
+            .read(
+                self.group_descriptors_offset + u64::from(group) * GROUP_DESCRIPTOR_LEN,
+                GROUP_DESCRIPTOR_LEN as usize,
+            )
+            .await
+            .map_err(|_| FsError::Io)?;
+        let inode_table = u64::from(read_u32(&descriptor, 8));
+
+        let data = self
+            .device
+            .read(
+                inode_table * self.block_size
+                    + u64::from(index % self.inodes_per_group) * self.inode_size,
+                INODE_LEN as usize,
+            )
+            .await
+            .map_err(|_| FsError::Io)?;
+
+        let mode = read_u16(&data, 0);
+        let mut size = u64::from(read_u32(&data, 4));
+        // For regular files, the field at offset 108 contains the high 32 bits of the size.
+        if self.large_files && mode & MODE_TYPE_MASK == MODE_REGULAR {
+            size |= u64::from(read_u32(&data, 108)) << 32;
+        }
+
+        let mut blocks = [0; 15];
+        for (n, block) in blocks.iter_mut().enumerate() {
+            *block = read_u32(&data, 40 + 4 * n);
+        }
+
+        Ok(Node { mode, size, blocks })
+    }
+
+    /// Returns the number of the block containing the block of the file with the given index,
+    /// or 0 if this block isn't allocated.
+    async fn block_number(&self, file: &Node, index: u64) -> Result<u32, FsError> {
+        let per_block = self.block_size / 4;
+
+        if index < NUM_DIRECT_BLOCKS {
+            return Ok(file.blocks[index as usize]);
+        }
+
+        let index = index - NUM_DIRECT_BLOCKS;
+        if index < per_block {
+            return self.indirect(file.blocks[12], index).await;
+        }
+
+        let index = index - per_block;
+        if index < per_block * per_block {
+            let block = self.indirect(file.blocks[13], index / per_block).await?;
+            return self.indirect(block, index % per_block).await;
+        }
+
+        let index = index - per_block * per_block;
+        if index < per_block * per_block * per_block {
+            let block = self
+                .indirect(file.blocks[14], index / (per_block * per_block))
+                .await?;
+            let block = self
+                .indirect(block, (index / per_block) % per_block)
+                .await?;
+            return self.indirect(block, index % per_block).await;
+        }
+
+        Err(FsError::Io)
+    }
+
+    /// Returns the entry with the given index of the given indirect block.
+    async fn indirect(&self, block: u32, index: u64) -> Result<u32, FsError> {
+        // An indirect block of 0 means that none of the blocks it covers are allocated.
+        if block == 0 {
+            return Ok(0);
+        }
+
+        let entry = self
+            .device
+            .read(u64::from(block) * self.block_size + 4 * index, 4)
+            .await
+            .map_err(|_| FsError::Io)?;
+        Ok(read_u32(&entry, 0))
+    }
+}
+
+impl Node {
+    /// Returns the metadata of this file or directory.
+    pub fn metadata(&self) -> Metadata {
+        let file_type = match self.mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => FileType::Directory,
+            MODE_REGULAR => FileType::File,
+            _ => FileType::Other,
+        };
+
+        Metadata {
+            file_type,
+            size: self.size,
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Ext2, Node, INCOMPAT_FILETYPE, MAGIC, MODE_DIRECTORY, MODE_REGULAR};
+    use crate::device::BlockDevice;
+    use futures::prelude::*;
+    use redshirt_fs_interface::{FileType, FsError};
+    use std::rc::Rc;
+
+    const BLOCK_SIZE: usize = 1024;
+    /// Number of blocks of the test images.
+    const NUM_BLOCKS: usize = 64;
+    /// First block of the inode table.
+    const INODE_TABLE: usize = 3;
+    /// Size of the `sparse` file of [`example_image`]. Its last block is block 300.
+    const SPARSE_LEN: u64 = 301 * 1024 - 100;
+
+    /// Content of `hello.txt` in [`example_image`].
+    pub fn hello_content() -> Vec<u8> {
+        (0..1500).map(|n| (n % 199) as u8).collect()
+    }
+
+    /// Builds an ext2 file system containing:
+    ///
+    /// - `hello.txt`, whose content is [`hello_content`].
+    /// - `sub`, a directory containing `nested.txt`.
+    /// - `link`, a symbolic link.
+    /// - `sparse`, a file whose blocks 0, 20 and 300 are filled with the value of their index,
+    ///   and the others aren't allocated.
+    /// - `huge`, a file with a size of `2^64 - 1` bytes and no block allocated.
+    ///
+    pub fn example_image() -> Vec<u8> {
+        let mut image = vec![0; BLOCK_SIZE * NUM_BLOCKS];
+
+        let superblock = &mut image[1024..2048];
+        write_u32(superblock, 0, 16);
+        write_u32(superblock, 4, NUM_BLOCKS as u32);
+        write_u32(superblock, 20, 1);
+        write_u32(superblock, 40, 16);
+        write_u16(superblock, 56, MAGIC);
+        write_u32(superblock, 76, 1);
+        write_u16(superblock, 88, 128);
+        write_u32(superblock, 96, INCOMPAT_FILETYPE);
+        // `RO_COMPAT_LARGE_FILE`
+        write_u32(superblock, 100, 0x2);
+
+        // Block group descriptor table.
+        write_u32(&mut image[2 * BLOCK_SIZE..], 8, INODE_TABLE as u32);
+
+        set_inode(&mut image, 2, MODE_DIRECTORY | 0o755, 1024, &[(0, 5)]);
+        write_block(
+            &mut image,
+            5,
+            &dir_block(&[
+                (2, "."),
+                (2, ".."),
+                (11, "hello.txt"),
+                (12, "sub"),
+                // Unused entry.
+                (0, "gone"),
+                (13, "link"),
+                (15, "sparse"),
+                (16, "huge"),
+            ]),
+        );
+
+        set_inode(
+            &mut image,
+            11,
+            MODE_REGULAR | 0o644,
+            1500,
+            &[(0, 7), (1, 8)],
+        );
+        let hello = hello_content();
+        write_block(&mut image, 7, &hello[..1024]);
+        write_block(&mut image, 8, &hello[1024..]);
+
+        set_inode(&mut image, 12, MODE_DIRECTORY | 0o755, 1024, &[(0, 6)]);
+        write_block(
+            &mut image,
+            6,
+            &dir_block(&[(12, "."), (2, ".."), (14, "nested.txt")]),
+        );
+        set_inode(&mut image, 14, MODE_REGULAR | 0o644, 5, &[(0, 9)]);
+        write_block(&mut image, 9, b"hello");
+
+        // Fast symbolic link, whose target is stored in the inode itself.
+        set_inode(&mut image, 13, 0xa1ff, 9, &[]);
+
+        // Block 20 is the 9th one of the singly indirect block, and block 300 the 33rd one of
+        // the first indirect block of the doubly indirect block.
+        set_inode(
+            &mut image,
+            15,
+            MODE_REGULAR | 0o644,
+            SPARSE_LEN,
+            &[(0, 10), (12, 11), (13, 13)],
+        );
+        write_block(&mut image, 10, &[0; 1024]);
+        write_u32(&mut image[11 * BLOCK_SIZE..], 4 * 8, 12);
+        write_block(&mut image, 12, &[20; 1024]);
+        write_u32(&mut image[13 * BLOCK_SIZE..], 0, 14);
+        write_u32(&mut image[14 * BLOCK_SIZE..], 4 * 32, 15);
+        write_block(&mut image, 15, &[44; 1024]);
+
+        set_inode(&mut image, 16, MODE_REGULAR | 0o644, u64::max_value(), &[]);
+
+        image
+    }
+
+    /// Writes an inode in the inode table. `blocks` contains the index and value of the block
+    /// pointers that aren't 0.
+    fn set_inode(image: &mut [u8], inode: usize, mode: u16, size: u64, blocks: &[(usize, u32)]) {
+        let offset = INODE_TABLE * BLOCK_SIZE + (inode - 1) * 128;
+        let data = &mut image[offset..offset + 128];
+        write_u16(data, 0, mode);
+        write_u32(data, 4, size as u32);
+        write_u32(data, 108, (size >> 32) as u32);
+        for (index, block) in blocks {
+            write_u32(data, 40 + 4 * index, *block);
+        }
+    }
+
+    /// Builds the content of a directory from a list of inodes and names.
+    fn dir_block(entries: &[(u32, &str)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (n, (inode, name)) in entries.iter().enumerate() {
+            let record_len = if n == entries.len() - 1 {
+                BLOCK_SIZE - out.len()
+            } else {
+                (8 + name.len() + 3) & !3
+            };
+
+            let start = out.len();
+            out.resize(start + record_len, 0);
+            write_u32(&mut out[start..], 0, *inode);
+            write_u16(&mut out[start..], 4, record_len as u16);
+            out[start + 6] = name.len() as u8;
+            out[start + 8..start + 8 + name.len()].copy_from_slice(name.as_bytes());
+        }
+        out
+    }
+
+    fn write_block(image: &mut [u8], block: usize, data: &[u8]) {
+        image[block * BLOCK_SIZE..block * BLOCK_SIZE + data.len()].copy_from_slice(data);
+    }
+
+    fn write_u16(data: &mut [u8], offset: usize, value: u16) {
+        data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Mounts the given image and runs `test` on the file system.
+    fn with_ext2<T, F: Future<Output = T>>(image: &[u8], test: impl FnOnce(Rc<Ext2>) -> F) -> T {
+        let device = Rc::new(BlockDevice::new(512, (image.len() / 512) as u64));
+        let ext2 = device.run_with_image(image, Ext2::mount(device.clone()));
+        let ext2 = Rc::new(ext2.unwrap().unwrap());
+        device.run_with_image(image, test(ext2))
+    }
+
+    /// Returns the entry of the root directory with the given name.
+    async fn root_entry(ext2: &Ext2, name: &str) -> Node {
+        let root = ext2.root().await.unwrap();
+        let entries = ext2.read_dir(&root).await.unwrap();
+        entries.into_iter().find(|(n, _)| n == name).unwrap().1
+    }
+
+    #[test]
+    fn read_dir() {
+        with_ext2(&example_image(), |ext2| async move {
+            let root = ext2.root().await.unwrap();
+            let entries = ext2.read_dir(&root).await.unwrap();
+            let names = entries.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+            assert_eq!(names, vec!["hello.txt", "sub", "link", "sparse", "huge"]);
+
+            let types = entries
+                .iter()
+                .map(|(_, node)| node.metadata().file_type)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                types,
+                vec![
+                    FileType::File,
+                    FileType::Directory,
+                    FileType::Other,
+                    FileType::File,
+                    FileType::File
+                ]
+            );
+            assert_eq!(entries[0].1.metadata().size, 1500);
+            assert_eq!(entries[4].1.metadata().size, u64::max_value());
+
+            let sub = ext2.read_dir(&entries[1].1).await.unwrap();
+            assert_eq!(sub.len(), 1);
+            assert_eq!(sub[0].0, "nested.txt");
+            assert_eq!(ext2.read(&sub[0].1, 0, 100).await.unwrap(), b"hello");
+        });
+    }
+
+    #[test]
+    fn read_file() {
+        let content = hello_content();
+
+        with_ext2(&example_image(), |ext2| async move {
+            let hello = root_entry(&ext2, "hello.txt").await;
+            assert_eq!(ext2.read(&hello, 0, 5000).await.unwrap(), content);
+            // Crosses the boundary between the two blocks.
+            assert_eq!(
+                ext2.read(&hello, 1000, 100).await.unwrap(),
+                &content[1000..1100]
+            );
+            assert_eq!(
+                ext2.read(&hello, 1200, u32::max_value()).await.unwrap(),
+                &content[1200..]
+            );
+            assert!(ext2.read(&hello, 1500, 10).await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn sparse_and_indirect_blocks() {
+        with_ext2(&example_image(), |ext2| async move {
+            let sparse = root_entry(&ext2, "sparse").await;
+            let data = ext2.read(&sparse, 0, u32::max_value()).await.unwrap();
+            assert_eq!(data.len() as u64, SPARSE_LEN);
+
+            for (index, block) in data.chunks(1024).enumerate() {
+                let expected = match index {
+                    20 => 20,
+                    300 => 44,
+                    _ => 0,
+                };
+                assert!(block.iter().all(|b| *b == expected), "block {}", index);
+            }
+
+            let end = ext2.read(&sparse, 300 * 1024 + 10, 5000).await.unwrap();
+            assert_eq!(end, vec![44; 1024 - 100 - 10]);
+        });
+    }
+
+    #[test]
+    fn huge_offsets() {
+        with_ext2(&example_image(), |ext2| async move {
+            let huge = root_entry(&ext2, "huge").await;
+            assert_eq!(ext2.read(&huge, 0, 10).await.unwrap(), vec![0; 10]);
+            // Beyond what triply indirect blocks can cover.
+            let offset = u64::max_value() - 10;
+            assert_eq!(
+                ext2.read(&huge, offset, u32::max_value())
+                    .await
+                    .unwrap_err(),
+                FsError::Io
+            );
+        });
+    }
+
+    #[test]
+    fn corrupted_directory() {
+        let mut image = example_image();
+        // Record length of `nested.txt` too small.
+        write_u16(&mut image[6 * BLOCK_SIZE..], 24 + 4, 4);
+
+        with_ext2(&image, |ext2| async move {
+            let sub = root_entry(&ext2, "sub").await;
+            assert_eq!(ext2.read_dir(&sub).await.unwrap_err(), FsError::Io);
+        });
+
+        let mut image = example_image();
+        // Root directory too large to be loaded.
+        set_inode(&mut image, 2, MODE_DIRECTORY | 0o755, 1 << 31, &[(0, 5)]);
+
+        with_ext2(&image, |ext2| async move {
+            let root = ext2.root().await.unwrap();
+            assert_eq!(ext2.read_dir(&root).await.unwrap_err(), FsError::Io);
+        });
+    }
+
+    #[test]
+    fn not_ext2() {
+        let mount = |image: Vec<u8>| {
+            let device = Rc::new(BlockDevice::new(512, (image.len() / 512) as u64));
+            device
+                .run_with_image(&image, Ext2::mount(device.clone()))
+                .unwrap()
+                .is_none()
+        };
+
+        assert!(mount(vec![0; BLOCK_SIZE * NUM_BLOCKS]));
+        assert!(mount(example_image()[..1536].to_vec()));
+
+        // Extents, which we don't support.
+        let mut image = example_image();
+        write_u32(&mut image[1024..], 96, INCOMPAT_FILETYPE | 0x40);
+        assert!(mount(image));
+    }
+}
@@ -0,0 +1,687 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! FAT32 file systems.
+//!
+//! The device starts with a boot sector containing the BIOS parameter block, which describes
+//! the layout of the file system. It is followed by reserved sectors, then by one or more copies
+//! of the file allocation table (FAT), then by the data area divided in clusters.
+//!
+//! For each cluster, the FAT contains the index of the next cluster of the same file, forming
+//! chains of clusters. Directories are files containing 32-bytes entries, and the root
+//! directory starts at a cluster indicated in the boot sector.
+//!
+//! Bibliography:
+//!
+//! - https://wiki.osdev.org/FAT
+//! - https://en.wikipedia.org/wiki/Design_of_the_FAT_file_system
+//!
+
+use crate::device::BlockDevice;
+
+use redshirt_fs_interface::{FileType, FsError, Metadata};
+use std::{convert::TryFrom as _, rc::Rc};
+
+/// Size of a directory entry.
+const DIR_ENTRY_LEN: usize = 32;
+
+/// Attributes of directory entries.
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+/// Combination of attributes indicating a long file name entry.
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// Values of the FAT greater or equal to this indicate the end of a chain.
+const END_OF_CHAIN: u32 = 0x0fff_fff8;
+
+/// Mounted FAT32 file system.
+pub struct Fat {
+    device: Rc<BlockDevice>,
+    /// Size in bytes of a cluster.
+    cluster_len: u64,
+    /// Offset in bytes of the first FAT.
+    fat_offset: u64,
+    /// Offset in bytes of the data area, where cluster 2 starts.
+    data_offset: u64,
+    /// Number of clusters in the data area. Valid clusters go from 2 to `num_clusters + 1`.
+    num_clusters: u32,
+    /// First cluster of the root directory.
+    root_cluster: u32,
+}
+
+/// File or directory in a FAT file system.
+#[derive(Debug, Clone)]
+pub struct Node {
+    /// First cluster of the content, or 0 if the file is empty.
+    first_cluster: u32,
+    /// Size of the file in bytes. Always 0 for directories.
+    size: u32,
+    /// True if this is a directory.
+    is_directory: bool,
+}
+
+impl Fat {
+    /// Tries to mount the device as a FAT32 file system.
+    ///
+    /// Returns `Ok(None)` if the device doesn't contain a FAT32 file system.
+    pub async fn mount(device: Rc<BlockDevice>) -> Result<Option<Fat>, FsError> {
+        let boot_sector = device.read(0, 512).await.map_err(|_| FsError::Io)?;
+        if boot_sector[510] != 0x55 || boot_sector[511] != 0xaa {
+            return Ok(None);
+        }
+
+        let bytes_per_sector = u64::from(read_u16(&boot_sector, 11));
+        let sectors_per_cluster = u64::from(boot_sector[13]);
+        let reserved_sectors = u64::from(read_u16(&boot_sector, 14));
+        let num_fats = u64::from(boot_sector[16]);
+        let root_entry_count = read_u16(&boot_sector, 17);
+        let total_sectors_16 = read_u16(&boot_sector, 19);
+        let fat_size_16 = read_u16(&boot_sector, 22);
+        let total_sectors_32 = read_u32(&boot_sector, 32);
+        let fat_size = u64::from(read_u32(&boot_sector, 36));
+        let root_cluster = read_u32(&boot_sector, 44);
+
+        if !bytes_per_sector.is_power_of_two()
+            || bytes_per_sector < 512
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved_sectors == 0
+            || num_fats == 0
+        {
+            return Ok(None);
+        }
+
+        // FAT12 and FAT16 have a fixed-size root directory and store the size of the FAT in
+        // a different field.
+        // TODO: support FAT12 and FAT16
+        if root_entry_count != 0 || fat_size_16 != 0 || fat_size == 0 {
+            return Ok(None);
+        }
+
+        let total_sectors = if total_sectors_16 != 0 {
+            u64::from(total_sectors_16)
+        } else {
+            u64::from(total_sectors_32)
+        };
+        let data_sector = reserved_sectors + num_fats * fat_size;
+        if data_sector >= total_sectors || total_sectors * bytes_per_sector > device.len() {
+            return Ok(None);
+        }
+
+        let num_clusters = (total_sectors - data_sector) / sectors_per_cluster;
+        let num_clusters = match u32::try_from(num_clusters) {
+            Ok(n) if n < END_OF_CHAIN - 2 => n,
+            _ => return Ok(None),
+        };
+
+        let fat = Fat {
+            device,
+            cluster_len: sectors_per_cluster * bytes_per_sector,
+            fat_offset: reserved_sectors * bytes_per_sector,
+            data_offset: data_sector * bytes_per_sector,
+            num_clusters,
+            root_cluster,
+        };
+
+        if !fat.is_valid_cluster(root_cluster) {
+            return Ok(None);
+        }
+
+        Ok(Some(fat))
+    }
+
+    /// Returns the root directory.
+    pub fn root(&self) -> Node {
+        Node {
+            first_cluster: self.root_cluster,
+            size: 0,
+            is_directory: true,
+        }
+    }
+
+    /// Returns the entries of the given directory.
+    pub async fn read_dir(&self, dir: &Node) -> Result<Vec<(String, Node)>, FsError> {
+        debug_assert!(dir.is_directory);
+
+        let mut entries = Vec::new();
+        // Parts of the long file name of the next entry, with their sequence number.
+        let mut long_name = Vec::<(u8, [u16; 13])>::new();
+        // Checksum of the short name found in the long file name entries.
+        let mut long_name_checksum = 0;
+
+        let mut cluster = if dir.first_cluster == 0 {
+            // Entries of the `..` directory point to cluster 0 for the root directory.
+            self.root_cluster
+        } else {
+            dir.first_cluster
+        };
+
+        for _ in 0..self.num_clusters {
+            let data = self.read_cluster(cluster).await?;
+
+            for entry in data.chunks(DIR_ENTRY_LEN) {
+                if entry[0] == 0 {
+                    // End of the directory.
+                    return Ok(entries);
+                }
+
+                if entry[0] == 0xe5 {
+                    // Deleted entry.
+                    long_name.clear();
+                    continue;
+                }
+
+                let attributes = entry[11];
+                if attributes & 0x3f == ATTR_LONG_NAME {
+                    if entry[0] & 0x40 != 0 {
+                        // Last part of a name. Parts are stored in reverse order.
+                        long_name.clear();
+                        long_name_checksum = entry[13];
+                    }
+                    let mut chars = [0; 13];
+                    for (n, offset) in [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30]
+                        .iter()
+                        .enumerate()
+                    {
+                        chars[n] = read_u16(entry, *offset);
+                    }
+                    long_name.push((entry[0] & 0x1f, chars));
+                    continue;
+                }
+
+                if attributes & ATTR_VOLUME_ID != 0 {
+                    long_name.clear();
+                    continue;
+                }
+
+                let name = if !long_name.is_empty()
+                    && short_name_checksum(&entry[..11]) == long_name_checksum
+                {
+                    long_name.sort_by_key(|(seq, _)| *seq);
+                    let chars = long_name
+                        .iter()
+                        .flat_map(|(_, chars)| chars.iter().cloned())
+                        .take_while(|c| *c != 0)
+                        .collect::<Vec<_>>();
+                    String::from_utf16_lossy(&chars)
+                } else {
+                    short_name(entry)
+                };
+                long_name.clear();
+
+                if name == "." || name == ".." {
+                    continue;
+                }
+
+                let first_cluster =
+                    (u32::from(read_u16(entry, 20)) << 16) | u32::from(read_u16(entry, 26));
+                let is_directory = attributes & ATTR_DIRECTORY != 0;
+                let size = if is_directory { 0 } else { read_u32(entry, 28) };
+
+                entries.push((
+                    name,
+                    Node {
+                        first_cluster,
+                        size,
+                        is_directory,
+                    },
+                ));
+            }
+
+            cluster = match self.next_cluster(cluster).await? {
+                Some(c) => c,
+                None => return Ok(entries),
+            };
+        }
+
+        // The chain of clusters loops.
+        Err(FsError::Io)
+    }
+
+    /// Reads at most `len` bytes of the given file, starting at `offset`.
+    pub async fn read(&self, file: &Node, offset: u64, len: u32) -> Result<Vec<u8>, FsError> {
+        debug_assert!(!file.is_directory);
+
+        let size = u64::from(file.size);
+        if offset >= size {
+            return Ok(Vec::new());
+        }
+        let end = size.min(offset.saturating_add(u64::from(len)));
+
+        // Skip the clusters before `offset`.
+        let mut cluster = file.first_cluster;
+        for _ in 0..offset / self.cluster_len {
+            cluster = self.next_cluster(cluster).await?.ok_or(FsError::Io)?;
+        }
+
+        let mut out = Vec::with_capacity(usize::try_from(end - offset).unwrap());
+        let mut position = offset;
+        loop {
+            let in_cluster = position % self.cluster_len;
+            let chunk_len = (self.cluster_len - in_cluster).min(end - position);
+            let data = self
+                .device
+                .read(
+                    self.cluster_offset(cluster)? + in_cluster,
+                    usize::try_from(chunk_len).unwrap(),
+                )
+                .await
+                .map_err(|_| FsError::Io)?;
+            out.extend_from_slice(&data);
+            position += chunk_len;
+
+            if position >= end {
+                return Ok(out);
+            }
+
+            cluster = self.next_cluster(cluster).await?.ok_or(FsError::Io)?;
+        }
+    }
+
+    /// Returns true if `cluster` is in the data area.
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.num_clusters
+    }
+
+    /// Returns the offset in bytes of the given cluster.
+    fn cluster_offset(&self, cluster: u32) -> Result<u64, FsError> {
+        if !self.is_valid_cluster(cluster) {
+            return Err(FsError::Io);
+        }
+
+        Ok(self.data_offset + u64::from(cluster - 2) * self.cluster_len)
+    }
+
+    /// Reads the content of the given cluster.
+    async fn read_cluster(&self, cluster: u32) -> Result<Vec<u8>, FsError> {
+        let offset = self.cluster_offset(cluster)?;
+        self.device
+            .read(offset, usize::try_from(self.cluster_len).unwrap())
+            .await
+            .map_err(|_| FsError::Io)
+    }
+
+    /// Returns the cluster following `cluster` in its chain, or `None` if this was the last
+    /// cluster.
+    async fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FsError> {
+        if !self.is_valid_cluster(cluster) {
+            return Err(FsError::Io);
+        }
+
+        let entry = self
+            .device
+            .read(self.fat_offset + 4 * u64::from(cluster), 4)
+            .await
+            .map_err(|_| FsError::Io)?;
+        // The 4 highest bits are reserved.
+        let next = read_u32(&entry, 0) & 0x0fff_ffff;
+
+        if next >= END_OF_CHAIN {
+            Ok(None)
+        } else if self.is_valid_cluster(next) {
+            Ok(Some(next))
+        } else {
+            // Free or bad cluster in the middle of a chain.
+            Err(FsError::Io)
+        }
+    }
+}
+
+impl Node {
+    /// Returns the metadata of this file or directory.
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            file_type: if self.is_directory {
+                FileType::Directory
+            } else {
+                FileType::File
+            },
+            size: u64::from(self.size),
+        }
+    }
+}
+
+/// Builds the name of a directory entry from its 8.3 short name.
+fn short_name(entry: &[u8]) -> String {
+    // Flags set by Windows NT to indicate that the base name or extension are lowercase.
+    let lowercase_base = entry[12] & 0x08 != 0;
+    let lowercase_extension = entry[12] & 0x10 != 0;
+
+    let mut base = entry[..8].to_vec();
+    // 0xe5 indicates a deleted entry, and is replaced with 0x05 if it's the actual first byte.
+    if base[0] == 0x05 {
+        base[0] = 0xe5;
+    }
+
+    let to_string = |bytes: &[u8], lowercase: bool| {
+        // Names are padded with spaces.
+        let len = bytes.iter().rposition(|b| *b != b' ').map_or(0, |p| p + 1);
+        let s = bytes[..len]
+            .iter()
+            .map(|b| char::from(*b))
+            .collect::<String>();
+        if lowercase {
+            s.to_lowercase()
+        } else {
+            s
+        }
+    };
+
+    let base = to_string(&base, lowercase_base);
+    let extension = to_string(&entry[8..11], lowercase_extension);
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, extension)
+    }
+}
+
+/// Computes the checksum of a short name, as stored in the long file name entries that
+/// precede it.
+fn short_name_checksum(name: &[u8]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, b| sum.rotate_right(1).wrapping_add(*b))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{short_name_checksum, Fat, Node, ATTR_DIRECTORY, ATTR_VOLUME_ID, END_OF_CHAIN};
+    use crate::device::BlockDevice;
+    use futures::prelude::*;
+    use redshirt_fs_interface::{FileType, FsError};
+    use std::rc::Rc;
+
+    /// Size of a sector and of a cluster.
+    const SECTOR_SIZE: usize = 512;
+    /// Number of clusters of the test images.
+    const NUM_CLUSTERS: usize = 64;
+
+    /// Content of the file named `HELLO.TXT` in [`example_image`], spanning three clusters.
+    pub fn hello_content() -> Vec<u8> {
+        (0..1300).map(|n| (n % 199) as u8).collect()
+    }
+
+    /// Builds a FAT32 file system containing:
+    ///
+    /// - `hello.txt`, whose content is [`hello_content`].
+    /// - `A long file name.txt`, an empty file.
+    /// - `SUB`, a directory spanning two clusters and containing 15 files named `F00` to `F14`.
+    ///
+    pub fn example_image() -> Vec<u8> {
+        let mut image = empty_image();
+
+        let long_name = *b"ALONGF~1TXT";
+        let mut root = vec![short_entry(b"REDSHIRT   ", ATTR_VOLUME_ID, 0, 0)];
+        root.extend(long_name_entries("A long file name.txt", &long_name));
+        root.push(short_entry(&long_name, 0, 0, 0));
+        // Short name in lowercase.
+        let mut hello = short_entry(b"HELLO   TXT", 0, 8, 1300);
+        hello[12] = 0x18;
+        root.push(hello);
+        // Deleted entry.
+        let mut deleted = short_entry(b"DELETED TXT", 0, 20, 10);
+        deleted[0] = 0xe5;
+        root.push(deleted);
+        root.push(short_entry(b"SUB        ", ATTR_DIRECTORY, 5, 0));
+        write_entries(&mut image, 2, &root);
+
+        let mut sub = vec![
+            short_entry(b".          ", ATTR_DIRECTORY, 5, 0),
+            short_entry(b"..         ", ATTR_DIRECTORY, 0, 0),
+        ];
+        for n in 0..15 {
+            let name = format!("F{:02}        ", n);
+            sub.push(short_entry(name.as_bytes(), 0, 0, 0));
+        }
+        write_entries(&mut image, 5, &sub[..16]);
+        write_entries(&mut image, 7, &sub[16..]);
+        set_fat(&mut image, 5, 7);
+        set_fat(&mut image, 7, END_OF_CHAIN);
+
+        let content = hello_content();
+        for (cluster, chunk) in [8, 9, 11].iter().zip(content.chunks(SECTOR_SIZE)) {
+            cluster_mut(&mut image, *cluster)[..chunk.len()].copy_from_slice(chunk);
+        }
+        set_fat(&mut image, 8, 9);
+        set_fat(&mut image, 9, 11);
+        set_fat(&mut image, 11, END_OF_CHAIN);
+
+        image
+    }
+
+    /// Builds a FAT32 file system with one FAT, clusters of one sector, and an empty root
+    /// directory in cluster 2.
+    fn empty_image() -> Vec<u8> {
+        let mut image = vec![0; SECTOR_SIZE * (2 + NUM_CLUSTERS)];
+        image[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+        // Sectors per cluster, reserved sectors, and number of FATs.
+        image[13] = 1;
+        image[14..16].copy_from_slice(&1u16.to_le_bytes());
+        image[16] = 1;
+        image[32..36].copy_from_slice(&(2 + NUM_CLUSTERS as u32).to_le_bytes());
+        // Size of the FAT and root cluster.
+        image[36..40].copy_from_slice(&1u32.to_le_bytes());
+        image[44..48].copy_from_slice(&2u32.to_le_bytes());
+        image[510] = 0x55;
+        image[511] = 0xaa;
+        set_fat(&mut image, 2, END_OF_CHAIN);
+        image
+    }
+
+    fn set_fat(image: &mut [u8], cluster: u32, next: u32) {
+        let offset = SECTOR_SIZE + 4 * cluster as usize;
+        image[offset..offset + 4].copy_from_slice(&next.to_le_bytes());
+    }
+
+    fn cluster_mut(image: &mut [u8], cluster: u32) -> &mut [u8] {
+        // The data area starts at sector 2, which is also the first cluster.
+        let offset = SECTOR_SIZE * cluster as usize;
+        &mut image[offset..offset + SECTOR_SIZE]
+    }
+
+    fn write_entries(image: &mut [u8], cluster: u32, entries: &[[u8; 32]]) {
+        let data = cluster_mut(image, cluster);
+        for (n, entry) in entries.iter().enumerate() {
+            data[n * 32..(n + 1) * 32].copy_from_slice(entry);
+        }
+    }
+
+    fn short_entry(name: &[u8], attributes: u8, cluster: u32, size: u32) -> [u8; 32] {
+        let mut entry = [0; 32];
+        entry[..11].copy_from_slice(name);
+        entry[11] = attributes;
+        entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    /// Builds the long file name entries preceding the entry with the given short name, in the
+    /// order in which they are stored.
+    fn long_name_entries(name: &str, short_name: &[u8]) -> Vec<[u8; 32]> {
+        let mut chars = name.encode_utf16().collect::<Vec<_>>();
+        if chars.len() % 13 != 0 {
+            chars.push(0);
+        }
+        while chars.len() % 13 != 0 {
+            chars.push(0xffff);
+        }
+
+        let num_entries = chars.len() / 13;
+        (0..num_entries)
+            .rev()
+            .map(|n| {
+                let mut entry = [0; 32];
+                entry[0] = n as u8 + 1;
+                if n == num_entries - 1 {
+                    entry[0] |= 0x40;
+                }
+                entry[11] = 0x0f;
+                entry[13] = short_name_checksum(short_name);
+                let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+                for (offset, chr) in offsets.iter().zip(&chars[n * 13..(n + 1) * 13]) {
+                    entry[*offset..*offset + 2].copy_from_slice(&chr.to_le_bytes());
+                }
+                entry
+            })
+            .collect()
+    }
+
+    /// Mounts the given image and runs `test` on the file system.
+    fn with_fat<T, F: Future<Output = T>>(image: &[u8], test: impl FnOnce(Rc<Fat>) -> F) -> T {
+        let device = Rc::new(BlockDevice::new(
+            SECTOR_SIZE as u32,
+            (image.len() / 512) as u64,
+        ));
+        let fat = device.run_with_image(image, Fat::mount(device.clone()));
+        let fat = Rc::new(fat.unwrap().unwrap());
+        device.run_with_image(image, test(fat))
+    }
+
+    async fn entry_names(fat: &Fat, dir: &Node) -> Result<Vec<String>, FsError> {
+        let entries = fat.read_dir(dir).await?;
+        Ok(entries.into_iter().map(|(name, _)| name).collect())
+    }
+
+    #[test]
+    fn read_dir() {
+        with_fat(&example_image(), |fat| async move {
+            let entries = fat.read_dir(&fat.root()).await.unwrap();
+            let names = entries.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+            assert_eq!(names, vec!["A long file name.txt", "hello.txt", "SUB"]);
+
+            let hello = entries[1].1.metadata();
+            assert_eq!(hello.file_type, FileType::File);
+            assert_eq!(hello.size, 1300);
+            assert_eq!(entries[2].1.metadata().file_type, FileType::Directory);
+
+            // `.` and `..` are skipped, and the directory spans two clusters.
+            let sub = entry_names(&fat, &entries[2].1).await.unwrap();
+            let expected = (0..15).map(|n| format!("F{:02}", n)).collect::<Vec<_>>();
+            assert_eq!(sub, expected);
+        });
+    }
+
+    #[test]
+    fn long_name_with_wrong_checksum() {
+        let mut image = example_image();
+        // Modify the short name that follows the long file name entries.
+        cluster_mut(&mut image, 2)[3 * 32] = b'B';
+
+        with_fat(&image, |fat| async move {
+            let names = entry_names(&fat, &fat.root()).await.unwrap();
+            assert_eq!(names, vec!["BLONGF~1.TXT", "hello.txt", "SUB"]);
+        });
+    }
+
+    #[test]
+    fn read_file() {
+        let content = hello_content();
+
+        with_fat(&example_image(), |fat| async move {
+            let entries = fat.read_dir(&fat.root()).await.unwrap();
+            let hello = &entries[1].1;
+
+            assert_eq!(fat.read(hello, 0, 5000).await.unwrap(), content);
+            // Crosses the boundary between the first and second clusters.
+            assert_eq!(fat.read(hello, 500, 100).await.unwrap(), &content[500..600]);
+            assert_eq!(
+                fat.read(hello, 1030, 100).await.unwrap(),
+                &content[1030..1130]
+            );
+            assert_eq!(
+                fat.read(hello, 1000, u32::max_value()).await.unwrap(),
+                &content[1000..]
+            );
+            assert!(fat.read(hello, 1300, 10).await.unwrap().is_empty());
+            assert!(fat
+                .read(hello, u64::max_value(), 10)
+                .await
+                .unwrap()
+                .is_empty());
+        });
+    }
+
+    #[test]
+    fn looping_directory() {
+        let mut image = example_image();
+        // The first cluster of the `SUB` directory, which is full, points to itself.
+        set_fat(&mut image, 5, 5);
+
+        with_fat(&image, |fat| async move {
+            let entries = fat.read_dir(&fat.root()).await.unwrap();
+            assert_eq!(fat.read_dir(&entries[2].1).await.unwrap_err(), FsError::Io);
+        });
+    }
+
+    #[test]
+    fn broken_file_chain() {
+        let mut image = example_image();
+        // Free cluster in the middle of the chain of `hello.txt`.
+        set_fat(&mut image, 9, 0);
+
+        with_fat(&image, |fat| async move {
+            let entries = fat.read_dir(&fat.root()).await.unwrap();
+            let hello = &entries[1].1;
+            assert_eq!(fat.read(hello, 0, 100).await.unwrap().len(), 100);
+            assert_eq!(fat.read(hello, 0, 5000).await.unwrap_err(), FsError::Io);
+        });
+
+        let mut image = example_image();
+        // Cluster past the end of the file system.
+        set_fat(&mut image, 9, 1000);
+
+        with_fat(&image, |fat| async move {
+            let entries = fat.read_dir(&fat.root()).await.unwrap();
+            let hello = &entries[1].1;
+            assert_eq!(fat.read(hello, 0, 5000).await.unwrap_err(), FsError::Io);
+        });
+    }
+
+    #[test]
+    fn not_fat32() {
+        let mount = |image: Vec<u8>| {
+            let device = Rc::new(BlockDevice::new(512, (image.len() / 512) as u64));
+            device
+                .run_with_image(&image, Fat::mount(device.clone()))
+                .unwrap()
+                .is_none()
+        };
+
+        assert!(mount(vec![0; 512 * 16]));
+
+        // FAT16 file systems have a fixed-size root directory.
+        let mut image = empty_image();
+        image[17..19].copy_from_slice(&512u16.to_le_bytes());
+        assert!(mount(image));
+
+        // Root directory outside of the data area.
+        let mut image = empty_image();
+        image[44..48].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(mount(image));
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Abstraction over the supported file systems.
+
+use crate::{device::BlockDevice, ext2, fat};
+
+use redshirt_fs_interface::{DirEntry, FileType, FsError, Metadata};
+use std::rc::Rc;
+
+/// Mounted file system.
+pub enum Filesystem {
+    Fat(fat::Fat),
+    Ext2(ext2::Ext2),
+}
+
+/// File or directory of a [`Filesystem`].
+#[derive(Debug, Clone)]
+pub enum Node {
+    Fat(fat::Node),
+    Ext2(ext2::Node),
+}
+
+impl Filesystem {
+    /// Tries to mount the file system of the given device.
+    ///
+    /// Returns `Ok(None)` if the device doesn't contain any supported file system.
+    pub async fn mount(device: Rc<BlockDevice>) -> Result<Option<Filesystem>, FsError> {
+        if let Some(fat) = fat::Fat::mount(device.clone()).await? {
+            return Ok(Some(Filesystem::Fat(fat)));
+        }
+
+        if let Some(ext2) = ext2::Ext2::mount(device).await? {
+            return Ok(Some(Filesystem::Ext2(ext2)));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns a human-readable name of the kind of file system.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filesystem::Fat(_) => "FAT32",
+            Filesystem::Ext2(_) => "ext2",
+        }
+    }
+
+    /// Returns the file or directory at the given path.
+    ///
+    /// Paths are made of components separated with `/`, and are always relative to the root
+    /// directory.
+    pub async fn lookup(&self, path: &str) -> Result<Node, FsError> {
+        let mut node = match self {
+            Filesystem::Fat(fat) => Node::Fat(fat.root()),
+            Filesystem::Ext2(ext2) => Node::Ext2(ext2.root().await?),
+        };
+
+        // TODO: `..` isn't handled properly, as FAT file systems don't have such entries in the
+        //       root directory
+        for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            let entries = self.read_dir(&node).await?;
+            node = entries
+                .into_iter()
+                .find(|(name, _)| self.names_equal(name, component))
+                .map(|(_, node)| node)
+                .ok_or(FsError::NotFound)?;
+        }
+
+        Ok(node)
+    }
+
+    /// Returns the entries of the given directory.
+    ///
+    /// Returns an error if `dir` isn't a directory.
+    pub async fn read_dir(&self, dir: &Node) -> Result<Vec<(String, Node)>, FsError> {
+        if dir.metadata().file_type != FileType::Directory {
+            return Err(FsError::NotADirectory);
+        }
+
+        match (self, dir) {
+            (Filesystem::Fat(fat), Node::Fat(dir)) => Ok(fat
+                .read_dir(dir)
+                .await?
+                .into_iter()
+                .map(|(name, node)| (name, Node::Fat(node)))
+                .collect()),
+            (Filesystem::Ext2(ext2), Node::Ext2(dir)) => Ok(ext2
+                .read_dir(dir)
+                .await?
+                .into_iter()
+                .map(|(name, node)| (name, Node::Ext2(node)))
+                .collect()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the entries of the given directory, in the format of the `fs` interface.
+    pub async fn read_dir_entries(&self, dir: &Node) -> Result<Vec<DirEntry>, FsError> {
+        Ok(self
+            .read_dir(dir)
+            .await?
+            .into_iter()
+            .map(|(name, node)| DirEntry {
+                name,
+                metadata: node.metadata(),
+            })
+            .collect())
+    }
+
+    /// Reads at most `len` bytes of the given file, starting at `offset`.
+    ///
+    /// Returns an error if `file` isn't a file.
+    pub async fn read(&self, file: &Node, offset: u64, len: u32) -> Result<Vec<u8>, FsError> {
+        match file.metadata().file_type {
+            FileType::File => {}
+            FileType::Directory => return Err(FsError::IsADirectory),
+            FileType::Other => return Err(FsError::InvalidHandle),
+        }
+
+        match (self, file) {
+            (Filesystem::Fat(fat), Node::Fat(file)) => fat.read(file, offset, len).await,
+            (Filesystem::Ext2(ext2), Node::Ext2(file)) => ext2.read(file, offset, len).await,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns true if the name of an entry matches a component of a path.
+    fn names_equal(&self, entry_name: &str, component: &str) -> bool {
+        match self {
+            // FAT file systems are case-insensitive.
+            Filesystem::Fat(_) => entry_name.to_lowercase() == component.to_lowercase(),
+            Filesystem::Ext2(_) => entry_name == component,
+        }
+    }
+}
+
+impl Node {
+    /// Returns the metadata of this file or directory.
+    pub fn metadata(&self) -> Metadata {
+        match self {
+            Node::Fat(node) => node.metadata(),
+            Node::Ext2(node) => node.metadata(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filesystem;
+    use crate::{device::BlockDevice, ext2, fat};
+    use futures::prelude::*;
+    use redshirt_fs_interface::{FileType, FsError};
+    use std::rc::Rc;
+
+    /// Mounts the given image and runs `test` on the file system.
+    fn with_fs<T, F: Future<Output = T>>(
+        image: &[u8],
+        test: impl FnOnce(Rc<Filesystem>) -> F,
+    ) -> T {
+        let device = Rc::new(BlockDevice::new(512, (image.len() / 512) as u64));
+        let fs = device.run_with_image(image, Filesystem::mount(device.clone()));
+        let fs = Rc::new(fs.unwrap().unwrap());
+        device.run_with_image(image, test(fs))
+    }
+
+    #[test]
+    fn no_filesystem() {
+        let image = vec![0; 64 * 1024];
+        let device = Rc::new(BlockDevice::new(512, 128));
+        let fs = device.run_with_image(&image, Filesystem::mount(device.clone()));
+        assert!(fs.unwrap().is_none());
+    }
+
+    #[test]
+    fn fat() {
+        let content = fat::tests::hello_content();
+
+        with_fs(&fat::tests::example_image(), |fs| async move {
+            assert_eq!(fs.name(), "FAT32");
+
+            // Names are case-insensitive.
+            let file = fs.lookup("/HELLO.txt").await.unwrap();
+            assert_eq!(fs.read(&file, 0, 5000).await.unwrap(), content);
+
+            let file = fs.lookup("sub/./f03").await.unwrap();
+            assert_eq!(file.metadata().file_type, FileType::File);
+
+            let entries = fs.read_dir_entries(&fs.lookup("").await.unwrap()).await;
+            assert_eq!(entries.unwrap().len(), 3);
+        });
+    }
+
+    #[test]
+    fn ext2() {
+        let content = ext2::tests::hello_content();
+
+        with_fs(&ext2::tests::example_image(), |fs| async move {
+            assert_eq!(fs.name(), "ext2");
+
+            let file = fs.lookup("hello.txt").await.unwrap();
+            assert_eq!(fs.read(&file, 0, 5000).await.unwrap(), content);
+
+            let file = fs.lookup("/sub//nested.txt").await.unwrap();
+            assert_eq!(fs.read(&file, 0, 5000).await.unwrap(), b"hello");
+
+            // Names are case-sensitive.
+            assert_eq!(fs.lookup("SUB").await.unwrap_err(), FsError::NotFound);
+        });
+    }
+
+    #[test]
+    fn errors() {
+        with_fs(&ext2::tests::example_image(), |fs| async move {
+            assert_eq!(fs.lookup("foo").await.unwrap_err(), FsError::NotFound);
+            assert_eq!(
+                fs.lookup("hello.txt/foo").await.unwrap_err(),
+                FsError::NotADirectory
+            );
+
+            let dir = fs.lookup("sub").await.unwrap();
+            assert_eq!(
+                fs.read(&dir, 0, 10).await.unwrap_err(),
+                FsError::IsADirectory
+            );
+
+            let link = fs.lookup("link").await.unwrap();
+            assert_eq!(
+                fs.read(&link, 0, 10).await.unwrap_err(),
+                FsError::InvalidHandle
+            );
+        });
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! File system service.
+//!
+//! Implements the `block` interface, in order to be notified of the block devices registered by
+//! the drivers of storage devices. The first device that contains a supported file system is
+//! mounted, and its content is exposed through the `fs` interface.
+//!
+//! Supported file systems are FAT32 and ext2. File systems are accessed in read-only mode.
+
+mod device;
+mod ext2;
+mod fat;
+mod filesystem;
+
+use device::BlockDevice;
+use filesystem::{Filesystem, Node};
+
+use futures::{
+    channel::oneshot,
+    future::{LocalBoxFuture, Shared},
+    prelude::*,
+    stream::FuturesUnordered,
+};
+use parity_scale_codec::DecodeAll;
+use redshirt_block_interface::ffi::BlockMessage;
+use redshirt_fs_interface::{ffi, FileType, FsError};
+use redshirt_syscalls_interface::{InterfaceMessage, InterfaceOrDestroyed, Pid};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+/// Maximum number of bytes returned by a single read. Larger reads are truncated, in order to
+/// bound the memory used for the response.
+const MAX_READ_LEN: u32 = 1024 * 1024;
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    redshirt_interface_interface::register_interface(redshirt_block_interface::ffi::INTERFACE)
+        .await
+        .unwrap();
+    redshirt_interface_interface::register_interface(ffi::INTERFACE)
+        .await
+        .unwrap();
+
+    let (mount_tx, mount_rx) = oneshot::channel();
+    let mut state = State {
+        devices: HashMap::new(),
+        mount_tx: Rc::new(RefCell::new(Some(mount_tx))),
+        filesystem: mount_rx.shared(),
+        open_files: Rc::new(RefCell::new(HashMap::new())),
+        next_handle: Rc::new(Cell::new(0)),
+        tasks: FuturesUnordered::new(),
+    };
+
+    loop {
+        // Requests that need to access the device are processed in the background, while we
+        // continue to receive messages, including the responses of the drivers.
+        let message = if state.tasks.is_empty() {
+            Some(redshirt_syscalls_interface::next_interface_message().await)
+        } else {
+            match future::select(
+                redshirt_syscalls_interface::next_interface_message(),
+                state.tasks.next(),
+            )
+            .await
+            {
+                future::Either::Left((message, _)) => Some(message),
+                future::Either::Right((_, _)) => None,
+            }
+        };
+
+        match message {
+            Some(InterfaceOrDestroyed::Interface(msg)) => {
+                if msg.interface == redshirt_block_interface::ffi::INTERFACE {
+                    state.on_block_message(msg);
+                } else if msg.interface == ffi::INTERFACE {
+                    state.on_fs_message(msg);
+                }
+            }
+            Some(InterfaceOrDestroyed::ProcessDestroyed(p)) => state.on_process_destroyed(p.pid),
+            None => {}
+        }
+    }
+}
+
+struct State {
+    /// Block devices registered by drivers, indexed by driver and identifier within the driver.
+    devices: HashMap<(Pid, u64), Rc<BlockDevice>>,
+    /// Sender used to report the mounted file system. `None` once a file system has been
+    /// mounted.
+    mount_tx: Rc<RefCell<Option<oneshot::Sender<Rc<Filesystem>>>>>,
+    /// Resolves once a file system has been mounted. Requests wait for it to be ready.
+    filesystem: Shared<oneshot::Receiver<Rc<Filesystem>>>,
+    /// Files opened by processes, indexed by handle, with the process that opened them.
+    open_files: Rc<RefCell<HashMap<u64, (Pid, Node)>>>,
+    /// Handle to assign to the next opened file.
+    next_handle: Rc<Cell<u64>>,
+    /// Requests in progress.
+    tasks: FuturesUnordered<LocalBoxFuture<'static, ()>>,
+}
+
+impl State {
+    fn on_block_message(&mut self, msg: InterfaceMessage) {
+        let msg_data: BlockMessage = match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(m) => m,
+            Err(_) => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_message_error(message_id);
+                }
+                return;
+            }
+        };
+
+        match msg_data {
+            BlockMessage::RegisterDevice {
+                id,
+                sector_size,
+                num_sectors,
+                ..
+            } => {
+                if sector_size == 0 || self.devices.contains_key(&(msg.emitter_pid, id)) {
+                    return;
+                }
+
+                let device = Rc::new(BlockDevice::new(sector_size, num_sectors));
+                self.devices.insert((msg.emitter_pid, id), device.clone());
+
+                if self.mount_tx.borrow().is_some() {
+                    let mount_tx = self.mount_tx.clone();
+                    self.tasks.push(Box::pin(async move {
+                        let filesystem = match Filesystem::mount(device).await {
+                            Ok(Some(fs)) => fs,
+                            _ => return,
+                        };
+
+                        // Another device might have been mounted in the meantime.
+                        if let Some(mount_tx) = mount_tx.borrow_mut().take() {
+                            redshirt_stdout_interface::stdout(format!(
+                                "Mounted {} file system\n",
+                                filesystem.name()
+                            ));
+                            let _ = mount_tx.send(Rc::new(filesystem));
+                        }
+                    }));
+                }
+            }
+            BlockMessage::UnregisterDevice(id) => {
+                if let Some(device) = self.devices.remove(&(msg.emitter_pid, id)) {
+                    device.on_unregistered();
+                }
+            }
+            BlockMessage::NextCommand(id) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                match self.devices.get(&(msg.emitter_pid, id)) {
+                    Some(device) => device.on_next_command(move |command| {
+                        redshirt_syscalls_interface::emit_answer(message_id, &command)
+                    }),
+                    None => redshirt_syscalls_interface::emit_message_error(message_id),
+                }
+            }
+            BlockMessage::CommandFinished {
+                id,
+                command_id,
+                result,
+            } => {
+                if let Some(device) = self.devices.get(&(msg.emitter_pid, id)) {
+                    device.on_command_finished(command_id, result);
+                }
+            }
+        }
+    }
+
+    fn on_fs_message(&mut self, msg: InterfaceMessage) {
+        let msg_data: ffi::FsMessage = match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(m) => m,
+            Err(_) => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_message_error(message_id);
+                }
+                return;
+            }
+        };
+
+        // All the messages but `Close` expect an answer.
+        if let ffi::FsMessage::Close(handle) = msg_data {
+            let mut open_files = self.open_files.borrow_mut();
+            if open_files
+                .get(&handle)
+                .map_or(false, |(pid, _)| *pid == msg.emitter_pid)
+            {
+                open_files.remove(&handle);
+            }
+            return;
+        }
+
+        let message_id = match msg.message_id {
+            Some(m) => m,
+            None => return,
+        };
+
+        let emitter_pid = msg.emitter_pid;
+        let filesystem = self.filesystem.clone();
+        let open_files = self.open_files.clone();
+        let next_handle = self.next_handle.clone();
+
+        self.tasks.push(Box::pin(async move {
+            // The sender is never dropped, as it's kept in the state.
+            let filesystem = filesystem.await.unwrap();
+
+            match msg_data {
+                ffi::FsMessage::Open(path) => {
+                    let result = filesystem
+                        .lookup(&path)
+                        .await
+                        .and_then(|node| match node.metadata().file_type {
+                            FileType::File => Ok(node),
+                            FileType::Directory => Err(FsError::IsADirectory),
+                            // TODO: symbolic links aren't followed
+                            FileType::Other => Err(FsError::NotFound),
+                        })
+                        .map(|node| {
+                            let handle = next_handle.get();
+                            next_handle.set(handle + 1);
+                            open_files.borrow_mut().insert(handle, (emitter_pid, node));
+                            handle
+                        });
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::OpenResponse { result },
+                    );
+                }
+                ffi::FsMessage::Read {
+                    handle,
+                    offset,
+                    len,
+                } => {
+                    let node = match open_files.borrow().get(&handle) {
+                        Some((pid, node)) if *pid == emitter_pid => Some(node.clone()),
+                        _ => None,
+                    };
+                    let result = match node {
+                        Some(node) => {
+                            let len = len.min(MAX_READ_LEN);
+                            filesystem.read(&node, offset, len).await
+                        }
+                        None => Err(FsError::InvalidHandle),
+                    };
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::ReadResponse { result },
+                    );
+                }
+                ffi::FsMessage::ReadDir(path) => {
+                    let result = match filesystem.lookup(&path).await {
+                        Ok(node) => filesystem.read_dir_entries(&node).await,
+                        Err(err) => Err(err),
+                    };
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::ReadDirResponse { result },
+                    );
+                }
+                ffi::FsMessage::Stat(path) => {
+                    let result = filesystem.lookup(&path).await.map(|node| node.metadata());
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::StatResponse { result },
+                    );
+                }
+                ffi::FsMessage::Close(_) => unreachable!(),
+            }
+        }));
+    }
+
+    fn on_process_destroyed(&mut self, pid: Pid) {
+        self.open_files
+            .borrow_mut()
+            .retain(|_, (owner, _)| *owner != pid);
+
+        let devices = self
+            .devices
+            .keys()
+            .filter(|(driver, _)| *driver == pid)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in devices {
+            if let Some(device) = self.devices.remove(&key) {
+                device.on_unregistered();
+            }
+        }
+    }
+}
@@ -26,7 +26,12 @@ fn main() {
 
         let stream = stream::unfold(listener, |mut l| {
             async move {
-                let connec = l.accept().await.0;
+                let connec = loop {
+                    match l.accept().await {
+                        Ok((connec, _)) => break connec,
+                        Err(err) => println!("Error while accepting connection: {}", err),
+                    }
+                };
                 Some((connec, l))
             }
         });
@@ -61,7 +61,7 @@ pub struct Device {
     pending_packet: Option<Vec<u8>>,
     /// Page that contains or will contain the next incoming Ethernet packet.
     next_to_read: u8,
-    /// MAC address of the device. // TODO: keep as a field? it isn't really useful
+    /// MAC address of the device.
     mac_address: [u8; 6],
 }
 
@@ -245,19 +245,24 @@ impl Device {
         Some(out_packet)
     }
 
-    /// Sends a packet out. Returns an error if the device's buffer is full, in which case we must
-    /// try again later.
+    /// Returns the MAC address of the device.
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.mac_address
+    }
+
+    /// Sends a packet out. Returns back the packet if the device's buffer is full, in which case
+    /// we must try again later.
     ///
     /// # Panic
     ///
     /// Panics if the packet is too large.
     ///
-    unsafe fn send_packet(&mut self, packet: impl Into<Vec<u8>>) -> Result<(), ()> {
+    pub unsafe fn send_packet(&mut self, packet: impl Into<Vec<u8>>) -> Result<(), Vec<u8>> {
+        let packet = packet.into();
         if self.pending_packet.is_some() {
-            return Err(())
+            return Err(packet)
         }
 
-        let packet = packet.into();
         assert!(packet.len() <= 1522);
         self.pending_packet = Some(packet);
         self.flush_out();
@@ -334,18 +339,23 @@ impl Device {
         self.transmitting = Some(page_start .. page_end);
     }
 
-    // TODO:
-    /*pub async unsafe fn on_interrupt(&mut self) {
+    /// Must be called after the device has triggered an interrupt, or if it might have.
+    ///
+    /// Returns the list of packets that have been received.
+    pub async unsafe fn on_interrupt(&mut self) -> Vec<Vec<u8>> {
         // Read the ISR (Interrupt Status Register) to determine why an interrupt has been raised.
         let status = redshirt_hardware_interface::port_read_u8(self.base_port + 7).await;
         // Write back the same status in order to clear the bits and allow further interrupts to
         // happen.
         redshirt_hardware_interface::port_write_u8(self.base_port + 7, status);
 
-        if (status & (1 << 0)) != 0 {
-            // Packet received with no error.
-            if let Some(packet) = self.read_one_incoming().await {
-                // TODO: implement
+        let mut packets = Vec::new();
+
+        // Bit 0 means that a packet has been received with no error, and bit 4 that the read
+        // ring buffer is full. In both cases, we read all the packets in the buffer.
+        if (status & (1 << 0)) != 0 || (status & (1 << 4)) != 0 {
+            while let Some(packet) = self.read_one_incoming().await {
+                packets.push(packet);
             }
         }
 
@@ -355,7 +365,9 @@ impl Device {
             self.transmitting = None;
             self.flush_out();
         }
-    }*/
+
+        packets
+    }
 }
 
 impl fmt::Debug for Device {
@@ -28,7 +28,12 @@
 
 mod device;
 
-use std::convert::TryFrom as _;
+use futures::prelude::*;
+use redshirt_hardware_interface::interrupts::InterruptSubscription;
+use std::time::Duration;
+
+/// Interval at which to check the device if interrupts aren't available.
+const POLLING_INTERVAL: Duration = Duration::from_millis(10);
 
 fn main() {
     redshirt_syscalls_interface::block_on(async_main());
@@ -42,42 +47,92 @@ async fn async_main() {
         if device.vendor_id == 0x10ec && device.device_id == 0x8029 {
             let port_number = device.base_address_registers.iter().filter_map(|bar| {
                 match bar {
-                    redshirt_pci_interface::PciBaseAddressRegister::Io { base_address } if *base_address != 0 => Some(*base_address),
+                    Some(redshirt_pci_interface::PciBaseAddressRegister::Io { base_address, .. }) if *base_address != 0 => Some(*base_address),
                     _ => None
                 }
             }).next();
 
             if let Some(port_number) = port_number {
                 unsafe {
-                    ne2k_devices.push(device::Device::reset(port_number).await);
+                    let ne2k = device::Device::reset(port_number).await;
                     redshirt_stdout_interface::stdout(format!("Initialized ne2000 at 0x{:x}\n", port_number));
+
+                    let interrupts = match device.interrupt_line {
+                        Some(irq) => InterruptSubscription::subscribe(u32::from(irq)).await.ok(),
+                        None => None,
+                    };
+                    ne2k_devices.push((ne2k, interrupts));
                 }
             }
         }
     }
 
-    if ne2k_devices.is_empty() {
-        return;
-    }
+    // TODO: only the first device is handled
+    let (device, interrupts) = match ne2k_devices.into_iter().next() {
+        Some(d) => d,
+        None => return,
+    };
+
+    run_device(device, interrupts).await
+}
 
-    ne2k_devices.shrink_to_fit();
+/// Transfers frames between the device and the network manager. Never returns.
+async fn run_device(mut device: device::Device, mut interrupts: Option<InterruptSubscription>) {
+    let registration = redshirt_ethernet_interface::register_interface(
+        redshirt_ethernet_interface::InterfaceConfig {
+            mac_address: device.mac_address(),
+        }
+    );
+
+    // Frame to send out obtained from the network manager, but that the device couldn't accept
+    // yet.
+    let mut unsent = None::<Vec<u8>>;
+
+    // Kept alive across iterations, as the underlying message can't be cancelled.
+    let mut next_to_send = Box::pin(registration.packet_to_send());
 
     loop {
-        //redshirt_stdout_interface::stdout(format!("Polling"));
-        let packet = match unsafe { ne2k_devices[0].read_one_incoming().await } {
-            Some(p) => p,
-            None => continue,
+        // If the device's interrupt has been successfully subscribed to, we wait for it to be
+        // triggered. Otherwise, we fall back to polling the device.
+        // Note that the first call to `on_interrupt` happens before waiting, in order to process
+        // the interrupts that might have happened before the subscription.
+        for packet in unsafe { device.on_interrupt().await } {
+            registration.packet_from_network(packet);
+        }
+
+        if let Some(packet) = unsent.take() {
+            unsent = unsafe { device.send_packet(packet).err() };
+        }
+
+        let interrupt = Box::pin(wait_interrupt(&mut interrupts));
+        if unsent.is_some() {
+            interrupt.await;
+            continue;
+        }
+
+        let to_send = match future::select(next_to_send.as_mut(), interrupt).await {
+            future::Either::Left((packet, _)) => Some(packet),
+            future::Either::Right(((), _)) => None,
         };
 
-        let (header, data) = etherparse::Ethernet2Header::read_from_slice(&packet).unwrap();
-        if header.ether_type == 0x86dd {
-            let (ip_header, ip_data) = etherparse::Ipv6Header::read_from_slice(&data).unwrap();
-            if ip_header.next_header == 0x11 {
-                let (udp_header, udp_data) = etherparse::UdpHeader::read_from_slice(&ip_data).unwrap();
-                redshirt_stdout_interface::stdout(format!("Headers: {:?} {:?}\n", ip_header, udp_header));
-            }
+        if let Some(packet) = to_send {
+            next_to_send = Box::pin(registration.packet_to_send());
+            unsent = unsafe { device.send_packet(packet).err() };
         }
+    }
+}
 
-        //redshirt_stdout_interface::stdout(format!("Header: {:?}\n", header));
+/// Waits for the next interrupt. If the subscription turns out to no longer be active, it is
+/// dropped and we fall back to polling the device.
+///
+/// If we're not subscribed to any interrupt, waits for a small amount of time instead.
+async fn wait_interrupt(interrupts: &mut Option<InterruptSubscription>) {
+    if let Some(subscription) = interrupts {
+        if subscription.wait().await.is_ok() {
+            return;
+        }
+        *interrupts = None;
     }
+
+    redshirt_time_interface::monotonic_wait(POLLING_INTERVAL).await;
 }
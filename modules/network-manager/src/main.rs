@@ -0,0 +1,680 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Network manager.
+//!
+//! Implements the `tcp` and `udp` interfaces on top of the network interfaces registered by the
+//! network drivers through the `ethernet` interface.
+//!
+//! The TCP/IP stack itself is provided by smoltcp. It handles ARP, IPv4, IPv6, ICMP, TCP and
+//! UDP, and each interface obtains an IPv4 address through DHCP.
+
+mod manager;
+
+use futures::prelude::*;
+use parity_scale_codec::DecodeAll;
+use redshirt_ethernet_interface::ffi::NetworkMessage;
+use redshirt_syscalls_interface::{InterfaceMessage, InterfaceOrDestroyed, MessageId, Pid};
+use redshirt_tcp_interface::ffi;
+use redshirt_udp_interface::ffi as udp_ffi;
+use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address, Ipv6Address};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    pin::Pin,
+    time::Duration,
+};
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    redshirt_interface_interface::register_interface(ffi::INTERFACE)
+        .await
+        .unwrap();
+    redshirt_interface_interface::register_interface(udp_ffi::INTERFACE)
+        .await
+        .unwrap();
+    redshirt_interface_interface::register_interface(redshirt_ethernet_interface::ffi::INTERFACE)
+        .await
+        .unwrap();
+
+    let mut state = State {
+        manager: manager::NetworkManager::new(),
+        interfaces: HashSet::new(),
+        sockets_owners: HashMap::new(),
+        pending_opens: HashMap::new(),
+        pending_accepts: HashMap::new(),
+        pending_reads: HashMap::new(),
+        pending_writes: HashMap::new(),
+        pending_shutdowns: HashMap::new(),
+        pending_udp_recvs: HashMap::new(),
+        pending_udp_sends: HashMap::new(),
+        pending_frames_requests: HashMap::new(),
+    };
+
+    // Timer that fires when the manager must be polled again, and the value of the monotonic
+    // clock when it fires.
+    let mut timer: Option<(u128, Pin<Box<dyn Future<Output = ()>>>)> = None;
+
+    loop {
+        let message = match timer.as_mut() {
+            Some((_, timer)) => {
+                match future::select(
+                    redshirt_syscalls_interface::next_interface_message(),
+                    timer.as_mut(),
+                )
+                .await
+                {
+                    future::Either::Left((message, _)) => Some(message),
+                    future::Either::Right(((), _)) => None,
+                }
+            }
+            None => Some(redshirt_syscalls_interface::next_interface_message().await),
+        };
+
+        if message.is_none() {
+            timer = None;
+        }
+
+        let now_nanos = redshirt_time_interface::monotonic_clock().await;
+        let now = Duration::from_nanos(now_nanos as u64);
+
+        match message {
+            Some(InterfaceOrDestroyed::Interface(msg)) => {
+                if msg.interface == ffi::INTERFACE {
+                    state.on_tcp_message(msg, now);
+                } else if msg.interface == udp_ffi::INTERFACE {
+                    state.on_udp_message(msg);
+                } else if msg.interface == redshirt_ethernet_interface::ffi::INTERFACE {
+                    state.on_network_message(msg, now);
+                }
+            }
+            Some(InterfaceOrDestroyed::ProcessDestroyed(p)) => state.on_process_destroyed(p.pid),
+            None => {}
+        }
+
+        state.poll(now);
+
+        if let Some(delay) = state.manager.next_poll_delay(now) {
+            let when = now_nanos.saturating_add(delay.as_nanos());
+            if timer.as_ref().map_or(true, |(t, _)| *t > when) {
+                timer = Some((
+                    when,
+                    Box::pin(redshirt_time_interface::monotonic_wait_until(when)),
+                ));
+            }
+        }
+    }
+}
+
+struct State {
+    /// State machine of the interfaces and sockets.
+    manager: manager::NetworkManager<(Pid, u64)>,
+    /// List of the interfaces registered by network drivers.
+    interfaces: HashSet<(Pid, u64)>,
+    /// Process that owns each TCP or UDP socket.
+    sockets_owners: HashMap<u32, Pid>,
+    /// `Open` messages waiting for the connection to be established, indexed by socket.
+    pending_opens: HashMap<u32, MessageId>,
+    /// `Accept` messages waiting for an incoming connection, indexed by listener.
+    pending_accepts: HashMap<u32, VecDeque<MessageId>>,
+    /// `Read` messages waiting for data, indexed by socket.
+    pending_reads: HashMap<u32, VecDeque<MessageId>>,
+    /// `Write` messages whose data hasn't been entirely queued yet, indexed by socket.
+    pending_writes: HashMap<u32, VecDeque<(MessageId, Vec<u8>)>>,
+    /// `Shutdown` messages waiting for the writes of the socket to be queued, indexed by socket.
+    pending_shutdowns: HashMap<u32, MessageId>,
+    /// `RecvFrom` messages waiting for a datagram, indexed by socket.
+    pending_udp_recvs: HashMap<u32, MessageId>,
+    /// `SendTo` messages whose datagram couldn't be queued yet, indexed by socket.
+    pending_udp_sends: HashMap<u32, VecDeque<(MessageId, IpEndpoint, Vec<u8>)>>,
+    /// `InterfaceWaitData` messages waiting for a frame to send out, indexed by interface.
+    pending_frames_requests: HashMap<(Pid, u64), MessageId>,
+}
+
+impl State {
+    fn on_tcp_message(&mut self, msg: InterfaceMessage, now: Duration) {
+        let msg_data: ffi::TcpMessage = match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(m) => m,
+            Err(_) => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_message_error(message_id);
+                }
+                return;
+            }
+        };
+
+        match msg_data {
+            ffi::TcpMessage::Listen(listen) => {
+                let result = self
+                    .manager
+                    .tcp_listen(ip_from_ffi(listen.local_ip), listen.port);
+                if let Ok((socket_id, _)) = result {
+                    self.sockets_owners.insert(socket_id, msg.emitter_pid);
+                }
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::TcpListenResponse { result },
+                    );
+                }
+            }
+            ffi::TcpMessage::Open(open) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                let remote = IpEndpoint::new(ip_from_ffi(open.ip), open.port);
+                match self.manager.tcp_connect(remote, now) {
+                    Ok(socket_id) => {
+                        self.sockets_owners.insert(socket_id, msg.emitter_pid);
+                        self.pending_opens.insert(socket_id, message_id);
+                    }
+                    Err(err) => {
+                        redshirt_syscalls_interface::emit_answer(
+                            message_id,
+                            &ffi::TcpOpenResponse { result: Err(err) },
+                        );
+                    }
+                }
+            }
+            ffi::TcpMessage::Accept(ffi::TcpAccept { socket_id }) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !self.is_owner(socket_id, msg.emitter_pid) {
+                    let result = Err(ffi::TcpError::InvalidSocket);
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::TcpAcceptResponse { result },
+                    );
+                    return;
+                }
+                self.pending_accepts
+                    .entry(socket_id)
+                    .or_default()
+                    .push_back(message_id);
+            }
+            ffi::TcpMessage::Read(ffi::TcpRead { socket_id }) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !self.is_owner(socket_id, msg.emitter_pid) {
+                    let result = Err(ffi::TcpError::InvalidSocket);
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::TcpReadResponse { result },
+                    );
+                    return;
+                }
+                self.pending_reads
+                    .entry(socket_id)
+                    .or_default()
+                    .push_back(message_id);
+            }
+            ffi::TcpMessage::Write(ffi::TcpWrite { socket_id, data }) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !self.is_owner(socket_id, msg.emitter_pid) {
+                    let result = Err(ffi::TcpError::InvalidSocket);
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::TcpWriteResponse { result },
+                    );
+                    return;
+                }
+                if self.pending_shutdowns.contains_key(&socket_id) {
+                    let result = Err(ffi::TcpError::NotConnected);
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::TcpWriteResponse { result },
+                    );
+                    return;
+                }
+                self.pending_writes
+                    .entry(socket_id)
+                    .or_default()
+                    .push_back((message_id, data));
+            }
+            ffi::TcpMessage::Shutdown(ffi::TcpShutdown { socket_id }) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !self.is_owner(socket_id, msg.emitter_pid)
+                    || self.pending_shutdowns.contains_key(&socket_id)
+                {
+                    let result = Err(ffi::TcpError::InvalidSocket);
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &ffi::TcpShutdownResponse { result },
+                    );
+                    return;
+                }
+                self.pending_shutdowns.insert(socket_id, message_id);
+            }
+            ffi::TcpMessage::SetOption(ffi::TcpSetOption { socket_id, option }) => {
+                if !self.is_owner(socket_id, msg.emitter_pid) {
+                    return;
+                }
+                let _ = match option {
+                    ffi::TcpOption::NoDelay(no_delay) => {
+                        self.manager.tcp_set_no_delay(socket_id, no_delay)
+                    }
+                    ffi::TcpOption::KeepAlive(interval) => self
+                        .manager
+                        .tcp_set_keep_alive(socket_id, interval.map(Duration::from_millis)),
+                };
+            }
+            ffi::TcpMessage::Close(ffi::TcpClose { socket_id }) => {
+                if self.is_owner(socket_id, msg.emitter_pid) {
+                    self.close_socket(socket_id);
+                }
+            }
+        }
+    }
+
+    fn on_udp_message(&mut self, msg: InterfaceMessage) {
+        let msg_data: udp_ffi::UdpMessage = match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(m) => m,
+            Err(_) => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_message_error(message_id);
+                }
+                return;
+            }
+        };
+
+        match msg_data {
+            udp_ffi::UdpMessage::Bind(bind) => {
+                let result = self.manager.udp_bind(ip_from_ffi(bind.local_ip), bind.port);
+                if let Ok((socket_id, _)) = result {
+                    self.sockets_owners.insert(socket_id, msg.emitter_pid);
+                }
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &udp_ffi::UdpBindResponse { result },
+                    );
+                }
+            }
+            udp_ffi::UdpMessage::SendTo(send) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !self.is_owner(send.socket_id, msg.emitter_pid) {
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &udp_ffi::UdpSendToResponse { result: Err(()) },
+                    );
+                    return;
+                }
+                let remote = IpEndpoint::new(ip_from_ffi(send.remote_ip), send.remote_port);
+                self.pending_udp_sends
+                    .entry(send.socket_id)
+                    .or_default()
+                    .push_back((message_id, remote, send.data));
+            }
+            udp_ffi::UdpMessage::RecvFrom(udp_ffi::UdpRecvFrom { socket_id }) => {
+                let message_id = match msg.message_id {
+                    Some(m) => m,
+                    None => return,
+                };
+                if !self.is_owner(socket_id, msg.emitter_pid)
+                    || self.pending_udp_recvs.contains_key(&socket_id)
+                {
+                    redshirt_syscalls_interface::emit_answer(
+                        message_id,
+                        &udp_ffi::UdpRecvFromResponse { result: Err(()) },
+                    );
+                    return;
+                }
+                self.pending_udp_recvs.insert(socket_id, message_id);
+            }
+            udp_ffi::UdpMessage::Close(udp_ffi::UdpClose { socket_id }) => {
+                if self.is_owner(socket_id, msg.emitter_pid) {
+                    self.manager.udp_close(socket_id);
+                    self.sockets_owners.remove(&socket_id);
+                }
+            }
+        }
+    }
+
+    fn on_network_message(&mut self, msg: InterfaceMessage, now: Duration) {
+        let msg_data: NetworkMessage = match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(m) => m,
+            Err(_) => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_message_error(message_id);
+                }
+                return;
+            }
+        };
+
+        match msg_data {
+            NetworkMessage::RegisterInterface { id, mac_address } => {
+                if self.interfaces.insert((msg.emitter_pid, id)) {
+                    self.manager
+                        .register_interface((msg.emitter_pid, id), mac_address, now);
+                }
+            }
+            NetworkMessage::UnregisterInterface(id) => {
+                self.unregister_interface((msg.emitter_pid, id));
+            }
+            NetworkMessage::InterfaceOnData { id, data } => {
+                self.manager
+                    .inject_interface_data(&(msg.emitter_pid, id), data);
+            }
+            NetworkMessage::InterfaceWaitData(id) => {
+                if let Some(message_id) = msg.message_id {
+                    if let Some(previous) = self
+                        .pending_frames_requests
+                        .insert((msg.emitter_pid, id), message_id)
+                    {
+                        redshirt_syscalls_interface::emit_message_error(previous);
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_process_destroyed(&mut self, pid: Pid) {
+        let sockets = self
+            .sockets_owners
+            .iter()
+            .filter(|(_, owner)| **owner == pid)
+            .map(|(socket_id, _)| *socket_id)
+            .collect::<Vec<_>>();
+        for socket_id in sockets {
+            self.close_socket(socket_id);
+        }
+
+        let interfaces = self
+            .interfaces
+            .iter()
+            .filter(|(owner, _)| *owner == pid)
+            .cloned()
+            .collect::<Vec<_>>();
+        for interface in interfaces {
+            self.unregister_interface(interface);
+        }
+    }
+
+    fn is_owner(&self, socket_id: u32, pid: Pid) -> bool {
+        self.sockets_owners.get(&socket_id) == Some(&pid)
+    }
+
+    fn close_socket(&mut self, socket_id: u32) {
+        self.manager.tcp_close(socket_id);
+        self.manager.udp_close(socket_id);
+        self.sockets_owners.remove(&socket_id);
+        // The messages concerning this socket are answered with an error in `poll`.
+    }
+
+    fn unregister_interface(&mut self, id: (Pid, u64)) {
+        self.interfaces.remove(&id);
+        self.manager.unregister_interface(&id);
+        if let Some(message_id) = self.pending_frames_requests.remove(&id) {
+            redshirt_syscalls_interface::emit_message_error(message_id);
+        }
+    }
+
+    /// Updates the state of the manager and answers the pending messages that can be answered.
+    fn poll(&mut self, now: Duration) {
+        // Answering messages can generate new network activity, which can in turn allow
+        // answering more messages.
+        loop {
+            self.manager.poll(now);
+            if !self.answer_pending_messages() {
+                break;
+            }
+        }
+
+        let manager = &mut self.manager;
+        self.pending_frames_requests
+            .retain(
+                |interface, message_id| match manager.read_ethernet_cable_out(interface) {
+                    Some(frame) => {
+                        redshirt_syscalls_interface::emit_answer(*message_id, &frame);
+                        false
+                    }
+                    None => true,
+                },
+            );
+    }
+
+    /// Answers the pending TCP messages that can be answered. Returns true if any socket has
+    /// been updated.
+    fn answer_pending_messages(&mut self) -> bool {
+        let mut progress = false;
+        let manager = &mut self.manager;
+
+        // Sockets that have failed before the user could be notified of their existence.
+        let mut failed = Vec::new();
+
+        self.pending_opens.retain(|socket_id, message_id| {
+            let result = match manager.tcp_open_status(*socket_id) {
+                Ok(false) => return true,
+                Ok(true) => connection_info(manager, *socket_id),
+                Err(err) => Err(err),
+            };
+            if result.is_err() {
+                failed.push(*socket_id);
+            }
+            redshirt_syscalls_interface::emit_answer(*message_id, &ffi::TcpOpenResponse { result });
+            false
+        });
+
+        let mut accepted = Vec::new();
+        self.pending_accepts.retain(|socket_id, queue| {
+            while let Some(message_id) = queue.front() {
+                let result = match manager.tcp_accept(*socket_id) {
+                    Ok(None) => break,
+                    Ok(Some(accepted_socket_id)) => {
+                        let result = connection_info(manager, accepted_socket_id);
+                        if result.is_ok() {
+                            accepted.push((*socket_id, accepted_socket_id));
+                        } else {
+                            manager.tcp_close(accepted_socket_id);
+                        }
+                        result
+                    }
+                    Err(err) => Err(err),
+                };
+                redshirt_syscalls_interface::emit_answer(
+                    *message_id,
+                    &ffi::TcpAcceptResponse { result },
+                );
+                queue.pop_front();
+            }
+            !queue.is_empty()
+        });
+
+        self.pending_reads.retain(|socket_id, queue| {
+            while let Some(message_id) = queue.front() {
+                let result = match manager.tcp_read(*socket_id) {
+                    Ok(None) => break,
+                    Ok(Some(data)) => {
+                        progress = true;
+                        Ok(data)
+                    }
+                    Err(err) => Err(err),
+                };
+                redshirt_syscalls_interface::emit_answer(
+                    *message_id,
+                    &ffi::TcpReadResponse { result },
+                );
+                queue.pop_front();
+            }
+            !queue.is_empty()
+        });
+
+        self.pending_writes.retain(|socket_id, queue| {
+            while let Some((message_id, data)) = queue.front_mut() {
+                let result = match manager.tcp_write(*socket_id, data) {
+                    Ok(n) if n < data.len() => {
+                        if n != 0 {
+                            progress = true;
+                            data.drain(..n);
+                        }
+                        break;
+                    }
+                    Ok(_) => {
+                        progress = true;
+                        Ok(())
+                    }
+                    Err(err) => Err(err),
+                };
+                redshirt_syscalls_interface::emit_answer(
+                    *message_id,
+                    &ffi::TcpWriteResponse { result },
+                );
+                queue.pop_front();
+            }
+            !queue.is_empty()
+        });
+
+        // Shutdowns are processed after all the data written beforehand has been queued.
+        let pending_writes = &self.pending_writes;
+        self.pending_shutdowns.retain(|socket_id, message_id| {
+            if pending_writes.contains_key(socket_id) {
+                return true;
+            }
+            progress = true;
+            let result = manager.tcp_shutdown(*socket_id);
+            redshirt_syscalls_interface::emit_answer(
+                *message_id,
+                &ffi::TcpShutdownResponse { result },
+            );
+            false
+        });
+
+        self.pending_udp_recvs.retain(|socket_id, message_id| {
+            let result = match manager.udp_recv_from(*socket_id) {
+                Ok(None) => return true,
+                Ok(Some((data, remote))) => Ok(udp_ffi::UdpDatagram {
+                    remote_ip: ip_to_ffi(remote.addr),
+                    remote_port: remote.port,
+                    data,
+                }),
+                Err(()) => Err(()),
+            };
+            redshirt_syscalls_interface::emit_answer(
+                *message_id,
+                &udp_ffi::UdpRecvFromResponse { result },
+            );
+            false
+        });
+
+        self.pending_udp_sends.retain(|socket_id, queue| {
+            while let Some((message_id, remote, data)) = queue.front() {
+                let result = match manager.udp_send_to(*socket_id, *remote, data) {
+                    Ok(false) => break,
+                    Ok(true) => {
+                        progress = true;
+                        Ok(())
+                    }
+                    Err(()) => Err(()),
+                };
+                redshirt_syscalls_interface::emit_answer(
+                    *message_id,
+                    &udp_ffi::UdpSendToResponse { result },
+                );
+                queue.pop_front();
+            }
+            !queue.is_empty()
+        });
+
+        for socket_id in failed {
+            self.close_socket(socket_id);
+        }
+
+        // Accepted sockets belong to the owner of the listener.
+        for (listener_id, accepted_socket_id) in accepted {
+            if let Some(owner) = self.sockets_owners.get(&listener_id).cloned() {
+                self.sockets_owners.insert(accepted_socket_id, owner);
+            }
+        }
+
+        progress
+    }
+}
+
+/// Builds the information about a connection to report to the user.
+fn connection_info(
+    manager: &mut manager::NetworkManager<(Pid, u64)>,
+    socket_id: u32,
+) -> Result<ffi::TcpConnection, ffi::TcpError> {
+    let (local, remote) = manager.tcp_endpoints(socket_id)?;
+    Ok(ffi::TcpConnection {
+        socket_id,
+        local_ip: ip_to_ffi(local.addr),
+        local_port: local.port,
+        remote_ip: ip_to_ffi(remote.addr),
+        remote_port: remote.port,
+    })
+}
+
+/// Converts an IP address from the `tcp` interface to smoltcp. IPv4 addresses are represented
+/// as IPv4-mapped IPv6 addresses.
+fn ip_from_ffi(ip: [u16; 8]) -> IpAddress {
+    let ip = Ipv6Address::new(ip[0], ip[1], ip[2], ip[3], ip[4], ip[5], ip[6], ip[7]);
+    if ip.is_unspecified() {
+        return IpAddress::Unspecified;
+    }
+
+    let bytes = ip.as_bytes();
+    if bytes[..10].iter().all(|b| *b == 0) && bytes[10] == 0xff && bytes[11] == 0xff {
+        let ipv4 = Ipv4Address::from_bytes(&bytes[12..]);
+        if ipv4.is_unspecified() {
+            IpAddress::Unspecified
+        } else {
+            IpAddress::Ipv4(ipv4)
+        }
+    } else {
+        IpAddress::Ipv6(ip)
+    }
+}
+
+/// Converts an IP address from smoltcp to the `tcp` interface.
+fn ip_to_ffi(ip: IpAddress) -> [u16; 8] {
+    let bytes = match ip {
+        IpAddress::Ipv4(ip) => {
+            let mut bytes = [0; 16];
+            bytes[10] = 0xff;
+            bytes[11] = 0xff;
+            bytes[12..].copy_from_slice(ip.as_bytes());
+            bytes
+        }
+        IpAddress::Ipv6(ip) => {
+            let mut bytes = [0; 16];
+            bytes.copy_from_slice(ip.as_bytes());
+            bytes
+        }
+        _ => [0; 16],
+    };
+
+    let mut out = [0; 8];
+    for (n, chunk) in bytes.chunks(2).enumerate() {
+        out[n] = u16::from_be_bytes([chunk[0], chunk[1]]);
+    }
+    out
+}
@@ -0,0 +1,994 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! State machine of the network manager.
+//!
+//! This module doesn't perform any system call. The [`NetworkManager`] is fed with Ethernet
+//! frames and with the current time, and the Ethernet frames to send out must be pulled from it.
+//!
+//! Each network interface has its own TCP/IP stack (provided by smoltcp), and each TCP socket
+//! belongs to a single network interface. Listening sockets are the exception: they listen on
+//! all the network interfaces at once. UDP sockets similarly receive datagrams from all the
+//! network interfaces.
+
+use redshirt_tcp_interface::ffi::TcpError;
+use smoltcp::{
+    dhcp::{Dhcpv4Client, Dhcpv4Config},
+    iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes},
+    phy,
+    socket::{
+        RawPacketMetadata, RawSocketBuffer, SocketHandle, SocketRef, SocketSet, TcpSocket,
+        TcpSocketBuffer, TcpState, UdpPacketMetadata, UdpSocket, UdpSocketBuffer,
+    },
+    time::{Duration as SmoltcpDuration, Instant},
+    wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv6Address},
+};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::Hash,
+    time::Duration,
+};
+
+/// Maximum number of frames that are buffered, in each direction, for each interface.
+const MAX_QUEUED_FRAMES: usize = 64;
+
+/// Size of the send buffer and of the receive buffer of each TCP socket.
+const TCP_BUFFER_SIZE: usize = 65536;
+
+/// Size of the send buffer and of the receive buffer of each UDP socket.
+const UDP_BUFFER_SIZE: usize = 65536;
+
+/// Maximum number of datagrams in the send buffer and in the receive buffer of each UDP socket.
+const UDP_MAX_DATAGRAMS: usize = 32;
+
+/// Time after which we give up establishing a TCP connection.
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of established connections that can wait to be accepted by a listener.
+/// Connections beyond this limit are reset.
+const MAX_BACKLOG: usize = 32;
+
+/// First port that is allocated when the user doesn't specify a port.
+const EPHEMERAL_PORTS_START: u16 = 49152;
+
+/// State machine of all the network interfaces and sockets.
+pub struct NetworkManager<TIfId> {
+    /// List of the network interfaces.
+    interfaces: HashMap<TIfId, Interface>,
+    /// List of the TCP sockets, indexed by their ID as reported to the user.
+    tcp_sockets: HashMap<u32, TcpSocketState<TIfId>>,
+    /// List of the UDP sockets, indexed by their ID as reported to the user.
+    udp_sockets: HashMap<u32, UdpSocketState<TIfId>>,
+    /// Sockets that have been closed by the user and that we keep alive until the connection
+    /// has been properly shut down.
+    closing_sockets: Vec<(TIfId, SocketHandle)>,
+    /// ID to assign to the next TCP or UDP socket.
+    next_socket_id: u32,
+    /// Port to try to assign to the next socket that doesn't have a port.
+    next_ephemeral_port: u16,
+}
+
+/// A network interface.
+struct Interface {
+    /// TCP/IP stack of the interface.
+    ethernet: EthernetInterface<'static, 'static, 'static, Device>,
+    /// Sockets of this interface. Contains the DHCP socket and the TCP sockets.
+    sockets: SocketSet<'static, 'static, 'static>,
+    /// Obtains an IPv4 address for the interface.
+    dhcp: Dhcpv4Client,
+}
+
+/// State of a UDP socket.
+struct UdpSocketState<TIfId> {
+    /// Local endpoint the socket is bound to.
+    endpoint: IpEndpoint,
+    /// For each interface, the socket bound to the endpoint.
+    handles: HashMap<TIfId, SocketHandle>,
+}
+
+/// State of a TCP socket.
+enum TcpSocketState<TIfId> {
+    /// Listens for incoming connections on all the interfaces.
+    Listener {
+        /// Local endpoint to listen on.
+        endpoint: IpEndpoint,
+        /// For each interface, the socket waiting for an incoming connection.
+        pending: HashMap<TIfId, SocketHandle>,
+        /// Connections that have been received but not yet accepted by the user. Contains
+        /// socket IDs.
+        backlog: VecDeque<u32>,
+    },
+    /// A connection, either established or in progress.
+    Stream(Stream<TIfId>),
+}
+
+/// A TCP connection.
+struct Stream<TIfId> {
+    /// Interface the socket belongs to.
+    interface: TIfId,
+    /// Socket within the interface.
+    handle: SocketHandle,
+    /// Local port of the connection.
+    local_port: u16,
+    /// If we are connecting to a remote, when the connection has been started.
+    connect_start: Option<Duration>,
+    /// True if the remote has closed its writing side.
+    remote_closed: bool,
+    /// If the connection has failed, the reason why.
+    error: Option<TcpError>,
+}
+
+impl<TIfId> Stream<TIfId> {
+    fn new(
+        interface: TIfId,
+        handle: SocketHandle,
+        local_port: u16,
+        connect_start: Option<Duration>,
+    ) -> Self {
+        Stream {
+            interface,
+            handle,
+            local_port,
+            connect_start,
+            remote_closed: false,
+            error: None,
+        }
+    }
+}
+
+impl<TIfId: Clone + Hash + Eq> NetworkManager<TIfId> {
+    /// Initializes a new `NetworkManager` without any interface.
+    pub fn new() -> Self {
+        NetworkManager {
+            interfaces: HashMap::new(),
+            tcp_sockets: HashMap::new(),
+            udp_sockets: HashMap::new(),
+            closing_sockets: Vec::new(),
+            next_socket_id: 1,
+            next_ephemeral_port: EPHEMERAL_PORTS_START,
+        }
+    }
+
+    /// Adds a new network interface. Does nothing if an interface with this ID already exists.
+    ///
+    /// The interface is assigned an IPv6 link-local address derived from its MAC address, and
+    /// tries to obtain an IPv4 address through DHCP.
+    pub fn register_interface(&mut self, id: TIfId, mac_address: [u8; 6], now: Duration) {
+        if self.interfaces.contains_key(&id) {
+            return;
+        }
+
+        let device = Device {
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+        };
+
+        let ethernet = EthernetInterfaceBuilder::new(device)
+            .ethernet_addr(EthernetAddress(mac_address))
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(vec![
+                IpCidr::new(link_local_address(mac_address).into(), 64),
+                // Placeholder for the address obtained through DHCP.
+                IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0),
+            ])
+            .routes(Routes::new(BTreeMap::new()))
+            .finalize();
+
+        let mut sockets = SocketSet::new(Vec::new());
+        let dhcp = Dhcpv4Client::new(
+            &mut sockets,
+            RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 900]),
+            RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; 1], vec![0; 600]),
+            to_instant(now),
+        );
+
+        let mut interface = Interface {
+            ethernet,
+            sockets,
+            dhcp,
+        };
+
+        // Existing listeners also listen on the new interface.
+        for socket in self.tcp_sockets.values_mut() {
+            if let TcpSocketState::Listener {
+                endpoint, pending, ..
+            } = socket
+            {
+                if let Ok(handle) = interface.new_listening_socket(*endpoint) {
+                    pending.insert(id.clone(), handle);
+                }
+            }
+        }
+
+        for socket in self.udp_sockets.values_mut() {
+            if let Ok(handle) = interface.new_udp_socket(socket.endpoint) {
+                socket.handles.insert(id.clone(), handle);
+            }
+        }
+
+        self.interfaces.insert(id, interface);
+    }
+
+    /// Removes a network interface. All the connections using this interface are dropped.
+    pub fn unregister_interface(&mut self, id: &TIfId) {
+        if self.interfaces.remove(id).is_none() {
+            return;
+        }
+
+        self.closing_sockets.retain(|(if_id, _)| if_id != id);
+        self.tcp_sockets.retain(|_, socket| match socket {
+            TcpSocketState::Listener { pending, .. } => {
+                pending.remove(id);
+                true
+            }
+            TcpSocketState::Stream(stream) => stream.interface != *id,
+        });
+        for socket in self.udp_sockets.values_mut() {
+            socket.handles.remove(id);
+        }
+    }
+
+    /// Injects an Ethernet frame received by the interface.
+    ///
+    /// The frame is buffered, and processed the next time [`NetworkManager::poll`] is called.
+    pub fn inject_interface_data(&mut self, id: &TIfId, data: Vec<u8>) {
+        if let Some(interface) = self.interfaces.get_mut(id) {
+            let device = interface.ethernet.device_mut();
+            if device.rx.len() < MAX_QUEUED_FRAMES {
+                device.rx.push_back(data);
+            }
+        }
+    }
+
+    /// Extracts the next Ethernet frame that the interface must send out.
+    pub fn read_ethernet_cable_out(&mut self, id: &TIfId) -> Option<Vec<u8>> {
+        let interface = self.interfaces.get_mut(id)?;
+        interface.ethernet.device_mut().tx.pop_front()
+    }
+
+    /// Processes the buffered incoming frames and updates the state of all the sockets.
+    ///
+    /// Must be called after [`NetworkManager::inject_interface_data`] or after any operation
+    /// on a socket, and when the delay returned by [`NetworkManager::next_poll_delay`] has
+    /// elapsed.
+    pub fn poll(&mut self, now: Duration) {
+        for interface in self.interfaces.values_mut() {
+            interface.poll(to_instant(now));
+        }
+
+        // Move the listening sockets that have received a connection to the backlog, and
+        // replace them with new listening sockets.
+        let mut accepted = Vec::new();
+        for socket in self.tcp_sockets.values_mut() {
+            let (endpoint, pending, backlog) = match socket {
+                TcpSocketState::Listener {
+                    endpoint,
+                    pending,
+                    backlog,
+                } => (endpoint, pending, backlog),
+                TcpSocketState::Stream(_) => continue,
+            };
+
+            for (if_id, handle) in pending.iter_mut() {
+                let interface = self.interfaces.get_mut(if_id).unwrap();
+                if interface.sockets.get::<TcpSocket>(*handle).is_listening() {
+                    continue;
+                }
+
+                let new_listening = match interface.new_listening_socket(*endpoint) {
+                    Ok(h) => h,
+                    Err(()) => continue,
+                };
+                let connection = std::mem::replace(handle, new_listening);
+
+                if backlog.len() >= MAX_BACKLOG {
+                    interface.sockets.get::<TcpSocket>(connection).abort();
+                    self.closing_sockets.push((if_id.clone(), connection));
+                    continue;
+                }
+
+                let socket_id = self.next_socket_id;
+                self.next_socket_id = self.next_socket_id.wrapping_add(1);
+                backlog.push_back(socket_id);
+                accepted.push((socket_id, if_id.clone(), connection, endpoint.port));
+            }
+        }
+
+        for (socket_id, interface, handle, local_port) in accepted {
+            let stream = Stream::new(interface, handle, local_port, None);
+            self.tcp_sockets
+                .insert(socket_id, TcpSocketState::Stream(stream));
+        }
+
+        // Detect the connections that have been closed by the remote or that have failed.
+        for socket in self.tcp_sockets.values_mut() {
+            let stream = match socket {
+                TcpSocketState::Stream(s) if s.error.is_none() => s,
+                _ => continue,
+            };
+
+            let interface = self.interfaces.get_mut(&stream.interface).unwrap();
+            let mut socket = interface.sockets.get::<TcpSocket>(stream.handle);
+            match socket.state() {
+                TcpState::SynSent | TcpState::SynReceived => {}
+                TcpState::CloseWait
+                | TcpState::LastAck
+                | TcpState::Closing
+                | TcpState::TimeWait => {
+                    stream.remote_closed = true;
+                }
+                TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => {
+                    if stream.connect_start.take().is_some() {
+                        socket.set_timeout(None);
+                    }
+                }
+                // An incoming connection that has been reset goes back to the `Listen` state.
+                TcpState::Closed | TcpState::Listen if !stream.remote_closed => {
+                    stream.error = Some(match stream.connect_start {
+                        Some(start) if now >= start + TCP_CONNECT_TIMEOUT => TcpError::TimedOut,
+                        Some(_) => TcpError::ConnectionRefused,
+                        None => TcpError::ConnectionReset,
+                    });
+                }
+                TcpState::Closed | TcpState::Listen => {}
+            }
+        }
+
+        // Destroy the closed sockets that have finished shutting down.
+        let interfaces = &mut self.interfaces;
+        self.closing_sockets.retain(|(if_id, handle)| {
+            let interface = interfaces.get_mut(if_id).unwrap();
+            let state = interface.sockets.get::<TcpSocket>(*handle).state();
+            if state == TcpState::Closed || state == TcpState::TimeWait {
+                interface.sockets.remove(*handle);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Returns the delay after which [`NetworkManager::poll`] must be called again, assuming no
+    /// other event happens in the meanwhile.
+    pub fn next_poll_delay(&self, now: Duration) -> Option<Duration> {
+        let now = to_instant(now);
+        self.interfaces
+            .values()
+            .flat_map(|interface| {
+                let iface_delay = interface.ethernet.poll_delay(&interface.sockets, now);
+                let dhcp_delay = interface.dhcp.next_poll(now);
+                iface_delay.into_iter().chain(Some(dhcp_delay))
+            })
+            .map(|delay| Duration::from_millis(delay.total_millis()))
+            .min()
+    }
+
+    /// Starts listening for TCP connections on all the interfaces.
+    ///
+    /// Pass `IpAddress::Unspecified` in order to listen on all the addresses. If `port` is 0, a
+    /// port is automatically assigned.
+    ///
+    /// On success, returns the ID of the socket and the port it's listening on.
+    pub fn tcp_listen(&mut self, local_ip: IpAddress, port: u16) -> Result<(u32, u16), TcpError> {
+        let port = if port == 0 {
+            self.allocate_port().ok_or(TcpError::AddrInUse)?
+        } else {
+            port
+        };
+
+        let in_use = self.tcp_sockets.values().any(|socket| match socket {
+            TcpSocketState::Listener { endpoint, .. } => overlaps(endpoint, &local_ip, port),
+            TcpSocketState::Stream(_) => false,
+        });
+        if in_use {
+            return Err(TcpError::AddrInUse);
+        }
+
+        let endpoint = IpEndpoint::new(local_ip, port);
+        let mut pending = HashMap::new();
+        for (if_id, interface) in self.interfaces.iter_mut() {
+            let handle = interface
+                .new_listening_socket(endpoint)
+                .map_err(|()| TcpError::AddrInUse)?;
+            pending.insert(if_id.clone(), handle);
+        }
+
+        let socket_id = self.next_socket_id;
+        self.next_socket_id = self.next_socket_id.wrapping_add(1);
+        self.tcp_sockets.insert(
+            socket_id,
+            TcpSocketState::Listener {
+                endpoint,
+                pending,
+                backlog: VecDeque::new(),
+            },
+        );
+        Ok((socket_id, port))
+    }
+
+    /// Extracts the next connection received by the given listener.
+    ///
+    /// Returns `Ok(None)` if no connection is available yet, and an error if the socket isn't a
+    /// listener.
+    pub fn tcp_accept(&mut self, socket_id: u32) -> Result<Option<u32>, TcpError> {
+        loop {
+            let accepted = match self.tcp_sockets.get_mut(&socket_id) {
+                Some(TcpSocketState::Listener { backlog, .. }) => match backlog.pop_front() {
+                    Some(id) => id,
+                    None => return Ok(None),
+                },
+                _ => return Err(TcpError::InvalidSocket),
+            };
+
+            // The connection might have been destroyed if its interface has been removed.
+            if self.tcp_sockets.contains_key(&accepted) {
+                return Ok(Some(accepted));
+            }
+        }
+    }
+
+    /// Starts connecting to the given remote. Use [`NetworkManager::tcp_open_status`] to know
+    /// when the connection is established.
+    ///
+    /// Returns an error if no interface is capable of reaching the remote.
+    pub fn tcp_connect(&mut self, remote: IpEndpoint, now: Duration) -> Result<u32, TcpError> {
+        // TODO: do a proper route lookup
+        let if_id = self
+            .interfaces
+            .iter()
+            .find(|(_, interface)| interface.can_reach(&remote.addr))
+            .map(|(id, _)| id.clone())
+            .ok_or(TcpError::NetworkUnreachable)?;
+
+        let local_port = self.allocate_port().ok_or(TcpError::AddrInUse)?;
+        let interface = self.interfaces.get_mut(&if_id).unwrap();
+        let mut socket = new_tcp_socket();
+        // The timeout is removed once the connection is established.
+        socket.set_timeout(Some(to_smoltcp_duration(TCP_CONNECT_TIMEOUT)));
+        socket
+            .connect(remote, local_port)
+            .map_err(|_| TcpError::NetworkUnreachable)?;
+        let handle = interface.sockets.add(socket);
+
+        let socket_id = self.next_socket_id;
+        self.next_socket_id = self.next_socket_id.wrapping_add(1);
+        let stream = Stream::new(if_id, handle, local_port, Some(now));
+        self.tcp_sockets
+            .insert(socket_id, TcpSocketState::Stream(stream));
+        Ok(socket_id)
+    }
+
+    /// Returns `Ok(true)` if the connection is established, `Ok(false)` if it is still being
+    /// established, and an error if the connection has failed.
+    pub fn tcp_open_status(&mut self, socket_id: u32) -> Result<bool, TcpError> {
+        let socket = self.tcp_socket(socket_id)?;
+        match socket.state() {
+            TcpState::SynSent | TcpState::SynReceived => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    /// Returns the local and remote endpoints of the given connection.
+    pub fn tcp_endpoints(&mut self, socket_id: u32) -> Result<(IpEndpoint, IpEndpoint), TcpError> {
+        let socket = self.tcp_socket(socket_id)?;
+        Ok((socket.local_endpoint(), socket.remote_endpoint()))
+    }
+
+    /// Extracts the data received on the given socket.
+    ///
+    /// Returns `Ok(None)` if no data is available yet, and an empty buffer if the remote has
+    /// closed its writing side.
+    pub fn tcp_read(&mut self, socket_id: u32) -> Result<Option<Vec<u8>>, TcpError> {
+        let mut socket = self.tcp_socket(socket_id)?;
+
+        if socket.can_recv() {
+            let data = socket
+                .recv(|data| (data.len(), data.to_vec()))
+                .map_err(|_| TcpError::ConnectionReset)?;
+            return Ok(Some(data));
+        }
+
+        match socket.state() {
+            TcpState::SynSent | TcpState::SynReceived => Ok(None),
+            _ if socket.may_recv() => Ok(None),
+            _ => Ok(Some(Vec::new())),
+        }
+    }
+
+    /// Queues data to send on the given socket. Returns the number of bytes that have been
+    /// queued, which can be 0 if the send buffer is full.
+    ///
+    /// Returns an error if the connection has failed or if its writing side has been shut down.
+    pub fn tcp_write(&mut self, socket_id: u32, data: &[u8]) -> Result<usize, TcpError> {
+        let mut socket = self.tcp_socket(socket_id)?;
+
+        match socket.state() {
+            TcpState::SynSent | TcpState::SynReceived => Ok(0),
+            _ if socket.may_send() => socket
+                .send_slice(data)
+                .map_err(|_| TcpError::ConnectionReset),
+            _ => Err(TcpError::NotConnected),
+        }
+    }
+
+    /// Closes the writing side of the given socket. Data that has already been queued is still
+    /// sent out.
+    pub fn tcp_shutdown(&mut self, socket_id: u32) -> Result<(), TcpError> {
+        let mut socket = self.tcp_socket(socket_id)?;
+        socket.close();
+        Ok(())
+    }
+
+    /// If true, data is sent out as soon as possible rather than being buffered.
+    pub fn tcp_set_no_delay(&mut self, socket_id: u32, _no_delay: bool) -> Result<(), TcpError> {
+        // smoltcp doesn't implement Nagle's algorithm, and always sends data out as soon as
+        // possible.
+        self.tcp_socket(socket_id)?;
+        Ok(())
+    }
+
+    /// Sets the interval at which keep-alive packets are sent, or disables them.
+    pub fn tcp_set_keep_alive(
+        &mut self,
+        socket_id: u32,
+        interval: Option<Duration>,
+    ) -> Result<(), TcpError> {
+        let mut socket = self.tcp_socket(socket_id)?;
+        socket.set_keep_alive(interval.map(to_smoltcp_duration));
+        Ok(())
+    }
+
+    /// Closes the given socket. The socket ID is no longer valid afterwards.
+    ///
+    /// Closing a listener also closes the connections that haven't been accepted yet.
+    pub fn tcp_close(&mut self, socket_id: u32) {
+        match self.tcp_sockets.remove(&socket_id) {
+            Some(TcpSocketState::Listener {
+                pending, backlog, ..
+            }) => {
+                for (if_id, handle) in pending {
+                    self.interfaces
+                        .get_mut(&if_id)
+                        .unwrap()
+                        .sockets
+                        .remove(handle);
+                }
+                for socket_id in backlog {
+                    self.tcp_close(socket_id);
+                }
+            }
+            Some(TcpSocketState::Stream(stream)) => {
+                self.interfaces
+                    .get_mut(&stream.interface)
+                    .unwrap()
+                    .sockets
+                    .get::<TcpSocket>(stream.handle)
+                    .close();
+                self.closing_sockets.push((stream.interface, stream.handle));
+            }
+            None => {}
+        }
+    }
+
+    /// Opens a UDP socket bound on all the interfaces.
+    ///
+    /// Pass `IpAddress::Unspecified` in order to receive datagrams destined to all the
+    /// addresses. If `port` is 0, a port is automatically assigned.
+    ///
+    /// On success, returns the ID of the socket and the port it's bound to. Returns an error if
+    /// another UDP socket is already bound to the same endpoint.
+    pub fn udp_bind(&mut self, local_ip: IpAddress, port: u16) -> Result<(u32, u16), ()> {
+        let port = if port == 0 {
+            self.allocate_port().ok_or(())?
+        } else {
+            port
+        };
+
+        let in_use = self
+            .udp_sockets
+            .values()
+            .any(|socket| overlaps(&socket.endpoint, &local_ip, port));
+        if in_use {
+            return Err(());
+        }
+
+        let endpoint = IpEndpoint::new(local_ip, port);
+        let mut handles = HashMap::new();
+        for (if_id, interface) in self.interfaces.iter_mut() {
+            let handle = interface.new_udp_socket(endpoint)?;
+            handles.insert(if_id.clone(), handle);
+        }
+
+        let socket_id = self.next_socket_id;
+        self.next_socket_id = self.next_socket_id.wrapping_add(1);
+        self.udp_sockets
+            .insert(socket_id, UdpSocketState { endpoint, handles });
+        Ok((socket_id, port))
+    }
+
+    /// Queues a datagram for sending.
+    ///
+    /// Returns `Ok(false)` if the send buffer is full, in which case the datagram must be sent
+    /// again later. Returns an error if the socket doesn't exist, if no interface is capable of
+    /// reaching the remote, or if the datagram is too large.
+    pub fn udp_send_to(
+        &mut self,
+        socket_id: u32,
+        remote: IpEndpoint,
+        data: &[u8],
+    ) -> Result<bool, ()> {
+        let socket = self.udp_sockets.get(&socket_id).ok_or(())?;
+
+        // TODO: do a proper route lookup
+        let (if_id, handle) = socket
+            .handles
+            .iter()
+            .find(|(if_id, _)| self.interfaces[*if_id].can_reach(&remote.addr))
+            .ok_or(())?;
+
+        let interface = self.interfaces.get_mut(if_id).unwrap();
+        let mut socket = interface.sockets.get::<UdpSocket>(*handle);
+        match socket.send_slice(data, remote) {
+            Ok(()) => Ok(true),
+            Err(smoltcp::Error::Exhausted) => Ok(false),
+            Err(_) => Err(()),
+        }
+    }
+
+    /// Extracts the next datagram received on the given socket, and the address of the sender.
+    ///
+    /// Returns `Ok(None)` if no datagram is available, and an error if the socket doesn't exist.
+    pub fn udp_recv_from(&mut self, socket_id: u32) -> Result<Option<(Vec<u8>, IpEndpoint)>, ()> {
+        let socket = self.udp_sockets.get(&socket_id).ok_or(())?;
+
+        for (if_id, handle) in socket.handles.iter() {
+            let interface = self.interfaces.get_mut(if_id).unwrap();
+            let mut socket = interface.sockets.get::<UdpSocket>(*handle);
+            if let Ok((data, remote)) = socket.recv() {
+                return Ok(Some((data.to_vec(), remote)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Closes the given UDP socket. The socket ID is no longer valid afterwards.
+    pub fn udp_close(&mut self, socket_id: u32) {
+        if let Some(socket) = self.udp_sockets.remove(&socket_id) {
+            for (if_id, handle) in socket.handles {
+                self.interfaces
+                    .get_mut(&if_id)
+                    .unwrap()
+                    .sockets
+                    .remove(handle);
+            }
+        }
+    }
+
+    /// Returns the smoltcp socket of the given stream, or an error if the connection has
+    /// failed.
+    fn tcp_socket(&mut self, socket_id: u32) -> Result<SocketRef<TcpSocket<'static>>, TcpError> {
+        let stream = match self.tcp_sockets.get(&socket_id) {
+            Some(TcpSocketState::Stream(stream)) => stream,
+            _ => return Err(TcpError::InvalidSocket),
+        };
+
+        if let Some(error) = stream.error {
+            return Err(error);
+        }
+
+        let interface = self.interfaces.get_mut(&stream.interface).unwrap();
+        Ok(interface.sockets.get::<TcpSocket>(stream.handle))
+    }
+
+    /// Returns a port to use for a socket whose port hasn't been specified, or `None` if all
+    /// the ephemeral ports are in use.
+    fn allocate_port(&mut self) -> Option<u16> {
+        for _ in EPHEMERAL_PORTS_START..=u16::max_value() {
+            let port = self.next_ephemeral_port;
+            self.next_ephemeral_port = self
+                .next_ephemeral_port
+                .checked_add(1)
+                .unwrap_or(EPHEMERAL_PORTS_START);
+            if !self.port_in_use(port) {
+                return Some(port);
+            }
+        }
+
+        None
+    }
+
+    /// Returns true if a TCP or UDP socket uses the given local port.
+    fn port_in_use(&self, port: u16) -> bool {
+        let tcp = self.tcp_sockets.values().any(|socket| match socket {
+            TcpSocketState::Listener { endpoint, .. } => endpoint.port == port,
+            TcpSocketState::Stream(stream) => stream.local_port == port,
+        });
+        tcp || self
+            .udp_sockets
+            .values()
+            .any(|socket| socket.endpoint.port == port)
+    }
+}
+
+impl Interface {
+    /// Processes the incoming frames and updates the sockets.
+    fn poll(&mut self, now: Instant) {
+        // An error while processing a frame interrupts the polling. Continue as long as there
+        // are frames left to process.
+        while self.ethernet.poll(&mut self.sockets, now).is_err() {
+            if self.ethernet.device().rx.is_empty() {
+                break;
+            }
+        }
+
+        if let Ok(Some(config)) = self.dhcp.poll(&mut self.ethernet, &mut self.sockets, now) {
+            self.apply_dhcp_config(config);
+        }
+    }
+
+    /// Updates the IPv4 configuration of the interface.
+    fn apply_dhcp_config(&mut self, config: Dhcpv4Config) {
+        if let Some(cidr) = config.address {
+            self.ethernet.update_ip_addrs(|addrs| {
+                for addr in addrs.iter_mut() {
+                    if let IpCidr::Ipv4(_) = addr {
+                        *addr = IpCidr::Ipv4(cidr);
+                    }
+                }
+            });
+        }
+
+        if let Some(router) = config.router {
+            let _ = self.ethernet.routes_mut().add_default_ipv4_route(router);
+        }
+    }
+
+    /// Returns true if the interface has an address of the same protocol as `addr`.
+    fn can_reach(&self, addr: &IpAddress) -> bool {
+        self.ethernet
+            .ip_addrs()
+            .iter()
+            .any(|cidr| match (cidr, addr) {
+                (IpCidr::Ipv4(cidr), IpAddress::Ipv4(_)) => !cidr.address().is_unspecified(),
+                (IpCidr::Ipv6(_), IpAddress::Ipv6(_)) => true,
+                _ => false,
+            })
+    }
+
+    /// Adds to the interface a new UDP socket bound to the given endpoint.
+    fn new_udp_socket(&mut self, endpoint: IpEndpoint) -> Result<SocketHandle, ()> {
+        let mut socket = UdpSocket::new(
+            UdpSocketBuffer::new(
+                vec![UdpPacketMetadata::EMPTY; UDP_MAX_DATAGRAMS],
+                vec![0; UDP_BUFFER_SIZE],
+            ),
+            UdpSocketBuffer::new(
+                vec![UdpPacketMetadata::EMPTY; UDP_MAX_DATAGRAMS],
+                vec![0; UDP_BUFFER_SIZE],
+            ),
+        );
+        socket.bind(endpoint).map_err(|_| ())?;
+        Ok(self.sockets.add(socket))
+    }
+
+    /// Adds to the interface a new socket listening on the given endpoint.
+    fn new_listening_socket(&mut self, endpoint: IpEndpoint) -> Result<SocketHandle, ()> {
+        let mut socket = new_tcp_socket();
+        socket.listen(endpoint).map_err(|_| ())?;
+        Ok(self.sockets.add(socket))
+    }
+}
+
+/// Returns true if a socket bound to `endpoint` receives the packets destined to `local_ip`
+/// and `port`, or the other way around.
+fn overlaps(endpoint: &IpEndpoint, local_ip: &IpAddress, port: u16) -> bool {
+    endpoint.port == port
+        && (endpoint.addr.is_unspecified()
+            || local_ip.is_unspecified()
+            || endpoint.addr == *local_ip)
+}
+
+/// Builds a new TCP socket with the default buffers.
+fn new_tcp_socket() -> TcpSocket<'static> {
+    TcpSocket::new(
+        TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        TcpSocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+    )
+}
+
+/// Builds the IPv6 link-local address corresponding to a MAC address, as described in
+/// RFC 4291, appendix A.
+pub fn link_local_address(mac_address: [u8; 6]) -> Ipv6Address {
+    let m = mac_address;
+    Ipv6Address::new(
+        0xfe80,
+        0,
+        0,
+        0,
+        u16::from_be_bytes([m[0] ^ 0x2, m[1]]),
+        u16::from_be_bytes([m[2], 0xff]),
+        u16::from_be_bytes([0xfe, m[3]]),
+        u16::from_be_bytes([m[4], m[5]]),
+    )
+}
+
+fn to_instant(now: Duration) -> Instant {
+    Instant::from_millis(now.as_millis() as i64)
+}
+
+fn to_smoltcp_duration(duration: Duration) -> SmoltcpDuration {
+    SmoltcpDuration::from_millis(duration.as_millis() as u64)
+}
+
+/// Buffers between smoltcp and the network driver.
+struct Device {
+    /// Frames received from the network and waiting to be processed.
+    rx: VecDeque<Vec<u8>>,
+    /// Frames waiting to be sent out.
+    tx: VecDeque<Vec<u8>>,
+}
+
+impl<'a> phy::Device<'a> for Device {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let frame = self.rx.pop_front()?;
+        Some((RxToken(frame), TxToken(&mut self.tx)))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        if self.tx.len() >= MAX_QUEUED_FRAMES {
+            return None;
+        }
+
+        Some(TxToken(&mut self.tx))
+    }
+
+    fn capabilities(&self) -> phy::DeviceCapabilities {
+        let mut caps = phy::DeviceCapabilities::default();
+        caps.max_transmission_unit = 1514;
+        caps
+    }
+}
+
+struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, _: Instant, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        f(&mut self.0)
+    }
+}
+
+struct TxToken<'a>(&'a mut VecDeque<Vec<u8>>);
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _: Instant, len: usize, f: F) -> smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> smoltcp::Result<R>,
+    {
+        let mut frame = vec![0; len];
+        let ret = f(&mut frame)?;
+        self.0.push_back(frame);
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{link_local_address, NetworkManager, EPHEMERAL_PORTS_START};
+    use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
+    use std::time::Duration;
+
+    #[test]
+    fn udp_bind_in_use() {
+        let mut manager = NetworkManager::<u32>::new();
+        let local_ip = IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 1));
+
+        let (socket, port) = manager.udp_bind(IpAddress::Unspecified, 5000).unwrap();
+        assert_eq!(port, 5000);
+        assert!(manager.udp_bind(IpAddress::Unspecified, 5000).is_err());
+        assert!(manager.udp_bind(local_ip, 5000).is_err());
+        assert!(manager.udp_bind(IpAddress::Unspecified, 5001).is_ok());
+
+        manager.udp_close(socket);
+        assert!(manager.udp_bind(local_ip, 5000).is_ok());
+        assert!(manager.udp_bind(IpAddress::Unspecified, 5000).is_err());
+    }
+
+    #[test]
+    fn allocated_ports_skip_used_ones() {
+        let mut manager = NetworkManager::<u32>::new();
+        let unspecified = IpAddress::Unspecified;
+
+        manager
+            .udp_bind(unspecified, EPHEMERAL_PORTS_START)
+            .unwrap();
+        manager
+            .tcp_listen(unspecified, EPHEMERAL_PORTS_START + 1)
+            .unwrap();
+
+        let (_, port) = manager.udp_bind(unspecified, 0).unwrap();
+        assert_eq!(port, EPHEMERAL_PORTS_START + 2);
+        let (_, port) = manager.tcp_listen(unspecified, 0).unwrap();
+        assert_eq!(port, EPHEMERAL_PORTS_START + 3);
+
+        // Allocation wraps around to the start of the range.
+        manager.next_ephemeral_port = u16::max_value();
+        manager.udp_bind(unspecified, u16::max_value()).unwrap();
+        let (_, port) = manager.udp_bind(unspecified, 0).unwrap();
+        assert_eq!(port, EPHEMERAL_PORTS_START + 4);
+    }
+
+    #[test]
+    fn tcp_loopback() {
+        let mac_a = [0x02, 0, 0, 0, 0, 1];
+        let mac_b = [0x02, 0, 0, 0, 0, 2];
+
+        let mut now = Duration::from_secs(0);
+        let mut a = NetworkManager::new();
+        a.register_interface(0, mac_a, now);
+        let mut b = NetworkManager::new();
+        b.register_interface(0, mac_b, now);
+
+        let (listener, port) = b.tcp_listen(IpAddress::Unspecified, 8000).unwrap();
+        assert_eq!(port, 8000);
+        let remote = IpEndpoint::new(link_local_address(mac_b).into(), 8000);
+        let stream = a.tcp_connect(remote, now).unwrap();
+
+        let mut accepted = None;
+        let mut written = false;
+        let mut received = Vec::new();
+
+        for _ in 0..1000 {
+            now += Duration::from_millis(10);
+            a.poll(now);
+            b.poll(now);
+
+            // Plug the two interfaces together.
+            while let Some(frame) = a.read_ethernet_cable_out(&0) {
+                b.inject_interface_data(&0, frame);
+            }
+            while let Some(frame) = b.read_ethernet_cable_out(&0) {
+                a.inject_interface_data(&0, frame);
+            }
+
+            if accepted.is_none() {
+                accepted = b.tcp_accept(listener).unwrap();
+            }
+
+            if !written && a.tcp_open_status(stream) == Ok(true) {
+                assert_eq!(a.tcp_write(stream, b"hello world"), Ok(11));
+                written = true;
+            }
+
+            if let Some(accepted) = accepted {
+                if let Some(data) = b.tcp_read(accepted).unwrap() {
+                    received.extend(data);
+                }
+            }
+
+            if received == b"hello world" {
+                return;
+            }
+        }
+
+        panic!("data never received")
+    }
+}
@@ -57,9 +57,7 @@ impl Transport for TcpConfig {
 
         Ok(Box::pin(
             async move {
-                let listener = redshirt_tcp_interface::TcpListener::bind(&socket_addr)
-                    .await
-                    .map_err(|()| io::Error::from(io::ErrorKind::Other))?;
+                let listener = redshirt_tcp_interface::TcpListener::bind(&socket_addr).await?;
                 let local_addr =
                     ip_to_multiaddr(listener.local_addr().ip(), listener.local_addr().port());
                 println!("Listening on {}", local_addr);
@@ -72,13 +70,15 @@ impl Transport for TcpConfig {
                 let then = stream::unfold(listener, move |mut s| {
                     let local_addr = local_addr.clone();
                     async move {
-                        let (socket, remote_addr) = s.accept().await;
-                        let ev = ListenerEvent::Upgrade {
-                            upgrade: future::ready(Ok(socket)),
-                            local_addr: local_addr.clone(),
-                            remote_addr: ip_to_multiaddr(remote_addr.ip(), remote_addr.port()),
+                        let ev = match s.accept().await {
+                            Ok((socket, remote_addr)) => Ok(ListenerEvent::Upgrade {
+                                upgrade: future::ready(Ok(socket)),
+                                local_addr: local_addr.clone(),
+                                remote_addr: ip_to_multiaddr(remote_addr.ip(), remote_addr.port()),
+                            }),
+                            Err(err) => Err(err),
                         };
-                        Some((Ok(ev), s))
+                        Some((ev, s))
                     }
                 });
 
@@ -89,6 +89,20 @@ impl Transport for TcpConfig {
     }
 
     fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        if let Ok((hostname, port, ipv6)) = multiaddr_to_dns(&addr) {
+            println!("Dialing {}", addr);
+            return Ok(Box::pin(async move {
+                let ips = redshirt_dns_interface::resolve(&hostname)
+                    .await
+                    .map_err(|()| io::Error::new(io::ErrorKind::Other, "DNS resolution failed"))?;
+                let ip = ips
+                    .into_iter()
+                    .find(|ip| ip.is_ipv6() == ipv6)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No matching address"))?;
+                redshirt_tcp_interface::TcpStream::connect(&SocketAddr::new(ip, port)).await
+            }));
+        }
+
         let socket_addr = if let Ok(socket_addr) = multiaddr_to_socketaddr(&addr) {
             if socket_addr.port() == 0 || socket_addr.ip().is_unspecified() {
                 debug!("Instantly refusing dialing {}, as it is invalid", addr);
@@ -103,9 +117,7 @@ impl Transport for TcpConfig {
 
         println!("Dialing {}", addr);
         Ok(Box::pin(async move {
-            redshirt_tcp_interface::TcpStream::connect(&socket_addr)
-                .await
-                .map_err(|()| io::Error::from(io::ErrorKind::Other))
+            redshirt_tcp_interface::TcpStream::connect(&socket_addr).await
         }))
     }
 }
@@ -127,6 +139,24 @@ fn multiaddr_to_socketaddr(addr: &Multiaddr) -> Result<SocketAddr, ()> {
     }
 }
 
+/// Turns a `/dns4/.../tcp/...` or `/dns6/.../tcp/...` multiaddr into a hostname, a port, and
+/// whether an IPv6 address is expected.
+fn multiaddr_to_dns(addr: &Multiaddr) -> Result<(String, u16, bool), ()> {
+    let mut iter = addr.iter();
+    let proto1 = iter.next().ok_or(())?;
+    let proto2 = iter.next().ok_or(())?;
+
+    if iter.next().is_some() {
+        return Err(());
+    }
+
+    match (proto1, proto2) {
+        (Protocol::Dns4(host), Protocol::Tcp(port)) => Ok((host.into_owned(), port, false)),
+        (Protocol::Dns6(host), Protocol::Tcp(port)) => Ok((host.into_owned(), port, true)),
+        _ => Err(()),
+    }
+}
+
 /// Create a [`Multiaddr`] from the given IP address and port number.
 fn ip_to_multiaddr(ip: IpAddr, port: u16) -> Multiaddr {
     let proto = match ip {
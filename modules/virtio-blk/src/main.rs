@@ -0,0 +1,267 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Driver for virtio block devices.
+//!
+//! This program scans the PCI space for virtio block devices. If it finds one, it registers a
+//! new block device towards the handler of the `block` interface, and executes the commands that
+//! the handler sends.
+
+use redshirt_block_interface::{BlockCommandKind, DeviceConfig};
+use redshirt_hardware_interface::malloc;
+use virtio::{
+    pci::{self, DeviceType},
+    queue::{Buffer, Virtqueue},
+};
+
+/// The device is read-only.
+const FEATURE_RO: u32 = 1 << 5;
+
+/// Size of a sector, as defined by the specifications. Unrelated to the actual block size of
+/// the underlying storage.
+const SECTOR_SIZE: u32 = 512;
+
+/// Size of the header that precedes each request.
+const REQUEST_HEADER_LEN: u32 = 16;
+
+/// Maximum number of sectors transferred by a single request. Larger commands are split into
+/// multiple requests, in order to bound the size of the memory allocated for DMA.
+const MAX_REQUEST_SECTORS: u32 = 128;
+
+/// Types of request.
+const REQUEST_TYPE_IN: u32 = 0;
+const REQUEST_TYPE_OUT: u32 = 1;
+
+/// Status written by the device when a request has succeeded.
+const STATUS_OK: u8 = 0;
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    // TODO: only the first device is handled
+    let info = match pci::find_devices(DeviceType::Block)
+        .await
+        .into_iter()
+        .next()
+    {
+        Some(d) => d,
+        None => return,
+    };
+
+    let mut device = match unsafe { pci::Device::init(&info, FEATURE_RO).await } {
+        Ok(d) => d,
+        Err(()) => return,
+    };
+
+    let num_sectors = {
+        let config = device.read_config(0, 8).await;
+        let mut capacity = [0; 8];
+        capacity.copy_from_slice(&config);
+        u64::from_le_bytes(capacity)
+    };
+
+    let mut queue = match device.setup_queue::<()>(0).await {
+        Ok(q) => q,
+        Err(()) => {
+            device.failed();
+            return;
+        }
+    };
+
+    device.driver_ok();
+
+    redshirt_stdout_interface::stdout(format!(
+        "Initialized virtio block device of {} sectors\n",
+        num_sectors
+    ));
+
+    let registration = redshirt_block_interface::register_device(DeviceConfig {
+        sector_size: SECTOR_SIZE,
+        num_sectors,
+        read_only: device.features() & FEATURE_RO != 0,
+    });
+
+    // TODO: commands are executed one by one, while the device could execute several of them
+    //       in parallel
+    loop {
+        let command = registration.next_command().await;
+        let result = match command.kind {
+            BlockCommandKind::Read {
+                sector,
+                num_sectors: count,
+            } => {
+                if !in_bounds(sector, u64::from(count), num_sectors) {
+                    Err(())
+                } else {
+                    read(&mut device, &mut queue, sector, count).await
+                }
+            }
+            BlockCommandKind::Write { sector, data } => {
+                let count = (data.len() / SECTOR_SIZE as usize) as u64;
+                if data.len() % SECTOR_SIZE as usize != 0 || !in_bounds(sector, count, num_sectors)
+                {
+                    Err(())
+                } else {
+                    write(&mut device, &mut queue, sector, data).await
+                }
+            }
+        };
+
+        registration.command_finished(command.command_id, result);
+    }
+}
+
+/// Returns true if the `count` sectors starting at `sector` are within a device of
+/// `num_sectors` sectors.
+fn in_bounds(sector: u64, count: u64, num_sectors: u64) -> bool {
+    sector
+        .checked_add(count)
+        .map_or(false, |end| end <= num_sectors)
+}
+
+/// Reads `count` sectors starting at `sector`, using as many requests as necessary.
+async fn read(
+    device: &mut pci::Device,
+    queue: &mut Virtqueue<()>,
+    sector: u64,
+    count: u32,
+) -> Result<Vec<u8>, ()> {
+    let mut out = Vec::new();
+    let mut done = 0;
+    while done < count {
+        let num = (count - done).min(MAX_REQUEST_SECTORS);
+        let data = execute_request(
+            device,
+            queue,
+            REQUEST_TYPE_IN,
+            sector + u64::from(done),
+            num * SECTOR_SIZE,
+            None,
+        )
+        .await?;
+        out.extend(data);
+        done += num;
+    }
+    Ok(out)
+}
+
+/// Writes `data`, whose length must be a multiple of the sector size, starting at `sector`,
+/// using as many requests as necessary.
+async fn write(
+    device: &mut pci::Device,
+    queue: &mut Virtqueue<()>,
+    sector: u64,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, ()> {
+    let chunk_len = (MAX_REQUEST_SECTORS * SECTOR_SIZE) as usize;
+    for (n, chunk) in data.chunks(chunk_len).enumerate() {
+        execute_request(
+            device,
+            queue,
+            REQUEST_TYPE_OUT,
+            sector + n as u64 * u64::from(MAX_REQUEST_SECTORS),
+            chunk.len() as u32,
+            Some(chunk.to_vec()),
+        )
+        .await?;
+    }
+    Ok(Vec::new())
+}
+
+/// Sends a request to the device and waits for it to be finished.
+///
+/// `data` must be `Some` if and only if this is a write request, in which case its length must
+/// be equal to `len`. `len` must be at most `MAX_REQUEST_SECTORS` sectors. Returns the data that
+/// has been read.
+async fn execute_request(
+    device: &mut pci::Device,
+    queue: &mut Virtqueue<()>,
+    ty: u32,
+    sector: u64,
+    len: u32,
+    data: Option<Vec<u8>>,
+) -> Result<Vec<u8>, ()> {
+    debug_assert!(len <= MAX_REQUEST_SECTORS * SECTOR_SIZE);
+
+    // The header, the data, and the status byte are allocated together.
+    let options = malloc::AllocOptions {
+        below_4gib: true,
+        cache_coherent: true,
+    };
+    let total_len = u64::from(REQUEST_HEADER_LEN) + u64::from(len) + 1;
+    let buffer = malloc::malloc_with_options(total_len, 8, options).await?;
+    let data_address = buffer + u64::from(REQUEST_HEADER_LEN);
+    let status_address = data_address + u64::from(len);
+
+    let mut request = Vec::with_capacity(REQUEST_HEADER_LEN as usize + len as usize);
+    request.extend_from_slice(&ty.to_le_bytes());
+    request.extend_from_slice(&0u32.to_le_bytes());
+    request.extend_from_slice(&sector.to_le_bytes());
+    if let Some(data) = &data {
+        debug_assert_eq!(data.len(), len as usize);
+        request.extend_from_slice(data);
+    }
+
+    unsafe {
+        redshirt_hardware_interface::write(buffer, request);
+        // Initialize the status to an error, in case the device doesn't write it.
+        redshirt_hardware_interface::write(status_address, vec![0xff]);
+
+        let mut buffers = Vec::with_capacity(3);
+        buffers.push(Buffer {
+            address: buffer,
+            len: REQUEST_HEADER_LEN,
+            device_writable: false,
+        });
+        if len != 0 {
+            buffers.push(Buffer {
+                address: data_address,
+                len,
+                device_writable: data.is_none(),
+            });
+        }
+        buffers.push(Buffer {
+            address: status_address,
+            len: 1,
+            device_writable: true,
+        });
+
+        if queue.push(&buffers, ()).is_err() {
+            malloc::free(buffer);
+            return Err(());
+        }
+    }
+
+    device.notify(queue);
+    if device.wait_used(queue).await.is_err() {
+        // The device has been reset, and no longer accesses the buffer.
+        malloc::free(buffer);
+        return Err(());
+    }
+
+    let status = unsafe { virtio::read_memory(status_address, 1).await };
+    let result = if status[0] != STATUS_OK {
+        Err(())
+    } else if data.is_none() {
+        Ok(unsafe { virtio::read_memory(data_address, len as usize).await })
+    } else {
+        Ok(Vec::new())
+    };
+
+    malloc::free(buffer);
+    result
+}
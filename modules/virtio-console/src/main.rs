@@ -0,0 +1,123 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Implements the stdout interface by writing to a virtio console.
+//!
+//! This program scans the PCI space for virtio consoles. If it finds one, it registers itself as
+//! the handler of the stdout interface and writes the messages to the console.
+
+use parity_scale_codec::DecodeAll;
+use redshirt_hardware_interface::malloc;
+use virtio::{
+    pci::{self, DeviceType},
+    queue::Buffer,
+};
+
+/// Index of the queue of data to write to the console.
+const TRANSMIT_QUEUE: u16 = 1;
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    // TODO: only the first device is handled
+    let info = match pci::find_devices(DeviceType::Console)
+        .await
+        .into_iter()
+        .next()
+    {
+        Some(d) => d,
+        None => return,
+    };
+
+    let mut device = match unsafe { pci::Device::init(&info, 0).await } {
+        Ok(d) => d,
+        Err(()) => return,
+    };
+
+    // TODO: the receive queue isn't used, as we have no interface for reading from the console
+    let mut queue = match device.setup_queue::<()>(TRANSMIT_QUEUE).await {
+        Ok(q) => q,
+        Err(()) => {
+            device.failed();
+            return;
+        }
+    };
+
+    device.driver_ok();
+
+    // Registration fails if another program, such as `x86-stdout`, already handles stdout.
+    if redshirt_interface_interface::register_interface(redshirt_stdout_interface::ffi::INTERFACE)
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        let msg = match redshirt_syscalls_interface::next_interface_message().await {
+            redshirt_syscalls_interface::InterfaceOrDestroyed::Interface(m) => m,
+            redshirt_syscalls_interface::InterfaceOrDestroyed::ProcessDestroyed(_) => continue,
+        };
+        assert_eq!(msg.interface, redshirt_stdout_interface::ffi::INTERFACE);
+        let message = match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(redshirt_stdout_interface::ffi::StdoutMessage::Message(m)) => m,
+            Err(_) => continue,
+        };
+
+        // Consoles expect `\r\n` as line endings.
+        let mut data = Vec::with_capacity(message.len());
+        for byte in message.bytes() {
+            match byte {
+                b'\r' => {}
+                b'\n' => data.extend_from_slice(b"\r\n"),
+                b => data.push(b),
+            }
+        }
+        if data.is_empty() {
+            continue;
+        }
+
+        let options = malloc::AllocOptions {
+            below_4gib: true,
+            cache_coherent: true,
+        };
+        let buffer = match malloc::malloc_with_options(data.len() as u64, 8, options).await {
+            Ok(b) => b,
+            Err(()) => continue,
+        };
+
+        let len = data.len() as u32;
+        unsafe {
+            redshirt_hardware_interface::write(buffer, data);
+            let buffers = [Buffer {
+                address: buffer,
+                len,
+                device_writable: false,
+            }];
+            let result = queue.push(&buffers, ());
+            debug_assert!(result.is_ok());
+        }
+
+        device.notify(&queue);
+        let result = device.wait_used(&mut queue).await;
+        // If the device misbehaves, it has been reset and no longer accesses the buffer.
+        malloc::free(buffer);
+        if result.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Driver for virtio network cards.
+//!
+//! This program scans the PCI space for virtio network cards. If it finds one, it registers a
+//! new network interface towards the network manager, and handles the communication between the
+//! network manager and the hardware.
+
+use futures::prelude::*;
+use redshirt_hardware_interface::malloc;
+use virtio::{
+    pci::{self, DeviceType},
+    queue::{Buffer, Virtqueue},
+};
+
+/// The device provides its MAC address in its configuration.
+const FEATURE_MAC: u32 = 1 << 5;
+
+/// Size of the header that precedes each frame in the queues.
+const HEADER_LEN: u32 = 10;
+/// Maximum size of a frame, without the frame check sequence.
+const MAX_FRAME_LEN: u32 = 1514;
+/// Number of buffers to make available to the device for receiving frames.
+const NUM_RX_BUFFERS: usize = 16;
+
+/// Index of the queue of frames received from the network.
+const RECEIVE_QUEUE: u16 = 0;
+/// Index of the queue of frames to send out.
+const TRANSMIT_QUEUE: u16 = 1;
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    // TODO: only the first device is handled
+    let info = match pci::find_devices(DeviceType::Network)
+        .await
+        .into_iter()
+        .next()
+    {
+        Some(d) => d,
+        None => return,
+    };
+
+    let device = match unsafe { pci::Device::init(&info, FEATURE_MAC).await } {
+        Ok(d) => d,
+        Err(()) => return,
+    };
+
+    // TODO: generate a random MAC address instead
+    if device.features() & FEATURE_MAC == 0 {
+        device.failed();
+        return;
+    }
+
+    let mut mac_address = [0; 6];
+    mac_address.copy_from_slice(&device.read_config(0, 6).await);
+
+    let (rx_queue, tx_queue) = match (
+        device.setup_queue(RECEIVE_QUEUE).await,
+        device.setup_queue(TRANSMIT_QUEUE).await,
+    ) {
+        (Ok(rx), Ok(tx)) => (rx, tx),
+        _ => {
+            device.failed();
+            return;
+        }
+    };
+
+    redshirt_stdout_interface::stdout(format!(
+        "Initialized virtio network card with MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\n",
+        mac_address[0],
+        mac_address[1],
+        mac_address[2],
+        mac_address[3],
+        mac_address[4],
+        mac_address[5]
+    ));
+
+    run_device(device, mac_address, rx_queue, tx_queue).await
+}
+
+/// Transfers frames between the device and the network manager. Only returns if the device
+/// misbehaves, in which case it has been reset.
+///
+/// Each element in the queues is a buffer allocated in physical memory, whose address is the
+/// user data of the element.
+async fn run_device(
+    mut device: pci::Device,
+    mac_address: [u8; 6],
+    mut rx_queue: Virtqueue<u64>,
+    mut tx_queue: Virtqueue<u64>,
+) {
+    for _ in 0..NUM_RX_BUFFERS {
+        let buffer = match malloc::malloc_with_options(
+            u64::from(HEADER_LEN + MAX_FRAME_LEN),
+            8,
+            alloc_options(),
+        )
+        .await
+        {
+            Ok(b) => b,
+            Err(()) => break,
+        };
+
+        unsafe {
+            push_rx_buffer(&mut rx_queue, buffer);
+        }
+    }
+
+    device.driver_ok();
+    device.notify(&rx_queue);
+
+    let registration = redshirt_ethernet_interface::register_interface(
+        redshirt_ethernet_interface::InterfaceConfig { mac_address },
+    );
+
+    // Frame to send out obtained from the network manager, but that the device couldn't accept
+    // yet.
+    let mut unsent = None::<Vec<u8>>;
+
+    // Kept alive across iterations, as the underlying message can't be cancelled.
+    let mut next_to_send = Box::pin(registration.packet_to_send());
+
+    loop {
+        // Note that the queues are processed before waiting, in order to process the frames
+        // that might have arrived before the device's interrupt has been subscribed to.
+        let mut received_any = false;
+        loop {
+            let (buffer, written) = match rx_queue.pop_used().await {
+                Ok(Some(used)) => used,
+                Ok(None) => break,
+                Err(()) => {
+                    device.reset();
+                    return;
+                }
+            };
+
+            if written > HEADER_LEN {
+                let frame_len = (written - HEADER_LEN).min(MAX_FRAME_LEN) as usize;
+                let frame =
+                    unsafe { virtio::read_memory(buffer + u64::from(HEADER_LEN), frame_len).await };
+                registration.packet_from_network(frame);
+            }
+
+            unsafe {
+                push_rx_buffer(&mut rx_queue, buffer);
+            }
+            received_any = true;
+        }
+        if received_any {
+            device.notify(&rx_queue);
+        }
+
+        loop {
+            match tx_queue.pop_used().await {
+                Ok(Some((buffer, _))) => malloc::free(buffer),
+                Ok(None) => break,
+                Err(()) => {
+                    device.reset();
+                    return;
+                }
+            }
+        }
+
+        if let Some(frame) = unsent.take() {
+            unsent = send_frame(&device, &mut tx_queue, frame).await.err();
+        }
+
+        if unsent.is_some() {
+            device.wait_event().await;
+            continue;
+        }
+
+        let to_send =
+            match future::select(next_to_send.as_mut(), Box::pin(device.wait_event())).await {
+                future::Either::Left((frame, _)) => Some(frame),
+                future::Either::Right(((), _)) => None,
+            };
+
+        if let Some(frame) = to_send {
+            next_to_send = Box::pin(registration.packet_to_send());
+            unsent = send_frame(&device, &mut tx_queue, frame).await.err();
+        }
+    }
+}
+
+/// Makes the given buffer, of length `HEADER_LEN + MAX_FRAME_LEN`, available to the device for
+/// receiving a frame.
+///
+/// # Safety
+///
+/// `buffer` must have been allocated with the right length, and must not be used by anything
+/// else.
+///
+unsafe fn push_rx_buffer(rx_queue: &mut Virtqueue<u64>, buffer: u64) {
+    // Without the `VIRTIO_F_ANY_LAYOUT` feature, legacy devices expect the header and the frame
+    // to be in separate buffers.
+    let buffers = [
+        Buffer {
+            address: buffer,
+            len: HEADER_LEN,
+            device_writable: true,
+        },
+        Buffer {
+            address: buffer + u64::from(HEADER_LEN),
+            len: MAX_FRAME_LEN,
+            device_writable: true,
+        },
+    ];
+
+    // We never push more buffers than the size of the queue.
+    if rx_queue.push(&buffers, buffer).is_err() {
+        malloc::free(buffer);
+    }
+}
+
+/// Passes a frame to the device for sending out.
+///
+/// Returns back the frame if the queue is full.
+async fn send_frame(
+    device: &pci::Device,
+    tx_queue: &mut Virtqueue<u64>,
+    frame: Vec<u8>,
+) -> Result<(), Vec<u8>> {
+    if tx_queue.num_free_descriptors() < 2 {
+        return Err(frame);
+    }
+
+    // TODO: frames larger than the MTU are silently truncated
+    let frame_len = (frame.len() as u32).min(MAX_FRAME_LEN);
+    let buffer =
+        match malloc::malloc_with_options(u64::from(HEADER_LEN + frame_len), 8, alloc_options())
+            .await
+        {
+            Ok(b) => b,
+            Err(()) => return Err(frame),
+        };
+
+    unsafe {
+        // A header full of zeroes indicates that the device doesn't need to offload anything.
+        let mut data = vec![0; HEADER_LEN as usize];
+        data.extend_from_slice(&frame[..frame_len as usize]);
+        redshirt_hardware_interface::write(buffer, data);
+
+        let buffers = [
+            Buffer {
+                address: buffer,
+                len: HEADER_LEN,
+                device_writable: false,
+            },
+            Buffer {
+                address: buffer + u64::from(HEADER_LEN),
+                len: frame_len,
+                device_writable: false,
+            },
+        ];
+        let result = tx_queue.push(&buffers, buffer);
+        debug_assert!(result.is_ok());
+    }
+
+    device.notify(tx_queue);
+    Ok(())
+}
+
+/// Constraints of the memory passed to the device.
+fn alloc_options() -> malloc::AllocOptions {
+    malloc::AllocOptions {
+        below_4gib: true,
+        cache_coherent: true,
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Driver for virtio entropy sources.
+//!
+//! This program scans the PCI space for virtio entropy devices. If it finds one, it periodically
+//! reads random bytes from it and passes them to the kernel, which mixes them in its random
+//! number generators.
+
+use std::time::Duration;
+use virtio::{
+    pci::{self, DeviceType},
+    queue::Buffer,
+};
+
+/// Number of bytes to request from the device every time.
+const ENTROPY_LEN: u32 = 64;
+
+/// Interval between two requests to the device.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    // TODO: only the first device is handled
+    let info = match pci::find_devices(DeviceType::EntropySource)
+        .await
+        .into_iter()
+        .next()
+    {
+        Some(d) => d,
+        None => return,
+    };
+
+    let mut device = match unsafe { pci::Device::init(&info, 0).await } {
+        Ok(d) => d,
+        Err(()) => return,
+    };
+
+    let mut queue = match device.setup_queue::<()>(0).await {
+        Ok(q) => q,
+        Err(()) => {
+            device.failed();
+            return;
+        }
+    };
+
+    let options = redshirt_hardware_interface::malloc::AllocOptions {
+        below_4gib: true,
+        cache_coherent: true,
+    };
+    let buffer = match redshirt_hardware_interface::malloc::malloc_with_options(
+        u64::from(ENTROPY_LEN),
+        8,
+        options,
+    )
+    .await
+    {
+        Ok(b) => b,
+        Err(()) => {
+            device.failed();
+            return;
+        }
+    };
+
+    device.driver_ok();
+
+    loop {
+        let pushed = unsafe {
+            let buffers = [Buffer {
+                address: buffer,
+                len: ENTROPY_LEN,
+                device_writable: true,
+            }];
+            queue.push(&buffers, ())
+        };
+        assert!(pushed.is_ok());
+        device.notify(&queue);
+
+        let ((), written) = match device.wait_used(&mut queue).await {
+            Ok(used) => used,
+            Err(()) => return,
+        };
+        let written = written.min(ENTROPY_LEN) as usize;
+        if written != 0 {
+            let entropy = unsafe { virtio::read_memory(buffer, written).await };
+            redshirt_random_interface::add_entropy(entropy);
+        }
+
+        redshirt_time_interface::monotonic_wait(REFRESH_INTERVAL).await;
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Common code for the drivers of virtio devices.
+//!
+//! Virtio is a standard for virtual devices, in other words devices emulated by a hypervisor
+//! such as QEMU. Each device communicates with its driver through one or more *virtqueues*,
+//! which are rings of buffers located in physical memory.
+//!
+//! Use [`pci::find_devices`] to find the devices of a certain type, then [`pci::Device::init`]
+//! to initialize one of them and [`pci::Device::setup_queue`] to set up its virtqueues.
+//!
+//! Bibliography:
+//!
+//! - https://docs.oasis-open.org/virtio/virtio/v1.1/virtio-v1.1.html
+//!
+
+pub mod pci;
+pub mod queue;
+
+/// Reads `len` bytes of physical memory starting at `address`.
+pub async unsafe fn read_memory(address: u64, len: usize) -> Vec<u8> {
+    let mut out = vec![0; len];
+    let mut builder = redshirt_hardware_interface::HardwareOperationsBuilder::with_capacity(1);
+    builder.read(address, &mut out);
+    builder.send().await;
+    out
+}
@@ -0,0 +1,293 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Virtio devices connected through PCI.
+//!
+//! Only the *legacy* interface is supported, where the registers of the device are accessed
+//! through I/O ports. QEMU exposes *transitional* devices by default, which support both the
+//! legacy and the modern interfaces.
+// TODO: support the modern interface, whose registers are described by vendor-specific PCI
+//       capabilities
+
+use crate::queue::Virtqueue;
+
+use redshirt_hardware_interface::{
+    interrupts::InterruptSubscription, HardwareOperationsBuilder, HardwareWriteOperationsBuilder,
+};
+use redshirt_pci_interface::{PciBaseAddressRegister, PciDeviceInfo};
+use std::time::Duration;
+
+/// PCI vendor ID of all the virtio devices.
+const VENDOR_ID: u16 = 0x1af4;
+
+/// Offsets of the registers of the legacy interface, relative to the first I/O BAR.
+const DEVICE_FEATURES: u32 = 0x0;
+const DRIVER_FEATURES: u32 = 0x4;
+const QUEUE_ADDRESS: u32 = 0x8;
+const QUEUE_SIZE: u32 = 0xc;
+const QUEUE_SELECT: u32 = 0xe;
+const QUEUE_NOTIFY: u32 = 0x10;
+const DEVICE_STATUS: u32 = 0x12;
+const ISR_STATUS: u32 = 0x13;
+/// Offset of the device-specific configuration, as long as MSI-X is disabled.
+const DEVICE_CONFIG: u32 = 0x14;
+
+/// Bits of the device status register.
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 128;
+
+/// Interval at which to check the queues if interrupts aren't available.
+const POLLING_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Kind of virtio device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeviceType {
+    Network,
+    Block,
+    Console,
+    EntropySource,
+}
+
+impl DeviceType {
+    /// Returns the PCI device ID of the transitional version of this kind of device.
+    fn transitional_device_id(&self) -> u16 {
+        match self {
+            DeviceType::Network => 0x1000,
+            DeviceType::Block => 0x1001,
+            DeviceType::Console => 0x1003,
+            DeviceType::EntropySource => 0x1005,
+        }
+    }
+}
+
+/// Returns the list of PCI devices of the given type that we support.
+pub async fn find_devices(ty: DeviceType) -> Vec<PciDeviceInfo> {
+    redshirt_pci_interface::get_pci_devices()
+        .await
+        .into_iter()
+        .filter(|d| d.vendor_id == VENDOR_ID && d.device_id == ty.transitional_device_id())
+        .collect()
+}
+
+/// Initialized virtio device.
+pub struct Device {
+    /// First I/O port of the registers of the device.
+    io_base: u32,
+    /// Features supported by both the device and the driver.
+    features: u32,
+    /// Subscription to the interrupts of the device, or `None` if we poll the device instead.
+    interrupts: Option<InterruptSubscription>,
+    /// True if the device has been reset because it misbehaved.
+    reset: bool,
+}
+
+impl Device {
+    /// Resets and initializes the given device, and negotiates the features. Only the features
+    /// in `supported_features` can be enabled.
+    ///
+    /// Returns an error if the device doesn't support the legacy interface.
+    ///
+    /// After this function returns, set up the queues of the device with
+    /// [`Device::setup_queue`], then call [`Device::driver_ok`].
+    ///
+    /// # Safety
+    ///
+    /// `info` must describe a virtio device, and only one `Device` must exist at any given time
+    /// for a specific PCI device.
+    ///
+    pub async unsafe fn init(info: &PciDeviceInfo, supported_features: u32) -> Result<Device, ()> {
+        let io_base = match info.base_address_registers.get(0) {
+            Some(Some(PciBaseAddressRegister::Io { base_address, .. })) if *base_address != 0 => {
+                *base_address
+            }
+            _ => return Err(()),
+        };
+
+        redshirt_pci_interface::enable_bus_master(info.location);
+
+        let mut ops = HardwareWriteOperationsBuilder::with_capacity(3);
+        // Writing 0 resets the device.
+        ops.port_write_u8(io_base + DEVICE_STATUS, 0);
+        ops.port_write_u8(io_base + DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+        ops.port_write_u8(io_base + DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        ops.send();
+
+        let mut device_features = 0;
+        let mut builder = HardwareOperationsBuilder::with_capacity(1);
+        builder.port_read_u32(io_base + DEVICE_FEATURES, &mut device_features);
+        builder.send().await;
+
+        let features = device_features & supported_features;
+        let mut ops = HardwareWriteOperationsBuilder::with_capacity(1);
+        ops.port_write_u32(io_base + DRIVER_FEATURES, features);
+        ops.send();
+
+        let interrupts = match info.interrupt_line {
+            Some(irq) => InterruptSubscription::subscribe(u32::from(irq)).await.ok(),
+            None => None,
+        };
+
+        Ok(Device {
+            io_base,
+            features,
+            interrupts,
+            reset: false,
+        })
+    }
+
+    /// Returns the features that have been negotiated.
+    pub fn features(&self) -> u32 {
+        self.features
+    }
+
+    /// Reads `len` bytes of the device-specific configuration, starting at `offset`.
+    pub async fn read_config(&self, offset: u32, len: u32) -> Vec<u8> {
+        let mut out = vec![0; len as usize];
+        let mut builder = HardwareOperationsBuilder::with_capacity(out.len());
+        for (n, byte) in out.iter_mut().enumerate() {
+            unsafe {
+                builder.port_read_u8(self.io_base + DEVICE_CONFIG + offset + n as u32, byte);
+            }
+        }
+        builder.send().await;
+        out
+    }
+
+    /// Allocates the memory of the queue with the given index and passes it to the device.
+    ///
+    /// Returns an error if the device doesn't have this queue or if the allocation fails.
+    pub async fn setup_queue<T>(&self, index: u16) -> Result<Virtqueue<T>, ()> {
+        let mut size = 0;
+        unsafe {
+            let mut builder = HardwareOperationsBuilder::with_capacity(2);
+            builder.port_write_u16(self.io_base + QUEUE_SELECT, index);
+            builder.port_read_u16(self.io_base + QUEUE_SIZE, &mut size);
+            builder.send().await;
+        }
+
+        let queue = Virtqueue::new(index, size).await?;
+
+        // The legacy interface expects the address divided by 4096.
+        let pfn = queue.physical_address() / 4096;
+        if pfn > u64::from(u32::max_value()) {
+            return Err(());
+        }
+
+        unsafe {
+            let mut ops = HardwareWriteOperationsBuilder::with_capacity(2);
+            ops.port_write_u16(self.io_base + QUEUE_SELECT, index);
+            ops.port_write_u32(self.io_base + QUEUE_ADDRESS, pfn as u32);
+            ops.send();
+        }
+
+        Ok(queue)
+    }
+
+    /// Indicates to the device that the driver is ready. Must be called after the queues have
+    /// been set up.
+    pub fn driver_ok(&self) {
+        unsafe {
+            redshirt_hardware_interface::port_write_u8(
+                self.io_base + DEVICE_STATUS,
+                STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+            );
+        }
+    }
+
+    /// Indicates to the device that something went wrong and that the driver gives up.
+    pub fn failed(&self) {
+        unsafe {
+            redshirt_hardware_interface::port_write_u8(self.io_base + DEVICE_STATUS, STATUS_FAILED);
+        }
+    }
+
+    /// Notifies the device that new buffers have been pushed to the given queue.
+    pub fn notify<T>(&self, queue: &Virtqueue<T>) {
+        unsafe {
+            let mut ops = HardwareWriteOperationsBuilder::with_capacity(1);
+            ops.port_write_u16(self.io_base + QUEUE_NOTIFY, queue.index());
+            ops.send();
+        }
+    }
+
+    /// Waits until the device might have processed buffers.
+    ///
+    /// If the device's interrupt has been successfully subscribed to, waits for it to be
+    /// triggered. Otherwise, or if the subscription is no longer active, waits for a small
+    /// amount of time.
+    pub async fn wait_event(&mut self) {
+        let interrupt = match &mut self.interrupts {
+            Some(subscription) => subscription.wait().await,
+            None => Err(()),
+        };
+
+        if interrupt.is_err() {
+            self.interrupts = None;
+            redshirt_time_interface::monotonic_wait(POLLING_INTERVAL).await;
+        }
+
+        // Reading the ISR status acknowledges the interrupt.
+        let mut isr = 0;
+        unsafe {
+            let mut builder = HardwareOperationsBuilder::with_capacity(1);
+            builder.port_read_u8(self.io_base + ISR_STATUS, &mut isr);
+            builder.send().await;
+        }
+    }
+
+    /// Waits until the device has finished processing a chain of buffers of the given queue,
+    /// and returns it. See [`Virtqueue::pop_used`].
+    ///
+    /// If the device misbehaves, it is reset and an error is returned. All the subsequent calls
+    /// return an error as well.
+    pub async fn wait_used<T>(&mut self, queue: &mut Virtqueue<T>) -> Result<(T, u32), ()> {
+        loop {
+            if self.reset {
+                return Err(());
+            }
+
+            match queue.pop_used().await {
+                Ok(Some(used)) => return Ok(used),
+                Ok(None) => {}
+                Err(()) => {
+                    self.reset();
+                    return Err(());
+                }
+            }
+
+            self.wait_event().await;
+        }
+    }
+
+    /// Resets the device, which makes it stop accessing the queues. The device can't be used
+    /// anymore afterwards.
+    ///
+    /// Must be called when the device misbehaves.
+    pub fn reset(&mut self) {
+        self.reset = true;
+        unsafe {
+            redshirt_hardware_interface::port_write_u8(self.io_base + DEVICE_STATUS, 0);
+        }
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        // Resetting the device makes it stop accessing the queues.
+        self.reset();
+    }
+}
@@ -0,0 +1,420 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Virtqueues.
+//!
+//! A virtqueue is made of three parts located in physical memory:
+//!
+//! - The descriptor table, where each descriptor points to a buffer. Descriptors can be chained
+//! together in order to pass multiple buffers at once to the device.
+//! - The available ring, where the driver writes the index of the first descriptor of the
+//! chains that the device must process.
+//! - The used ring, where the device writes the index of the first descriptor of the chains it
+//! has finished processing.
+//!
+//! Since we can't directly access physical memory, all the accesses go through the `hardware`
+//! interface.
+
+use redshirt_hardware_interface::{
+    malloc, HardwareOperationsBuilder, HardwareWriteOperationsBuilder,
+};
+use std::convert::TryFrom as _;
+
+/// Alignment of the used ring, as required by the legacy interface.
+const USED_RING_ALIGNMENT: u64 = 4096;
+
+/// The descriptor continues in the `next` field.
+const DESC_F_NEXT: u16 = 1;
+/// The buffer is written by the device rather than read.
+const DESC_F_WRITE: u16 = 2;
+
+/// Buffer located in physical memory, to pass to the device.
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    /// Physical address of the buffer.
+    pub address: u64,
+    /// Length of the buffer, in bytes.
+    pub len: u32,
+    /// If true, the device writes to the buffer. Otherwise, the device reads from it.
+    pub device_writable: bool,
+}
+
+/// Virtqueue of a device.
+///
+/// The `T` generic is user data attached to each chain of buffers pushed to the queue, and
+/// returned once the device has processed the chain.
+///
+/// The memory of the queue is freed when the `Virtqueue` is dropped. The device must have been
+/// reset beforehand.
+pub struct Virtqueue<T> {
+    /// Index of the queue within the device.
+    index: u16,
+    /// Location of the queue in memory and state of its descriptors.
+    state: QueueState<T>,
+}
+
+/// State of a virtqueue, independent of the way its memory is accessed.
+struct QueueState<T> {
+    /// Number of descriptors of the queue.
+    size: u16,
+    /// Physical address of the descriptor table. The available ring immediately follows.
+    descriptors: u64,
+    /// Physical address of the available ring.
+    available: u64,
+    /// Physical address of the used ring.
+    used: u64,
+    /// List of descriptors that aren't in use.
+    free_descriptors: Vec<u16>,
+    /// Value of the index of the available ring. Incremented every time we push a chain.
+    next_available: u16,
+    /// Value of the index of the used ring the last time we processed an element of this ring.
+    last_used: u16,
+    /// For each descriptor that is the head of a chain passed to the device, the descriptors of
+    /// the chain and the user data.
+    in_flight: Vec<Option<(Vec<u16>, T)>>,
+}
+
+impl<T> Virtqueue<T> {
+    /// Allocates the memory of a new virtqueue.
+    pub(crate) async fn new(index: u16, size: u16) -> Result<Virtqueue<T>, ()> {
+        if size == 0 {
+            return Err(());
+        }
+
+        let options = malloc::AllocOptions {
+            below_4gib: true,
+            cache_coherent: true,
+        };
+        let total_len = QueueState::<T>::memory_len(size);
+        let address = malloc::malloc_with_options(total_len, 4096, options).await?;
+
+        Ok(Virtqueue {
+            index,
+            state: QueueState::new(size, address),
+        })
+    }
+
+    /// Returns the index of the queue within the device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Returns the physical address of the memory of the queue.
+    pub(crate) fn physical_address(&self) -> u64 {
+        self.state.descriptors
+    }
+
+    /// Returns the number of descriptors that aren't in use. Each buffer pushed to the queue
+    /// uses one descriptor.
+    pub fn num_free_descriptors(&self) -> usize {
+        self.state.free_descriptors.len()
+    }
+
+    /// Makes the given buffers available to the device, as a single chain. `user_data` is
+    /// returned by [`Virtqueue::pop_used`] once the device has processed the buffers.
+    ///
+    /// Returns back `user_data` if there aren't enough free descriptors in the queue.
+    ///
+    /// The device must then be notified with [`Device::notify`](crate::pci::Device::notify).
+    ///
+    /// # Safety
+    ///
+    /// The buffers must stay valid until the device has processed them.
+    ///
+    pub unsafe fn push(&mut self, buffers: &[Buffer], user_data: T) -> Result<(), T> {
+        let writes = self.state.push(buffers, user_data)?;
+
+        // The index of the available ring must only be updated after the ring entry has been
+        // written. The operations are performed in order.
+        let mut ops = HardwareWriteOperationsBuilder::with_capacity(writes.len());
+        for (address, data) in writes {
+            ops.write(address, data);
+        }
+        ops.send();
+        Ok(())
+    }
+
+    /// Returns the next chain of buffers that the device has finished processing, alongside
+    /// with the number of bytes that the device has written to the buffers.
+    ///
+    /// Returns `Ok(None)` if the device hasn't finished processing any chain since the previous
+    /// call. Returns an error if the device returned a chain that it doesn't own, in which case
+    /// the device is misbehaving and must be reset.
+    pub async fn pop_used(&mut self) -> Result<Option<(T, u32)>, ()> {
+        let used_index = unsafe {
+            let mut out = [0; 2];
+            let mut builder = HardwareOperationsBuilder::with_capacity(1);
+            builder.read(self.state.used_index_address(), &mut out);
+            builder.send().await;
+            u16::from_le_bytes(out)
+        };
+
+        let element_address = match self.state.next_used_element(used_index) {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+
+        let element = unsafe {
+            let mut out = [0; 8];
+            let mut builder = HardwareOperationsBuilder::with_capacity(1);
+            builder.read(element_address, &mut out);
+            builder.send().await;
+            out
+        };
+
+        self.state.pop_used(element).map(Some)
+    }
+}
+
+impl<T> Drop for Virtqueue<T> {
+    fn drop(&mut self) {
+        malloc::free(self.state.descriptors);
+    }
+}
+
+impl<T> QueueState<T> {
+    /// Returns the number of bytes of memory used by a queue of the given size.
+    fn memory_len(size: u16) -> u64 {
+        let (_, used_offset) = Self::layout(size);
+        let used_len = 6 + 8 * u64::from(size);
+        used_offset + align_up(used_len, USED_RING_ALIGNMENT)
+    }
+
+    /// Returns the offsets of the available ring and of the used ring of a queue of the given
+    /// size, relative to the descriptor table.
+    fn layout(size: u16) -> (u64, u64) {
+        let descriptors_len = 16 * u64::from(size);
+        let available_len = 6 + 2 * u64::from(size);
+        let used_offset = align_up(descriptors_len + available_len, USED_RING_ALIGNMENT);
+        (descriptors_len, used_offset)
+    }
+
+    /// Initializes the state of a queue of the given size, whose memory starts at `address`.
+    fn new(size: u16, address: u64) -> Self {
+        let (available_offset, used_offset) = Self::layout(size);
+        QueueState {
+            size,
+            descriptors: address,
+            available: address + available_offset,
+            used: address + used_offset,
+            free_descriptors: (0..size).rev().collect(),
+            next_available: 0,
+            last_used: 0,
+            in_flight: (0..size).map(|_| None).collect(),
+        }
+    }
+
+    /// Allocates descriptors for the given chain of buffers. Returns the memory writes, as
+    /// addresses and data, that make the chain available to the device when performed in order.
+    ///
+    /// Returns back `user_data` if there aren't enough free descriptors.
+    fn push(&mut self, buffers: &[Buffer], user_data: T) -> Result<Vec<(u64, Vec<u8>)>, T> {
+        if buffers.is_empty() || buffers.len() > self.free_descriptors.len() {
+            return Err(user_data);
+        }
+
+        let chain = (0..buffers.len())
+            .map(|_| self.free_descriptors.pop().unwrap())
+            .collect::<Vec<_>>();
+
+        let mut writes = Vec::with_capacity(buffers.len() + 2);
+        for (n, buffer) in buffers.iter().enumerate() {
+            let mut flags = 0;
+            if buffer.device_writable {
+                flags |= DESC_F_WRITE;
+            }
+            let next = match chain.get(n + 1) {
+                Some(next) => {
+                    flags |= DESC_F_NEXT;
+                    *next
+                }
+                None => 0,
+            };
+
+            let mut descriptor = Vec::with_capacity(16);
+            descriptor.extend_from_slice(&buffer.address.to_le_bytes());
+            descriptor.extend_from_slice(&buffer.len.to_le_bytes());
+            descriptor.extend_from_slice(&flags.to_le_bytes());
+            descriptor.extend_from_slice(&next.to_le_bytes());
+            writes.push((self.descriptors + 16 * u64::from(chain[n]), descriptor));
+        }
+
+        let slot = u64::from(self.next_available % self.size);
+        writes.push((
+            self.available + 4 + 2 * slot,
+            chain[0].to_le_bytes().to_vec(),
+        ));
+        self.next_available = self.next_available.wrapping_add(1);
+        writes.push((
+            self.available + 2,
+            self.next_available.to_le_bytes().to_vec(),
+        ));
+
+        let head = usize::from(chain[0]);
+        debug_assert!(self.in_flight[head].is_none());
+        self.in_flight[head] = Some((chain, user_data));
+        Ok(writes)
+    }
+
+    /// Returns the physical address of the index of the used ring.
+    fn used_index_address(&self) -> u64 {
+        self.used + 2
+    }
+
+    /// Returns the physical address of the next element of the used ring to process, given the
+    /// current value of the index of the used ring. Returns `None` if there is no new element.
+    fn next_used_element(&self, used_index: u16) -> Option<u64> {
+        if used_index == self.last_used {
+            return None;
+        }
+
+        let slot = u64::from(self.last_used % self.size);
+        Some(self.used + 4 + 8 * slot)
+    }
+
+    /// Processes the element of the used ring returned by [`QueueState::next_used_element`],
+    /// given its content. Returns the user data and the number of bytes written by the device.
+    ///
+    /// Returns an error if the element doesn't designate a chain that the device owns.
+    fn pop_used(&mut self, element: [u8; 8]) -> Result<(T, u32), ()> {
+        let id = u32::from_le_bytes([element[0], element[1], element[2], element[3]]);
+        let len = u32::from_le_bytes([element[4], element[5], element[6], element[7]]);
+        self.last_used = self.last_used.wrapping_add(1);
+
+        let (chain, user_data) = usize::try_from(id)
+            .ok()
+            .and_then(|id| self.in_flight.get_mut(id))
+            .and_then(|entry| entry.take())
+            .ok_or(())?;
+        self.free_descriptors.extend(chain);
+        Ok((user_data, len))
+    }
+}
+
+/// Rounds `value` up to a multiple of `alignment`.
+fn align_up(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) / alignment * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Buffer, QueueState, DESC_F_NEXT, DESC_F_WRITE};
+
+    fn buffer(address: u64, device_writable: bool) -> Buffer {
+        Buffer {
+            address,
+            len: 64,
+            device_writable,
+        }
+    }
+
+    /// Builds the content of an element of the used ring.
+    fn used_element(id: u32, len: u32) -> [u8; 8] {
+        let mut out = [0; 8];
+        out[..4].copy_from_slice(&id.to_le_bytes());
+        out[4..].copy_from_slice(&len.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn descriptors_alloc_free() {
+        let mut queue = QueueState::new(4, 0x10000);
+        assert_eq!(queue.free_descriptors.len(), 4);
+
+        let buffers = [
+            buffer(0xa000, false),
+            buffer(0xb000, true),
+            buffer(0xc000, true),
+        ];
+        let writes = queue.push(&buffers, "first").unwrap();
+        assert_eq!(queue.free_descriptors.len(), 1);
+
+        // Three descriptors, then the available ring entry, then the available ring index.
+        assert_eq!(writes.len(), 5);
+        let mut chain = Vec::new();
+        for (n, (address, descriptor)) in writes[..3].iter().enumerate() {
+            assert_eq!((address - 0x10000) % 16, 0);
+            chain.push(((address - 0x10000) / 16) as u16);
+            assert_eq!(descriptor[..8], buffers[n].address.to_le_bytes());
+            assert_eq!(descriptor[8..12], 64u32.to_le_bytes());
+            let flags = u16::from_le_bytes([descriptor[12], descriptor[13]]);
+            assert_eq!(flags & DESC_F_WRITE != 0, buffers[n].device_writable);
+            assert_eq!(flags & DESC_F_NEXT != 0, n != 2);
+        }
+        for n in 0..2 {
+            let next = u16::from_le_bytes([writes[n].1[14], writes[n].1[15]]);
+            assert_eq!(next, chain[n + 1]);
+        }
+        assert_eq!(
+            writes[3],
+            (queue.available + 4, chain[0].to_le_bytes().to_vec())
+        );
+        assert_eq!(writes[4], (queue.available + 2, vec![1, 0]));
+
+        // Not enough descriptors left.
+        let buffers = [buffer(0xd000, false), buffer(0xe000, false)];
+        assert_eq!(queue.push(&buffers, "second"), Err("second"));
+        assert!(queue.push(&[], "empty").is_err());
+        queue.push(&[buffer(0xd000, false)], "second").unwrap();
+        assert!(queue.free_descriptors.is_empty());
+
+        // Only the head of a chain can be returned by the device.
+        assert!(queue.next_used_element(1).is_some());
+        assert!(queue
+            .pop_used(used_element(u32::from(chain[1]), 0))
+            .is_err());
+        assert!(queue.pop_used(used_element(4, 0)).is_err());
+
+        assert_eq!(
+            queue.pop_used(used_element(u32::from(chain[0]), 12)),
+            Ok(("first", 12))
+        );
+        assert_eq!(queue.free_descriptors.len(), 3);
+        // A chain can't be returned twice.
+        assert!(queue
+            .pop_used(used_element(u32::from(chain[0]), 12))
+            .is_err());
+
+        let buffers = [
+            buffer(0xa000, false),
+            buffer(0xb000, true),
+            buffer(0xc000, true),
+        ];
+        assert!(queue.push(&buffers, "third").is_ok());
+        assert!(queue.free_descriptors.is_empty());
+    }
+
+    #[test]
+    fn rings_wrap_around() {
+        let mut queue = QueueState::new(4, 0x10000);
+
+        // Goes around the rings many times, and past the wrap-around of the 16 bits indices.
+        for n in 0..70000u32 {
+            let writes = queue.push(&[buffer(0xa000, true)], n).unwrap();
+            let head = ((writes[0].0 - 0x10000) / 16) as u32;
+            let slot = u64::from(n % 4);
+            assert_eq!(writes[1].0, queue.available + 4 + 2 * slot);
+            assert_eq!(writes[2].1, ((n + 1) as u16).to_le_bytes().to_vec());
+
+            assert_eq!(queue.next_used_element(n as u16), None);
+            let element = queue.next_used_element((n + 1) as u16).unwrap();
+            assert_eq!(element, queue.used + 4 + 8 * slot);
+            assert_eq!(queue.pop_used(used_element(head, 7)), Ok((n, 7)));
+            assert_eq!(queue.next_used_element((n + 1) as u16), None);
+        }
+
+        assert_eq!(queue.free_descriptors.len(), 4);
+    }
+}
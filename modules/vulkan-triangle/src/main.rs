@@ -97,7 +97,7 @@ fn main() {
     let surface = WindowBuilder::new().build_vk_surface(&events_loop, instance.clone()).unwrap();
     let window = surface.window();*/
     let window =
-        redshirt_syscalls_interface::block_on(redshirt_window_interface::Window::open()).unwrap();
+        redshirt_syscalls_interface::block_on(redshirt_window_interface::Window::open("vulkan-triangle", 1024, 768)).unwrap();
 
     // The next step is to choose which GPU queue will execute our draw commands.
     //
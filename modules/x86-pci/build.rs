@@ -28,6 +28,7 @@ fn main() {
 
     let mut current_vendor_id = None::<u16>;
     let mut current_vendor_name = None;
+    let mut vendors = Vec::new();
 
     let vendor_regex = Regex::new(r"^(\w{4})  (.*)$").unwrap();
     let device_regex = Regex::new(r"^\t(\w{4})  (.*)$").unwrap();
@@ -51,6 +52,7 @@ fn main() {
         } else if let Some(regex_match) = vendor_regex.captures(line) {
             current_vendor_id = Some(u16::from_str_radix(regex_match.get(1).unwrap().as_str(), 16).unwrap());
             current_vendor_name = Some(regex_match.get(2).unwrap().as_str().to_string());
+            vendors.push((current_vendor_id.unwrap(), current_vendor_name.clone().unwrap()));
 
         } else if !line.is_empty() && !line.starts_with("\t\t") {
             write!(f, r##"
@@ -63,4 +65,20 @@ fn main() {
             ].iter().cloned().collect()
         }}
     "#).unwrap();
+
+    write!(f, r#"
+        fn build_vendors_info() -> hashbrown::HashMap<u16, &'static str> {{
+            [
+    "#).unwrap();
+
+    for (vendor_id, vendor_name) in vendors {
+        write!(f, r##"
+                (0x{:x}, r#"{}"#),
+            "##, vendor_id, vendor_name).unwrap();
+    }
+
+    write!(f, r#"
+            ].iter().cloned().collect()
+        }}
+    "#).unwrap();
 }
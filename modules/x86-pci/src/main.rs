@@ -16,11 +16,15 @@
 //! Implements the PCI interface.
 //!
 //! See https://en.wikipedia.org/wiki/PCI_configuration_space
-
-// TODO: support Enhanced Configuration Access Mechanism (ECAM)
+//!
+//! The configuration space is accessed through the Enhanced Configuration Access Mechanism
+//! (ECAM) if the kernel reports memory regions for it, and through the legacy I/O ports
+//! `0xcf8` and `0xcfc` otherwise.
 
 use parity_scale_codec::DecodeAll;
-use std::{borrow::Cow, convert::TryFrom as _};
+use redshirt_pci_interface::ffi::{PciMessage, PciDeviceLocation};
+use redshirt_syscalls_interface::Pid;
+use std::{collections::{HashMap, HashSet}, convert::TryFrom as _, future::Future, pin::Pin};
 
 include!(concat!(env!("OUT_DIR"), "/build-pci.rs"));
 
@@ -32,119 +36,456 @@ async fn async_main() {
     redshirt_interface_interface::register_interface(redshirt_pci_interface::ffi::INTERFACE)
         .await.unwrap();
 
+    // Regions that can't be claimed are ignored, in which case the legacy I/O ports are used.
+    let mut ecam_regions = Vec::new();
+    for region in redshirt_hardware_interface::pci_ecam_regions().await {
+        // TODO: support other segment groups
+        if region.segment_group != 0 || region.end_bus < region.start_bus {
+            continue;
+        }
+
+        let len = u64::from(region.end_bus - region.start_bus + 1) << 20;
+        if redshirt_hardware_interface::claim_mmio(region.base_address, len).await.is_ok() {
+            ecam_regions.push(region);
+        }
+    }
+
+    let config = ConfigAccess { ecam_regions };
+
     let devices = unsafe {
-        read_pci_devices().await
+        read_pci_devices(&config).await
     };
 
+    for device in &devices {
+        redshirt_stdout_interface::stdout(format!(
+            "PCI device: {} - {}\n",
+            device.vendor_name.as_ref().map(|n| n.as_str()).unwrap_or("Unknown"),
+            device.device_name.as_ref().map(|n| n.as_str()).unwrap_or("Unknown")
+        ));
+    }
+
+    let locations = devices.iter().map(|d| d.location).collect::<HashSet<_>>();
+
+    // Process that has reconfigured each device. Other processes aren't allowed to modify the
+    // device until the owner is destroyed.
+    let mut owners = HashMap::<PciDeviceLocation, Pid>::new();
+
     loop {
         let msg = match redshirt_syscalls_interface::next_interface_message().await {
             redshirt_syscalls_interface::InterfaceOrDestroyed::Interface(m) => m,
-            redshirt_syscalls_interface::InterfaceOrDestroyed::ProcessDestroyed(_) => continue,
+            redshirt_syscalls_interface::InterfaceOrDestroyed::ProcessDestroyed(p) => {
+                owners.retain(|_, pid| *pid != p.pid);
+                continue;
+            }
         };
         assert_eq!(msg.interface, redshirt_pci_interface::ffi::INTERFACE);
-        let redshirt_pci_interface::ffi::PciMessage::GetDevicesList =
-            DecodeAll::decode_all(&msg.actual_data).unwrap();       // TODO: don't unwrap
-        redshirt_syscalls_interface::emit_answer(msg.message_id.unwrap(), &redshirt_pci_interface::ffi::GetDevicesListResponse {
-            devices: devices.clone(),
-        });
+
+        let msg_data: PciMessage = match DecodeAll::decode_all(&msg.actual_data) {
+            Ok(m) => m,
+            Err(_) => continue,     // TODO: answer with an error
+        };
+
+        match msg_data {
+            PciMessage::GetDevicesList => {
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_answer(message_id, &redshirt_pci_interface::ffi::GetDevicesListResponse {
+                        devices: devices.clone(),
+                    });
+                }
+            }
+            PciMessage::ReadConfig { location, offset } => {
+                let result = if locations.contains(&location) && config.is_valid_offset(location.bus, offset) {
+                    Ok(unsafe { config.read_u32(location.bus, location.device, location.function, offset).await })
+                } else {
+                    Err(())
+                };
+                if let Some(message_id) = msg.message_id {
+                    redshirt_syscalls_interface::emit_answer(message_id, &result);
+                }
+            }
+            PciMessage::WriteConfig { location, offset, value } => {
+                if locations.contains(&location) && config.is_valid_offset(location.bus, offset)
+                    && claim(&mut owners, location, msg.emitter_pid)
+                {
+                    unsafe { config.write_u32(location.bus, location.device, location.function, offset, value); }
+                }
+            }
+            PciMessage::EnableBusMaster(location) => {
+                if locations.contains(&location) && claim(&mut owners, location, msg.emitter_pid) {
+                    unsafe {
+                        let command = config.read_u32(location.bus, location.device, location.function, 0x4).await;
+                        // The upper 16 bits are the status register, whose bits are cleared by
+                        // writing 1. We write 0 to them in order to not modify them.
+                        config.write_u32(location.bus, location.device, location.function, 0x4, (command & 0xffff) | (1 << 2));
+                    }
+                }
+            }
+        }
     }
 }
 
-lazy_static::lazy_static! {
-    static ref PCI_DEVICES: hashbrown::HashMap<(u16, u16), (&'static str, &'static str)> = build_pci_info();
+/// Makes `pid` the owner of the device at `location` if it doesn't have one yet. Returns `false`
+/// if the device is owned by a different process.
+fn claim(owners: &mut HashMap<PciDeviceLocation, Pid>, location: PciDeviceLocation, pid: Pid) -> bool {
+    *owners.entry(location).or_insert(pid) == pid
 }
 
-async unsafe fn read_pci_devices() -> Vec<redshirt_pci_interface::PciDeviceInfo> {
+/// Enumerates all the PCI devices of the system.
+async unsafe fn read_pci_devices(config: &ConfigAccess) -> Vec<redshirt_pci_interface::PciDeviceInfo> {
     // https://wiki.osdev.org/PCI
-    let pci_devices = build_pci_info();
-    read_bus_pci_devices(0).await
-}
+    let names = (build_vendors_info(), build_pci_info());
 
-async unsafe fn read_bus_pci_devices(bus_idx: u8) -> Vec<redshirt_pci_interface::PciDeviceInfo> {
     let mut out = Vec::new();
+    let mut visited_buses = HashSet::new();
 
-    for device_idx in 0 .. 32 {
-        for func_idx in 0 .. 8 {    // TODO: check function 0 only first
-            let (vendor_id, device_id) = {
-                let vendor_device = pci_cfg_read_u32(bus_idx, device_idx, func_idx, 0).await;
-                let vendor_id = u16::try_from(vendor_device & 0xffff).unwrap();
-                let device_id = u16::try_from(vendor_device >> 16).unwrap();
-                (vendor_id, device_id)
-            };
+    // If the host bridge is a multi-function device, each function is a separate host
+    // controller responsible for the bus of the same number.
+    let host_header_ty = (config.read_u32(0, 0, 0, 0xc).await >> 16) & 0xff;
+    if (host_header_ty & 0x80) == 0 {
+        read_bus_pci_devices(config, 0, &names, &mut visited_buses, &mut out).await;
+    } else {
+        for func_idx in 0 .. 8 {
+            if (config.read_u32(0, 0, func_idx, 0).await & 0xffff) == 0xffff {
+                continue;
+            }
+            read_bus_pci_devices(config, func_idx, &names, &mut visited_buses, &mut out).await;
+        }
+    }
+
+    out
+}
+
+/// Names of the vendors and devices, as generated by the build script.
+type Names = (hashbrown::HashMap<u16, &'static str>, hashbrown::HashMap<(u16, u16), (&'static str, &'static str)>);
 
-            if vendor_id == 0xffff {
+/// Enumerates the devices of the given bus and pushes them to `out`. Buses behind PCI-to-PCI
+/// bridges are enumerated as well.
+// Note: this function isn't `async` because it is recursive, which requires boxing the `Future`.
+unsafe fn read_bus_pci_devices<'a>(
+    config: &'a ConfigAccess,
+    bus_idx: u8,
+    names: &'a Names,
+    visited_buses: &'a mut HashSet<u8>,
+    out: &'a mut Vec<redshirt_pci_interface::PciDeviceInfo>
+) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+    Box::pin(async move {
+        // Protects against misconfigured bridges that would make us loop forever.
+        if !visited_buses.insert(bus_idx) {
+            return;
+        }
+
+        for device_idx in 0 .. 32 {
+            if (config.read_u32(bus_idx, device_idx, 0, 0).await & 0xffff) == 0xffff {
                 continue;
             }
 
-            let (_bist, header_ty, latency, cache_line) = {
-                let val = pci_cfg_read_u32(bus_idx, device_idx, func_idx, 0xc).await;
-                let bytes = val.to_be_bytes();
-                (bytes[0], bytes[1], bytes[2], bytes[3])
-            };
+            // Functions other than 0 only exist if the device is multi-function.
+            let multifunction = (config.read_u32(bus_idx, device_idx, 0, 0xc).await & (0x80 << 16)) != 0;
+            let num_functions = if multifunction { 8 } else { 1 };
+
+            for func_idx in 0 .. num_functions {
+                let location = PciDeviceLocation { bus: bus_idx, device: device_idx, function: func_idx };
+                let device = match read_device(config, location, names).await {
+                    Some(d) => d,
+                    None => continue,
+                };
+
+                // PCI-to-PCI bridge. The devices behind it are on its secondary bus.
+                let secondary_bus = if device.class_code == 0x6 && device.subclass == 0x4 {
+                    Some(((config.read_u32(bus_idx, device_idx, func_idx, 0x18).await >> 8) & 0xff) as u8)
+                } else {
+                    None
+                };
+
+                out.push(device);
+
+                if let Some(secondary_bus) = secondary_bus {
+                    read_bus_pci_devices(config, secondary_bus, names, visited_buses, out).await;
+                }
+            }
+        }
+    })
+}
+
+/// Reads the information about the device function at the given location. Returns `None` if
+/// there's no device there.
+async unsafe fn read_device(config: &ConfigAccess, location: PciDeviceLocation, names: &Names) -> Option<redshirt_pci_interface::PciDeviceInfo> {
+    let PciDeviceLocation { bus, device, function } = location;
+
+    let (vendor_id, device_id) = {
+        let vendor_device = config.read_u32(bus, device, function, 0).await;
+        let vendor_id = u16::try_from(vendor_device & 0xffff).unwrap();
+        let device_id = u16::try_from(vendor_device >> 16).unwrap();
+        (vendor_id, device_id)
+    };
+
+    if vendor_id == 0xffff {
+        return None;
+    }
+
+    let (class_code, subclass, prog_if, revision_id) = {
+        let val = config.read_u32(bus, device, function, 0x8).await;
+        let bytes = val.to_be_bytes();
+        (bytes[0], bytes[1], bytes[2], bytes[3])
+    };
+
+    let header_ty = {
+        let val = config.read_u32(bus, device, function, 0xc).await;
+        val.to_le_bytes()[2] & 0x7f
+    };
 
-            let (vendor_name, device_name) = match PCI_DEVICES.get(&(vendor_id, device_id)) {
-                Some((v, d)) => (Cow::Borrowed(*v), Cow::Borrowed(*d)),
-                None => (
-                    Cow::Owned(format!("Unknown <0x{:x}>", vendor_id)),
-                    Cow::Owned(format!("Unknown <0x{:x}>", device_id))
-                ),
+    let (vendor_name, device_name) = match names.1.get(&(vendor_id, device_id)) {
+        Some((v, d)) => (Some(v.to_string()), Some(d.to_string())),
+        None => (names.0.get(&vendor_id).map(|v| v.to_string()), None),
+    };
+
+    // Number of BARs depends on the header type. Type 0 is a regular device, type 1 a
+    // PCI-to-PCI bridge, and type 2 a CardBus bridge.
+    let num_bars = match header_ty {
+        0x0 => 6,
+        0x1 => 2,
+        _ => 0,
+    };
+
+    let (interrupt_line, interrupt_pin) = {
+        let val = config.read_u32(bus, device, function, 0x3c).await;
+        let bytes = val.to_le_bytes();
+        // A pin of 0 means that the device doesn't use interrupts, and a line of 0xff
+        // means that the firmware hasn't routed the interrupt.
+        let (line, pin) = (bytes[0], bytes[1]);
+        if pin != 0 && line != 0xff {
+            (Some(line), Some(pin))
+        } else if pin != 0 {
+            (None, Some(pin))
+        } else {
+            (None, None)
+        }
+    };
+
+    Some(redshirt_pci_interface::PciDeviceInfo {
+        location,
+        vendor_id,
+        device_id,
+        vendor_name,
+        device_name,
+        class_code,
+        subclass,
+        prog_if,
+        revision_id,
+        base_address_registers: read_bars(config, location, num_bars).await,
+        interrupt_line,
+        interrupt_pin,
+        capabilities: if header_ty <= 0x1 { read_capabilities(config, location).await } else { Vec::new() },
+    })
+}
+
+/// Reads the base address registers of the device and determines their size.
+async unsafe fn read_bars(config: &ConfigAccess, location: PciDeviceLocation, num_bars: u8) -> Vec<Option<redshirt_pci_interface::PciBaseAddressRegister>> {
+    let PciDeviceLocation { bus, device, function } = location;
+
+    // Disable the I/O and memory decoding while we size the BARs, otherwise writing all 1s to
+    // a BAR might make the device answer accesses that target other devices.
+    let command = config.read_u32(bus, device, function, 0x4).await & 0xffff;
+    config.write_u32(bus, device, function, 0x4, command & !0b11);
+
+    let mut list = Vec::with_capacity(usize::from(num_bars));
+    let mut bar_n = 0;
+    while bar_n < num_bars {
+        let offset = 0x10 + u16::from(bar_n) * 0x4;
+        let bar = config.read_u32(bus, device, function, offset).await;
+
+        // Write all 1s, and read back the value. The bits that remain 0 indicate the size.
+        config.write_u32(bus, device, function, offset, 0xffffffff);
+        let size_mask = config.read_u32(bus, device, function, offset).await;
+        config.write_u32(bus, device, function, offset, bar);
+
+        if (bar & 0x1) == 0 {
+            let prefetchable = (bar & (1 << 3)) != 0;
+            let is_64bits = ((bar >> 1) & 0b11) == 0b10 && bar_n + 1 < num_bars;
+
+            let (base_address, size) = if is_64bits {
+                let offset_hi = offset + 0x4;
+                let bar_hi = config.read_u32(bus, device, function, offset_hi).await;
+                config.write_u32(bus, device, function, offset_hi, 0xffffffff);
+                let size_mask_hi = config.read_u32(bus, device, function, offset_hi).await;
+                config.write_u32(bus, device, function, offset_hi, bar_hi);
+
+                let base_address = (u64::from(bar_hi) << 32) | u64::from(bar & !0b1111);
+                let size_mask = (u64::from(size_mask_hi) << 32) | u64::from(size_mask & !0b1111);
+                (base_address, (!size_mask).wrapping_add(1))
+            } else {
+                let size_mask = size_mask & !0b1111;
+                (u64::from(bar & !0b1111), u64::from((!size_mask).wrapping_add(1)))
             };
 
-            let class_code = pci_cfg_read_u32(bus_idx, device_idx, func_idx, 0x8).await;
-
-            out.push(redshirt_pci_interface::PciDeviceInfo {
-                vendor_id,
-                device_id,
-                base_address_registers: {
-                    let mut list = Vec::with_capacity(6);
-                    for bar_n in 0..6 {
-                        let bar = pci_cfg_read_u32(bus_idx, device_idx, func_idx, 0x10 + bar_n * 0x4).await;
-                        list.push(if (bar & 0x1) == 0 {
-                            let prefetchable = (bar & (1 << 3)) != 0;
-                            let base_address = bar & !0b1111;
-                            redshirt_pci_interface::PciBaseAddressRegister::Memory {
-                                base_address,
-                                prefetchable,
-                            }
-                        } else {
-                            let base_address = bar & !0b11;
-                            redshirt_pci_interface::PciBaseAddressRegister::Io {
-                                base_address,
-                            }
-                        });
-                    }
-                    list
-                },
+            list.push(if size_mask != 0 {
+                Some(redshirt_pci_interface::PciBaseAddressRegister::Memory {
+                    base_address,
+                    size,
+                    prefetchable,
+                    is_64bits,
+                })
+            } else {
+                None
             });
 
-            redshirt_stdout_interface::stdout(format!("PCI device: {} - {}\n", vendor_name, device_name));
+            if is_64bits {
+                list.push(None);
+                bar_n += 1;
+            }
+        } else {
+            // Only the lower 16 bits of I/O BARs are meaningful on x86.
+            let size_mask = (size_mask & !0b11) | 0xffff0000;
+            list.push(if size_mask != 0xffff0000 {
+                Some(redshirt_pci_interface::PciBaseAddressRegister::Io {
+                    base_address: bar & !0b11,
+                    size: (!size_mask).wrapping_add(1),
+                })
+            } else {
+                None
+            });
+        }
+
+        bar_n += 1;
+    }
+
+    config.write_u32(bus, device, function, 0x4, command);
+    list
+}
+
+/// Walks the capabilities list of the device.
+async unsafe fn read_capabilities(config: &ConfigAccess, location: PciDeviceLocation) -> Vec<redshirt_pci_interface::PciCapability> {
+    let PciDeviceLocation { bus, device, function } = location;
+
+    let mut out = Vec::new();
+
+    // Bit 4 of the status register indicates whether the capabilities list is present.
+    let status = config.read_u32(bus, device, function, 0x4).await >> 16;
+    if (status & (1 << 4)) == 0 {
+        return out;
+    }
+
+    let mut offset = (config.read_u32(bus, device, function, 0x34).await & 0xfc) as u8;
 
-            // TODO: wrong; need to enumerate other PCI buses
+    // There can't be more than 48 capabilities in the 192 bytes after the header. Limiting the
+    // number of iterations protects us against loops in the list.
+    for _ in 0 .. 48 {
+        if offset < 0x40 {
+            break;
         }
+
+        let header = config.read_u32(bus, device, function, u16::from(offset)).await;
+        let id = (header & 0xff) as u8;
+        let next = ((header >> 8) & 0xfc) as u8;
+        let message_control = (header >> 16) as u16;
+
+        out.push(match id {
+            0x05 => redshirt_pci_interface::PciCapability::Msi {
+                offset,
+                is_64bits: (message_control & (1 << 7)) != 0,
+                max_vectors: 1 << ((message_control >> 1) & 0b111).min(5),
+                per_vector_masking: (message_control & (1 << 8)) != 0,
+            },
+            0x11 if offset <= 0xf4 => {
+                let table = config.read_u32(bus, device, function, u16::from(offset) + 4).await;
+                let pending = config.read_u32(bus, device, function, u16::from(offset) + 8).await;
+                redshirt_pci_interface::PciCapability::MsiX {
+                    offset,
+                    table_size: (message_control & 0x7ff) + 1,
+                    table_bar: (table & 0b111) as u8,
+                    table_offset: table & !0b111,
+                    pending_bar: (pending & 0b111) as u8,
+                    pending_offset: pending & !0b111,
+                }
+            },
+            id => redshirt_pci_interface::PciCapability::Other { id, offset },
+        });
+
+        offset = next;
     }
 
     out
 }
 
-// TODO: ensure endianess? PCI is always little endian, but what if we're on a BE platform?
-async unsafe fn pci_cfg_read_u32(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
+/// Access to the configuration space of the PCI devices.
+struct ConfigAccess {
+    /// Memory regions where the configuration space is mapped. If a bus isn't covered by any
+    /// of these regions, the legacy I/O ports are used.
+    ecam_regions: Vec<redshirt_hardware_interface::ffi::PciEcamRegion>,
+}
+
+impl ConfigAccess {
+    /// Returns the physical memory address of the configuration space of the given function, if
+    /// it is accessible through ECAM.
+    fn ecam_address(&self, bus: u8, slot: u8, func: u8) -> Option<u64> {
+        let region = self.ecam_regions.iter().find(|r| bus >= r.start_bus && bus <= r.end_bus)?;
+        Some(region.base_address +
+            (u64::from(bus - region.start_bus) << 20) +
+            (u64::from(slot) << 15) +
+            (u64::from(func) << 12))
+    }
+
+    /// Returns true if `offset` can be accessed for the given bus. The legacy I/O ports only give
+    /// access to the first 256 bytes of the configuration space, while ECAM gives access to 4kiB.
+    fn is_valid_offset(&self, bus: u8, offset: u16) -> bool {
+        let max = if self.ecam_regions.iter().any(|r| bus >= r.start_bus && bus <= r.end_bus) {
+            4096
+        } else {
+            256
+        };
+
+        offset < max && offset % 4 == 0
+    }
+
+    // TODO: ensure endianess? PCI is always little endian, but what if we're on a BE platform?
+    async unsafe fn read_u32(&self, bus: u8, slot: u8, func: u8, offset: u16) -> u32 {
+        assert!(self.is_valid_offset(bus, offset));
+
+        if let Some(address) = self.ecam_address(bus, slot, func) {
+            let mut out = [0];
+            let mut operations_builder = redshirt_hardware_interface::HardwareOperationsBuilder::new();
+            operations_builder.read_u32(address + u64::from(offset), &mut out);
+            operations_builder.send().await;
+            return out[0];
+        }
+
+        let offset = u8::try_from(offset).unwrap();
+        let mut operations_builder = redshirt_hardware_interface::HardwareOperationsBuilder::new();
+        operations_builder.port_write_u32(0xcf8, config_address(bus, slot, func, offset));
+        let mut out = 0;
+        // TODO: is it correct to immediately read back afterwards without delay? seems weird to me
+        operations_builder.port_read_u32(0xcfc, &mut out);
+        operations_builder.send().await;
+        out
+    }
+
+    unsafe fn write_u32(&self, bus: u8, slot: u8, func: u8, offset: u16, value: u32) {
+        assert!(self.is_valid_offset(bus, offset));
+
+        let mut operations_builder = redshirt_hardware_interface::HardwareWriteOperationsBuilder::new();
+        if let Some(address) = self.ecam_address(bus, slot, func) {
+            operations_builder.write_one_u32(address + u64::from(offset), value);
+        } else {
+            let offset = u8::try_from(offset).unwrap();
+            operations_builder.port_write_u32(0xcf8, config_address(bus, slot, func, offset));
+            operations_builder.port_write_u32(0xcfc, value);
+        }
+        operations_builder.send();
+    }
+}
+
+/// Returns the value to write to port `0xcf8` in order to access the given configuration
+/// register.
+fn config_address(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
     //assert!(bus < 256); // commented out because always true
     assert!(slot < 32);
     assert!(func < 8);
     //assert!(offset < 256) // commented out because always true
     assert_eq!(offset & 3, 0);
 
-    let addr: u32 = 0x80000000 |
+    0x80000000 |
         (u32::from(bus) << 16) |
         (u32::from(slot) << 11) |
         (u32::from(func) << 8) |
-        u32::from(offset);
-
-    let mut operations_builder = redshirt_hardware_interface::HardwareOperationsBuilder::new();
-    operations_builder.port_write_u32(0xcf8, addr);
-    let mut out = 0;
-    // TODO: is it correct to immediately read back afterwards without delay? seems weird to me
-    operations_builder.port_read_u32(0xcfc, &mut out);
-    operations_builder.send().await;
-    out
+        u32::from(offset)
 }
@@ -0,0 +1,279 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The PS/2 controller, also known as the 8042.
+//!
+//! The controller has up to two ports, normally connected respectively to a keyboard and a
+//! mouse. Commands for the controller are written to the command port, while data for the
+//! devices and bytes sent by the devices go through the data port.
+
+use redshirt_hardware_interface::port_read_u8;
+use std::time::Duration;
+
+/// I/O port used to exchange data with the devices.
+const DATA_PORT: u32 = 0x60;
+/// I/O port used to read the status register (when reading) or send commands (when writing).
+const STATUS_COMMAND_PORT: u32 = 0x64;
+
+/// Bits of the status register.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// Set if the byte in the output buffer comes from the second port.
+const STATUS_SECOND_PORT: u8 = 1 << 5;
+
+/// Commands of the controller.
+const COMMAND_READ_CONFIG: u8 = 0x20;
+const COMMAND_WRITE_CONFIG: u8 = 0x60;
+const COMMAND_DISABLE_SECOND_PORT: u8 = 0xa7;
+const COMMAND_ENABLE_SECOND_PORT: u8 = 0xa8;
+const COMMAND_TEST_SECOND_PORT: u8 = 0xa9;
+const COMMAND_SELF_TEST: u8 = 0xaa;
+const COMMAND_TEST_FIRST_PORT: u8 = 0xab;
+const COMMAND_DISABLE_FIRST_PORT: u8 = 0xad;
+const COMMAND_ENABLE_FIRST_PORT: u8 = 0xae;
+const COMMAND_WRITE_SECOND_PORT: u8 = 0xd4;
+
+/// Bits of the configuration byte.
+const CONFIG_FIRST_PORT_INTERRUPT: u8 = 1 << 0;
+const CONFIG_SECOND_PORT_INTERRUPT: u8 = 1 << 1;
+const CONFIG_SECOND_PORT_CLOCK_DISABLED: u8 = 1 << 5;
+/// If set, the controller translates the scancodes of the keyboard to scancode set 1.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// Bytes sent by the devices.
+pub const DEVICE_ACK: u8 = 0xfa;
+pub const DEVICE_RESEND: u8 = 0xfe;
+pub const DEVICE_SELF_TEST_PASSED: u8 = 0xaa;
+
+/// Command common to all devices that resets them.
+pub const DEVICE_COMMAND_RESET: u8 = 0xff;
+
+/// Interval between two reads of the status register while waiting for the controller.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// Number of times we read the status register before giving up. Resetting a device is known
+/// to take up to several hundred milliseconds.
+const MAX_POLLS: u32 = 1000;
+
+/// Port of the controller.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Port {
+    /// First port, normally connected to a keyboard.
+    First,
+    /// Second port, normally connected to a mouse.
+    Second,
+}
+
+/// Initialized PS/2 controller.
+pub struct Controller {
+    /// True if the controller has a working second port.
+    has_second_port: bool,
+}
+
+impl Controller {
+    /// Checks whether a PS/2 controller is present, and initializes it. The ports are enabled
+    /// but interrupts are disabled, and the devices aren't reset.
+    ///
+    /// Returns an error if no working controller has been found.
+    ///
+    /// # Safety
+    ///
+    /// Assumes that the ports of the PS/2 controller aren't used for something else, and that
+    /// only one `Controller` exists at any given time.
+    ///
+    // TODO: the ACPI tables indicate whether a PS/2 controller exists, which we should check
+    pub async unsafe fn init() -> Result<Self, ()> {
+        // If there is no controller, reading the status register returns 0xff.
+        if port_read_u8(STATUS_COMMAND_PORT).await == 0xff {
+            return Err(());
+        }
+
+        let controller = Controller {
+            has_second_port: false,
+        };
+
+        controller.command(COMMAND_DISABLE_FIRST_PORT).await?;
+        controller.command(COMMAND_DISABLE_SECOND_PORT).await?;
+
+        // Discard what the devices might have sent in the past.
+        for _ in 0..16 {
+            if port_read_u8(STATUS_COMMAND_PORT).await & STATUS_OUTPUT_FULL == 0 {
+                break;
+            }
+            port_read_u8(DATA_PORT).await;
+        }
+
+        let config = controller.read_config().await?;
+        let config = (config & !(CONFIG_FIRST_PORT_INTERRUPT | CONFIG_SECOND_PORT_INTERRUPT))
+            | CONFIG_TRANSLATION;
+        controller.write_config(config).await?;
+
+        controller.command(COMMAND_SELF_TEST).await?;
+        if controller.read_data().await? != 0x55 {
+            return Err(());
+        }
+        // The self test might reset the controller.
+        controller.write_config(config).await?;
+
+        // If the controller has a second port, enabling it clears the corresponding bit of the
+        // configuration.
+        controller.command(COMMAND_ENABLE_SECOND_PORT).await?;
+        let mut has_second_port =
+            controller.read_config().await? & CONFIG_SECOND_PORT_CLOCK_DISABLED == 0;
+        controller.command(COMMAND_DISABLE_SECOND_PORT).await?;
+
+        controller.command(COMMAND_TEST_FIRST_PORT).await?;
+        let first_port_works = controller.read_data().await? == 0x0;
+        if has_second_port {
+            controller.command(COMMAND_TEST_SECOND_PORT).await?;
+            has_second_port = controller.read_data().await? == 0x0;
+        }
+
+        if !first_port_works && !has_second_port {
+            return Err(());
+        }
+
+        if first_port_works {
+            controller.command(COMMAND_ENABLE_FIRST_PORT).await?;
+        }
+        if has_second_port {
+            controller.command(COMMAND_ENABLE_SECOND_PORT).await?;
+        }
+
+        Ok(Controller { has_second_port })
+    }
+
+    /// Returns true if the controller has a working second port.
+    pub fn has_second_port(&self) -> bool {
+        self.has_second_port
+    }
+
+    /// Enables the interrupts of the given ports. IRQ 1 is triggered when the device of the
+    /// first port sends data, and IRQ 12 when the device of the second port sends data.
+    pub async fn enable_interrupts(&self, first_port: bool, second_port: bool) -> Result<(), ()> {
+        let mut config = self.read_config().await?;
+        if first_port {
+            config |= CONFIG_FIRST_PORT_INTERRUPT;
+        }
+        if second_port && self.has_second_port {
+            config |= CONFIG_SECOND_PORT_INTERRUPT;
+        }
+        self.write_config(config).await
+    }
+
+    /// Sends a byte to the device connected to the given port, and waits for the device to
+    /// acknowledge it.
+    ///
+    /// Returns an error if the device doesn't acknowledge the byte, which is the case if
+    /// nothing is connected to the port.
+    pub async fn send_to_device(&self, port: Port, byte: u8) -> Result<(), ()> {
+        // Devices ask for the byte to be sent again if it has been corrupted.
+        for _ in 0..3 {
+            if port == Port::Second {
+                self.command(COMMAND_WRITE_SECOND_PORT).await?;
+            }
+            self.write_data(byte).await?;
+
+            match self.read_data().await? {
+                DEVICE_ACK => return Ok(()),
+                DEVICE_RESEND => continue,
+                _ => return Err(()),
+            }
+        }
+
+        Err(())
+    }
+
+    /// Waits for the next byte sent by a device, and returns it.
+    ///
+    /// Returns an error if nothing is received after a while. Must only be used during the
+    /// initialization of the devices, as it doesn't report which port the byte comes from.
+    pub async fn read_data(&self) -> Result<u8, ()> {
+        for _ in 0..MAX_POLLS {
+            unsafe {
+                if port_read_u8(STATUS_COMMAND_PORT).await & STATUS_OUTPUT_FULL != 0 {
+                    return Ok(port_read_u8(DATA_PORT).await);
+                }
+            }
+            redshirt_time_interface::monotonic_wait(POLL_INTERVAL).await;
+        }
+
+        Err(())
+    }
+
+    /// Returns all the bytes that have been sent by the devices and not read yet. Can be empty.
+    ///
+    /// This should be called whenever the controller triggers an interrupt.
+    pub async fn read_available(&self) -> Vec<(Port, u8)> {
+        let mut out = Vec::new();
+
+        unsafe {
+            loop {
+                let status = port_read_u8(STATUS_COMMAND_PORT).await;
+                if status & STATUS_OUTPUT_FULL == 0 {
+                    break;
+                }
+
+                let port = if self.has_second_port && status & STATUS_SECOND_PORT != 0 {
+                    Port::Second
+                } else {
+                    Port::First
+                };
+                out.push((port, port_read_u8(DATA_PORT).await));
+            }
+        }
+
+        out
+    }
+
+    async fn read_config(&self) -> Result<u8, ()> {
+        self.command(COMMAND_READ_CONFIG).await?;
+        self.read_data().await
+    }
+
+    async fn write_config(&self, config: u8) -> Result<(), ()> {
+        self.command(COMMAND_WRITE_CONFIG).await?;
+        self.write_data(config).await
+    }
+
+    async fn command(&self, command: u8) -> Result<(), ()> {
+        self.wait_input_empty().await?;
+        unsafe {
+            redshirt_hardware_interface::port_write_u8(STATUS_COMMAND_PORT, command);
+        }
+        Ok(())
+    }
+
+    async fn write_data(&self, byte: u8) -> Result<(), ()> {
+        self.wait_input_empty().await?;
+        unsafe {
+            redshirt_hardware_interface::port_write_u8(DATA_PORT, byte);
+        }
+        Ok(())
+    }
+
+    /// Waits until the controller is ready to accept a byte on the data or command port.
+    async fn wait_input_empty(&self) -> Result<(), ()> {
+        for _ in 0..MAX_POLLS {
+            unsafe {
+                if port_read_u8(STATUS_COMMAND_PORT).await & STATUS_INPUT_FULL == 0 {
+                    return Ok(());
+                }
+            }
+            redshirt_time_interface::monotonic_wait(POLL_INTERVAL).await;
+        }
+
+        Err(())
+    }
+}
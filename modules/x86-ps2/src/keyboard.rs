@@ -0,0 +1,291 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! PS/2 keyboards.
+//!
+//! Keyboards use scancode set 2 by default, which the controller translates to scancode set 1.
+//! In scancode set 1, pressing a key sends its code, and releasing it sends its code with the
+//! highest bit set. Some keys have codes prefixed with `0xe0`.
+
+use crate::controller::{Controller, Port, DEVICE_COMMAND_RESET, DEVICE_SELF_TEST_PASSED};
+use redshirt_input_interface::{keys, InputEvent};
+
+/// Command that makes the keyboard start sending scancodes.
+const COMMAND_ENABLE_SCANNING: u8 = 0xf4;
+
+/// USB HID usage IDs of the keys that have a special meaning for the decoder.
+const USAGE_CAPS_LOCK: u32 = 0x39;
+const USAGE_PAUSE: u32 = 0x48;
+const USAGE_LEFT_SHIFT: u32 = 0xe1;
+const USAGE_RIGHT_SHIFT: u32 = 0xe5;
+
+/// USB HID usage IDs of the keys of scancode set 1, indexed by scancode. `0` means unknown.
+#[rustfmt::skip]
+const SET1_USAGES: [u8; 0x59] = [
+    0x00, 0x29, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23, // 0x00: -, Esc, 1 to 7
+    0x24, 0x25, 0x26, 0x27, 0x2d, 0x2e, 0x2a, 0x2b, // 0x08: 8, 9, 0, -, =, Backspace, Tab
+    0x14, 0x1a, 0x08, 0x15, 0x17, 0x1c, 0x18, 0x0c, // 0x10: Q W E R T Y U I
+    0x12, 0x13, 0x2f, 0x30, 0x28, 0xe0, 0x04, 0x16, // 0x18: O P [ ] Enter LCtrl A S
+    0x07, 0x09, 0x0a, 0x0b, 0x0d, 0x0e, 0x0f, 0x33, // 0x20: D F G H J K L ;
+    0x34, 0x35, 0xe1, 0x31, 0x1d, 0x1b, 0x06, 0x19, // 0x28: ' ` LShift \ Z X C V
+    0x05, 0x11, 0x10, 0x36, 0x37, 0x38, 0xe5, 0x55, // 0x30: B N M , . / RShift KP*
+    0xe2, 0x2c, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e, // 0x38: LAlt Space CapsLock F1 to F5
+    0x3f, 0x40, 0x41, 0x42, 0x43, 0x53, 0x47, 0x5f, // 0x40: F6 to F10 NumLock ScrollLock KP7
+    0x60, 0x61, 0x56, 0x5c, 0x5d, 0x5e, 0x57, 0x59, // 0x48: KP8 KP9 KP- KP4 KP5 KP6 KP+ KP1
+    0x5a, 0x5b, 0x62, 0x63, 0x00, 0x00, 0x64, 0x44, // 0x50: KP2 KP3 KP0 KP. - - NonUS\ F11
+    0x45,                                           // 0x58: F12
+];
+
+/// Returns the USB HID usage ID of the key of scancode set 1 prefixed with `0xe0`, or `None` if
+/// the key is unknown.
+///
+/// The "fake shifts" (`0xe0 0x2a` and `0xe0 0x36`) that some keyboards send around some keys
+/// are treated as unknown keys.
+fn extended_usage(code: u8) -> Option<u32> {
+    Some(match code {
+        0x1c => 0x58, // Keypad Enter
+        0x1d => 0xe4, // Right Control
+        0x35 => 0x54, // Keypad /
+        0x37 => 0x46, // Print Screen
+        0x38 => 0xe6, // Right Alt
+        0x47 => 0x4a, // Home
+        0x48 => 0x52, // Up
+        0x49 => 0x4b, // Page Up
+        0x4b => 0x50, // Left
+        0x4d => 0x4f, // Right
+        0x4f => 0x4d, // End
+        0x50 => 0x51, // Down
+        0x51 => 0x4e, // Page Down
+        0x52 => 0x49, // Insert
+        0x53 => 0x4c, // Delete
+        0x5b => 0xe3, // Left GUI
+        0x5c => 0xe7, // Right GUI
+        0x5d => 0x65, // Application
+        _ => return None,
+    })
+}
+
+/// Resets the keyboard connected to the first port and makes it start sending scancodes.
+///
+/// Returns an error if no keyboard is connected.
+pub async fn init(controller: &Controller) -> Result<(), ()> {
+    controller
+        .send_to_device(Port::First, DEVICE_COMMAND_RESET)
+        .await?;
+    if controller.read_data().await? != DEVICE_SELF_TEST_PASSED {
+        return Err(());
+    }
+
+    controller
+        .send_to_device(Port::First, COMMAND_ENABLE_SCANNING)
+        .await
+}
+
+/// Turns the bytes sent by a keyboard into events.
+///
+/// Keysyms are determined according to the US QWERTY layout.
+// TODO: support other layouts, and update the LEDs of the keyboard
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// Progress of the sequence of bytes being received.
+    state: State,
+    /// True if the left shift key is being held.
+    left_shift: bool,
+    /// True if the right shift key is being held.
+    right_shift: bool,
+    /// True if caps lock is enabled.
+    caps_lock: bool,
+}
+
+#[derive(Debug)]
+enum State {
+    /// Waiting for the start of a sequence.
+    Idle,
+    /// Received the `0xe0` prefix.
+    Extended,
+    /// Received the `0xe1` prefix, which is only used by the pause key. The pause key sends
+    /// `0xe1 0x1d 0x45` when pressed and `0xe1 0x9d 0xc5` when released.
+    Pause {
+        /// Number of bytes of the sequence remaining.
+        remaining: u8,
+        /// True if the sequence corresponds to the key being released.
+        released: bool,
+    },
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::Idle
+    }
+}
+
+impl Decoder {
+    /// Initializes a new decoder.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Processes a byte sent by the keyboard. Returns an event if a key has been pressed or
+    /// released.
+    pub fn push(&mut self, byte: u8) -> Option<InputEvent> {
+        let (scancode, pressed) = match self.state {
+            State::Idle if byte == 0xe0 => {
+                self.state = State::Extended;
+                return None;
+            }
+            State::Idle if byte == 0xe1 => {
+                self.state = State::Pause {
+                    remaining: 2,
+                    released: false,
+                };
+                return None;
+            }
+            State::Idle => {
+                let usage = SET1_USAGES.get(usize::from(byte & 0x7f)).cloned();
+                match usage {
+                    Some(usage) if usage != 0 => (u32::from(usage), byte & 0x80 == 0),
+                    _ => return None,
+                }
+            }
+            State::Extended => {
+                self.state = State::Idle;
+                (extended_usage(byte & 0x7f)?, byte & 0x80 == 0)
+            }
+            State::Pause {
+                remaining,
+                released,
+            } => {
+                let released = released || byte & 0x80 != 0;
+                if remaining > 1 {
+                    self.state = State::Pause {
+                        remaining: remaining - 1,
+                        released,
+                    };
+                    return None;
+                }
+
+                self.state = State::Idle;
+                (USAGE_PAUSE, !released)
+            }
+        };
+
+        match scancode {
+            USAGE_LEFT_SHIFT => self.left_shift = pressed,
+            USAGE_RIGHT_SHIFT => self.right_shift = pressed,
+            USAGE_CAPS_LOCK if pressed => self.caps_lock = !self.caps_lock,
+            _ => {}
+        }
+
+        let shift = self.left_shift || self.right_shift;
+        Some(InputEvent::Key {
+            scancode,
+            keysym: keys::us_qwerty_keysym(scancode, shift, self.caps_lock),
+            pressed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decoder;
+    use redshirt_input_interface::InputEvent;
+
+    fn key(scancode: u32, keysym: u32, pressed: bool) -> Option<InputEvent> {
+        Some(InputEvent::Key {
+            scancode,
+            keysym,
+            pressed,
+        })
+    }
+
+    #[test]
+    fn shift_and_letters() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.push(0x1e), key(0x04, u32::from(b'a'), true));
+        assert_eq!(decoder.push(0x9e), key(0x04, u32::from(b'a'), false));
+        assert_eq!(decoder.push(0x2a), key(0xe1, 0xffe1, true));
+        assert_eq!(decoder.push(0x02), key(0x1e, u32::from(b'!'), true));
+        assert_eq!(decoder.push(0x1e), key(0x04, u32::from(b'A'), true));
+        assert_eq!(decoder.push(0xaa), key(0xe1, 0xffe1, false));
+    }
+
+    #[test]
+    fn caps_lock() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.push(0x3a), key(0x39, 0xffe5, true));
+        assert_eq!(decoder.push(0xba), key(0x39, 0xffe5, false));
+        // Caps lock only applies to letters, and shift cancels it.
+        assert_eq!(decoder.push(0x10), key(0x14, u32::from(b'Q'), true));
+        assert_eq!(decoder.push(0x02), key(0x1e, u32::from(b'1'), true));
+        assert_eq!(decoder.push(0x36), key(0xe5, 0xffe2, true));
+        assert_eq!(decoder.push(0x10), key(0x14, u32::from(b'q'), true));
+        assert_eq!(decoder.push(0x02), key(0x1e, u32::from(b'!'), true));
+        assert_eq!(decoder.push(0xb6), key(0xe5, 0xffe2, false));
+        // Pressing caps lock again disables it.
+        assert_eq!(decoder.push(0x3a), key(0x39, 0xffe5, true));
+        assert_eq!(decoder.push(0xba), key(0x39, 0xffe5, false));
+        assert_eq!(decoder.push(0x10), key(0x14, u32::from(b'q'), true));
+    }
+
+    #[test]
+    fn special_keys() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.push(0x01), key(0x29, 0xff1b, true));
+        assert_eq!(decoder.push(0x1c), key(0x28, 0xff0d, true));
+        assert_eq!(decoder.push(0x3b), key(0x3a, 0xffbe, true));
+        assert_eq!(decoder.push(0x58), key(0x45, 0xffc9, true));
+        assert_eq!(decoder.push(0x47), key(0x5f, 0xffb7, true));
+        assert_eq!(decoder.push(0x52), key(0x62, 0xffb0, true));
+        assert_eq!(decoder.push(0xe0), None);
+        assert_eq!(decoder.push(0x1c), key(0x58, 0xff8d, true));
+        assert_eq!(decoder.push(0xe0), None);
+        assert_eq!(decoder.push(0x35), key(0x54, 0xffaf, true));
+    }
+
+    #[test]
+    fn unknown_codes() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.push(0x00), None);
+        assert_eq!(decoder.push(0x54), None);
+        assert_eq!(decoder.push(0x7f), None);
+        // The decoder isn't disturbed by unknown codes.
+        assert_eq!(decoder.push(0x1e), key(0x04, u32::from(b'a'), true));
+    }
+
+    #[test]
+    fn extended() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.push(0xe0), None);
+        assert_eq!(decoder.push(0x48), key(0x52, 0xff52, true));
+        assert_eq!(decoder.push(0xe0), None);
+        assert_eq!(decoder.push(0xc8), key(0x52, 0xff52, false));
+        // Fake shift.
+        assert_eq!(decoder.push(0xe0), None);
+        assert_eq!(decoder.push(0x2a), None);
+    }
+
+    #[test]
+    fn pause() {
+        let mut decoder = Decoder::new();
+        for byte in &[0xe1, 0x1d] {
+            assert_eq!(decoder.push(*byte), None);
+        }
+        assert_eq!(decoder.push(0x45), key(0x48, 0xff13, true));
+        for byte in &[0xe1, 0x9d] {
+            assert_eq!(decoder.push(*byte), None);
+        }
+        assert_eq!(decoder.push(0xc5), key(0x48, 0xff13, false));
+    }
+}
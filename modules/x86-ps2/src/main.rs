@@ -0,0 +1,140 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Driver for the PS/2 controller found on x86 platforms, and for the keyboard and mouse
+//! connected to it.
+//!
+//! This program initializes the controller and the devices, then reports what the user does
+//! through the `input` interface.
+//!
+//! Bibliography:
+//!
+//! - https://wiki.osdev.org/%228042%22_PS/2_Controller
+//! - https://wiki.osdev.org/PS/2_Keyboard
+//! - https://wiki.osdev.org/PS/2_Mouse
+//!
+
+mod controller;
+mod keyboard;
+mod mouse;
+
+use controller::{Controller, Port};
+use futures::prelude::*;
+use redshirt_hardware_interface::interrupts::InterruptSubscription;
+use redshirt_input_interface::DeviceKind;
+use std::time::Duration;
+
+/// IRQ line of the first port of the controller.
+const FIRST_PORT_IRQ: u32 = 1;
+/// IRQ line of the second port of the controller.
+const SECOND_PORT_IRQ: u32 = 12;
+
+/// Interval at which to check the controller if interrupts aren't available.
+const POLLING_INTERVAL: Duration = Duration::from_millis(10);
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    let controller = match unsafe { Controller::init().await } {
+        Ok(c) => c,
+        Err(()) => return,
+    };
+
+    // The mouse is initialized first, as the keyboard starts sending scancodes as soon as it is
+    // initialized, which would be mixed with the responses of the mouse.
+    let mut mouse = if controller.has_second_port() {
+        mouse::init(&controller).await.ok().map(|decoder| {
+            (
+                decoder,
+                redshirt_input_interface::register_device(DeviceKind::Pointer),
+            )
+        })
+    } else {
+        None
+    };
+
+    let mut keyboard = match keyboard::init(&controller).await {
+        Ok(()) => Some((
+            keyboard::Decoder::new(),
+            redshirt_input_interface::register_device(DeviceKind::Keyboard),
+        )),
+        Err(()) => None,
+    };
+
+    if keyboard.is_none() && mouse.is_none() {
+        return;
+    }
+
+    // The devices share the output buffer of the controller. If we miss the interrupts of one
+    // device, its data blocks the other device as well. We poll the controller instead if any
+    // of the subscriptions fails.
+    let mut interrupts = Vec::new();
+    for (irq, enabled) in &[
+        (FIRST_PORT_IRQ, keyboard.is_some()),
+        (SECOND_PORT_IRQ, mouse.is_some()),
+    ] {
+        if *enabled {
+            match InterruptSubscription::subscribe(*irq).await {
+                Ok(subscription) => interrupts.push(subscription),
+                Err(()) => {
+                    interrupts.clear();
+                    break;
+                }
+            }
+        }
+    }
+
+    if controller
+        .enable_interrupts(keyboard.is_some(), mouse.is_some())
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        // Note that the first read happens before waiting, in order to process the data that
+        // might have been received before the subscription.
+        for (port, byte) in controller.read_available().await {
+            match (port, &mut keyboard, &mut mouse) {
+                (Port::First, Some((decoder, device)), _) => {
+                    if let Some(event) = decoder.push(byte) {
+                        device.send_event(event);
+                    }
+                }
+                (Port::Second, _, Some((decoder, device))) => {
+                    for event in decoder.push(byte) {
+                        device.send_event(event);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if interrupts.is_empty() {
+            redshirt_time_interface::monotonic_wait(POLLING_INTERVAL).await;
+        } else {
+            let result = future::select_all(interrupts.iter_mut().map(|i| Box::pin(i.wait())))
+                .await
+                .0;
+            // As above, we poll the controller if one of the subscriptions is no longer active.
+            if result.is_err() {
+                interrupts.clear();
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! PS/2 mice.
+//!
+//! Once enabled, mice send packets of three bytes, or four bytes if the mouse has a wheel and
+//! the wheel has been enabled. The first byte contains the state of the buttons and the high
+//! bits of the movement, the second and third bytes the movement, and the fourth byte the
+//! movement of the wheel.
+
+use crate::controller::{Controller, Port, DEVICE_COMMAND_RESET, DEVICE_SELF_TEST_PASSED};
+use redshirt_input_interface::InputEvent;
+
+/// Commands of the mouse.
+const COMMAND_GET_ID: u8 = 0xf2;
+const COMMAND_SET_SAMPLE_RATE: u8 = 0xf3;
+const COMMAND_ENABLE_REPORTING: u8 = 0xf4;
+
+/// Identifier reported by mice whose wheel is enabled.
+const ID_WHEEL: u8 = 3;
+
+/// Bits of the first byte of a packet.
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_OVERFLOW: u8 = (1 << 6) | (1 << 7);
+
+/// Resets the mouse connected to the second port, enables its wheel if it has one, and makes
+/// it start sending packets.
+///
+/// Returns an error if no mouse is connected.
+pub async fn init(controller: &Controller) -> Result<Decoder, ()> {
+    controller
+        .send_to_device(Port::Second, DEVICE_COMMAND_RESET)
+        .await?;
+    if controller.read_data().await? != DEVICE_SELF_TEST_PASSED {
+        return Err(());
+    }
+    // The self test result is followed with the identifier of the mouse, which we don't need.
+    let _ = controller.read_data().await;
+
+    // Setting this sequence of sample rates is how the wheel of IntelliMouse-compatible mice
+    // is enabled. Mice that don't support it keep reporting the same identifier.
+    for rate in &[200, 100, 80] {
+        controller
+            .send_to_device(Port::Second, COMMAND_SET_SAMPLE_RATE)
+            .await?;
+        controller.send_to_device(Port::Second, *rate).await?;
+    }
+    controller
+        .send_to_device(Port::Second, COMMAND_GET_ID)
+        .await?;
+    let has_wheel = controller.read_data().await? == ID_WHEEL;
+
+    controller
+        .send_to_device(Port::Second, COMMAND_ENABLE_REPORTING)
+        .await?;
+    Ok(Decoder::new(has_wheel))
+}
+
+/// Turns the bytes sent by a mouse into events.
+#[derive(Debug)]
+pub struct Decoder {
+    /// True if packets contain a fourth byte for the wheel.
+    has_wheel: bool,
+    /// Packet being received.
+    packet: [u8; 4],
+    /// Number of bytes of `packet` that have been received.
+    packet_len: usize,
+    /// State of the buttons as of the previous packet. Bit 0 is the left button, bit 1 the
+    /// right button, and bit 2 the middle button.
+    buttons: u8,
+}
+
+impl Decoder {
+    /// Initializes a new decoder. `has_wheel` must be true if the wheel of the mouse has been
+    /// enabled.
+    pub fn new(has_wheel: bool) -> Self {
+        Decoder {
+            has_wheel,
+            packet: [0; 4],
+            packet_len: 0,
+            buttons: 0,
+        }
+    }
+
+    /// Processes a byte sent by the mouse. Returns the events corresponding to the packet if
+    /// the byte completes a packet.
+    pub fn push(&mut self, byte: u8) -> Vec<InputEvent> {
+        // If we get out of sync with the mouse, we discard bytes until we find one that looks
+        // like the start of a packet.
+        if self.packet_len == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return Vec::new();
+        }
+
+        self.packet[self.packet_len] = byte;
+        self.packet_len += 1;
+        if self.packet_len < if self.has_wheel { 4 } else { 3 } {
+            return Vec::new();
+        }
+        self.packet_len = 0;
+
+        let mut events = Vec::new();
+        let flags = self.packet[0];
+
+        // The movement is a 9 bits two's complement number, and the Y axis points up.
+        if flags & PACKET_OVERFLOW == 0 {
+            let mut dx = i32::from(self.packet[1]);
+            if flags & PACKET_X_SIGN != 0 {
+                dx -= 0x100;
+            }
+            let mut dy = i32::from(self.packet[2]);
+            if flags & PACKET_Y_SIGN != 0 {
+                dy -= 0x100;
+            }
+
+            if dx != 0 || dy != 0 {
+                events.push(InputEvent::PointerMotion { dx, dy: -dy });
+            }
+        }
+
+        let buttons = flags & 0x7;
+        for button in 0..3 {
+            if (buttons ^ self.buttons) & (1 << button) != 0 {
+                events.push(InputEvent::PointerButton {
+                    button,
+                    pressed: buttons & (1 << button) != 0,
+                });
+            }
+        }
+        self.buttons = buttons;
+
+        if self.has_wheel {
+            // The movement of the wheel is a 4 bits two's complement number, positive towards
+            // the user.
+            let delta = i32::from(((self.packet[3] << 4) as i8) >> 4);
+            if delta != 0 {
+                events.push(InputEvent::PointerWheel { delta: -delta });
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Decoder;
+    use redshirt_input_interface::InputEvent;
+
+    #[test]
+    fn movement_and_buttons() {
+        let mut decoder = Decoder::new(false);
+        // Y sign bit set, left button pressed.
+        assert!(decoder.push(0x29).is_empty());
+        assert!(decoder.push(0x05).is_empty());
+        assert_eq!(
+            decoder.push(0xfe),
+            vec![
+                InputEvent::PointerMotion { dx: 5, dy: 2 },
+                InputEvent::PointerButton {
+                    button: 0,
+                    pressed: true
+                },
+            ]
+        );
+
+        // X sign bit set, left button released.
+        assert_eq!(decoder.push(0x18), vec![]);
+        decoder.push(0xff);
+        assert_eq!(
+            decoder.push(0x00),
+            vec![
+                InputEvent::PointerMotion { dx: -1, dy: 0 },
+                InputEvent::PointerButton {
+                    button: 0,
+                    pressed: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn resync() {
+        let mut decoder = Decoder::new(false);
+        assert!(decoder.push(0x00).is_empty());
+        assert!(decoder.push(0x08).is_empty());
+        assert!(decoder.push(0x00).is_empty());
+        assert_eq!(
+            decoder.push(0x01),
+            vec![InputEvent::PointerMotion { dx: 0, dy: -1 }]
+        );
+    }
+
+    #[test]
+    fn wheel() {
+        let mut decoder = Decoder::new(true);
+        for byte in &[0x08, 0x00, 0x00] {
+            assert!(decoder.push(*byte).is_empty());
+        }
+        assert_eq!(
+            decoder.push(0x0f),
+            vec![InputEvent::PointerWheel { delta: 1 }]
+        );
+    }
+}
@@ -22,7 +22,21 @@ fn main() {
     redshirt_syscalls_interface::block_on(async_main());
 }
 
-async fn async_main() -> ! {
+async fn async_main() {
+    // If the bootloader has set up a graphical framebuffer, there's no text mode to write to.
+    // The `framebuffer-console` module handles this situation instead.
+    match redshirt_hardware_interface::framebuffer().await {
+        None | Some(redshirt_hardware_interface::ffi::FramebufferInfo {
+            format: redshirt_hardware_interface::ffi::FramebufferFormat::Text, ..
+        }) => {}
+        Some(_) => return,
+    }
+
+    // Claim the VGA text buffer.
+    if redshirt_hardware_interface::claim_mmio(ptr_of(0, 0), 80 * 25 * 2).await.is_err() {
+        return;
+    }
+
     redshirt_interface_interface::register_interface(redshirt_stdout_interface::ffi::INTERFACE)
         .await.unwrap();
 
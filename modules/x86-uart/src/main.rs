@@ -0,0 +1,128 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Driver for the 16550 UART found on x86 platforms.
+//!
+//! This program initializes the UART at COM1, then implements the `serial` interface by sending
+//! and receiving data through it.
+//!
+//! Bibliography:
+//!
+//! - https://wiki.osdev.org/Serial_Ports
+//! - https://en.wikibooks.org/wiki/Serial_Programming/8250_UART_Programming
+//!
+
+mod uart;
+
+use futures::prelude::*;
+use parity_scale_codec::DecodeAll;
+use redshirt_hardware_interface::interrupts::InterruptSubscription;
+use redshirt_serial_interface::ffi::SerialMessage;
+use redshirt_syscalls_interface::{InterfaceOrDestroyed, MessageId, Pid};
+use std::{collections::VecDeque, mem, time::Duration};
+
+/// Base I/O port of COM1.
+const COM1_PORT: u32 = 0x3f8;
+/// IRQ line of COM1.
+const COM1_IRQ: u32 = 4;
+/// Baud rate to initialize the UART with.
+const DEFAULT_BAUD_RATE: u32 = 115200;
+/// Interval at which to check the UART if interrupts aren't available.
+const POLLING_INTERVAL: Duration = Duration::from_millis(10);
+
+fn main() {
+    redshirt_syscalls_interface::block_on(async_main());
+}
+
+async fn async_main() {
+    let uart = match unsafe { uart::Uart::init(COM1_PORT, DEFAULT_BAUD_RATE).await } {
+        Ok(uart) => uart,
+        Err(()) => return,
+    };
+
+    let mut interrupts = InterruptSubscription::subscribe(COM1_IRQ).await.ok();
+
+    redshirt_interface_interface::register_interface(redshirt_serial_interface::ffi::INTERFACE)
+        .await
+        .unwrap();
+
+    // Data received from the UART but not delivered yet.
+    let mut received = Vec::new();
+    // Read messages waiting for data to be received.
+    let mut pending_reads = VecDeque::<(Pid, MessageId)>::new();
+
+    // Kept alive across iterations, as the underlying message can't be cancelled.
+    let mut next_message = Box::pin(redshirt_syscalls_interface::next_interface_message());
+
+    loop {
+        // Note that the first read happens before waiting, in order to process the data that
+        // might have been received before the subscription.
+        received.extend(uart.read_available().await);
+        if !received.is_empty() {
+            if let Some((_, message_id)) = pending_reads.pop_front() {
+                redshirt_syscalls_interface::emit_answer(
+                    message_id,
+                    &mem::replace(&mut received, Vec::new()),
+                );
+            }
+        }
+
+        let interrupt = Box::pin(wait_interrupt(&mut interrupts));
+        let msg = match future::select(next_message.as_mut(), interrupt).await {
+            future::Either::Left((msg, _)) => msg,
+            future::Either::Right(((), _)) => continue,
+        };
+        next_message = Box::pin(redshirt_syscalls_interface::next_interface_message());
+
+        let msg = match msg {
+            InterfaceOrDestroyed::Interface(msg) => msg,
+            InterfaceOrDestroyed::ProcessDestroyed(destroyed) => {
+                pending_reads.retain(|(p, _)| *p != destroyed.pid);
+                continue;
+            }
+        };
+
+        match (SerialMessage::decode_all(&msg.actual_data), msg.message_id) {
+            (Ok(SerialMessage::Write(data)), _) => uart.write(&data).await,
+            (Ok(SerialMessage::Read), Some(message_id)) => {
+                pending_reads.push_back((msg.emitter_pid, message_id));
+            }
+            (Ok(SerialMessage::Configure(config)), Some(message_id)) => {
+                let result = uart.set_baud_rate(config.baud_rate);
+                redshirt_syscalls_interface::emit_answer(message_id, &result);
+            }
+            (Ok(_), None) => {}
+            (Err(_), Some(message_id)) => {
+                redshirt_syscalls_interface::emit_message_error(message_id)
+            }
+            (Err(_), None) => {}
+        }
+    }
+}
+
+/// Waits for the next interrupt. If the subscription turns out to no longer be active, it is
+/// dropped and we fall back to polling the UART.
+///
+/// If we're not subscribed to any interrupt, waits for a small amount of time instead.
+async fn wait_interrupt(interrupts: &mut Option<InterruptSubscription>) {
+    if let Some(subscription) = interrupts {
+        if subscription.wait().await.is_ok() {
+            return;
+        }
+        *interrupts = None;
+    }
+
+    redshirt_time_interface::monotonic_wait(POLLING_INTERVAL).await;
+}
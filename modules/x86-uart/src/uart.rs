@@ -0,0 +1,147 @@
+// Copyright (C) 2019  Pierre Krieger
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::convert::TryFrom as _;
+
+/// Frequency of the clock that drives the UART, divided by 16. The baud rate is obtained by
+/// dividing this value by the divisor latch.
+const BASE_BAUD_RATE: u32 = 115200;
+
+/// Size of the transmit FIFO.
+const FIFO_SIZE: usize = 16;
+
+/// Registers, relative to the base port.
+const REG_DATA: u32 = 0;
+const REG_INTERRUPT_ENABLE: u32 = 1;
+const REG_FIFO_CONTROL: u32 = 2;
+const REG_LINE_CONTROL: u32 = 3;
+const REG_MODEM_CONTROL: u32 = 4;
+const REG_LINE_STATUS: u32 = 5;
+const REG_SCRATCH: u32 = 7;
+
+/// Bits of the line status register.
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// 16550-compatible UART accessed through I/O ports.
+pub struct Uart {
+    /// Base I/O port of the UART. All registers are derived from this one.
+    base_port: u32,
+}
+
+impl Uart {
+    /// Assumes that a 16550-compatible UART might be found at `base_port`. Checks that it is
+    /// there, then initializes it with the given baud rate, 8 data bits, no parity, and one stop
+    /// bit. Enables the "received data available" interrupt.
+    ///
+    /// Returns an error if no UART is detected, or if the baud rate isn't supported.
+    pub async unsafe fn init(base_port: u32, baud_rate: u32) -> Result<Self, ()> {
+        // The scratch register isn't used by the UART. If we can't read back what we write in it,
+        // then there's most likely nothing at this port.
+        redshirt_hardware_interface::port_write_u8(base_port + REG_SCRATCH, 0xae);
+        if redshirt_hardware_interface::port_read_u8(base_port + REG_SCRATCH).await != 0xae {
+            return Err(());
+        }
+
+        let uart = Uart { base_port };
+
+        // Disable all interrupts during the configuration.
+        redshirt_hardware_interface::port_write_u8(base_port + REG_INTERRUPT_ENABLE, 0);
+        uart.set_baud_rate(baud_rate)?;
+        // Enable and clear the FIFOs, with a 14 bytes threshold for the receive interrupt.
+        redshirt_hardware_interface::port_write_u8(base_port + REG_FIFO_CONTROL, 0xc7);
+        // Set DTR, RTS and OUT2. OUT2 must be set in order for interrupts to reach the interrupt
+        // controller.
+        redshirt_hardware_interface::port_write_u8(base_port + REG_MODEM_CONTROL, 0x0b);
+        // Enable the "received data available" interrupt.
+        redshirt_hardware_interface::port_write_u8(base_port + REG_INTERRUPT_ENABLE, 0x1);
+
+        Ok(uart)
+    }
+
+    /// Modifies the baud rate of the UART. Also sets the line to 8 data bits, no parity, and one
+    /// stop bit.
+    ///
+    /// Returns an error if the baud rate isn't supported.
+    pub fn set_baud_rate(&self, baud_rate: u32) -> Result<(), ()> {
+        if baud_rate == 0 || BASE_BAUD_RATE % baud_rate != 0 {
+            return Err(());
+        }
+        let divisor = u16::try_from(BASE_BAUD_RATE / baud_rate).map_err(|_| ())?;
+
+        unsafe {
+            let mut ops = redshirt_hardware_interface::HardwareWriteOperationsBuilder::new();
+            // Setting the most significant bit of the line control register makes the data and
+            // interrupt enable registers point to the divisor latch.
+            ops.port_write_u8(self.base_port + REG_LINE_CONTROL, 0x80);
+            ops.port_write_u8(self.base_port + REG_DATA, divisor.to_le_bytes()[0]);
+            ops.port_write_u8(
+                self.base_port + REG_INTERRUPT_ENABLE,
+                divisor.to_le_bytes()[1],
+            );
+            // 8 data bits, no parity, one stop bit.
+            ops.port_write_u8(self.base_port + REG_LINE_CONTROL, 0x03);
+            ops.send();
+        }
+
+        Ok(())
+    }
+
+    /// Sends out the given data.
+    pub async fn write(&self, data: &[u8]) {
+        for chunk in data.chunks(FIFO_SIZE) {
+            unsafe {
+                // Wait for the transmit FIFO to be empty, after which we can write an entire
+                // chunk in it.
+                // TODO: use the "transmitter empty" interrupt instead of polling
+                while redshirt_hardware_interface::port_read_u8(self.base_port + REG_LINE_STATUS)
+                    .await
+                    & LINE_STATUS_TRANSMIT_EMPTY
+                    == 0
+                {}
+
+                let mut ops =
+                    redshirt_hardware_interface::HardwareWriteOperationsBuilder::with_capacity(
+                        chunk.len(),
+                    );
+                for byte in chunk {
+                    ops.port_write_u8(self.base_port + REG_DATA, *byte);
+                }
+                ops.send();
+            }
+        }
+    }
+
+    /// Returns all the data that has been received and not read yet. Can be empty.
+    ///
+    /// This should be called whenever the UART triggers an interrupt, which also acknowledges
+    /// the interrupt.
+    pub async fn read_available(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        unsafe {
+            while redshirt_hardware_interface::port_read_u8(self.base_port + REG_LINE_STATUS).await
+                & LINE_STATUS_DATA_READY
+                != 0
+            {
+                out.push(
+                    redshirt_hardware_interface::port_read_u8(self.base_port + REG_DATA).await,
+                );
+            }
+        }
+
+        out
+    }
+}